@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use subwoofer::console::Command;
+
+fuzz_target!(|data: &str| {
+    let _ = Command::parse(data);
+});
@@ -0,0 +1,17 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use subwoofer::devices::parse_saved_settings;
+use subwoofer::mapping::IntensityMapping;
+
+fuzz_target!(|data: &str| {
+    let Ok(saved) = parse_saved_settings(data) else {
+        return;
+    };
+
+    let mapping = IntensityMapping::default();
+    for device in saved.values() {
+        let intensity = mapping.scale(1.0, device.multiplier);
+        assert!((0.0..=1.0).contains(&intensity));
+    }
+});
@@ -0,0 +1,21 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use subwoofer::state::Snapshot;
+
+fuzz_target!(|data: &str| {
+    let Ok(snapshot) = Snapshot::from_json(data) else {
+        return;
+    };
+
+    // Whatever a snapshot contains, the mapping it produces must stay within bounds.
+    let mapping = snapshot.settings.mapping();
+    for envelope in [0.0, 0.5, 1.0, f64::MAX] {
+        let intensity = mapping.map(envelope);
+        assert!((0.0..=1.0).contains(&intensity));
+    }
+    for device in snapshot.devices.values() {
+        let intensity = mapping.scale(1.0, device.multiplier);
+        assert!((0.0..=1.0).contains(&intensity));
+    }
+});
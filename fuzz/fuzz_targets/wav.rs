@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use subwoofer::audio::parse_wav;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok((_, samples)) = parse_wav(data) else {
+        return;
+    };
+    assert!(samples.iter().all(|sample| sample.is_finite()));
+});
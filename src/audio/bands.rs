@@ -0,0 +1,105 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::dsp::{BUTTERWORTH_Q, Biquad};
+
+/// A named range of frequencies, followed on its own, alongside how strongly it counts towards the overall intensity.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BandSpec {
+    pub name: String,
+    pub low_hz: f32,
+    pub high_hz: f32,
+    /// Bands weighted at zero only drive the devices routed to them.
+    pub weight: f32,
+}
+
+impl FromStr for BandSpec {
+    type Err = String;
+
+    /// Parses `name=low-high`, optionally followed by `*weight`, such as `sub=20-60*0.5`.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let Some((name, range)) = value.split_once('=') else {
+            return Err(format!("expected `name=low-high`, not {}", value));
+        };
+        let (range, weight) = match range.split_once('*') {
+            Some((range, weight)) => match weight.trim().parse::<f32>() {
+                Ok(weight) if weight.is_finite() && weight >= 0.0 => (range, weight),
+                _ => return Err(format!("weight must be a positive number, not {}", weight)),
+            },
+            None => (range, 1.0),
+        };
+        let parse = |hz: &str| hz.trim().parse::<f32>().ok().filter(|hz| *hz > 0.0);
+        let (low_hz, high_hz) = range
+            .split_once('-')
+            .and_then(|(low, high)| Some((parse(low)?, parse(high)?)))
+            .filter(|(low, high)| low < high)
+            .ok_or_else(|| format!("expected a range such as 20-60, not {}", range))?;
+        if name.trim().is_empty() {
+            return Err("no band name given".to_string());
+        }
+        Ok(Self {
+            name: name.trim().to_string(),
+            low_hz,
+            high_hz,
+            weight,
+        })
+    }
+}
+
+impl fmt::Display for BandSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}={}-{}*{}",
+            self.name, self.low_hz, self.high_hz, self.weight
+        )
+    }
+}
+
+/// Splits mono audio into bands, measuring each one's level.
+pub struct BandSplitter {
+    /// A high-pass and a low-pass filter for each band, applied in turn.
+    filters: Vec<(Biquad, Biquad)>,
+    weights: Vec<f32>,
+}
+
+impl BandSplitter {
+    pub fn new(bands: &[BandSpec], sample_rate: f32) -> Self {
+        Self {
+            filters: bands
+                .iter()
+                .map(|band| {
+                    (
+                        Biquad::highpass(sample_rate, band.low_hz, BUTTERWORTH_Q),
+                        Biquad::lowpass(sample_rate, band.high_hz, BUTTERWORTH_Q),
+                    )
+                })
+                .collect(),
+            weights: bands.iter().map(|band| band.weight).collect(),
+        }
+    }
+
+    /// Returns the RMS level of each band over `block`.
+    pub fn process(&mut self, block: &[f32]) -> Vec<f64> {
+        self.filters
+            .iter_mut()
+            .map(|(highpass, lowpass)| {
+                let power: f32 = block
+                    .iter()
+                    .map(|sample| lowpass.run(highpass.run(*sample)).powi(2))
+                    .sum();
+                (power / block.len().max(1) as f32).sqrt() as f64
+            })
+            .collect()
+    }
+
+    /// Combines band levels into an overall intensity, according to each band's weight.
+    pub fn combine(&self, levels: &[f64]) -> f64 {
+        levels
+            .iter()
+            .zip(&self.weights)
+            .map(|(level, weight)| level * *weight as f64)
+            .sum()
+    }
+}
@@ -0,0 +1,113 @@
+use anyhow::{Context, bail};
+use std::process::Command;
+
+use super::pulse::pactl;
+use super::{list_monitor_sources, list_output_devs};
+
+/// Parts of device names that give away a virtual cable, alongside which cable it is.
+const KNOWN_CABLES: &[(&str, &str)] = &[
+    ("CABLE Input", "VB-Cable"),
+    ("VB-Audio", "VB-Audio"),
+    ("BlackHole", "BlackHole"),
+    ("Soundflower", "Soundflower"),
+    ("Loopback Audio", "Loopback"),
+];
+
+/// The name given to the null sink we create.
+pub const NULL_SINK_NAME: &str = "subwoofer";
+
+/// A virtual cable found among output devices or monitor sources.
+#[derive(Clone, Debug)]
+pub struct VirtualCable {
+    /// The device or monitor source to capture.
+    pub device: String,
+    pub kind: &'static str,
+}
+
+/// Finds virtual cables that audio can be routed through, such as VB-Cable on Windows,
+/// BlackHole on macOS, or a null sink with PulseAudio or PipeWire.
+pub fn detect_virtual_cables() -> Vec<VirtualCable> {
+    let mut cables: Vec<VirtualCable> = list_output_devs()
+        .into_iter()
+        .filter_map(|(device, _)| cable_kind(&device).map(|kind| VirtualCable { device, kind }))
+        .collect();
+    cables.extend(null_sink_monitors().into_iter().map(|device| VirtualCable {
+        device,
+        kind: "null sink",
+    }));
+    cables
+}
+
+/// Which virtual cable an output device is, if it's one we recognise.
+pub(super) fn cable_kind(device: &str) -> Option<&'static str> {
+    KNOWN_CABLES
+        .iter()
+        .find(|(pattern, _)| device.contains(pattern))
+        .map(|(_, kind)| *kind)
+}
+
+/// The monitor sources of every PulseAudio or PipeWire null sink.
+fn null_sink_monitors() -> Vec<String> {
+    let Some(modules) = pactl(&["list", "short", "modules"]) else {
+        return Vec::new();
+    };
+    modules
+        .lines()
+        .filter(|line| line.contains("module-null-sink"))
+        .filter_map(|line| {
+            line.split_whitespace()
+                .find_map(|argument| argument.strip_prefix("sink_name="))
+        })
+        .map(|sink| format!("{}.monitor", sink))
+        .collect()
+}
+
+/// Creates a null sink with PulseAudio or PipeWire, looped back to the default sink so that
+/// anything routed to it is still heard. Returns the name of its monitor source.
+pub fn create_null_sink() -> anyhow::Result<String> {
+    let monitor = format!("{}.monitor", NULL_SINK_NAME);
+    if list_monitor_sources().contains(&monitor) {
+        return Ok(monitor);
+    }
+    load_module(&[
+        "module-null-sink",
+        &format!("sink_name={}", NULL_SINK_NAME),
+        "sink_properties=device.description=Subwoofer",
+    ])?;
+    load_module(&["module-loopback", &format!("source={}", monitor)])?;
+    Ok(monitor)
+}
+
+fn load_module(args: &[&str]) -> anyhow::Result<()> {
+    let status = Command::new("pactl")
+        .arg("load-module")
+        .args(args)
+        .status()
+        .context("failed to run pactl")?;
+    if !status.success() {
+        bail!("pactl failed to load {}", args[0]);
+    }
+    Ok(())
+}
+
+/// Explains how to route audio through a virtual cable on this platform.
+pub fn routing_instructions() -> &'static str {
+    if cfg!(target_os = "windows") {
+        "\
+Install VB-Cable (https://vb-audio.com/Cable/), then either:
+  - set \"CABLE Input\" as the output of the application you want to follow, or
+  - set it as the default output, and enable \"Listen to this device\" on \"CABLE Output\"
+    in the Recording tab of the Sound control panel, so that you can still hear it.
+Then type `audio` and switch to \"CABLE Input\"."
+    } else if cfg!(target_os = "macos") {
+        "\
+Install BlackHole (https://existential.audio/blackhole/), then create a Multi-Output Device
+in Audio MIDI Setup with both BlackHole and your speakers, and set it as the output.
+Then type `audio` and switch to BlackHole."
+    } else {
+        "\
+Type `cable create` to create a \"Subwoofer\" sink, which plays through to your speakers.
+Then route the applications you want to follow to it, e.g. with pavucontrol or
+`pactl move-sink-input <stream> subwoofer`, and we'll switch to its monitor automatically."
+    }
+}
@@ -0,0 +1,190 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// Where the LFE channel sits in the standard 5.1 and 7.1 layouts (FL, FR, FC, LFE, ...).
+const LFE_INDEX: usize = 3;
+
+/// The fewest channels audio with an LFE channel has, i.e. 5.1.
+const SURROUND_CHANNELS: u16 = 6;
+
+/// The most channels that can be given their own weight, enough for 7.1 audio.
+pub const MAX_WEIGHTED_CHANNELS: usize = 8;
+
+/// Which channels of interleaved audio are analyzed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ChannelSelection {
+    /// Averages every channel together.
+    #[default]
+    Downmix,
+    /// Only the left channel.
+    Left,
+    /// Only the right channel.
+    Right,
+    /// Only the LFE channel of 5.1 or 7.1 audio, i.e. exactly what a subwoofer would play.
+    Lfe,
+    /// Only the channel at the given index.
+    Index(u16),
+    /// Averages the channels from the first index to the last, inclusive, such as a pair of an interface's inputs.
+    Range(u16, u16),
+    /// Mixes channels together in proportion to their weights, in channel order.
+    /// Channels without a weight are left out.
+    Weighted([f32; MAX_WEIGHTED_CHANNELS]),
+}
+
+impl ChannelSelection {
+    /// The single channel to analyze given `channels` in total, or `None` to downmix.
+    /// Selections the audio doesn't have fall back to downmixing.
+    fn resolve(self, channels: u16) -> Option<usize> {
+        match self {
+            ChannelSelection::Downmix
+            | ChannelSelection::Weighted(_)
+            | ChannelSelection::Range(..) => None,
+            ChannelSelection::Left => Some(0),
+            ChannelSelection::Right => Some(1).filter(|_| channels >= 2),
+            ChannelSelection::Lfe => Some(LFE_INDEX).filter(|_| channels >= SURROUND_CHANNELS),
+            ChannelSelection::Index(index) => Some(index as usize).filter(|_| index < channels),
+        }
+    }
+
+    /// How many channels audio needs for this selection to be honoured.
+    pub fn min_channels(self) -> u16 {
+        match self {
+            ChannelSelection::Downmix | ChannelSelection::Left => 1,
+            ChannelSelection::Right => 2,
+            ChannelSelection::Lfe => SURROUND_CHANNELS,
+            ChannelSelection::Index(index) | ChannelSelection::Range(_, index) => {
+                index.saturating_add(1)
+            }
+            ChannelSelection::Weighted(weights) => weights
+                .iter()
+                .rposition(|w| *w != 0.0)
+                .map_or(1, |last| last as u16 + 1),
+        }
+    }
+
+    /// Whether this selection can be honoured for audio with `channels` channels.
+    pub fn supports(self, channels: u16) -> bool {
+        match self {
+            ChannelSelection::Downmix => true,
+            ChannelSelection::Weighted(weights) => Self::total(&weights, channels) > 0.0,
+            ChannelSelection::Range(_, last) => last < channels,
+            _ => self.resolve(channels).is_some(),
+        }
+    }
+
+    /// The combined weight of those of `weights` that `channels` channels have.
+    fn total(weights: &[f32], channels: u16) -> f32 {
+        weights
+            .iter()
+            .take(channels as usize)
+            .map(|w| w.abs())
+            .sum()
+    }
+
+    /// Reduces interleaved frames to mono, according to this selection.
+    pub fn extract(self, data: &[f32], channels: u16) -> Vec<f32> {
+        let frames = data.chunks_exact(channels as usize);
+        if let ChannelSelection::Weighted(weights) = self {
+            let total = Self::total(&weights, channels);
+            if total > 0.0 {
+                return frames
+                    .map(|frame| {
+                        frame
+                            .iter()
+                            .zip(weights)
+                            .map(|(sample, weight)| sample * weight)
+                            .sum::<f32>()
+                            / total
+                    })
+                    .collect();
+            }
+        }
+        if let ChannelSelection::Range(first, last) = self
+            && last < channels
+        {
+            let (first, last) = (first as usize, last as usize);
+            let count = (last - first + 1) as f32;
+            return frames
+                .map(|frame| frame[first..=last].iter().sum::<f32>() / count)
+                .collect();
+        }
+        match self.resolve(channels) {
+            Some(index) => frames.map(|frame| frame[index]).collect(),
+            None => frames
+                .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+                .collect(),
+        }
+    }
+}
+
+impl FromStr for ChannelSelection {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "downmix" | "mono" => Ok(ChannelSelection::Downmix),
+            "left" => Ok(ChannelSelection::Left),
+            "right" => Ok(ChannelSelection::Right),
+            "lfe" => Ok(ChannelSelection::Lfe),
+            weights if weights.contains(',') => {
+                let parsed: Vec<f32> = weights
+                    .split(',')
+                    .map(|weight| weight.trim().parse::<f32>())
+                    .collect::<Result<_, _>>()
+                    .ok()
+                    .filter(|parsed: &Vec<f32>| parsed.iter().all(|w| w.is_finite()))
+                    .ok_or_else(|| format!("invalid channel weights: {}", weights))?;
+                if parsed.len() > MAX_WEIGHTED_CHANNELS {
+                    return Err(format!(
+                        "at most {} channels can be weighted",
+                        MAX_WEIGHTED_CHANNELS
+                    ));
+                }
+                let mut array = [0.0; MAX_WEIGHTED_CHANNELS];
+                array[..parsed.len()].copy_from_slice(&parsed);
+                Ok(ChannelSelection::Weighted(array))
+            }
+            range if range.contains('-') => {
+                let (first, last) = range
+                    .split_once('-')
+                    .and_then(|(first, last)| Some((first.trim().parse().ok()?, last.trim().parse().ok()?)))
+                    .ok_or_else(|| format!("invalid channel range: {}", range))?;
+                if first > last {
+                    return Err(format!("channel range {} runs backwards", range));
+                }
+                Ok(ChannelSelection::Range(first, last))
+            }
+            index => index.parse().map(ChannelSelection::Index).map_err(|_| {
+                format!(
+                    "expected `downmix`, `left`, `right`, `lfe`, a channel index, a range of them, or comma-separated weights, not {}",
+                    index
+                )
+            }),
+        }
+    }
+}
+
+impl fmt::Display for ChannelSelection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChannelSelection::Downmix => write!(f, "downmix"),
+            ChannelSelection::Left => write!(f, "left"),
+            ChannelSelection::Right => write!(f, "right"),
+            ChannelSelection::Lfe => write!(f, "lfe"),
+            ChannelSelection::Index(index) => write!(f, "{}", index),
+            ChannelSelection::Range(first, last) => write!(f, "{}-{}", first, last),
+            ChannelSelection::Weighted(weights) => {
+                // Trailing unweighted channels needn't be spelled out, but at least two are, so it reads back as weights.
+                let used = weights
+                    .iter()
+                    .rposition(|w| *w != 0.0)
+                    .map_or(0, |last| last + 1);
+                let weights: Vec<String> =
+                    weights[..used.max(2)].iter().map(f32::to_string).collect();
+                write!(f, "{}", weights.join(","))
+            }
+        }
+    }
+}
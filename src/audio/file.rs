@@ -0,0 +1,297 @@
+use anyhow::{Context, bail};
+use std::io::{Cursor, ErrorKind};
+use std::path::{Path, PathBuf};
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::DecoderOptions;
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+use super::{AudioFormat, AudioSink, AudioSource, RunningSource, spawn_paced};
+use crate::events::EventBus;
+
+/// Decodes an audio file, delivering it in real time as though it were playing.
+pub struct FileSource {
+    path: PathBuf,
+    format: AudioFormat,
+    samples: Vec<f32>,
+}
+
+impl FileSource {
+    /// Reads and decodes the entire file up front.
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let (format, samples) = read_audio(path)?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            format,
+            samples,
+        })
+    }
+}
+
+impl AudioSource for FileSource {
+    fn describe(&self) -> String {
+        format!("file {}", self.path.display())
+    }
+
+    fn start(self: Box<Self>, sink: AudioSink, _bus: &EventBus) -> anyhow::Result<RunningSource> {
+        let samples = self.samples;
+        let mut position = 0;
+        Ok(spawn_paced(self.format, sink, move |block| {
+            if position >= samples.len() {
+                return false;
+            }
+            let end = usize::min(position + block.len(), samples.len());
+            let chunk = &samples[position..end];
+            block[..chunk.len()].copy_from_slice(chunk);
+            block[chunk.len()..].fill(0.0);
+            position = end;
+            true
+        }))
+    }
+}
+
+/// Reads and decodes an entire WAV, FLAC or MP3 file into interleaved samples.
+pub fn read_audio(path: &Path) -> anyhow::Result<(AudioFormat, Vec<f32>)> {
+    let bytes =
+        std::fs::read(path).with_context(|| format!("unable to read {}", path.display()))?;
+    // WAV files are decoded ourselves, as we accept more of their encodings.
+    if bytes.starts_with(b"RIFF") {
+        return parse_wav(&bytes);
+    }
+    let extension = path.extension().and_then(|extension| extension.to_str());
+    decode_compressed(bytes, extension)
+        .with_context(|| format!("unable to decode {}", path.display()))
+}
+
+/// Decodes a FLAC or MP3 file into interleaved samples, using `extension` as a hint to its container.
+fn decode_compressed(
+    bytes: Vec<u8>,
+    extension: Option<&str>,
+) -> anyhow::Result<(AudioFormat, Vec<f32>)> {
+    let mut hint = Hint::new();
+    if let Some(extension) = extension {
+        hint.with_extension(extension);
+    }
+    let stream = MediaSourceStream::new(Box::new(Cursor::new(bytes)), Default::default());
+    let mut reader = symphonia::default::get_probe()
+        .format(
+            &hint,
+            stream,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )
+        .context("not a WAV, FLAC or MP3 file")?
+        .format;
+    let track = reader.default_track().context("no audio track found")?;
+    let track_id = track.id;
+    let mut decoder =
+        symphonia::default::get_codecs().make(&track.codec_params, &DecoderOptions::default())?;
+
+    let mut format = None;
+    let mut samples = Vec::new();
+    loop {
+        let packet = match reader.next_packet() {
+            Ok(packet) => packet,
+            // Files simply end, without any other sign that they're about to.
+            Err(SymphoniaError::IoError(e)) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            // A corrupt frame only loses a moment of audio, so we carry on past it.
+            Err(SymphoniaError::DecodeError(_)) => continue,
+            Err(e) => return Err(e.into()),
+        };
+        let spec = *decoded.spec();
+        let block = AudioFormat {
+            sample_rate: spec.rate,
+            channels: spec.channels.count() as u16,
+        };
+        if *format.get_or_insert(block) != block {
+            bail!("the format changes partway through");
+        }
+        let mut buffer = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+        buffer.copy_interleaved_ref(decoded);
+        samples.extend_from_slice(buffer.samples());
+    }
+
+    let format = format.context("no audio found")?;
+    if format.channels == 0 || format.sample_rate == 0 {
+        bail!("invalid channel count or sample rate");
+    }
+    Ok((format, samples))
+}
+
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+/// Decodes a RIFF/WAVE file into interleaved samples.
+///
+/// Supports 8, 16, 24 and 32-bit integer PCM, alongside 32-bit float.
+/// Files may come from anywhere, so malformed input must only ever produce an error.
+pub fn parse_wav(bytes: &[u8]) -> anyhow::Result<(AudioFormat, Vec<f32>)> {
+    if bytes.get(0..4) != Some(b"RIFF") || bytes.get(8..12) != Some(b"WAVE") {
+        bail!("not a WAV file");
+    }
+
+    let mut fmt: Option<(u16, AudioFormat, u16)> = None;
+    let mut offset: usize = 12;
+    while let Some(header) = offset.checked_add(8).and_then(|end| bytes.get(offset..end)) {
+        let id = &header[0..4];
+        let size = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+        let body_start = offset + 8;
+        let body = bytes
+            .get(body_start..body_start.saturating_add(size))
+            .unwrap_or(&bytes[body_start..]);
+
+        match id {
+            b"fmt " => {
+                let Some(body) = body.get(0..16) else {
+                    bail!("truncated fmt chunk");
+                };
+                let read_u16 = |at: usize| u16::from_le_bytes([body[at], body[at + 1]]);
+                let mut tag = read_u16(0);
+                let channels = read_u16(2);
+                let sample_rate = u32::from_le_bytes([body[4], body[5], body[6], body[7]]);
+                let bits = read_u16(14);
+                if tag == WAVE_FORMAT_EXTENSIBLE {
+                    // The actual format lives at the start of the sub-format GUID.
+                    let full = bytes
+                        .get(body_start..body_start.saturating_add(size))
+                        .unwrap_or_default();
+                    let Some(sub) = full.get(24..26) else {
+                        bail!("truncated extensible fmt chunk");
+                    };
+                    tag = u16::from_le_bytes([sub[0], sub[1]]);
+                }
+                if channels == 0 || sample_rate == 0 {
+                    bail!("invalid channel count or sample rate");
+                }
+                fmt = Some((
+                    tag,
+                    AudioFormat {
+                        sample_rate,
+                        channels,
+                    },
+                    bits,
+                ));
+            }
+            b"data" => {
+                let Some((tag, format, bits)) = fmt else {
+                    bail!("data chunk before fmt chunk");
+                };
+                let samples = decode_samples(tag, bits, body)?;
+                return Ok((format, samples));
+            }
+            _ => {}
+        }
+
+        // Chunks are padded to an even length.
+        offset = body_start.saturating_add(size).saturating_add(size & 1);
+    }
+
+    bail!("no data chunk found")
+}
+
+fn decode_samples(tag: u16, bits: u16, data: &[u8]) -> anyhow::Result<Vec<f32>> {
+    Ok(match (tag, bits) {
+        (WAVE_FORMAT_PCM, 8) => data.iter().map(|&b| (b as f32 - 128.0) / 128.0).collect(),
+        (WAVE_FORMAT_PCM, 16) => data
+            .chunks_exact(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]) as f32 / 32768.0)
+            .collect(),
+        (WAVE_FORMAT_PCM, 24) => data
+            .chunks_exact(3)
+            .map(|b| (i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8) as f32 / 8388608.0)
+            .collect(),
+        (WAVE_FORMAT_PCM, 32) => data
+            .chunks_exact(4)
+            .map(|b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32 / 2147483648.0)
+            .collect(),
+        (WAVE_FORMAT_IEEE_FLOAT, 32) => data
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            // Float files can contain anything; keep it within what the filter expects.
+            .map(|sample| {
+                if sample.is_finite() {
+                    sample.clamp(-1.0, 1.0)
+                } else {
+                    0.0
+                }
+            })
+            .collect(),
+        _ => bail!("unsupported WAV encoding (format {}, {} bits)", tag, bits),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a WAV file whose data chunk claims to be `data_size` bytes long, whatever `data` holds.
+    fn wav(tag: u16, bits: u16, data: &[u8], data_size: u32) -> Vec<u8> {
+        let mut bytes = b"RIFF\0\0\0\0WAVEfmt ".to_vec();
+        bytes.extend_from_slice(&16u32.to_le_bytes());
+        bytes.extend_from_slice(&tag.to_le_bytes());
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(&8000u32.to_le_bytes());
+        bytes.extend_from_slice(&(8000 * bits as u32 / 8).to_le_bytes());
+        bytes.extend_from_slice(&(bits / 8).to_le_bytes());
+        bytes.extend_from_slice(&bits.to_le_bytes());
+        bytes.extend_from_slice(b"data");
+        bytes.extend_from_slice(&data_size.to_le_bytes());
+        bytes.extend_from_slice(data);
+        bytes
+    }
+
+    #[test]
+    fn decodes_pcm() {
+        let data = [0x00, 0x40, 0x00, 0xC0];
+        let (format, samples) = parse_wav(&wav(WAVE_FORMAT_PCM, 16, &data, 4)).unwrap();
+        assert_eq!(
+            format,
+            AudioFormat {
+                sample_rate: 8000,
+                channels: 1
+            }
+        );
+        assert_eq!(samples, vec![0.5, -0.5]);
+    }
+
+    #[test]
+    fn rejects_truncated_header() {
+        let bytes = wav(WAVE_FORMAT_PCM, 16, &[], 0);
+        for length in [0, 4, 11, 20, 35] {
+            assert!(
+                parse_wav(&bytes[..length]).is_err(),
+                "accepted the first {length} bytes"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_non_pcm_formats() {
+        // IMA ADPCM, which is compressed.
+        let bytes = wav(0x11, 4, &[0; 8], 8);
+        let error = parse_wav(&bytes).unwrap_err();
+        assert!(error.to_string().contains("unsupported WAV encoding"));
+    }
+
+    #[test]
+    fn decodes_what_there_is_of_an_overlong_data_chunk() {
+        // Claims a megabyte of data, but holds a sample and a half.
+        let bytes = wav(WAVE_FORMAT_PCM, 16, &[0x00, 0x40, 0x00], 1 << 20);
+        let (_, samples) = parse_wav(&bytes).unwrap();
+        assert_eq!(samples, vec![0.5]);
+
+        let bytes = wav(WAVE_FORMAT_PCM, 16, &[], u32::MAX);
+        assert_eq!(parse_wav(&bytes).unwrap().1, Vec::<f32>::new());
+    }
+}
@@ -0,0 +1,252 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::dsp::{BUTTERWORTH_Q, Biquad};
+use crate::mapping::IntensityMapping;
+
+/// A broad category of content, each with its own tuned profile.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Genre {
+    /// Fast, bass-heavy music with a strong pulse, such as drum & bass or dubstep.
+    Electronic,
+    /// Band music, with a beat but less dominant bass.
+    Rock,
+    /// Slow-moving music without much of a beat.
+    Ambient,
+    /// Podcasts, audiobooks, and other speech.
+    Spoken,
+}
+
+impl Genre {
+    pub fn name(self) -> String {
+        self.to_possible_value()
+            .map(|value| value.get_name().to_string())
+            .unwrap_or_default()
+    }
+
+    /// Adjusts `base` to suit this genre. The ceiling is never raised.
+    pub fn profile(self, base: IntensityMapping) -> IntensityMapping {
+        let (gain_factor, deadzone) = match self {
+            // Plenty of bass already; a deadzone keeps pulses distinct.
+            Genre::Electronic => (1.0, 0.1),
+            Genre::Rock => (1.2, 0.05),
+            // Quiet, sustained bass needs a boost to be felt at all.
+            Genre::Ambient => (2.0, 0.0),
+            Genre::Spoken => (1.5, 0.1),
+        };
+        IntensityMapping {
+            gain: base.gain * gain_factor,
+            deadzone,
+            ..base
+        }
+    }
+}
+
+/// How much audio each classification is based upon.
+const WINDOW_SECS: f32 = 8.0;
+/// Onsets are measured over frames of this length.
+const FRAME_SECS: f32 = 0.01;
+/// Tempos considered when looking for a beat.
+const MIN_BPM: f32 = 60.0;
+const MAX_BPM: f32 = 200.0;
+/// Frequencies below this count as bass, for the purposes of classification.
+const BASS_HZ: f32 = 150.0;
+/// Below this, the rise in energy between frames is too slight to be considered a beat at all.
+const MIN_ONSET_STRENGTH: f32 = 0.1;
+/// Windows quieter than this are not classified at all.
+const MIN_RMS: f32 = 0.01;
+
+/// Features extracted from a window of audio.
+#[derive(Clone, Copy, Debug)]
+pub struct GenreFeatures {
+    /// The most likely tempo.
+    pub bpm: f32,
+    /// How strongly periodic onsets are at that tempo, from 0 to 1.
+    pub pulse_clarity: f32,
+    /// The fraction of energy below 150 Hz.
+    pub bass_ratio: f32,
+    /// Zero crossings per sample, a cheap stand-in for brightness.
+    pub zero_crossing_rate: f32,
+}
+
+impl GenreFeatures {
+    /// Classifies content based on simple thresholds over its features.
+    pub fn classify(&self) -> Genre {
+        if self.pulse_clarity > 0.3 && self.bass_ratio > 0.4 && self.bpm >= 115.0 {
+            Genre::Electronic
+        } else if self.pulse_clarity < 0.15
+            && self.zero_crossing_rate > 0.05
+            && self.bass_ratio < 0.2
+        {
+            Genre::Spoken
+        } else if self.pulse_clarity < 0.15 {
+            Genre::Ambient
+        } else {
+            Genre::Rock
+        }
+    }
+}
+
+/// A lightweight classifier, deciding upon a genre every few seconds of audio.
+pub struct GenreClassifier {
+    sample_rate: f32,
+    bass: Biquad,
+    frame_len: usize,
+    frame_energy: f32,
+    frame_filled: usize,
+    /// Energy of each completed frame within the current window.
+    frames: Vec<f32>,
+    total_energy: f64,
+    bass_energy: f64,
+    zero_crossings: usize,
+    samples: usize,
+    last_sample: f32,
+    /// The most recent classification, which must repeat before being reported.
+    candidate: Option<Genre>,
+    reported: Option<Genre>,
+}
+
+impl GenreClassifier {
+    pub fn new(sample_rate: f32) -> Self {
+        Self {
+            sample_rate,
+            bass: Biquad::lowpass(sample_rate, BASS_HZ, BUTTERWORTH_Q),
+            frame_len: ((sample_rate * FRAME_SECS) as usize).max(1),
+            frame_energy: 0.0,
+            frame_filled: 0,
+            frames: Vec::new(),
+            total_energy: 0.0,
+            bass_energy: 0.0,
+            zero_crossings: 0,
+            samples: 0,
+            last_sample: 0.0,
+            candidate: None,
+            reported: None,
+        }
+    }
+
+    /// Processes a block of mono samples, returning a genre once it has newly and consistently been detected.
+    pub fn process(&mut self, direct_values: &[f32]) -> Option<Genre> {
+        let mut detected = None;
+        for &sample in direct_values {
+            let sample = sample.clamp(-1.0, 1.0);
+            let bass = self.bass.run(sample);
+            self.total_energy += (sample * sample) as f64;
+            self.bass_energy += (bass * bass) as f64;
+            if (sample >= 0.0) != (self.last_sample >= 0.0) {
+                self.zero_crossings += 1;
+            }
+            self.last_sample = sample;
+            self.samples += 1;
+
+            self.frame_energy += sample * sample;
+            self.frame_filled += 1;
+            if self.frame_filled == self.frame_len {
+                self.frames.push(self.frame_energy);
+                self.frame_energy = 0.0;
+                self.frame_filled = 0;
+            }
+
+            if self.samples as f32 >= self.sample_rate * WINDOW_SECS {
+                if let Some(features) = self.features() {
+                    detected = self.settle(features.classify()).or(detected);
+                }
+                self.start_window();
+            }
+        }
+        detected
+    }
+
+    /// Only reports a genre once two consecutive windows agree on it, so that a single odd passage doesn't flip profiles.
+    fn settle(&mut self, genre: Genre) -> Option<Genre> {
+        let agreed = self.candidate == Some(genre);
+        self.candidate = Some(genre);
+        if agreed && self.reported != Some(genre) {
+            self.reported = Some(genre);
+            return Some(genre);
+        }
+        None
+    }
+
+    fn start_window(&mut self) {
+        self.frames.clear();
+        self.total_energy = 0.0;
+        self.bass_energy = 0.0;
+        self.zero_crossings = 0;
+        self.samples = 0;
+    }
+
+    /// Extracts features from the current window, or nothing if it's too quiet to judge.
+    pub fn features(&self) -> Option<GenreFeatures> {
+        let rms = (self.total_energy / self.samples.max(1) as f64).sqrt() as f32;
+        if rms < MIN_RMS || self.frames.len() < 2 {
+            return None;
+        }
+
+        // Onset strength is the rise in energy from one frame to the next.
+        let onsets: Vec<f32> = self
+            .frames
+            .windows(2)
+            .map(|pair| (pair[1] - pair[0]).max(0.0))
+            .collect();
+        let onset_total: f32 = onsets.iter().sum();
+        let onset_strength = onset_total / self.frames.iter().sum::<f32>().max(f32::EPSILON);
+        let mean = onset_total / onsets.len() as f32;
+        let centered: Vec<f32> = onsets.iter().map(|onset| onset - mean).collect();
+        let zero_lag: f32 = centered.iter().map(|onset| onset * onset).sum();
+
+        // The tempo is wherever onsets best correlate with themselves.
+        let frames_per_sec = 1.0 / FRAME_SECS;
+        let min_lag = (frames_per_sec * 60.0 / MAX_BPM) as usize;
+        let max_lag = ((frames_per_sec * 60.0 / MIN_BPM) as usize).min(centered.len() - 1);
+        let correlation = |lag: usize| -> f32 {
+            centered
+                .iter()
+                .zip(&centered[lag..])
+                .map(|(a, b)| a * b)
+                .sum()
+        };
+        let strongest = |lags: std::ops::RangeInclusive<usize>| {
+            lags.map(|lag| (lag, correlation(lag)))
+                .fold((0, 0.0), |best, candidate| {
+                    if candidate.1 > best.1 {
+                        candidate
+                    } else {
+                        best
+                    }
+                })
+        };
+        let min_lag = min_lag.max(1);
+        let (mut best_lag, mut best) = strongest(min_lag..=max_lag);
+
+        // Fast tempos rarely land on a whole number of frames, and so often correlate
+        // best at twice their period. Prefer the faster tempo should it come close.
+        let half = best_lag / 2;
+        if half > min_lag {
+            let (lag, value) = strongest(half - 1..=half + 1);
+            if value > best * 0.5 {
+                (best_lag, best) = (lag, value);
+            }
+        }
+        // Steady sounds still fluctuate a little from frame to frame, which mustn't pass for a beat.
+        if onset_strength < MIN_ONSET_STRENGTH {
+            best = 0.0;
+        }
+
+        Some(GenreFeatures {
+            bpm: if best_lag > 0 {
+                60.0 * frames_per_sec / best_lag as f32
+            } else {
+                0.0
+            },
+            pulse_clarity: if zero_lag > 0.0 {
+                (best / zero_lag).clamp(0.0, 1.0)
+            } else {
+                0.0
+            },
+            bass_ratio: (self.bass_energy / self.total_energy.max(f64::EPSILON)) as f32,
+            zero_crossing_rate: self.zero_crossings as f32 / self.samples as f32,
+        })
+    }
+}
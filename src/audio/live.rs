@@ -0,0 +1,357 @@
+use clap::ValueEnum;
+use cpal::{
+    BufferSize, Device, FromSample, Host, HostId, SampleFormat, SizedSample, Stream, StreamConfig,
+    StreamError, SupportedBufferSize, SupportedStreamConfig,
+    traits::{DeviceTrait, HostTrait, StreamTrait},
+};
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, IsTerminal, stdin};
+use std::sync::OnceLock;
+
+use super::cables::cable_kind;
+#[cfg(windows)]
+use super::loopback::LoopbackSource;
+use super::{AudioFormat, AudioSink, AudioSource, CaptureOptions, RunningSource};
+use crate::events::{AppEvent, AudioEvent, EventBus};
+
+/// Captures whatever is playing on an output device, or what an input device such as a microphone hears.
+pub struct LiveSource {
+    device: Device,
+    options: CaptureOptions,
+    input: bool,
+}
+
+/// Buffers smaller than this tend to underrun, no matter what the device claims to support.
+const MIN_BUFFER_FRAMES: u32 = 64;
+
+/// Sample formats live audio can be captured in. Everything is converted to `f32` for analysis.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CaptureFormat {
+    F32,
+    I16,
+    I32,
+    U16,
+}
+
+impl CaptureFormat {
+    fn sample_format(self) -> SampleFormat {
+        match self {
+            CaptureFormat::F32 => SampleFormat::F32,
+            CaptureFormat::I16 => SampleFormat::I16,
+            CaptureFormat::I32 => SampleFormat::I32,
+            CaptureFormat::U16 => SampleFormat::U16,
+        }
+    }
+}
+
+impl LiveSource {
+    pub fn new(device: Device, options: CaptureOptions) -> Self {
+        Self {
+            device,
+            options,
+            input: false,
+        }
+    }
+
+    /// Captures from an input device, such as a microphone or line-in.
+    pub fn input(device: Device, options: CaptureOptions) -> Self {
+        Self {
+            device,
+            options,
+            input: true,
+        }
+    }
+}
+
+impl LiveSource {
+    /// A configuration like `default`, but with at least as many channels and the sample format requested,
+    /// if the device has one. `None` if the default already suits.
+    fn preferred_config(&self, default: &SupportedStreamConfig) -> Option<SupportedStreamConfig> {
+        let channels = default.channels().max(self.options.min_channels);
+        let format = self
+            .options
+            .sample_format
+            .map_or(default.sample_format(), CaptureFormat::sample_format);
+        if channels == default.channels() && format == default.sample_format() {
+            return None;
+        }
+
+        let configs: Vec<_> = if self.input {
+            self.device.supported_input_configs().ok()?.collect()
+        } else {
+            self.device.supported_output_configs().ok()?.collect()
+        };
+        let rate = default.sample_rate();
+        configs
+            .into_iter()
+            .filter(|range| {
+                range.channels() >= channels
+                    && range.sample_format() == format
+                    && (range.min_sample_rate()..=range.max_sample_rate()).contains(&rate)
+            })
+            .min_by_key(|range| range.channels())
+            .map(|range| range.with_sample_rate(rate))
+    }
+
+    /// Builds a stream for samples that need converting to `f32` first.
+    fn build_converted<T>(
+        &self,
+        config: &StreamConfig,
+        format: AudioFormat,
+        mut sink: AudioSink,
+        bus: &EventBus,
+    ) -> anyhow::Result<Stream>
+    where
+        T: SizedSample,
+        f32: FromSample<T>,
+    {
+        let mut converted = Vec::new();
+        let stream = self.device.build_input_stream(
+            config,
+            move |data: &[T], _| {
+                converted.clear();
+                converted.extend(data.iter().map(|sample| sample.to_sample::<f32>()));
+                sink.push(format, &converted);
+            },
+            report_errors(bus),
+            None,
+        )?;
+        Ok(stream)
+    }
+}
+
+/// Publishes stream errors to the bus.
+fn report_errors(bus: &EventBus) -> impl FnMut(StreamError) + Send + 'static {
+    let bus = bus.clone();
+    move |err| bus.publish(AppEvent::Audio(AudioEvent::StreamError(err.to_string())))
+}
+
+impl AudioSource for LiveSource {
+    fn describe(&self) -> String {
+        self.device
+            .name()
+            .unwrap_or_else(|_| "<unknown>".to_string())
+    }
+
+    fn start(
+        self: Box<Self>,
+        mut sink: AudioSink,
+        bus: &EventBus,
+    ) -> anyhow::Result<RunningSource> {
+        let mut supported = if self.input {
+            self.device.default_input_config()?
+        } else {
+            self.device.default_output_config()?
+        };
+        // Devices configured for stereo by default may still offer surround, which is needed to reach e.g. the LFE channel.
+        if let Some(preferred) = self.preferred_config(&supported) {
+            supported = preferred;
+        }
+        if let Some(requested) = self.options.sample_format
+            && requested.sample_format() != supported.sample_format()
+        {
+            println!(
+                "{} can't capture {:?} samples here; using {:?} instead.",
+                self.describe(),
+                requested,
+                supported.sample_format()
+            );
+        }
+        let mut config = supported.config();
+        let frames = self
+            .options
+            .buffer_frames
+            .or(self.options.low_latency.then_some(MIN_BUFFER_FRAMES));
+        if let Some(frames) = frames
+            && let SupportedBufferSize::Range { min, max } = supported.buffer_size()
+        {
+            config.buffer_size = BufferSize::Fixed(frames.clamp(*min, *max));
+        }
+        let format = AudioFormat {
+            sample_rate: config.sample_rate.0,
+            channels: config.channels,
+        };
+
+        // Input streams on output devices are loopback captures, on whichever host supports them.
+        let stream = match supported.sample_format() {
+            SampleFormat::F32 => self.device.build_input_stream(
+                &config,
+                move |data: &[f32], _| sink.push(format, data),
+                report_errors(bus),
+                None,
+            )?,
+            SampleFormat::I16 => self.build_converted::<i16>(&config, format, sink, bus)?,
+            SampleFormat::I32 => self.build_converted::<i32>(&config, format, sink, bus)?,
+            SampleFormat::U16 => self.build_converted::<u16>(&config, format, sink, bus)?,
+            other => anyhow::bail!("unsupported sample format {:?}", other),
+        };
+        stream.play()?;
+        Ok(RunningSource::new(format, stream))
+    }
+}
+
+/// Captures whatever is playing on an output device.
+///
+/// On Windows, unless another host was chosen with [`select_host`], the endpoint is captured natively
+/// with WASAPI loopback, which keeps delivering silence while nothing plays.
+pub fn output_source(device: Device, options: CaptureOptions) -> Box<dyn AudioSource> {
+    #[cfg(windows)]
+    if host().id() == HostId::Wasapi
+        && let Ok(name) = device.name()
+    {
+        return Box::new(LoopbackSource::new(name, options));
+    }
+    Box::new(LiveSource::new(device, options))
+}
+
+/// The host chosen with [`select_host`], if any.
+static HOST: OnceLock<HostId> = OnceLock::new();
+
+/// Uses the named audio host, such as JACK or ASIO, rather than the platform's default.
+/// This must be done before any devices are opened.
+pub fn select_host(name: &str) -> anyhow::Result<()> {
+    let available = cpal::available_hosts();
+    let Some(id) = available
+        .iter()
+        .find(|id| id.name().eq_ignore_ascii_case(name))
+    else {
+        let names: Vec<&str> = available.iter().map(|id| id.name()).collect();
+        if name.eq_ignore_ascii_case("asio") && !cfg!(feature = "asio") {
+            anyhow::bail!(
+                "ASIO is only available on Windows builds with the `asio` feature; available hosts are: {}",
+                names.join(", ")
+            );
+        }
+        anyhow::bail!(
+            "audio host {} is unavailable; available hosts are: {}",
+            name,
+            names.join(", ")
+        );
+    };
+    let _ = HOST.set(*id);
+    Ok(())
+}
+
+/// The audio host devices are found on.
+fn host() -> Host {
+    HOST.get()
+        .and_then(|id| cpal::host_from_id(*id).ok())
+        .unwrap_or_else(cpal::default_host)
+}
+
+/// Helps to select available output devices.
+pub fn list_output_devs() -> Vec<(String, cpal::Device)> {
+    named(host().output_devices().unwrap())
+}
+
+/// Helps to select available input devices, such as microphones.
+pub fn list_input_devs() -> Vec<(String, cpal::Device)> {
+    named(host().input_devices().unwrap())
+}
+
+/// Pairs each device with its name, sorted by name.
+fn named(devices: impl Iterator<Item = Device>) -> Vec<(String, Device)> {
+    let mut devs: Vec<(String, Device)> = devices
+        .map(|dev| {
+            (
+                match dev.name() {
+                    Ok(name) => name,
+                    Err(_) => "<unknown>".to_string(),
+                },
+                dev,
+            )
+        })
+        .collect();
+    devs.sort_by(|(n1, _), (n2, _)| n1.cmp(n2));
+    devs
+}
+
+/// Finds an output device by name.
+pub fn find_output_dev(name: &str) -> Option<cpal::Device> {
+    list_output_devs()
+        .into_iter()
+        .find(|(dev_name, _)| dev_name == name)
+        .map(|(_, dev)| dev)
+}
+
+/// Finds an input device by name.
+pub fn find_input_dev(name: &str) -> Option<cpal::Device> {
+    list_input_devs()
+        .into_iter()
+        .find(|(dev_name, _)| dev_name == name)
+        .map(|(_, dev)| dev)
+}
+
+/// Prints available input devices alongside their index.
+pub fn print_input_devs(devs: &[(String, cpal::Device)]) {
+    devs.iter().enumerate().for_each(|(i, (name, dev))| {
+        // Interfaces often default to a stereo pair, so the most channels they offer is worth knowing for `--channel`.
+        let channels = dev
+            .supported_input_configs()
+            .ok()
+            .and_then(|configs| configs.map(|config| config.channels()).max())
+            .map(|channels| format!(" (up to {} channels)", channels))
+            .unwrap_or_default();
+        println!(
+            "  [{}] {}{} {:?}",
+            i,
+            name,
+            channels,
+            dev.default_input_config()
+        );
+    });
+}
+
+/// Prints available output devices alongside their index.
+pub fn print_output_devs(devs: &[(String, cpal::Device)]) {
+    devs.iter().enumerate().for_each(|(i, (name, dev))| {
+        let cable = cable_kind(name)
+            .map(|kind| format!(" (virtual cable: {})", kind))
+            .unwrap_or_default();
+        println!(
+            "  [{}] {}{} {:?}",
+            i,
+            name,
+            cable,
+            dev.default_output_config().unwrap()
+        );
+    });
+}
+
+/// The system's default output device, if there is one.
+pub fn default_output_dev() -> Option<cpal::Device> {
+    host().default_output_device()
+}
+
+/// The name of the system's default output device, if there is one.
+pub fn default_output_name() -> Option<String> {
+    default_output_dev().and_then(|dev| dev.name().ok())
+}
+
+/// Helps to select the default output device.
+///
+/// Without a terminal to ask on, such as when launched from a desktop shortcut,
+/// the system's default output device is used instead, which can then be switched from the waveform window.
+pub fn select_output_dev() -> cpal::Device {
+    let mut devs = list_output_devs();
+    assert!(!devs.is_empty(), "no output devices found!");
+    if devs.len() == 1 {
+        return devs.remove(0).1;
+    }
+    if !stdin().is_terminal() {
+        return default_output_dev().unwrap_or_else(|| devs.remove(0).1);
+    }
+    println!("Type the number of the output device audio is playing to, and press enter.");
+    print_output_devs(&devs);
+    loop {
+        let mut input = String::new();
+        if stdin().lock().read_line(&mut input).unwrap_or(0) == 0 {
+            return default_output_dev().unwrap_or_else(|| devs.remove(0).1);
+        }
+        match input.trim().parse::<usize>() {
+            Ok(index) if index < devs.len() => return devs.remove(index).1,
+            _ => println!("Type a number between 0 and {}.", devs.len() - 1),
+        }
+    }
+}
@@ -0,0 +1,180 @@
+use anyhow::Context;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, mpsc};
+use std::thread;
+use std::time::{Duration, Instant};
+use wasapi::{
+    AudioCaptureClient, AudioClient, DeviceEnumerator, Direction, SampleType, StreamMode,
+};
+
+use super::{
+    AudioFormat, AudioSink, AudioSource, CaptureFormat, CaptureOptions, RunningSource, StopOnDrop,
+};
+use crate::events::{AppEvent, AudioEvent, EventBus};
+
+/// How long to wait for audio before taking nothing to be playing, and filling in silence.
+const IDLE_TIMEOUT: Duration = Duration::from_millis(50);
+
+/// Captures whatever is playing on a Windows output device with WASAPI loopback,
+/// so that neither Stereo Mix nor a virtual cable is needed.
+///
+/// Windows delivers nothing at all while nothing plays, so silence is filled in meanwhile,
+/// keeping analysis and the waveform window moving.
+pub struct LoopbackSource {
+    device: String,
+    options: CaptureOptions,
+}
+
+impl LoopbackSource {
+    pub fn new(device: String, options: CaptureOptions) -> Self {
+        Self { device, options }
+    }
+}
+
+/// An output device's endpoint, opened for loopback capture.
+struct Capture {
+    client: AudioClient,
+    capture: AudioCaptureClient,
+    format: AudioFormat,
+}
+
+impl Capture {
+    fn open(device: &str, options: CaptureOptions) -> anyhow::Result<Self> {
+        let device = DeviceEnumerator::new()?
+            .get_device_collection(&Direction::Render)?
+            .get_device_with_name(device)?;
+        let mut client = device.get_iaudioclient()?;
+        if let Some(requested) = options.sample_format
+            && requested != CaptureFormat::F32
+        {
+            println!(
+                "WASAPI loopback can't capture {:?} samples; using F32 instead.",
+                requested
+            );
+        }
+        // The endpoint's own mix format needs no conversion, other than for the channels `--channel` needs.
+        let mix = client.get_mixformat()?;
+        let format = AudioFormat {
+            sample_rate: mix.get_samplespersec(),
+            channels: mix.get_nchannels().max(options.min_channels),
+        };
+        let wave = wasapi::WaveFormat::new(
+            32,
+            32,
+            &SampleType::Float,
+            format.sample_rate as usize,
+            format.channels as usize,
+            None,
+        );
+        let (default_period, min_period) = client.get_device_period()?;
+        let buffer_duration_hns = match options.buffer_frames {
+            Some(frames) => {
+                wasapi::calculate_period_100ns(frames as i64, format.sample_rate as i64)
+                    .max(min_period)
+            }
+            None if options.low_latency => min_period,
+            None => default_period,
+        };
+        // Capturing from a render endpoint is what makes this loopback.
+        client.initialize_client(
+            &wave,
+            &Direction::Capture,
+            &StreamMode::EventsShared {
+                autoconvert: true,
+                buffer_duration_hns,
+            },
+        )?;
+        let capture = client.get_audiocaptureclient()?;
+        Ok(Self {
+            client,
+            capture,
+            format,
+        })
+    }
+
+    /// Delivers audio to `sink` until `stopped` is set, or capture fails.
+    fn run(&self, sink: &mut AudioSink, stopped: &AtomicBool) -> anyhow::Result<()> {
+        let event = self.client.set_get_eventhandle()?;
+        self.client.start_stream()?;
+        let frame_len = self.format.channels as usize * 4;
+        let mut bytes = VecDeque::new();
+        let mut samples = Vec::new();
+        let mut heard = Instant::now();
+        while !stopped.load(Ordering::Relaxed) {
+            // Should nothing arrive in time, nothing is playing, so however long we waited is filled with silence.
+            if event
+                .wait_for_event(IDLE_TIMEOUT.as_millis() as u32)
+                .is_err()
+            {
+                let frames =
+                    (heard.elapsed().as_secs_f64() * self.format.sample_rate as f64) as usize;
+                heard = Instant::now();
+                samples.clear();
+                samples.resize(frames * self.format.channels as usize, 0.0);
+                sink.push(self.format, &samples);
+                continue;
+            }
+            heard = Instant::now();
+            while self.capture.get_next_packet_size()?.unwrap_or(0) > 0 {
+                let start = bytes.len();
+                let info = self.capture.read_from_device_to_deque(&mut bytes)?;
+                // Packets flagged as silent may hold anything at all.
+                if info.flags.silent {
+                    bytes.range_mut(start..).for_each(|byte| *byte = 0);
+                }
+            }
+            // Only whole frames can be analyzed; any remainder waits for the next packet.
+            let whole = bytes.len() - bytes.len() % frame_len;
+            let frames: Vec<u8> = bytes.drain(..whole).collect();
+            samples.clear();
+            samples.extend(
+                frames
+                    .chunks_exact(4)
+                    .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])),
+            );
+            sink.push(self.format, &samples);
+        }
+        self.client.stop_stream()?;
+        Ok(())
+    }
+}
+
+impl AudioSource for LoopbackSource {
+    fn describe(&self) -> String {
+        self.device.clone()
+    }
+
+    fn start(
+        self: Box<Self>,
+        mut sink: AudioSink,
+        bus: &EventBus,
+    ) -> anyhow::Result<RunningSource> {
+        let (opened, format) = mpsc::channel();
+        let stopped = Arc::new(AtomicBool::new(false));
+        let thread_stopped = stopped.clone();
+        let bus = bus.clone();
+        // COM objects stay on the thread that created them, so the endpoint is opened and read on one of its own.
+        thread::spawn(move || {
+            let _ = wasapi::initialize_mta().ok();
+            let capture = match Capture::open(&self.device, self.options) {
+                Ok(capture) => capture,
+                Err(e) => {
+                    let _ = opened.send(Err(e));
+                    return;
+                }
+            };
+            let _ = opened.send(Ok(capture.format));
+            if let Err(e) = capture.run(&mut sink, &thread_stopped)
+                && !thread_stopped.load(Ordering::Relaxed)
+            {
+                bus.publish(AppEvent::Audio(AudioEvent::StreamError(format!("{:#}", e))));
+            }
+        });
+        let format = format
+            .recv()
+            .context("WASAPI loopback capture stopped unexpectedly")?
+            .context("unable to capture with WASAPI loopback")?;
+        Ok(RunningSource::new(format, StopOnDrop(stopped)))
+    }
+}
@@ -0,0 +1,149 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use super::{AudioSink, AudioSource, RunningSource};
+use crate::events::EventBus;
+
+/// One device or application within a mix, alongside how strongly it counts.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MixInput {
+    pub device: String,
+    pub gain: f32,
+    /// Whether this is an input device, such as a DJ deck's line-in, rather than an output device.
+    #[serde(default)]
+    pub input: bool,
+    /// Whether this is an application, captured via PulseAudio or PipeWire, rather than a device.
+    #[serde(default)]
+    pub application: bool,
+}
+
+/// Marks a mixed device as an input device rather than an output device.
+const INPUT_PREFIX: &str = "input:";
+/// Marks a mixed source as an application rather than a device.
+const APPLICATION_PREFIX: &str = "app:";
+
+impl FromStr for MixInput {
+    type Err = String;
+
+    /// Parses `device` or `device=gain`, prefixed with `input:` for input devices, or `app:` for applications.
+    /// Device names may themselves contain `=`, so anything that doesn't end in a gain is taken as the name in full.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (value, input, application) = if let Some(value) = value.strip_prefix(INPUT_PREFIX) {
+            (value, true, false)
+        } else if let Some(value) = value.strip_prefix(APPLICATION_PREFIX) {
+            (value, false, true)
+        } else {
+            (value, false, false)
+        };
+        let (device, gain) = match value.rsplit_once('=') {
+            Some((device, gain)) => match gain.trim().parse::<f32>() {
+                Ok(gain) if gain.is_finite() && gain >= 0.0 => (device, gain),
+                Ok(_) => return Err(format!("gain must be a positive number, not {}", gain)),
+                Err(_) => (value, 1.0),
+            },
+            None => (value, 1.0),
+        };
+        if device.trim().is_empty() {
+            return Err("no device given".to_string());
+        }
+        Ok(Self {
+            device: device.trim().to_string(),
+            gain,
+            input,
+            application,
+        })
+    }
+}
+
+impl fmt::Display for MixInput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.input {
+            write!(f, "{}", INPUT_PREFIX)?;
+        } else if self.application {
+            write!(f, "{}", APPLICATION_PREFIX)?;
+        }
+        write!(f, "{}={}", self.device, self.gain)
+    }
+}
+
+/// How the envelopes of mixed sources are combined into one.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MixCombine {
+    /// Whichever source is loudest drives devices alone.
+    #[default]
+    Max,
+    /// Every source adds to the intensity, up to full.
+    Sum,
+}
+
+/// The latest envelope of each mixed source, so that they can be combined however often each arrives.
+pub(super) struct MixLevels {
+    combine: MixCombine,
+    levels: Mutex<Vec<f64>>,
+}
+
+impl MixLevels {
+    fn new(combine: MixCombine, sources: usize) -> Self {
+        Self {
+            combine,
+            levels: Mutex::new(vec![0.0; sources]),
+        }
+    }
+
+    /// Records the latest envelope of the source at `index`, returning every source's combined.
+    pub(super) fn update(&self, index: usize, level: f64) -> f64 {
+        let mut levels = self.levels.lock().unwrap();
+        levels[index] = level;
+        match self.combine {
+            MixCombine::Max => levels.iter().copied().fold(0.0, f64::max),
+            MixCombine::Sum => levels.iter().sum::<f64>().min(1.0),
+        }
+    }
+}
+
+/// Captures several sources at once, such as game audio on headphones alongside music on speakers,
+/// or a music player weighted above a game with voice chat left out.
+///
+/// Devices run on their own clocks, so rather than mixing samples, each source is analyzed
+/// separately and its envelope scaled by its gain. Whenever any source's envelope arrives,
+/// it's combined with the latest of every other's, which is what the vibration loop receives.
+// TODO(spotlightishere): Capture applications on Windows too, with WASAPI process loopback,
+// which cpal has no support for yet.
+pub struct MixSource {
+    sources: Vec<(Box<dyn AudioSource>, f32)>,
+}
+
+impl MixSource {
+    pub fn new(sources: Vec<(Box<dyn AudioSource>, f32)>) -> Self {
+        Self { sources }
+    }
+}
+
+impl AudioSource for MixSource {
+    fn describe(&self) -> String {
+        let sources: Vec<String> = self
+            .sources
+            .iter()
+            .map(|(source, gain)| format!("{} at {}", source.describe(), gain))
+            .collect();
+        format!("mix of {}", sources.join(", "))
+    }
+
+    fn start(self: Box<Self>, sink: AudioSink, bus: &EventBus) -> anyhow::Result<RunningSource> {
+        let levels = Arc::new(MixLevels::new(sink.options.mix_combine, self.sources.len()));
+        let mut running = Vec::with_capacity(self.sources.len());
+        for (position, (source, gain)) in self.sources.into_iter().enumerate() {
+            // Only the first source is drawn, as several at once would be illegible.
+            let fork = sink.fork(gain, position == 0, (levels.clone(), position));
+            running.push(source.start(fork, bus)?);
+        }
+        let Some(format) = running.first().map(|source| source.format) else {
+            anyhow::bail!("no sources to mix");
+        };
+        Ok(RunningSource::new(format, running))
+    }
+}
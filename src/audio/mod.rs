@@ -0,0 +1,1262 @@
+mod bands;
+mod cables;
+mod channels;
+mod file;
+mod genre;
+mod live;
+#[cfg(windows)]
+mod loopback;
+mod mix;
+mod network;
+mod playback;
+mod pulse;
+#[cfg(feature = "stems")]
+mod stems;
+mod synthetic;
+mod voice;
+
+pub use bands::{BandSpec, BandSplitter};
+pub use cables::{
+    NULL_SINK_NAME, VirtualCable, create_null_sink, detect_virtual_cables, routing_instructions,
+};
+pub use channels::ChannelSelection;
+pub use file::{FileSource, parse_wav, read_audio};
+pub use genre::{Genre, GenreClassifier, GenreFeatures};
+pub use live::{
+    CaptureFormat, LiveSource, default_output_dev, default_output_name, find_input_dev,
+    find_output_dev, list_input_devs, list_output_devs, output_source, print_input_devs,
+    print_output_devs, select_host, select_output_dev,
+};
+#[cfg(windows)]
+pub use loopback::LoopbackSource;
+use mix::MixLevels;
+pub use mix::{MixCombine, MixInput, MixSource};
+pub use network::NetworkSource;
+pub use playback::PlaybackSource;
+pub use pulse::{
+    PulseMonitorSource, default_monitor_source, list_application_streams, list_monitor_sources,
+    print_monitor_sources,
+};
+#[cfg(feature = "stems")]
+pub use stems::StemSeparator;
+pub use synthetic::{SyntheticSource, TestSignal};
+pub use voice::VoiceDetector;
+
+use clap::ValueEnum;
+use lowpass_filter::LowpassFilter;
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+use std::collections::VecDeque;
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::dsp::{
+    BUTTERWORTH_Q, Band, Biquad, BlockSummary, EnvelopeFollower, FftSettings, FluxDetector,
+    HarmonicPercussive, KickDetector, LoudnessMeter, LoudnessNormalizer, LoudnessWeighting,
+    Resampler, SpectrumAnalyzer,
+};
+use crate::envelope::EnvelopeSender;
+use crate::events::{AppEvent, AudioEvent, EventBus};
+
+/// Every source is resampled to this rate before analysis, so that filters and thresholds
+/// behave the same regardless of the device's own rate.
+pub const PROCESSING_RATE: u32 = 48000;
+
+/// Everything below this frequency is considered bass, unless configured otherwise.
+pub const DEFAULT_CUTOFF_HZ: u32 = 80;
+/// The cutoffs that can be chosen, spanning everything audible so that high-pass filters can reach e.g. hi-hats.
+pub const CUTOFF_RANGE_HZ: RangeInclusive<u32> = 20..=20_000;
+
+/// How long the envelope takes to rise, and to fall, unless configured otherwise.
+pub const DEFAULT_ATTACK: Duration = Duration::from_millis(10);
+pub const DEFAULT_RELEASE: Duration = Duration::from_millis(100);
+
+/// How long the visualizer shows clipping for after it happens.
+const CLIP_HOLD: Duration = Duration::from_millis(500);
+/// Clipping is reported on the console at most this often.
+const CLIP_REPORT_INTERVAL: Duration = Duration::from_secs(5);
+/// How long speech counts as ongoing after any source last detected it, spanning the gaps between blocks.
+const SPEECH_HOLD: Duration = Duration::from_millis(100);
+
+/// Number of samples retained for display, roughly five seconds at common sample rates.
+pub const HISTORY_LEN: usize = 1 << 18;
+
+/// Recent raw and filtered audio, kept around for the visualizer.
+pub struct AudioMonitor {
+    inner: Mutex<MonitorBuffers>,
+    clipped_at: Mutex<Option<Instant>>,
+    /// Set while the stream has failed, until it's reopened.
+    stream_lost: AtomicBool,
+    /// The latest level of each band from `--band`, in the order they were given.
+    band_levels: Mutex<Vec<f64>>,
+    /// Set once analysis detects an onset, until it's taken.
+    onset: AtomicBool,
+    /// When speech was last detected, with `AnalysisOptions::detect_voice`.
+    speech_at: Mutex<Option<Instant>>,
+}
+
+struct MonitorBuffers {
+    raw: VecDeque<f32>,
+    filtered: VecDeque<f32>,
+    sample_rate: f32,
+}
+
+impl AudioMonitor {
+    pub fn new(sample_rate: f32) -> Self {
+        Self {
+            inner: Mutex::new(MonitorBuffers {
+                raw: VecDeque::from(vec![0.0; HISTORY_LEN]),
+                filtered: VecDeque::from(vec![0.0; HISTORY_LEN]),
+                sample_rate,
+            }),
+            clipped_at: Mutex::new(None),
+            stream_lost: AtomicBool::new(false),
+            band_levels: Mutex::new(Vec::new()),
+            onset: AtomicBool::new(false),
+            speech_at: Mutex::new(None),
+        }
+    }
+
+    /// Clears all history, e.g. after switching to a source with a different sample rate.
+    fn reset(&self, sample_rate: f32) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.raw.iter_mut().for_each(|sample| *sample = 0.0);
+        inner.filtered.iter_mut().for_each(|sample| *sample = 0.0);
+        inner.sample_rate = sample_rate;
+    }
+
+    fn push(&self, raw: &[f32], filtered: &[f32]) {
+        let mut inner = self.inner.lock().unwrap();
+        let MonitorBuffers {
+            raw: raw_history,
+            filtered: filtered_history,
+            ..
+        } = &mut *inner;
+        for (history, block) in [(raw_history, raw), (filtered_history, filtered)] {
+            history.extend(block.iter().copied());
+            let excess = history.len().saturating_sub(HISTORY_LEN);
+            history.drain(..excess);
+        }
+    }
+
+    /// The latest level of each band the audio is split into, if any.
+    pub fn band_levels(&self) -> Vec<f64> {
+        self.band_levels.lock().unwrap().clone()
+    }
+
+    /// Whether analysis has detected an onset since this was last called, with onsets other than `level`.
+    pub fn take_onset(&self) -> bool {
+        self.onset.swap(false, Ordering::Relaxed)
+    }
+
+    /// Whether any source is currently carrying speech, should it be detected.
+    pub fn speaking(&self) -> bool {
+        self.speech_at
+            .lock()
+            .unwrap()
+            .is_some_and(|at| at.elapsed() < SPEECH_HOLD)
+    }
+
+    /// Returns copies of the raw and filtered history, oldest sample first.
+    pub fn snapshot(&self) -> (Vec<f32>, Vec<f32>) {
+        let inner = self.inner.lock().unwrap();
+        (
+            inner.raw.iter().copied().collect(),
+            inner.filtered.iter().copied().collect(),
+        )
+    }
+
+    pub fn sample_rate(&self) -> f32 {
+        self.inner.lock().unwrap().sample_rate
+    }
+
+    /// Whether incoming audio clipped just now, after input gain.
+    pub fn clipping(&self) -> bool {
+        self.clipped_at
+            .lock()
+            .unwrap()
+            .is_some_and(|at| at.elapsed() < CLIP_HOLD)
+    }
+
+    /// Whether the stream has failed and not yet been reopened.
+    pub fn stream_lost(&self) -> bool {
+        self.stream_lost.load(Ordering::Relaxed)
+    }
+
+    pub fn set_stream_lost(&self, lost: bool) {
+        self.stream_lost.store(lost, Ordering::Relaxed);
+    }
+}
+
+/// What audio is analyzed for.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AnalysisMode {
+    /// Follows bass, below the configured --cutoff.
+    #[default]
+    Bass,
+    /// Follows speech, ignoring everything else, e.g. for responding to a voice on a call.
+    Voice,
+    /// Follows the level of the bass band, as measured by an FFT. Steadier than following the filter,
+    /// but slower to respond, as each measurement spans about 85 ms unless `--fft-size` says otherwise.
+    Spectrum,
+    /// Follows overall loudness, measured in LUFS, so that equally loud audio drives devices equally
+    /// regardless of where its energy lies.
+    Loudness,
+}
+
+/// How far back loudness is measured over, with `AnalysisMode::Loudness`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LoudnessWindow {
+    /// Momentary loudness, over the last 400 ms.
+    #[default]
+    Momentary,
+    /// Short-term loudness, over the last 3 seconds, which is much steadier.
+    ShortTerm,
+}
+
+impl LoudnessWindow {
+    pub fn duration(self) -> Duration {
+        match self {
+            LoudnessWindow::Momentary => Duration::from_millis(400),
+            LoudnessWindow::ShortTerm => Duration::from_secs(3),
+        }
+    }
+}
+
+/// Which part of the bass is followed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BassComponent {
+    /// Everything below the cutoff.
+    #[default]
+    All,
+    /// Only hits, such as kicks and snares.
+    Percussive,
+    /// Only sustained bass, ignoring hits.
+    Sustained,
+}
+
+/// How frequencies are weighted against each other before analysis.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Weighting {
+    /// Raw amplitude, with every frequency counting the same.
+    #[default]
+    None,
+    /// A-weighting, which matches how loud quiet sounds seem.
+    A,
+    /// K-weighting, as used for loudness normalization in broadcasting and streaming.
+    K,
+}
+
+/// How the envelope is measured from the filtered signal.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum EnvelopeDetector {
+    /// The signal's power, which is steadier.
+    #[default]
+    Rms,
+    /// The signal's peaks, which reacts more sharply.
+    Peak,
+}
+
+/// What counts as an onset, for pulses and tempo detection.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OnsetMethod {
+    /// Any sudden rise in the envelope.
+    #[default]
+    Level,
+    /// Kick drums alone, so that bass lines don't set off pulses.
+    Kick,
+    /// Any sudden change in the spectrum, which still finds hits within heavily compressed masters.
+    Flux,
+}
+
+/// How each block's envelope is summarized as the single value sent on for it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BlockStatistic {
+    /// The envelope at the end of the block. How representative that is depends on the block's size.
+    Last,
+    /// The envelope's root mean square across the block.
+    #[default]
+    Rms,
+    /// The envelope's highest point within the block, reacting most sharply.
+    Peak,
+    /// The envelope's level at a percentile across the block, which peaks can't skew.
+    Percentile,
+}
+
+/// Anything below this is mostly DC offset and rumble, which shouldn't drive devices.
+const BASS_FLOOR_HZ: f32 = 20.0;
+
+/// How long the click injected by a [`LatencyProbe`] lasts.
+const CLICK_DURATION_SECS: f32 = 0.05;
+/// Clicks stay this far below the Nyquist frequency, so that they're never aliased.
+const MAX_CLICK_HZ: f32 = PROCESSING_RATE as f32 * 0.4;
+
+/// Injects a click into the analysis path, so that the time taken for it to reach devices can be measured.
+#[derive(Default)]
+pub struct LatencyProbe {
+    requested: AtomicBool,
+    injected_at: Mutex<Option<Instant>>,
+    /// The frequency of the most recent click, as the bits of an `f32`.
+    click_hz: AtomicU32,
+}
+
+impl LatencyProbe {
+    /// Asks for a click to be injected into the next block of audio analyzed.
+    pub fn request(&self) {
+        self.requested.store(true, Ordering::Relaxed);
+    }
+
+    /// When the most recent click was injected, if it hasn't yet been taken.
+    pub fn injected_at(&self) -> Option<Instant> {
+        *self.injected_at.lock().unwrap()
+    }
+
+    /// Takes the most recent click, so that it's only ever measured once.
+    pub fn take(&self) -> Option<Instant> {
+        self.injected_at.lock().unwrap().take()
+    }
+
+    /// The frequency in Hz of the most recent click.
+    pub fn click_hz(&self) -> f32 {
+        f32::from_bits(self.click_hz.load(Ordering::Relaxed))
+    }
+}
+
+/// Options for capturing live audio.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CaptureOptions {
+    /// Requests the smallest buffer the device supports, trading CPU time for latency.
+    pub low_latency: bool,
+    /// Prefers a configuration with at least this many channels, should the default have fewer.
+    pub min_channels: u16,
+    /// Requests buffers of exactly this many frames, within what the device supports.
+    pub buffer_frames: Option<u32>,
+    /// Prefers a configuration with this sample format over the device's default.
+    pub sample_format: Option<CaptureFormat>,
+    /// How far ahead of what's heard audio is analyzed, for sources that play it themselves.
+    // TODO(spotlightishere): Look ahead of live audio too, by capturing it from a virtual device
+    // and playing it on to the real one ourselves, delayed by this much.
+    pub lookahead: Duration,
+}
+
+/// Everything about how audio is analyzed, regardless of where it comes from.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AnalysisOptions {
+    pub mode: AnalysisMode,
+    /// Which part of the bass is followed, when following bass.
+    pub component: BassComponent,
+    pub weighting: Weighting,
+    pub channels: ChannelSelection,
+    /// Everything below this frequency is considered bass.
+    pub cutoff_hz: u32,
+    pub slope: FilterSlope,
+    pub filter: FilterType,
+    /// How narrow band-pass and notch filters are, and how resonant others are at their cutoff.
+    pub q: f32,
+    /// Evens out loudness between tracks before analysis.
+    pub normalize: bool,
+    /// Incoming audio is multiplied by this much before filtering.
+    pub input_gain: f32,
+    /// Bands measured on their own. When any are given, their weighted sum replaces the envelope.
+    pub bands: Vec<BandSpec>,
+    pub detector: EnvelopeDetector,
+    /// How quickly the envelope rises to meet the signal.
+    pub attack: Duration,
+    /// How quickly the envelope falls once the signal quietens.
+    pub release: Duration,
+    pub block_statistic: BlockStatistic,
+    /// The percentile from 0 to 100 measured, with `BlockStatistic::Percentile`.
+    pub block_percentile: f32,
+    pub loudness_window: LoudnessWindow,
+    /// How audio is split into blocks for each FFT.
+    pub fft: FftSettings,
+    pub onsets: OnsetMethod,
+    /// How far above its recent average a band must rise for an onset, as a ratio.
+    pub onset_sensitivity: f64,
+    /// Detects speech alongside whatever is followed, for ducking.
+    pub detect_voice: bool,
+    /// How the envelopes of several sources are combined, when mixing them.
+    pub mix_combine: MixCombine,
+    /// An ONNX model isolating the bass and drums before analysis, with the `stems` feature.
+    pub stem_model: Option<PathBuf>,
+}
+
+impl Default for AnalysisOptions {
+    fn default() -> Self {
+        Self {
+            mode: AnalysisMode::default(),
+            component: BassComponent::default(),
+            weighting: Weighting::default(),
+            channels: ChannelSelection::default(),
+            cutoff_hz: DEFAULT_CUTOFF_HZ,
+            slope: FilterSlope::default(),
+            filter: FilterType::default(),
+            q: BUTTERWORTH_Q,
+            normalize: false,
+            input_gain: 1.0,
+            bands: Vec::new(),
+            detector: EnvelopeDetector::default(),
+            attack: DEFAULT_ATTACK,
+            release: DEFAULT_RELEASE,
+            block_statistic: BlockStatistic::default(),
+            block_percentile: 90.0,
+            loudness_window: LoudnessWindow::default(),
+            fft: FftSettings::default(),
+            onsets: OnsetMethod::default(),
+            onset_sensitivity: 1.5,
+            detect_voice: false,
+            mix_combine: MixCombine::default(),
+            stem_model: None,
+        }
+    }
+}
+
+impl AnalysisOptions {
+    fn envelope_follower(&self, sample_rate: f32) -> EnvelopeFollower {
+        EnvelopeFollower::new(
+            sample_rate,
+            self.attack,
+            self.release,
+            self.detector == EnvelopeDetector::Rms,
+        )
+        .with_summary(match self.block_statistic {
+            BlockStatistic::Last => BlockSummary::Last,
+            BlockStatistic::Rms => BlockSummary::Rms,
+            BlockStatistic::Peak => BlockSummary::Peak,
+            BlockStatistic::Percentile => BlockSummary::Percentile(self.block_percentile / 100.0),
+        })
+    }
+
+    /// The frequency latency clicks are played at: the middle of whatever's followed, on a log scale,
+    /// so that no filter removes them before they're measured.
+    fn click_hz(&self) -> f32 {
+        let (low, high) = if self.mode == AnalysisMode::Voice {
+            (voice::SPEECH_LOW_HZ, voice::SPEECH_HIGH_HZ)
+        } else if let Some(band) = self.bands.iter().find(|band| band.weight > 0.0) {
+            (band.low_hz, band.high_hz)
+        } else {
+            let band = AnalysisFilter::bands(self)[0];
+            (band.low_hz, band.high_hz)
+        };
+        (low.max(BASS_FLOOR_HZ) * high.max(BASS_FLOOR_HZ))
+            .sqrt()
+            .min(MAX_CLICK_HZ)
+    }
+}
+
+/// How steeply frequencies above the cutoff are rolled off.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+pub enum FilterSlope {
+    /// 6 dB per octave, letting plenty of mid-bass through.
+    #[default]
+    #[value(name = "6")]
+    #[serde(rename = "6")]
+    Gentle,
+    /// 12 dB per octave.
+    #[value(name = "12")]
+    #[serde(rename = "12")]
+    Moderate,
+    /// 24 dB per octave, for little beyond the cutoff.
+    #[value(name = "24")]
+    #[serde(rename = "24")]
+    Steep,
+}
+
+/// Which frequencies the analysis filter lets through.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FilterType {
+    /// Everything below the cutoff, i.e. bass.
+    #[default]
+    Lowpass,
+    /// Everything above the cutoff, such as hi-hats.
+    Highpass,
+    /// Only frequencies around the cutoff, such as a vocal band.
+    Bandpass,
+    /// Everything but the frequencies around the cutoff.
+    Notch,
+}
+
+/// Butterworth Qs for each biquad in a fourth-order filter.
+const FOURTH_ORDER_QS: [f32; 2] = [0.5412, 1.3066];
+/// Nothing above this is audible, so it bounds the bands measured by the spectrum.
+const HEARING_CEILING_HZ: f32 = 20_000.0;
+
+/// The filter separating what's followed from everything else.
+enum AnalysisFilter {
+    /// A single pole, rolling off at 6 dB per octave.
+    OnePole(LowpassFilter<f32>),
+    /// Biquads in series, each adding 12 dB per octave.
+    Biquads(Vec<Biquad>),
+}
+
+impl AnalysisFilter {
+    fn new(options: &AnalysisOptions, sample_rate: f32) -> Self {
+        let frequency = options.cutoff_hz as f32;
+        let stage = |q: f32| match options.filter {
+            FilterType::Lowpass => Biquad::lowpass(sample_rate, frequency, q),
+            FilterType::Highpass => Biquad::highpass(sample_rate, frequency, q),
+            FilterType::Bandpass => Biquad::bandpass(sample_rate, frequency, q),
+            FilterType::Notch => Biquad::notch(sample_rate, frequency, q),
+        };
+        match (options.slope, options.filter) {
+            (FilterSlope::Gentle, FilterType::Lowpass) => {
+                AnalysisFilter::OnePole(LowpassFilter::<f32>::new(sample_rate, frequency))
+            }
+            (FilterSlope::Gentle, FilterType::Highpass) => {
+                AnalysisFilter::Biquads(vec![Biquad::highpass_first_order(sample_rate, frequency)])
+            }
+            // Band-pass and notch filters are at least second order, so they're never gentler than this.
+            (FilterSlope::Gentle | FilterSlope::Moderate, _) => {
+                AnalysisFilter::Biquads(vec![stage(options.q)])
+            }
+            // Steep low and high-pass filters are Butterworth, for a flat passband.
+            (FilterSlope::Steep, FilterType::Lowpass | FilterType::Highpass) => {
+                AnalysisFilter::Biquads(FOURTH_ORDER_QS.into_iter().map(stage).collect())
+            }
+            (FilterSlope::Steep, _) => AnalysisFilter::Biquads(vec![stage(options.q); 2]),
+        }
+    }
+
+    /// The bands the filter passes, for measuring with the spectrum instead.
+    fn bands(options: &AnalysisOptions) -> Vec<Band> {
+        let frequency = options.cutoff_hz as f32;
+        // The edges of a band-pass filter of the given Q, which sit symmetrically about its centre on a log scale.
+        let spread = 1.0 / (2.0 * options.q.max(0.01));
+        let centre = (1.0 + spread * spread).sqrt();
+        let (low, high) = (frequency * (centre - spread), frequency * (centre + spread));
+        match options.filter {
+            FilterType::Lowpass => vec![Band::new(BASS_FLOOR_HZ, frequency)],
+            FilterType::Highpass => vec![Band::new(frequency, HEARING_CEILING_HZ)],
+            FilterType::Bandpass => vec![Band::new(low, high)],
+            FilterType::Notch => vec![
+                Band::new(BASS_FLOOR_HZ, low),
+                Band::new(high, HEARING_CEILING_HZ),
+            ],
+        }
+    }
+
+    fn run(&mut self, sample: f32) -> f32 {
+        match self {
+            AnalysisFilter::OnePole(filter) => filter.run(sample),
+            AnalysisFilter::Biquads(stages) => stages
+                .iter_mut()
+                .fold(sample, |sample, stage| stage.run(sample)),
+        }
+    }
+}
+
+/// Turns blocks of mono samples into envelope values, according to an [`AnalysisMode`].
+enum Analyzer {
+    Bass(AnalysisFilter, EnvelopeFollower),
+    /// Follows only part of the bass, separated after filtering.
+    Separated(
+        AnalysisFilter,
+        HarmonicPercussive,
+        BassComponent,
+        EnvelopeFollower,
+    ),
+    Voice(VoiceDetector),
+    /// The filter is kept purely for the visualizer; the envelope comes from the spectrum.
+    Spectrum {
+        filter: AnalysisFilter,
+        spectrum: SpectrumAnalyzer,
+        level: f64,
+    },
+    /// The filter is kept purely for the visualizer; the envelope comes from the loudness.
+    Loudness(AnalysisFilter, LoudnessMeter),
+}
+
+impl Analyzer {
+    fn new(options: &AnalysisOptions, sample_rate: f32) -> Self {
+        match options.mode {
+            AnalysisMode::Bass => {
+                let filter = AnalysisFilter::new(options, sample_rate);
+                let follower = options.envelope_follower(sample_rate);
+                match options.component {
+                    BassComponent::All => Analyzer::Bass(filter, follower),
+                    component => Analyzer::Separated(
+                        filter,
+                        HarmonicPercussive::new(sample_rate),
+                        component,
+                        follower,
+                    ),
+                }
+            }
+            AnalysisMode::Voice => Analyzer::Voice(VoiceDetector::new(sample_rate)),
+            AnalysisMode::Spectrum => Analyzer::Spectrum {
+                filter: AnalysisFilter::new(options, sample_rate),
+                spectrum: SpectrumAnalyzer::with_fft(
+                    sample_rate,
+                    AnalysisFilter::bands(options),
+                    options.fft,
+                ),
+                level: 0.0,
+            },
+            AnalysisMode::Loudness => Analyzer::Loudness(
+                AnalysisFilter::new(options, sample_rate),
+                LoudnessMeter::new(sample_rate, options.loudness_window.duration()),
+            ),
+        }
+    }
+
+    /// Returns the filtered block, alongside the envelope value derived from it.
+    fn run(&mut self, direct_values: &[f32]) -> (Vec<f32>, f64) {
+        match self {
+            Analyzer::Bass(filter, follower) => {
+                let filtered = audio_transform_fn(direct_values, filter);
+                let intensity = follower.process(&filtered);
+                (filtered, intensity)
+            }
+            Analyzer::Separated(filter, separation, component, follower) => {
+                let filtered = audio_transform_fn(direct_values, filter);
+                let separated: Vec<f32> = filtered
+                    .into_iter()
+                    .map(|sample| {
+                        let (sustained, percussive) = separation.split(sample);
+                        if *component == BassComponent::Percussive {
+                            percussive
+                        } else {
+                            sustained
+                        }
+                    })
+                    .collect();
+                let intensity = follower.process(&separated);
+                (separated, intensity)
+            }
+            Analyzer::Voice(detector) => detector.process(direct_values),
+            Analyzer::Spectrum {
+                filter,
+                spectrum,
+                level,
+            } => {
+                let filtered = audio_transform_fn(direct_values, filter);
+                // Levels are RMS, so several bands combine as the root of their summed squares.
+                if let Some(levels) = spectrum.process(direct_values) {
+                    *level = levels.iter().map(|level| level * level).sum::<f32>().sqrt() as f64;
+                }
+                (filtered, *level)
+            }
+            Analyzer::Loudness(filter, meter) => {
+                let filtered = audio_transform_fn(direct_values, filter);
+                // Loudness is converted back to an amplitude, so that mappings suit it much as they do other envelopes.
+                let lufs = meter.process(direct_values);
+                (filtered, 10f64.powf(lufs / 20.0))
+            }
+        }
+    }
+}
+
+/// The shape of the interleaved samples a source produces.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AudioFormat {
+    pub sample_rate: u32,
+    pub channels: u16,
+}
+
+/// Where every source delivers its audio. This is the entry point into analysis,
+/// so sources never need to know about filtering or the vibration loop.
+pub struct AudioSink {
+    sender: Arc<EnvelopeSender>,
+    monitor: Arc<AudioMonitor>,
+    options: AnalysisOptions,
+    analysis: Option<Analysis>,
+    bus: EventBus,
+    probe: Arc<LatencyProbe>,
+    /// How many more samples of an injected click are left to play.
+    click_remaining: usize,
+    /// Envelope values are scaled by this much, so that mixed sources can be balanced against each other.
+    gain: f32,
+    /// Whether audio is shown in the visualizer. Only one of several mixed sources can be.
+    monitored: bool,
+    /// Where this sink's envelope is combined with those of other mixed sources, and its place there.
+    mix: Option<(Arc<MixLevels>, usize)>,
+    /// The input gain, as the bits of an `f32`, so that it can be changed while running.
+    input_gain: Arc<AtomicU32>,
+    last_clip_report: Option<Instant>,
+    /// The last format that couldn't be analyzed, so that it's only reported once.
+    rejected: Option<AudioFormat>,
+}
+
+/// Analysis state tied to a particular format, which starts afresh should the format change.
+struct Analysis {
+    format: AudioFormat,
+    resampler: Resampler,
+    analyzer: Analyzer,
+    classifier: GenreClassifier,
+    normalizer: Option<LoudnessNormalizer>,
+    weighting: Option<LoudnessWeighting>,
+    bands: Option<BandSplitter>,
+    onsets: Option<SpectralOnsets>,
+    voice: Option<VoiceDetector>,
+    #[cfg(feature = "stems")]
+    stems: Option<StemSeparator>,
+}
+
+/// Detects onsets from the spectrum, for onset methods that the envelope alone can't tell.
+enum SpectralOnsets {
+    Kick(KickDetector),
+    Flux(FluxDetector),
+}
+
+impl SpectralOnsets {
+    fn new(options: &AnalysisOptions, sample_rate: f32) -> Option<Self> {
+        let sensitivity = options.onset_sensitivity;
+        match options.onsets {
+            OnsetMethod::Level => None,
+            OnsetMethod::Kick => Some(Self::Kick(KickDetector::new(
+                sample_rate,
+                sensitivity,
+                options.fft,
+            ))),
+            OnsetMethod::Flux => Some(Self::Flux(FluxDetector::new(
+                sample_rate,
+                sensitivity,
+                options.fft,
+            ))),
+        }
+    }
+
+    /// Feeds mono samples, returning whether an onset begins within them.
+    fn process(&mut self, samples: &[f32]) -> bool {
+        match self {
+            Self::Kick(detector) => detector.process(samples),
+            Self::Flux(detector) => detector.process(samples),
+        }
+    }
+}
+
+impl AudioSink {
+    /// A sink feeding the same envelope channel, but analyzing its audio separately,
+    /// for one of several sources mixed together.
+    fn fork(&self, gain: f32, monitored: bool, mix: (Arc<MixLevels>, usize)) -> Self {
+        Self {
+            sender: self.sender.clone(),
+            monitor: self.monitor.clone(),
+            options: self.options.clone(),
+            analysis: None,
+            bus: self.bus.clone(),
+            probe: self.probe.clone(),
+            click_remaining: 0,
+            gain: self.gain * gain,
+            monitored: self.monitored && monitored,
+            mix: Some(mix),
+            input_gain: self.input_gain.clone(),
+            last_clip_report: None,
+            rejected: None,
+        }
+    }
+
+    /// Analyzes a block of interleaved samples.
+    pub fn push(&mut self, format: AudioFormat, data: &[f32]) {
+        // Audio is resampled to our processing rate before filtering, so any rate will do, but it must have one.
+        // Formats without any channels or samples can only come from misconfigured sources.
+        if format.channels == 0 || format.sample_rate == 0 {
+            if self.rejected != Some(format) {
+                self.rejected = Some(format);
+                self.bus
+                    .publish(AppEvent::Audio(AudioEvent::FormatRejected {
+                        sample_rate: format.sample_rate,
+                        channels: format.channels,
+                        reason: "it has no channels or sample rate".to_string(),
+                    }));
+            }
+            return;
+        }
+
+        // Our analyzer persists across blocks, so that block boundaries don't reset its filters.
+        // It only starts afresh if the format changes underneath us.
+        let analysis = match &mut self.analysis {
+            Some(analysis) if analysis.format == format => analysis,
+            _ => {
+                let sample_rate = PROCESSING_RATE as f32;
+                if self.monitored {
+                    self.monitor.reset(sample_rate);
+                }
+                if !self.options.channels.supports(format.channels) {
+                    println!(
+                        "Channel {} is unavailable with {} channels; downmixing instead.",
+                        self.options.channels, format.channels
+                    );
+                }
+                #[cfg(not(feature = "stems"))]
+                if self.options.stem_model.is_some() {
+                    println!(
+                        "Separating stems needs the stems feature; analyzing the full mix instead."
+                    );
+                }
+                self.analysis.insert(Analysis {
+                    format,
+                    resampler: Resampler::new(format.sample_rate as f32, sample_rate),
+                    analyzer: Analyzer::new(&self.options, sample_rate),
+                    classifier: GenreClassifier::new(sample_rate),
+                    normalizer: self
+                        .options
+                        .normalize
+                        .then(|| LoudnessNormalizer::new(sample_rate)),
+                    weighting: match self.options.weighting {
+                        Weighting::None => None,
+                        Weighting::A => Some(LoudnessWeighting::a_weighting(
+                            sample_rate,
+                            self.options.cutoff_hz as f32,
+                        )),
+                        Weighting::K => Some(LoudnessWeighting::k_weighting(
+                            sample_rate,
+                            self.options.cutoff_hz as f32,
+                        )),
+                    },
+                    bands: (!self.options.bands.is_empty())
+                        .then(|| BandSplitter::new(&self.options.bands, sample_rate)),
+                    onsets: SpectralOnsets::new(&self.options, sample_rate),
+                    voice: self
+                        .options
+                        .detect_voice
+                        .then(|| VoiceDetector::new(sample_rate)),
+                    #[cfg(feature = "stems")]
+                    stems: self.options.stem_model.as_deref().map(StemSeparator::new),
+                })
+            }
+        };
+
+        // Reduce interleaved frames to mono prior to any other processing, then bring them to our processing rate.
+        let direct_values = self.options.channels.extract(data, format.channels);
+        let mut direct_values = analysis.resampler.process(&direct_values);
+        // The bass and drums are isolated before anything else sees the audio, so that vocals never reach the filter.
+        #[cfg(feature = "stems")]
+        if let Some(stems) = &mut analysis.stems {
+            direct_values = stems.process(&direct_values);
+        }
+        if let Some(normalizer) = &mut analysis.normalizer {
+            normalizer.process(&mut direct_values);
+        }
+        let input_gain = f32::from_bits(self.input_gain.load(Ordering::Relaxed));
+        if input_gain != 1.0 {
+            direct_values
+                .iter_mut()
+                .for_each(|sample| *sample *= input_gain);
+        }
+        // Anything beyond full scale is clamped by the filter, distorting what it sees.
+        if direct_values.iter().any(|sample| sample.abs() > 1.0) {
+            let now = Instant::now();
+            if self.monitored {
+                *self.monitor.clipped_at.lock().unwrap() = Some(now);
+            }
+            if self
+                .last_clip_report
+                .is_none_or(|at| now - at >= CLIP_REPORT_INTERVAL)
+            {
+                self.last_clip_report = Some(now);
+                self.bus
+                    .publish(AppEvent::Audio(AudioEvent::Clipping { input_gain }));
+            }
+        }
+
+        // Weighting comes after clipping is checked, as it's the raw level that clips,
+        // but before any click, as a click must arrive at full scale to be recognised.
+        if let Some(weighting) = &mut analysis.weighting {
+            direct_values
+                .iter_mut()
+                .for_each(|sample| *sample = weighting.run(*sample));
+        }
+
+        // A requested click replaces whatever is playing, so that it's unmistakable downstream.
+        let sample_rate = PROCESSING_RATE as f32;
+        if self.probe.requested.swap(false, Ordering::Relaxed) {
+            self.click_remaining = (sample_rate * CLICK_DURATION_SECS) as usize;
+            self.probe
+                .click_hz
+                .store(self.options.click_hz().to_bits(), Ordering::Relaxed);
+            *self.probe.injected_at.lock().unwrap() = Some(Instant::now());
+        }
+        if self.click_remaining > 0 {
+            let total = (sample_rate * CLICK_DURATION_SECS) as usize;
+            let click_hz = self.probe.click_hz();
+            for sample in direct_values.iter_mut() {
+                let played = total.saturating_sub(self.click_remaining);
+                *sample = (std::f32::consts::TAU * click_hz * played as f32 / sample_rate).sin();
+                self.click_remaining = self.click_remaining.saturating_sub(1);
+            }
+        }
+        let (raw_values, mut intensity) = analysis.analyzer.run(&direct_values);
+        // Once split into bands, the overall intensity is their weighted sum instead.
+        if let Some(splitter) = &mut analysis.bands {
+            let levels = splitter.process(&direct_values);
+            intensity = splitter.combine(&levels);
+            if self.monitored {
+                *self.monitor.band_levels.lock().unwrap() = levels;
+            }
+        }
+        // Some onsets are told apart by the spectrum, so they're detected here rather than from the envelope.
+        if let Some(onsets) = &mut analysis.onsets
+            && onsets.process(&direct_values)
+        {
+            self.monitor.onset.store(true, Ordering::Relaxed);
+        }
+        // Speech is detected on its own, regardless of what's followed, so that it can duck the intensity.
+        // TODO(spotlightishere): Allow a microphone to act as the sidechain alone, without driving devices.
+        if let Some(voice) = &mut analysis.voice
+            && voice.process(&direct_values).1 > 0.0
+        {
+            *self.monitor.speech_at.lock().unwrap() = Some(Instant::now());
+        }
+        if self.monitored {
+            self.monitor.push(&direct_values, &raw_values);
+        }
+        if let Some(genre) = analysis.classifier.process(&direct_values) {
+            self.bus
+                .publish(AppEvent::Audio(AudioEvent::GenreDetected(genre)));
+        }
+
+        // Lastly, broadcast our adjusted value!
+        // A full queue is handled by the channel's backpressure policy.
+        let mut value = intensity * self.gain as f64;
+        if let Some((levels, index)) = &self.mix {
+            value = levels.update(*index, value);
+        }
+        if self.sender.send(value).is_err() {
+            println!("Error while sending to channel... closed!");
+        }
+    }
+}
+
+/// Something audio can be analyzed from.
+///
+/// Implementations only need to deliver interleaved samples into the [`AudioSink`] they are given;
+/// everything downstream is shared between all sources.
+pub trait AudioSource: Send {
+    /// A human-readable description, used when reporting on this source.
+    fn describe(&self) -> String;
+
+    /// Begins delivering audio into `sink`. Audio flows until the returned value is dropped.
+    fn start(self: Box<Self>, sink: AudioSink, bus: &EventBus) -> anyhow::Result<RunningSource>;
+}
+
+/// A source that is currently delivering audio.
+pub struct RunningSource {
+    pub format: AudioFormat,
+    /// Whatever keeps the source alive, such as a cpal stream. Dropping it stops the source.
+    _guard: Box<dyn Any>,
+}
+
+impl RunningSource {
+    pub fn new(format: AudioFormat, guard: impl Any) -> Self {
+        Self {
+            format,
+            _guard: Box::new(guard),
+        }
+    }
+}
+
+/// Stops a paced source's thread once dropped.
+struct StopOnDrop(Arc<AtomicBool>);
+
+impl Drop for StopOnDrop {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+/// How much audio paced sources produce at once.
+const PACED_BLOCK: Duration = Duration::from_millis(10);
+
+/// Runs `fill` on its own thread in real time, handing it a block of interleaved samples
+/// to fill roughly every 10 ms. The source ends once `fill` returns false.
+fn spawn_paced(
+    format: AudioFormat,
+    mut sink: AudioSink,
+    mut fill: impl FnMut(&mut [f32]) -> bool + Send + 'static,
+) -> RunningSource {
+    let stop = Arc::new(AtomicBool::new(false));
+    let thread_stop = stop.clone();
+    let frames = (format.sample_rate as u128 * PACED_BLOCK.as_millis() / 1000) as usize;
+    let mut block = vec![0.0; frames.max(1) * format.channels as usize];
+
+    thread::spawn(move || {
+        // Tracking an absolute deadline avoids drifting from accumulated sleep error.
+        let mut deadline = Instant::now();
+        while !thread_stop.load(Ordering::Relaxed) {
+            if !fill(&mut block) {
+                break;
+            }
+            sink.push(format, &block);
+            deadline += PACED_BLOCK;
+            thread::sleep(deadline.saturating_duration_since(Instant::now()));
+        }
+    });
+
+    RunningSource::new(format, StopOnDrop(stop))
+}
+
+/// Describes a source in a way that can be saved and reopened later.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SourceSpec {
+    /// Captures whatever is playing on the named output device.
+    Live(String),
+    /// Captures the named input device, such as a microphone or line-in.
+    Input(String),
+    /// Captures the named PulseAudio or PipeWire monitor source.
+    Monitor(String),
+    /// Captures only what the named application plays, via PulseAudio or PipeWire.
+    Application(String),
+    /// Decodes a WAV, FLAC or MP3 file in real time.
+    File(PathBuf),
+    /// Plays a WAV, FLAC or MP3 file through the default output device, analyzing it as it plays.
+    Playback(PathBuf),
+    /// Listens for raw 16-bit little-endian PCM over UDP, or RTP carrying L16 audio.
+    Network {
+        address: String,
+        sample_rate: u32,
+        channels: u16,
+        #[serde(default)]
+        rtp: bool,
+    },
+    /// Generates a test signal.
+    Synthetic(TestSignal),
+    /// Captures several devices or applications at once, each with its own gain.
+    Mix(Vec<MixInput>),
+}
+
+impl SourceSpec {
+    pub fn open(&self, capture: CaptureOptions) -> anyhow::Result<Box<dyn AudioSource>> {
+        Ok(match self {
+            SourceSpec::Live(name) => match find_output_dev(name) {
+                Some(device) => output_source(device, capture),
+                None => anyhow::bail!("output device {} is not available", name),
+            },
+            SourceSpec::Input(name) => match find_input_dev(name) {
+                Some(device) => Box::new(LiveSource::input(device, capture)),
+                None => anyhow::bail!("input device {} is not available", name),
+            },
+            SourceSpec::Monitor(name) => Box::new(PulseMonitorSource::new(name.clone())),
+            SourceSpec::Application(name) => Box::new(PulseMonitorSource::application(name)?),
+            SourceSpec::File(path) => Box::new(FileSource::open(path)?),
+            SourceSpec::Playback(path) => match default_output_dev() {
+                Some(device) => {
+                    Box::new(PlaybackSource::open(path, device)?.with_lookahead(capture.lookahead))
+                }
+                None => anyhow::bail!("no default output device to play {} on", path.display()),
+            },
+            SourceSpec::Network {
+                address,
+                sample_rate,
+                channels,
+                rtp,
+            } => Box::new(
+                NetworkSource::new(
+                    address.clone(),
+                    AudioFormat {
+                        sample_rate: *sample_rate,
+                        channels: *channels,
+                    },
+                )
+                .with_rtp(*rtp),
+            ),
+            SourceSpec::Synthetic(signal) => Box::new(SyntheticSource::new(*signal)),
+            SourceSpec::Mix(inputs) => {
+                let mut sources = Vec::with_capacity(inputs.len());
+                for input in inputs {
+                    // Applications weighted to nothing are left out, so that they needn't be playing.
+                    if input.application && input.gain == 0.0 {
+                        continue;
+                    }
+                    let spec = if input.application {
+                        SourceSpec::Application(input.device.clone())
+                    } else if input.input {
+                        SourceSpec::Input(input.device.clone())
+                    } else {
+                        SourceSpec::Live(input.device.clone())
+                    };
+                    sources.push((spec.open(capture)?, input.gain));
+                }
+                Box::new(MixSource::new(sources))
+            }
+        })
+    }
+}
+
+impl fmt::Display for SourceSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SourceSpec::Live(name) => write!(f, "{}", name),
+            SourceSpec::Input(name) => write!(f, "input {}", name),
+            SourceSpec::Monitor(name) => write!(f, "monitor {}", name),
+            SourceSpec::Application(name) => write!(f, "application {}", name),
+            SourceSpec::File(path) => write!(f, "file {}", path.display()),
+            SourceSpec::Playback(path) => write!(f, "playing {}", path.display()),
+            SourceSpec::Network { address, .. } => write!(f, "network {}", address),
+            SourceSpec::Synthetic(signal) => {
+                write!(
+                    f,
+                    "synthetic {}",
+                    signal.to_possible_value().unwrap().get_name()
+                )
+            }
+            SourceSpec::Mix(inputs) => {
+                let inputs: Vec<String> = inputs.iter().map(MixInput::to_string).collect();
+                write!(f, "mix of {}", inputs.join(", "))
+            }
+        }
+    }
+}
+
+enum AudioRequest {
+    Switch(Box<dyn AudioSource>),
+    Shutdown,
+}
+
+/// Controls the capture thread, which owns the current source.
+///
+/// cpal streams cannot be moved across threads, so all source management happens
+/// on a dedicated thread and is requested through this handle.
+#[derive(Clone)]
+pub struct AudioHandle {
+    requests: mpsc::Sender<AudioRequest>,
+    input_gain: Arc<AtomicU32>,
+    options: Arc<Mutex<AnalysisOptions>>,
+}
+
+impl AudioHandle {
+    /// Tears down the current source and begins analyzing `source` instead.
+    pub fn switch(&self, source: Box<dyn AudioSource>) {
+        let _ = self.requests.send(AudioRequest::Switch(source));
+    }
+
+    /// The options audio is currently analyzed with.
+    pub fn analysis_options(&self) -> AnalysisOptions {
+        self.options.lock().unwrap().clone()
+    }
+
+    /// Changes how audio is analyzed from the next source onwards.
+    /// Switching to the current source again applies them straight away.
+    pub fn set_analysis_options(&self, options: AnalysisOptions) {
+        *self.options.lock().unwrap() = options;
+    }
+
+    /// Changes how much incoming audio is multiplied by before filtering.
+    pub fn set_input_gain(&self, gain: f32) {
+        self.input_gain.store(gain.to_bits(), Ordering::Relaxed);
+    }
+
+    /// Stops capturing entirely. Once this happens, the envelope channel is closed.
+    pub fn shutdown(&self) {
+        let _ = self.requests.send(AudioRequest::Shutdown);
+    }
+}
+
+/// Spawns the capture thread, beginning with `source`.
+pub fn spawn_capture(
+    source: Box<dyn AudioSource>,
+    sender: EnvelopeSender,
+    monitor: Arc<AudioMonitor>,
+    options: AnalysisOptions,
+    probe: Arc<LatencyProbe>,
+    bus: EventBus,
+) -> AudioHandle {
+    let (requests, incoming) = mpsc::channel();
+    let sender = Arc::new(sender);
+    let input_gain = Arc::new(AtomicU32::new(options.input_gain.to_bits()));
+    let thread_input_gain = input_gain.clone();
+    let options = Arc::new(Mutex::new(options));
+    let thread_options = options.clone();
+
+    thread::spawn(move || {
+        let start = |source| {
+            start_source(
+                source,
+                &sender,
+                &monitor,
+                thread_options.lock().unwrap().clone(),
+                &thread_input_gain,
+                &probe,
+                &bus,
+            )
+        };
+        let mut running = start(source);
+        while let Ok(request) = incoming.recv() {
+            match request {
+                AudioRequest::Switch(source) => {
+                    // Stop the old source first, so that we never feed two at once.
+                    drop(running.take());
+                    running = start(source);
+                }
+                AudioRequest::Shutdown => break,
+            }
+        }
+        // Dropping both the source and our sender closes the envelope channel.
+        drop(running);
+        bus.publish(AppEvent::Audio(AudioEvent::Stopped));
+    });
+
+    AudioHandle {
+        requests,
+        input_gain,
+        options,
+    }
+}
+
+fn start_source(
+    source: Box<dyn AudioSource>,
+    sender: &Arc<EnvelopeSender>,
+    monitor: &Arc<AudioMonitor>,
+    options: AnalysisOptions,
+    input_gain: &Arc<AtomicU32>,
+    probe: &Arc<LatencyProbe>,
+    bus: &EventBus,
+) -> Option<RunningSource> {
+    let name = source.describe();
+    let sink = AudioSink {
+        sender: sender.clone(),
+        monitor: monitor.clone(),
+        options,
+        analysis: None,
+        bus: bus.clone(),
+        probe: probe.clone(),
+        click_remaining: 0,
+        gain: 1.0,
+        monitored: true,
+        mix: None,
+        input_gain: input_gain.clone(),
+        last_clip_report: None,
+        rejected: None,
+    };
+    match source.start(sink, bus) {
+        Ok(running) => {
+            bus.publish(AppEvent::Audio(AudioEvent::StreamOpened {
+                device: name,
+                sample_rate: running.format.sample_rate,
+                channels: running.format.channels,
+            }));
+            Some(running)
+        }
+        Err(e) => {
+            bus.publish(AppEvent::Audio(AudioEvent::StreamFailed {
+                device: name,
+                reason: e.to_string(),
+            }));
+            None
+        }
+    }
+}
+
+/// Applies our analysis filter to a block of mono samples, returning the filtered block.
+/// Envelope followers then measure its level, with the vibration loop mapping that onto an intensity.
+fn audio_transform_fn(direct_values: &[f32], filter: &mut AnalysisFilter) -> Vec<f32> {
+    direct_values
+        .iter()
+        .map(|sample| filter.run(sample.clamp(-1.0, 1.0)))
+        .collect()
+}
@@ -0,0 +1,117 @@
+use std::net::UdpSocket;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use super::{AudioFormat, AudioSink, AudioSource, RunningSource, StopOnDrop};
+use crate::events::{AppEvent, AudioEvent, EventBus};
+
+/// The largest datagram we'll accept, comfortably above common MTUs.
+const MAX_DATAGRAM: usize = 65536;
+
+/// The RTP version we understand, per RFC 3550.
+const RTP_VERSION: u8 = 2;
+
+/// Listens for raw, interleaved 16-bit little-endian PCM sent over UDP,
+/// e.g. `ffmpeg -f s16le udp://host:port`, or RTP carrying L16 audio.
+pub struct NetworkSource {
+    address: String,
+    format: AudioFormat,
+    rtp: bool,
+}
+
+impl NetworkSource {
+    pub fn new(address: String, format: AudioFormat) -> Self {
+        Self {
+            address,
+            format,
+            rtp: false,
+        }
+    }
+
+    /// Expects RTP packets carrying big-endian L16 audio (RFC 3551), e.g. `ffmpeg -c:a pcm_s16be -f rtp rtp://host:port`.
+    pub fn with_rtp(mut self, rtp: bool) -> Self {
+        self.rtp = rtp;
+        self
+    }
+}
+
+/// The audio carried by an RTP packet, or `None` if it isn't one.
+fn rtp_payload(packet: &[u8]) -> Option<&[u8]> {
+    let first = *packet.first()?;
+    if first >> 6 != RTP_VERSION {
+        return None;
+    }
+    let csrc_count = (first & 0x0f) as usize;
+    let mut start = 12 + 4 * csrc_count;
+    if first & 0x10 != 0 {
+        let extension = packet.get(start + 2..start + 4)?;
+        start += 4 + 4 * u16::from_be_bytes([extension[0], extension[1]]) as usize;
+    }
+    let mut end = packet.len();
+    if first & 0x20 != 0 {
+        end = end.checked_sub(*packet.last()? as usize)?;
+    }
+    packet.get(start..end)
+}
+
+impl AudioSource for NetworkSource {
+    fn describe(&self) -> String {
+        format!("network {}", self.address)
+    }
+
+    fn start(
+        self: Box<Self>,
+        mut sink: AudioSink,
+        bus: &EventBus,
+    ) -> anyhow::Result<RunningSource> {
+        let socket = UdpSocket::bind(&self.address)?;
+        // Waking up periodically lets us notice when we've been asked to stop.
+        socket.set_read_timeout(Some(Duration::from_millis(100)))?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let format = self.format;
+        let rtp = self.rtp;
+        let bus = bus.clone();
+        thread::spawn(move || {
+            let mut datagram = vec![0u8; MAX_DATAGRAM];
+            while !thread_stop.load(Ordering::Relaxed) {
+                let length = match socket.recv(&mut datagram) {
+                    Ok(length) => length,
+                    Err(e)
+                        if matches!(
+                            e.kind(),
+                            std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                        ) =>
+                    {
+                        continue;
+                    }
+                    Err(e) => {
+                        bus.publish(AppEvent::Audio(AudioEvent::StreamError(e.to_string())));
+                        break;
+                    }
+                };
+                let samples: Vec<f32> = if rtp {
+                    // Anything that isn't RTP, such as RTCP sent to the same port, is ignored.
+                    let Some(payload) = rtp_payload(&datagram[..length]) else {
+                        continue;
+                    };
+                    payload
+                        .chunks_exact(2)
+                        .map(|b| i16::from_be_bytes([b[0], b[1]]) as f32 / 32768.0)
+                        .collect()
+                } else {
+                    datagram[..length]
+                        .chunks_exact(2)
+                        .map(|b| i16::from_le_bytes([b[0], b[1]]) as f32 / 32768.0)
+                        .collect()
+                };
+                sink.push(format, &samples);
+            }
+        });
+
+        Ok(RunningSource::new(format, StopOnDrop(stop)))
+    }
+}
@@ -0,0 +1,119 @@
+use anyhow::bail;
+use cpal::{
+    Device, SampleFormat,
+    traits::{DeviceTrait, StreamTrait},
+};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use super::{AudioFormat, AudioSink, AudioSource, RunningSource, read_audio};
+use crate::events::{AppEvent, AudioEvent, EventBus};
+
+/// Plays an audio file through an output device, analyzing exactly what is heard.
+///
+/// This is handy for trying settings out against a particular song,
+/// without having to play it from somewhere else at the same time.
+/// As we play it ourselves, it can also be analyzed ahead of what's heard.
+pub struct PlaybackSource {
+    path: PathBuf,
+    device: Device,
+    format: AudioFormat,
+    samples: Vec<f32>,
+    lookahead: Duration,
+}
+
+impl PlaybackSource {
+    /// Reads and decodes the entire file up front.
+    pub fn open(path: &Path, device: Device) -> anyhow::Result<Self> {
+        let (format, samples) = read_audio(path)?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            device,
+            format,
+            samples,
+            lookahead: Duration::ZERO,
+        })
+    }
+
+    /// Analyzes audio `lookahead` before it's heard, so that commands reach devices early
+    /// enough to make up for their latency.
+    pub fn with_lookahead(mut self, lookahead: Duration) -> Self {
+        self.lookahead = lookahead;
+        self
+    }
+}
+
+impl AudioSource for PlaybackSource {
+    fn describe(&self) -> String {
+        format!("playing {}", self.path.display())
+    }
+
+    fn start(
+        self: Box<Self>,
+        mut sink: AudioSink,
+        bus: &EventBus,
+    ) -> anyhow::Result<RunningSource> {
+        let supported = self.device.default_output_config()?;
+        if supported.sample_format() != SampleFormat::F32 {
+            bail!(
+                "unsupported output sample format {:?}",
+                supported.sample_format()
+            );
+        }
+        let config = supported.config();
+        let output = AudioFormat {
+            sample_rate: config.sample_rate.0,
+            channels: config.channels,
+        };
+
+        // The device rarely shares the file's format, so we resample and remap channels as we go.
+        let file = self.format;
+        let samples = self.samples;
+        let frames = samples.len() / file.channels as usize;
+        let step = file.sample_rate as f64 / output.sample_rate as f64;
+        let mut position = 0.0;
+        let ahead = self.lookahead.as_secs_f64() * file.sample_rate as f64;
+        let mut analyzed = Vec::new();
+        let sample = move |frame: usize, channel: usize| {
+            let channel = channel.min(file.channels as usize - 1);
+            samples
+                .get(frame * file.channels as usize + channel)
+                .copied()
+                .unwrap_or(0.0)
+        };
+
+        let bus = bus.clone();
+        let stream = self.device.build_output_stream(
+            &config,
+            move |data: &mut [f32], _| {
+                analyzed.resize(data.len(), 0.0);
+                let interpolate = |position: f64, channel: usize| {
+                    let whole = position as usize;
+                    let fraction = (position - whole as f64) as f32;
+                    let (a, b) = (sample(whole, channel), sample(whole + 1, channel));
+                    a + (b - a) * fraction
+                };
+                for (frame, analyzed) in data
+                    .chunks_exact_mut(output.channels as usize)
+                    .zip(analyzed.chunks_exact_mut(output.channels as usize))
+                {
+                    for (channel, (out, analyzed)) in
+                        frame.iter_mut().zip(analyzed.iter_mut()).enumerate()
+                    {
+                        *out = interpolate(position, channel);
+                        *analyzed = interpolate(position + ahead, channel);
+                    }
+                    // Once the file ends, silence plays on.
+                    if (position as usize) < frames {
+                        position += step;
+                    }
+                }
+                sink.push(output, &analyzed);
+            },
+            move |err| bus.publish(AppEvent::Audio(AudioEvent::StreamError(err.to_string()))),
+            None,
+        )?;
+        stream.play()?;
+        Ok(RunningSource::new(output, stream))
+    }
+}
@@ -0,0 +1,238 @@
+use anyhow::Context;
+use std::io::Read;
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use super::{AudioFormat, AudioSink, AudioSource, RunningSource};
+use crate::events::{AppEvent, AudioEvent, EventBus};
+
+/// The format we ask `parec` to deliver, regardless of the monitored sink's own.
+const FORMAT: AudioFormat = AudioFormat {
+    sample_rate: 48000,
+    channels: 2,
+};
+
+/// Captures a PulseAudio or PipeWire monitor source, which carries whatever is playing on its sink,
+/// or a single application's playback stream.
+///
+/// ALSA doesn't surface monitor sources at all, so recording is handed off to `parec`,
+/// which PipeWire's Pulse compatibility layer provides as well.
+pub struct PulseMonitorSource {
+    target: Target,
+}
+
+enum Target {
+    Device(String),
+    /// A sink input, i.e. a stream from one application.
+    Stream {
+        index: u32,
+        application: String,
+    },
+}
+
+impl PulseMonitorSource {
+    pub fn new(name: String) -> Self {
+        Self {
+            target: Target::Device(name),
+        }
+    }
+
+    /// Captures whichever stream `application` is currently playing.
+    ///
+    /// Streams come and go as an application starts and stops playing,
+    /// so the application's name is looked up afresh each time.
+    pub fn application(application: &str) -> anyhow::Result<Self> {
+        let index = list_application_streams()
+            .into_iter()
+            .find(|(_, name)| name == application)
+            .map(|(index, _)| index)
+            .with_context(|| format!("{} isn't playing anything", application))?;
+        Ok(Self {
+            target: Target::Stream {
+                index,
+                application: application.to_string(),
+            },
+        })
+    }
+}
+
+/// Kills `parec` once dropped, which in turn ends the reading thread.
+struct KillOnDrop {
+    child: Arc<Mutex<Child>>,
+    /// Set before killing `parec`, so that the reading thread doesn't report it as having died.
+    stopped: Arc<AtomicBool>,
+}
+
+impl Drop for KillOnDrop {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::Relaxed);
+        let mut child = self.child.lock().unwrap();
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+}
+
+/// Describes why `parec` stopped delivering audio, from its exit status and whatever it printed.
+fn describe_exit(child: &Mutex<Child>, stderr: &str) -> String {
+    let mut reason = match child.lock().unwrap().try_wait() {
+        Ok(Some(status)) => format!("parec exited ({})", status),
+        _ => "parec exited".to_string(),
+    };
+    let stderr = stderr.trim();
+    if !stderr.is_empty() {
+        reason.push_str(": ");
+        reason.push_str(stderr);
+    }
+    reason
+}
+
+impl AudioSource for PulseMonitorSource {
+    fn describe(&self) -> String {
+        match &self.target {
+            Target::Device(name) => format!("monitor {}", name),
+            Target::Stream { application, .. } => format!("application {}", application),
+        }
+    }
+
+    fn start(
+        self: Box<Self>,
+        mut sink: AudioSink,
+        bus: &EventBus,
+    ) -> anyhow::Result<RunningSource> {
+        let target = match &self.target {
+            Target::Device(name) => format!("--device={}", name),
+            Target::Stream { index, .. } => format!("--monitor-stream={}", index),
+        };
+        let mut child = Command::new("parec")
+            .arg(target)
+            .arg("--format=float32le")
+            .arg(format!("--rate={}", FORMAT.sample_rate))
+            .arg(format!("--channels={}", FORMAT.channels))
+            .arg("--raw")
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .context("failed to run parec")?;
+        let mut stdout = child.stdout.take().context("parec has no output")?;
+        // Whatever parec prints is kept for explaining why it exited, read on its own so that it never blocks parec.
+        let mut stderr = child.stderr.take().context("parec has no error output")?;
+        let errors = thread::spawn(move || {
+            let mut errors = String::new();
+            let _ = stderr.read_to_string(&mut errors);
+            errors
+        });
+        let guard = KillOnDrop {
+            child: Arc::new(Mutex::new(child)),
+            stopped: Arc::new(AtomicBool::new(false)),
+        };
+
+        let bus = bus.clone();
+        let (child, stopped) = (guard.child.clone(), guard.stopped.clone());
+        thread::spawn(move || {
+            // Roughly 10 ms at a time, so as not to add latency of our own.
+            let mut bytes = vec![0u8; 480 * FORMAT.channels as usize * 4];
+            let mut filled = 0;
+            loop {
+                match stdout.read(&mut bytes[filled..]) {
+                    // Unless we stopped it ourselves, parec exiting means capture has silently ended,
+                    // such as when the monitored sink goes away, and should be recovered from.
+                    Ok(0) => {
+                        if !stopped.load(Ordering::Relaxed) {
+                            let errors = errors.join().unwrap_or_default();
+                            bus.publish(AppEvent::Audio(AudioEvent::StreamError(describe_exit(
+                                &child, &errors,
+                            ))));
+                        }
+                        break;
+                    }
+                    Ok(length) => filled += length,
+                    Err(e) => {
+                        bus.publish(AppEvent::Audio(AudioEvent::StreamError(e.to_string())));
+                        break;
+                    }
+                }
+                // Only whole samples can be decoded; any remainder waits for the next read.
+                let whole = filled - filled % 4;
+                let samples: Vec<f32> = bytes[..whole]
+                    .chunks_exact(4)
+                    .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                    .collect();
+                sink.push(FORMAT, &samples);
+                bytes.copy_within(whole..filled, 0);
+                filled -= whole;
+            }
+        });
+
+        Ok(RunningSource::new(FORMAT, guard))
+    }
+}
+
+/// Runs `pactl` with `args`, returning its output if it succeeded.
+pub(super) fn pactl(args: &[&str]) -> Option<String> {
+    // Output is parsed, so it mustn't be translated.
+    let output = Command::new("pactl")
+        .args(args)
+        .env("LC_ALL", "C")
+        .output()
+        .ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Lists PulseAudio or PipeWire monitor sources, one for each sink.
+///
+/// This is empty wherever `pactl` isn't available, such as outside of Linux.
+pub fn list_monitor_sources() -> Vec<String> {
+    let Some(sources) = pactl(&["list", "short", "sources"]) else {
+        return Vec::new();
+    };
+    let mut monitors: Vec<String> = sources
+        .lines()
+        .filter_map(|line| line.split('\t').nth(1))
+        .filter(|name| name.ends_with(".monitor"))
+        .map(str::to_string)
+        .collect();
+    monitors.sort();
+    monitors
+}
+
+/// The monitor source of the default sink, if there is one.
+pub fn default_monitor_source() -> Option<String> {
+    let sink = pactl(&["get-default-sink"])?;
+    let monitor = format!("{}.monitor", sink.trim());
+    list_monitor_sources()
+        .into_iter()
+        .find(|name| *name == monitor)
+}
+
+/// Lists applications currently playing audio, alongside the index of their stream.
+pub fn list_application_streams() -> Vec<(u32, String)> {
+    let Some(inputs) = pactl(&["list", "sink-inputs"]) else {
+        return Vec::new();
+    };
+    let mut streams = Vec::new();
+    let mut index = None;
+    for line in inputs.lines() {
+        let line = line.trim();
+        if let Some(number) = line.strip_prefix("Sink Input #") {
+            index = number.parse().ok();
+        } else if let Some(name) = line.strip_prefix("application.name = ")
+            && let Some(index) = index.take()
+        {
+            streams.push((index, name.trim_matches('"').to_string()));
+        }
+    }
+    streams
+}
+
+/// Prints available monitor sources alongside their index.
+pub fn print_monitor_sources(monitors: &[String]) {
+    monitors
+        .iter()
+        .enumerate()
+        .for_each(|(i, name)| println!("  [{}] {}", i, name));
+}
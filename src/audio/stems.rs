@@ -0,0 +1,147 @@
+use anyhow::Context;
+use ort::session::Session;
+use ort::value::Tensor;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+
+/// How many samples the model separates at once, about 85 ms at our processing rate.
+/// Analysis runs this far behind the audio while separating.
+const CHUNK_LEN: usize = 4096;
+/// How many chunks may await separation before the model is taken to be too slow to keep up.
+const MAX_PENDING_CHUNKS: usize = 4;
+
+/// ONNX Runtime's library, loaded when first separating unless `ORT_DYLIB_PATH` names another.
+#[cfg(target_os = "windows")]
+const RUNTIME_LIBRARY: &str = "onnxruntime.dll";
+#[cfg(target_os = "macos")]
+const RUNTIME_LIBRARY: &str = "libonnxruntime.dylib";
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+const RUNTIME_LIBRARY: &str = "libonnxruntime.so";
+
+/// Isolates the bass and drums from the rest of the mix with an ONNX source separation model,
+/// so that male vocals and kick bleed stop reaching the filter.
+///
+/// Models take a mono waveform at our processing rate, shaped `[1, 1, samples]`, and return the isolated stem
+/// in the same shape. They run on their own thread, so that loading and inference never hold up capture.
+/// Should the model fail, or fall behind, audio is analyzed as it is instead.
+pub struct StemSeparator {
+    model: PathBuf,
+    chunks: mpsc::Sender<Vec<f32>>,
+    separated: mpsc::Receiver<Vec<f32>>,
+    /// Samples that don't yet fill a chunk.
+    pending: Vec<f32>,
+    /// How many chunks have been sent for separation without coming back yet.
+    in_flight: usize,
+    /// Whether the model has stopped working, or fallen behind, which is only reported once.
+    failed: bool,
+}
+
+impl StemSeparator {
+    pub fn new(model: &Path) -> Self {
+        let (chunks, input) = mpsc::channel::<Vec<f32>>();
+        let (output, separated) = mpsc::channel();
+        let path = model.to_path_buf();
+        thread::spawn(move || {
+            let mut session = match load(&path) {
+                Ok(session) => session,
+                Err(e) => {
+                    println!("Stem separation is unavailable: {:#}", e);
+                    return;
+                }
+            };
+            for chunk in input {
+                match separate(&mut session, chunk) {
+                    Ok(stem) => {
+                        if output.send(stem).is_err() {
+                            return;
+                        }
+                    }
+                    Err(e) => {
+                        println!("Stem separation failed: {:#}", e);
+                        return;
+                    }
+                }
+            }
+        });
+        Self {
+            model: model.to_path_buf(),
+            chunks,
+            separated,
+            pending: Vec::with_capacity(CHUNK_LEN),
+            in_flight: 0,
+            failed: false,
+        }
+    }
+
+    /// Feeds mono samples, returning whatever has been separated since the previous call.
+    pub fn process(&mut self, samples: &[f32]) -> Vec<f32> {
+        if self.failed {
+            return samples.to_vec();
+        }
+        self.pending.extend_from_slice(samples);
+        let mut separated: Vec<f32> = self.separated.try_iter().flatten().collect();
+        self.in_flight -= separated.len().div_ceil(CHUNK_LEN).min(self.in_flight);
+        while self.pending.len() >= CHUNK_LEN {
+            let rest = self.pending.split_off(CHUNK_LEN);
+            let chunk = std::mem::replace(&mut self.pending, rest);
+            if self.in_flight >= MAX_PENDING_CHUNKS {
+                self.fail("it can't keep up with the audio");
+                separated.extend(chunk);
+                continue;
+            }
+            // Only once the thread has given up does sending fail.
+            match self.chunks.send(chunk) {
+                Ok(()) => self.in_flight += 1,
+                Err(mpsc::SendError(chunk)) => {
+                    self.fail("the model stopped working");
+                    separated.extend(chunk);
+                }
+            }
+        }
+        if self.failed {
+            separated.append(&mut self.pending);
+        }
+        separated
+    }
+
+    fn fail(&mut self, reason: &str) {
+        if !self.failed {
+            println!(
+                "Analyzing audio without separating stems, as {} ({}).",
+                reason,
+                self.model.display()
+            );
+            self.failed = true;
+        }
+    }
+}
+
+/// Loads ONNX Runtime, should it not be already, and then the model at `path`.
+fn load(path: &Path) -> anyhow::Result<Session> {
+    // ort panics should the runtime be missing once it's used, so it's loaded beforehand, where that can fail gracefully.
+    let runtime = std::env::var_os("ORT_DYLIB_PATH")
+        .filter(|runtime| !runtime.is_empty())
+        .map_or_else(|| PathBuf::from(RUNTIME_LIBRARY), PathBuf::from);
+    ort::init_from(&runtime)
+        .with_context(|| format!("unable to load ONNX Runtime from {}", runtime.display()))?
+        .commit();
+    Session::builder()
+        .and_then(|mut builder| builder.commit_from_file(path))
+        .with_context(|| format!("unable to load {}", path.display()))
+}
+
+/// Runs the model over a chunk of samples, returning the isolated stem.
+fn separate(session: &mut Session, chunk: Vec<f32>) -> anyhow::Result<Vec<f32>> {
+    let len = chunk.len();
+    let input = Tensor::from_array(([1, 1, len], chunk))?;
+    let outputs = session.run(ort::inputs![input])?;
+    let (_, stem) = outputs[0].try_extract_tensor::<f32>()?;
+    anyhow::ensure!(
+        stem.len() >= len,
+        "the model returned {} samples for {}",
+        stem.len(),
+        len
+    );
+    Ok(stem[..len].to_vec())
+}
@@ -0,0 +1,127 @@
+use clap::ValueEnum;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::f32::consts::TAU;
+
+use super::{AudioFormat, AudioSink, AudioSource, RunningSource, spawn_paced};
+use crate::events::EventBus;
+
+/// The range a sweep covers, in Hz, spanning the bass band and a little beyond.
+const SWEEP_RANGE: (f32, f32) = (20.0, 200.0);
+/// How long a single sweep across the range takes, in seconds.
+const SWEEP_SECS: f32 = 10.0;
+
+/// What a synthetic source generates.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TestSignal {
+    /// A low tone pulsing on and off, to check that devices respond.
+    #[default]
+    Pulse,
+    /// A steady low tone, for calibrating against a constant level.
+    Tone,
+    /// A sine sweeping from 20 Hz to 200 Hz and back, to hear where the cutoff sits.
+    Sweep,
+    /// Pink noise, whose energy is spread evenly across octaves much like music's.
+    PinkNoise,
+}
+
+/// Generates a test signal, handy for checking that devices respond without having to play anything.
+pub struct SyntheticSource {
+    pub signal: TestSignal,
+    /// Frequency of the tone itself, in Hz, for pulses and steady tones.
+    pub frequency: f32,
+    /// How many times per second the tone pulses on.
+    pub pulse_rate: f32,
+    pub amplitude: f32,
+}
+
+impl SyntheticSource {
+    pub fn new(signal: TestSignal) -> Self {
+        Self {
+            signal,
+            ..Self::default()
+        }
+    }
+}
+
+impl Default for SyntheticSource {
+    fn default() -> Self {
+        Self {
+            signal: TestSignal::default(),
+            frequency: 50.0,
+            pulse_rate: 2.0,
+            amplitude: 0.5,
+        }
+    }
+}
+
+/// Filters white noise into pink noise, using Paul Kellet's economy filter.
+#[derive(Default)]
+struct PinkFilter {
+    b: [f32; 3],
+}
+
+impl PinkFilter {
+    fn run(&mut self, white: f32) -> f32 {
+        self.b[0] = 0.99765 * self.b[0] + white * 0.0990460;
+        self.b[1] = 0.96300 * self.b[1] + white * 0.2965164;
+        self.b[2] = 0.57000 * self.b[2] + white * 1.0526913;
+        // The filter's gain is roughly 4, so we scale back to roughly unity.
+        (self.b[0] + self.b[1] + self.b[2] + white * 0.1848) * 0.25
+    }
+}
+
+impl AudioSource for SyntheticSource {
+    fn describe(&self) -> String {
+        match self.signal {
+            TestSignal::Pulse => format!("synthetic {} Hz pulse", self.frequency),
+            TestSignal::Tone => format!("synthetic {} Hz tone", self.frequency),
+            TestSignal::Sweep => "synthetic sweep".to_string(),
+            TestSignal::PinkNoise => "synthetic pink noise".to_string(),
+        }
+    }
+
+    fn start(self: Box<Self>, sink: AudioSink, _bus: &EventBus) -> anyhow::Result<RunningSource> {
+        let format = AudioFormat {
+            sample_rate: 48000,
+            channels: 1,
+        };
+        let step = 1.0 / format.sample_rate as f32;
+        let mut time = 0.0f32;
+        // Sweeps change frequency continuously, so their phase is accumulated rather than derived from time.
+        let mut phase = 0.0f32;
+        let mut rng = StdRng::from_os_rng();
+        let mut pink = PinkFilter::default();
+        Ok(spawn_paced(format, sink, move |block| {
+            for sample in block.iter_mut() {
+                *sample = match self.signal {
+                    TestSignal::Pulse => {
+                        // The tone is present for the first half of every pulse.
+                        let gate = if (time * self.pulse_rate).fract() < 0.5 {
+                            1.0
+                        } else {
+                            0.0
+                        };
+                        (TAU * self.frequency * time).sin() * gate
+                    }
+                    TestSignal::Tone => (TAU * self.frequency * time).sin(),
+                    TestSignal::Sweep => {
+                        // Sweeping logarithmically spends equal time in each octave.
+                        let progress = (time / SWEEP_SECS).fract();
+                        let there_and_back = 1.0 - (2.0 * progress - 1.0).abs();
+                        let (low, high) = SWEEP_RANGE;
+                        let frequency = low * (high / low).powf(there_and_back);
+                        phase = (phase + frequency * step).fract();
+                        (TAU * phase).sin()
+                    }
+                    TestSignal::PinkNoise => pink.run(rng.random_range(-1.0..=1.0)),
+                } * self.amplitude;
+                // Wrapping keeps precision from degrading over long sessions.
+                time = (time + step) % 1000.0;
+            }
+            true
+        }))
+    }
+}
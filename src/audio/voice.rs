@@ -0,0 +1,77 @@
+use crate::dsp::{BUTTERWORTH_Q, Biquad};
+
+/// The band most speech energy falls within, as carried by a telephone line.
+pub(super) const SPEECH_LOW_HZ: f32 = 300.0;
+pub(super) const SPEECH_HIGH_HZ: f32 = 3400.0;
+
+/// How far above the noise floor (as an amplitude ratio, roughly 10 dB) speech must be.
+const ACTIVATION_RATIO: f32 = 3.0;
+/// Anything quieter than this is never considered speech.
+const MIN_LEVEL: f32 = 0.005;
+/// How long speech is considered to continue after it was last detected, bridging gaps between words.
+const HANGOVER_SECS: f32 = 0.3;
+/// How quickly the noise floor follows rising and falling levels.
+const FLOOR_RISE_SECS: f32 = 5.0;
+const FLOOR_FALL_SECS: f32 = 0.05;
+
+/// Detects speech, measuring its energy within the speech band.
+///
+/// This is a simple energy-based detector: speech is whatever rises well above a slowly adapting noise floor.
+pub struct VoiceDetector {
+    sample_rate: f32,
+    highpass: Biquad,
+    lowpass: Biquad,
+    noise_floor: f32,
+    hangover: f32,
+}
+
+impl VoiceDetector {
+    pub fn new(sample_rate: f32) -> Self {
+        Self {
+            sample_rate,
+            highpass: Biquad::highpass(sample_rate, SPEECH_LOW_HZ, BUTTERWORTH_Q),
+            lowpass: Biquad::lowpass(sample_rate, SPEECH_HIGH_HZ, BUTTERWORTH_Q),
+            noise_floor: MIN_LEVEL,
+            hangover: 0.0,
+        }
+    }
+
+    /// Filters a block of mono samples down to the speech band, returning the filtered block
+    /// alongside its level if speech is present, or zero otherwise.
+    pub fn process(&mut self, direct_values: &[f32]) -> (Vec<f32>, f64) {
+        let band: Vec<f32> = direct_values
+            .iter()
+            .map(|sample| self.lowpass.run(self.highpass.run(sample.clamp(-1.0, 1.0))))
+            .collect();
+        if band.is_empty() {
+            return (band, 0.0);
+        }
+        let level =
+            (band.iter().map(|sample| sample * sample).sum::<f32>() / band.len() as f32).sqrt();
+        let block_secs = band.len() as f32 / self.sample_rate;
+
+        let speaking = level > MIN_LEVEL && level > self.noise_floor * ACTIVATION_RATIO;
+        if speaking {
+            self.hangover = HANGOVER_SECS;
+        } else {
+            self.hangover = (self.hangover - block_secs).max(0.0);
+        }
+
+        // The floor only rises slowly, so that speech itself barely lifts it.
+        let time_constant = if level > self.noise_floor {
+            FLOOR_RISE_SECS
+        } else {
+            FLOOR_FALL_SECS
+        };
+        let alpha = (block_secs / time_constant).min(1.0);
+        self.noise_floor =
+            (self.noise_floor + (level - self.noise_floor) * alpha).max(MIN_LEVEL / 2.0);
+
+        let envelope = if self.hangover > 0.0 {
+            level as f64
+        } else {
+            0.0
+        };
+        (band, envelope)
+    }
+}
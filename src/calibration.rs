@@ -0,0 +1,126 @@
+use std::time::Duration;
+
+use crate::mapping::IntensityMapping;
+
+/// How long the noise floor is measured for, with nothing playing.
+const NOISE_DURATION: Duration = Duration::from_secs(3);
+/// How long typical music is measured for.
+const MUSIC_DURATION: Duration = Duration::from_secs(10);
+/// How long we wait for music to start before giving up.
+const MUSIC_TIMEOUT: Duration = Duration::from_secs(60);
+/// Music is considered to have started once it's this many times louder than the noise floor.
+const MUSIC_THRESHOLD: f64 = 4.0;
+/// Typical peaks are mapped to this intensity, leaving headroom for louder passages.
+const TARGET_PEAK: f64 = 0.9;
+/// The deadzone sits this far above the mapped noise floor.
+const DEADZONE_MARGIN: f64 = 1.5;
+
+/// How far along a calibration is.
+#[derive(Clone, Debug)]
+pub enum CalibrationProgress {
+    /// Measuring the noise floor; nothing should be playing.
+    MeasuringNoise,
+    /// Waiting for typical music to start playing.
+    WaitingForMusic,
+    MeasuringMusic,
+    /// Calibration succeeded, producing this mapping.
+    Finished(IntensityMapping),
+    Failed(String),
+}
+
+enum Phase {
+    Noise,
+    Waiting,
+    Music,
+}
+
+/// Derives gain and deadzone from what the envelope looks like in silence, and then with music playing.
+pub struct Calibration {
+    base: IntensityMapping,
+    phase: Phase,
+    elapsed: Duration,
+    noise: Vec<f64>,
+    noise_floor: f64,
+    music: Vec<f64>,
+}
+
+impl Calibration {
+    /// Begins calibrating. The ceiling of `base` is kept as-is, as it's a limit the user chose deliberately.
+    pub fn new(base: IntensityMapping) -> Self {
+        Self {
+            base,
+            phase: Phase::Noise,
+            elapsed: Duration::ZERO,
+            noise: Vec::new(),
+            noise_floor: 0.0,
+            music: Vec::new(),
+        }
+    }
+
+    /// Records an envelope value observed `elapsed` after the previous one,
+    /// returning progress whenever the calibration moves on.
+    pub fn record(&mut self, envelope: f64, elapsed: Duration) -> Option<CalibrationProgress> {
+        let envelope = if envelope.is_finite() {
+            envelope.abs()
+        } else {
+            0.0
+        };
+        self.elapsed += elapsed;
+        match self.phase {
+            Phase::Noise => {
+                self.noise.push(envelope);
+                if self.elapsed < NOISE_DURATION {
+                    return None;
+                }
+                self.noise_floor = percentile(&mut self.noise, 0.95);
+                self.phase = Phase::Waiting;
+                self.elapsed = Duration::ZERO;
+                Some(CalibrationProgress::WaitingForMusic)
+            }
+            Phase::Waiting => {
+                if envelope > (self.noise_floor * MUSIC_THRESHOLD).max(f64::EPSILON) {
+                    self.phase = Phase::Music;
+                    self.elapsed = Duration::ZERO;
+                    Some(CalibrationProgress::MeasuringMusic)
+                } else if self.elapsed >= MUSIC_TIMEOUT {
+                    Some(CalibrationProgress::Failed(
+                        "no music was detected".to_string(),
+                    ))
+                } else {
+                    None
+                }
+            }
+            Phase::Music => {
+                self.music.push(envelope);
+                if self.elapsed < MUSIC_DURATION {
+                    return None;
+                }
+                Some(self.finish())
+            }
+        }
+    }
+
+    fn finish(&mut self) -> CalibrationProgress {
+        let peak = percentile(&mut self.music, 0.95);
+        if peak <= self.noise_floor {
+            return CalibrationProgress::Failed("music was no louder than silence".to_string());
+        }
+        let gain = TARGET_PEAK / peak;
+        let deadzone = (self.noise_floor * gain * DEADZONE_MARGIN).clamp(0.0, 0.5);
+        CalibrationProgress::Finished(IntensityMapping {
+            gain,
+            deadzone,
+            ..self.base
+        })
+    }
+}
+
+/// The value below which `fraction` of `values` fall.
+fn percentile(values: &mut [f64], fraction: f64) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    values.sort_by(f64::total_cmp);
+    let index = ((values.len() - 1) as f64 * fraction).round() as usize;
+    values[index]
+}
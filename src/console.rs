@@ -0,0 +1,439 @@
+use clap::ValueEnum;
+use std::io::{BufRead, stdin};
+use std::thread;
+use std::time::Duration;
+
+use crate::audio::{CUTOFF_RANGE_HZ, FilterSlope, FilterType, Genre, TestSignal};
+use crate::events::{AppEvent, EventBus};
+use crate::mapping::ResponseCurve;
+use crate::patterns::{Metronome, Pattern, ResponseMode};
+use crate::state::GenreMode;
+
+/// A command typed into the console while subwoofer is running.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    Help,
+    /// Lists output devices that can be monitored.
+    ListAudio,
+    /// Switches the monitored output device to the given index.
+    SwitchAudio(usize),
+    /// Lists input devices, such as microphones, that can be captured.
+    ListInputs,
+    /// Switches to capturing the input device at the given index.
+    SwitchInput(usize),
+    /// Lists PulseAudio or PipeWire monitor sources that can be captured.
+    ListMonitors,
+    /// Switches to capturing the monitor source at the given index.
+    SwitchMonitor(usize),
+    /// Lists applications currently playing audio.
+    ListApplications,
+    /// Switches to capturing only what the named application plays.
+    SwitchApplication(String),
+    /// Lists virtual cables that audio can be routed through, and how to route it.
+    ListCables,
+    /// Creates a null sink to route audio through, where the platform allows it.
+    CreateCable,
+    /// Switches to analyzing a file, a UDP stream on the given address, or a test tone.
+    SwitchSource(SourceRequest),
+    /// Lists built-in patterns.
+    ListPatterns,
+    /// Drives devices with a built-in pattern instead of audio, or returns to audio given `None`.
+    SetPattern(Option<Pattern>),
+    /// Pulses devices at the given tempo instead of following audio, or stops given `None`.
+    SetMetronome(Option<f64>),
+    /// Shows the current genre profile.
+    ShowGenre,
+    /// Changes whether devices follow the audio's level, or pulse on its onsets.
+    SetResponse(ResponseMode),
+    /// Shows the tempo detected in the audio.
+    ShowTempo,
+    /// Shows each stage audio passes through, in order.
+    ShowPipeline,
+    /// Changes how the genre profile is chosen.
+    SetGenre(GenreMode),
+    /// Measures silence and then music, deriving gain and deadzone from them.
+    Calibrate,
+    /// Changes how much incoming audio is multiplied by before filtering.
+    SetInputGain(f32),
+    /// Changes what counts as bass, restarting analysis.
+    SetCutoff(u32),
+    SetSlope(FilterSlope),
+    SetFilter(FilterType),
+    SetQ(f32),
+    /// Changes how quickly the envelope rises, restarting analysis.
+    SetAttack(Duration),
+    /// Changes how quickly the envelope falls, restarting analysis.
+    SetRelease(Duration),
+    /// Lists the bands audio is split into, alongside their levels and the devices following each.
+    ListBands,
+    /// Routes the device with the given name to follow a band, or the overall intensity given `None`.
+    RouteBand(Option<String>, String),
+    /// Routes one motor, counting from zero, of the device with the given name to follow a band,
+    /// or whatever the device follows given `None`.
+    RouteMotor(usize, Option<String>, String),
+    /// Lists connected devices alongside their settings.
+    ListDevices,
+    /// Enables or disables the device with the given name.
+    EnableDevice(bool, String),
+    /// Scales the intensity sent to the device with the given name.
+    ScaleDevice(f64, String),
+    /// Caps the intensity every device may be sent, whatever produced it.
+    SetMaxIntensity(f64),
+    /// Changes the response curve, and the gamma exponent if given.
+    SetCurve(ResponseCurve, Option<f64>),
+    /// Opens the editor for the custom curve.
+    EditCurve,
+    /// Remembers the current mapping for the current source, applying it whenever that source is chosen again.
+    Remember,
+    /// Forgets the mapping remembered for the current source.
+    Forget,
+    /// Starts or stops multiplying output by the boost factor.
+    Boost(bool),
+    /// Shows statistics for the session so far.
+    Stats,
+    /// Shows an overview of intensity over the session so far.
+    Heatmap,
+    /// Writes the session's intensity timeline and event log to the given path.
+    Export(String),
+    /// Measures how long audio takes to turn into device commands.
+    MeasureLatency,
+    /// Allows output to be sent to devices.
+    Arm,
+    /// Stops devices immediately, and sends nothing further until armed again.
+    Disarm,
+    /// Stops sending output to devices, while continuing to analyze audio.
+    Pause,
+    Resume,
+    /// Saves the current runtime state.
+    Snapshot,
+    /// Restores the most recently saved runtime state.
+    Restore,
+    Quit,
+}
+
+/// A non-device source requested from the console.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SourceRequest {
+    /// An output device, by name.
+    Device(String),
+    File(String),
+    /// Plays the file through the default output device while analyzing it.
+    Playback(String),
+    Network(String),
+    Synthetic(TestSignal),
+}
+
+impl Command {
+    /// Parses a single line of input, returning a message describing the problem on failure.
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let mut words = line.split_whitespace();
+        let Some(name) = words.next() else {
+            return Err("no command given".to_string());
+        };
+        let argument = words.next();
+
+        match (name, argument) {
+            ("help", _) => Ok(Command::Help),
+            ("audio", None) => Ok(Command::ListAudio),
+            // Anything other than an index is taken as the device's name.
+            ("audio", Some(first)) => match first.parse() {
+                Ok(index) => Ok(Command::SwitchAudio(index)),
+                Err(_) => Ok(Command::SwitchSource(SourceRequest::Device(
+                    std::iter::once(first)
+                        .chain(words)
+                        .collect::<Vec<_>>()
+                        .join(" "),
+                ))),
+            },
+            ("input", None) => Ok(Command::ListInputs),
+            ("input", Some(index)) => index
+                .parse()
+                .map(Command::SwitchInput)
+                .map_err(|_| format!("invalid device index: {}", index)),
+            ("monitor", None) => Ok(Command::ListMonitors),
+            ("monitor", Some(index)) => index
+                .parse()
+                .map(Command::SwitchMonitor)
+                .map_err(|_| format!("invalid monitor index: {}", index)),
+            ("app", None) => Ok(Command::ListApplications),
+            ("app", Some(first)) => Ok(Command::SwitchApplication(
+                std::iter::once(first)
+                    .chain(words)
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            )),
+            ("cable", None) => Ok(Command::ListCables),
+            ("cable", Some("create")) => Ok(Command::CreateCable),
+            ("cable", _) => Err("expected `cable` or `cable create`".to_string()),
+            ("source", Some("file")) => match words.collect::<Vec<_>>().join(" ") {
+                path if !path.is_empty() => Ok(Command::SwitchSource(SourceRequest::File(path))),
+                _ => Err("no file given".to_string()),
+            },
+            ("source", Some("play")) => match words.collect::<Vec<_>>().join(" ") {
+                path if !path.is_empty() => {
+                    Ok(Command::SwitchSource(SourceRequest::Playback(path)))
+                }
+                _ => Err("no file given".to_string()),
+            },
+            ("source", Some("udp")) => match words.next() {
+                Some(address) => Ok(Command::SwitchSource(SourceRequest::Network(
+                    address.to_string(),
+                ))),
+                None => Err("no address given".to_string()),
+            },
+            ("source", Some("synthetic")) => match words.next() {
+                None => Ok(Command::SwitchSource(SourceRequest::Synthetic(
+                    TestSignal::default(),
+                ))),
+                Some(name) => TestSignal::from_str(name, true)
+                    .map(|signal| Command::SwitchSource(SourceRequest::Synthetic(signal)))
+                    .map_err(|_| format!("unknown test signal: {}", name)),
+            },
+            ("source", _) => Err(
+                "expected `source file <path>`, `source play <path>`, `source udp <address>` or `source synthetic`"
+                    .to_string(),
+            ),
+            ("pattern", None) => Ok(Command::ListPatterns),
+            ("pattern", Some("off")) => Ok(Command::SetPattern(None)),
+            ("pattern", Some(name)) => Pattern::from_str(name, true)
+                .map(|pattern| Command::SetPattern(Some(pattern)))
+                .map_err(|_| format!("unknown pattern: {}", name)),
+            ("metronome", Some("off")) => Ok(Command::SetMetronome(None)),
+            ("metronome", Some(bpm)) => match bpm.parse::<f64>() {
+                Ok(bpm) if Metronome::BPM_RANGE.contains(&bpm) => {
+                    Ok(Command::SetMetronome(Some(bpm)))
+                }
+                _ => Err(format!(
+                    "tempo must be between {} and {} BPM",
+                    Metronome::BPM_RANGE.start(),
+                    Metronome::BPM_RANGE.end()
+                )),
+            },
+            ("metronome", None) => Err("expected `metronome <bpm>` or `metronome off`".to_string()),
+            ("genre", None) => Ok(Command::ShowGenre),
+            ("genre", Some("auto")) => Ok(Command::SetGenre(GenreMode::Auto)),
+            ("genre", Some("off")) => Ok(Command::SetGenre(GenreMode::Off)),
+            ("genre", Some(name)) => Genre::from_str(name, true)
+                .map(|genre| Command::SetGenre(GenreMode::Manual(genre)))
+                .map_err(|_| format!("unknown genre: {}", name)),
+            ("calibrate", _) => Ok(Command::Calibrate),
+            ("latency", _) => Ok(Command::MeasureLatency),
+            ("input-gain", Some(gain)) => match gain.parse::<f32>() {
+                Ok(gain) if gain.is_finite() && gain > 0.0 => Ok(Command::SetInputGain(gain)),
+                _ => Err(format!("input gain must be a positive number, not {}", gain)),
+            },
+            ("input-gain", None) => Err("expected `input-gain <factor>`".to_string()),
+            ("max", Some(max)) => match max.parse::<f64>() {
+                Ok(max) if (0.0..=1.0).contains(&max) => Ok(Command::SetMaxIntensity(max)),
+                _ => Err(format!("the maximum must be between 0 and 1, not {}", max)),
+            },
+            ("max", None) => Err("expected `max <intensity>`".to_string()),
+            ("cutoff", Some(hz)) => match hz.parse::<u32>() {
+                Ok(hz) if CUTOFF_RANGE_HZ.contains(&hz) => Ok(Command::SetCutoff(hz)),
+                _ => Err(format!(
+                    "cutoff must be between {} and {} Hz",
+                    CUTOFF_RANGE_HZ.start(),
+                    CUTOFF_RANGE_HZ.end()
+                )),
+            },
+            ("cutoff", None) => Err("expected `cutoff <hz>`".to_string()),
+            ("slope", Some(slope)) => FilterSlope::from_str(slope, true)
+                .map(Command::SetSlope)
+                .map_err(|_| "slope must be 6, 12 or 24 dB per octave".to_string()),
+            ("slope", None) => Err("expected `slope <6|12|24>`".to_string()),
+            ("filter", Some(filter)) => FilterType::from_str(filter, true)
+                .map(Command::SetFilter)
+                .map_err(|_| format!("unknown filter: {}", filter)),
+            ("filter", None) => {
+                Err("expected `filter <lowpass|highpass|bandpass|notch>`".to_string())
+            }
+            ("q", Some(q)) => match q.parse::<f32>() {
+                Ok(q) if q.is_finite() && q > 0.0 => Ok(Command::SetQ(q)),
+                _ => Err(format!("Q must be a positive number, not {}", q)),
+            },
+            ("q", None) => Err("expected `q <value>`".to_string()),
+            ("attack" | "release", Some(ms)) => match ms.parse::<u64>() {
+                Ok(ms) if name == "attack" => Ok(Command::SetAttack(Duration::from_millis(ms))),
+                Ok(ms) => Ok(Command::SetRelease(Duration::from_millis(ms))),
+                Err(_) => Err(format!("expected a number of milliseconds, not {}", ms)),
+            },
+            ("attack" | "release", None) => Err(format!("expected `{} <ms>`", name)),
+            ("bands", _) => Ok(Command::ListBands),
+            ("route", Some(band)) => {
+                let device = words.collect::<Vec<_>>().join(" ");
+                if device.is_empty() {
+                    return Err("expected `route <band|off> <device name>`".to_string());
+                }
+                let band = (band != "off").then(|| band.to_string());
+                Ok(Command::RouteBand(band, device))
+            }
+            ("route", None) => Err("expected `route <band|off> <device name>`".to_string()),
+            ("motor", Some(motor)) => {
+                let usage = "expected `motor <n> <band|off> <device name>`";
+                let motor = match motor.parse::<usize>() {
+                    Ok(motor) if motor >= 1 => motor - 1,
+                    _ => return Err(format!("motors are numbered from 1, not {}", motor)),
+                };
+                let Some(band) = words.next() else {
+                    return Err(usage.to_string());
+                };
+                let device = words.collect::<Vec<_>>().join(" ");
+                if device.is_empty() {
+                    return Err(usage.to_string());
+                }
+                let band = (band != "off").then(|| band.to_string());
+                Ok(Command::RouteMotor(motor, band, device))
+            }
+            ("motor", None) => Err("expected `motor <n> <band|off> <device name>`".to_string()),
+            ("devices", _) => Ok(Command::ListDevices),
+            ("device", Some(action @ ("on" | "off" | "scale"))) => {
+                let multiplier = if action == "scale" {
+                    match words.next().map(|multiplier| multiplier.parse::<f64>()) {
+                        Some(Ok(multiplier)) if multiplier.is_finite() && multiplier >= 0.0 => {
+                            Some(multiplier)
+                        }
+                        _ => return Err("expected `device scale <x> <device name>`".to_string()),
+                    }
+                } else {
+                    None
+                };
+                let device = words.collect::<Vec<_>>().join(" ");
+                if device.is_empty() {
+                    return Err(format!("expected a device name after `device {}`", action));
+                }
+                Ok(match multiplier {
+                    Some(multiplier) => Command::ScaleDevice(multiplier, device),
+                    None => Command::EnableDevice(action == "on", device),
+                })
+            }
+            ("device", _) => Err("expected `device <on|off|scale> ...`".to_string()),
+            ("response", Some(response)) => ResponseMode::from_str(response, true)
+                .map(Command::SetResponse)
+                .map_err(|_| format!("unknown response: {}", response)),
+            ("response", None) => Err("expected `response <follow|pulse>`".to_string()),
+            ("curve", Some("edit")) => Ok(Command::EditCurve),
+            ("curve", Some(curve)) => {
+                let curve = ResponseCurve::from_str(curve, true)
+                    .map_err(|_| format!("unknown curve: {}", curve))?;
+                match words.next().map(str::parse::<f64>) {
+                    None => Ok(Command::SetCurve(curve, None)),
+                    Some(Ok(gamma)) if gamma.is_finite() && gamma > 0.0 => {
+                        Ok(Command::SetCurve(curve, Some(gamma)))
+                    }
+                    Some(_) => Err("gamma must be a positive number".to_string()),
+                }
+            }
+            ("curve", None) => Err("expected `curve <linear|log|exp|gamma|custom> [gamma]` or `curve edit`".to_string()),
+            ("tempo", _) => Ok(Command::ShowTempo),
+            ("pipeline", _) => Ok(Command::ShowPipeline),
+            ("remember", _) => Ok(Command::Remember),
+            ("forget", _) => Ok(Command::Forget),
+            ("boost", Some("on")) => Ok(Command::Boost(true)),
+            ("boost", Some("off")) => Ok(Command::Boost(false)),
+            ("boost", _) => Err("expected `boost on` or `boost off`".to_string()),
+            ("stats", _) => Ok(Command::Stats),
+            ("heatmap", _) => Ok(Command::Heatmap),
+            ("export", Some(first)) => Ok(Command::Export(
+                std::iter::once(first)
+                    .chain(words)
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            )),
+            ("export", None) => Err("no path given".to_string()),
+            ("arm", _) => Ok(Command::Arm),
+            ("disarm", _) => Ok(Command::Disarm),
+            ("pause", _) => Ok(Command::Pause),
+            ("resume", _) => Ok(Command::Resume),
+            ("snapshot", _) => Ok(Command::Snapshot),
+            ("restore", _) => Ok(Command::Restore),
+            ("quit" | "exit", _) => Ok(Command::Quit),
+            _ => Err(format!("unknown command: {}", name)),
+        }
+    }
+}
+
+pub const HELP: &str = "\
+Available commands:
+  help         show this message
+  audio        list output devices that can be monitored
+  audio <n>    switch monitoring to output device <n>
+  audio <name>            switch monitoring to the output device named <name>
+  input        list input devices, such as microphones
+  input <n>    switch to capturing input device <n>
+  monitor      list PulseAudio or PipeWire monitor sources
+  monitor <n>  switch to capturing monitor source <n>
+  app          list applications currently playing audio
+  app <name>   switch to capturing only what application <name> plays
+  cable        find virtual cables and explain how to route audio through one
+  cable create            create a null sink to route audio through (Linux)
+  source file <path>      analyze a WAV, FLAC or MP3 file instead
+  source play <path>      play a WAV, FLAC or MP3 file through the default output device, analyzing it as it plays
+  source udp <address>    analyze raw 16-bit PCM received over UDP instead
+  source synthetic [signal]  analyze a test signal instead: pulse, tone, sweep or pink-noise
+  pattern      list built-in patterns
+  pattern <name>          play a built-in pattern instead of audio
+  pattern off             return to following audio
+  metronome <bpm>         pulse on the beat at <bpm> instead of audio
+  metronome off           stop the metronome
+  genre        show the current genre profile
+  genre auto              detect the genre and apply a matching profile
+  genre <name>            always apply the profile for <name>
+  genre off               apply no genre profile
+  calibrate    derive gain and deadzone from silence, then typical music
+  input-gain <x>          multiply incoming audio by <x> before filtering
+  max <x>                 never send any device more than intensity <x>, from 0 to 1
+  cutoff <hz>             move the filter to <hz>, e.g. what counts as bass
+  slope <6|12|24>         roll off beyond the cutoff by this many dB per octave
+  filter <type>           follow frequencies below (lowpass), above (highpass), around (bandpass)
+                          or away from (notch) the cutoff
+  q <value>               narrow or widen band-pass and notch filters
+  attack <ms>             make the envelope rise over <ms> milliseconds
+  release <ms>            make the envelope fall over <ms> milliseconds
+  bands        list the bands from --band, their levels, and which devices follow each
+  route <band> <device>   have the device named <device> follow <band> on its own
+  route off <device>      have the device named <device> follow the overall intensity again
+  motor <n> <band> <device>  have motor <n> of the device named <device> follow <band> on its own
+  motor <n> off <device>  have motor <n> follow whatever the rest of the device follows
+  devices      list connected devices, and whether and how strongly each is driven
+  device on <device>      start driving the device named <device>
+  device off <device>     stop driving the device named <device>
+  device scale <x> <device>  multiply the intensity sent to the device named <device> by <x>
+  response <follow|pulse> follow the audio's level, or pulse on each onset such as a kick
+  curve <name> [gamma]    feel levels linearly, or along a log, exp, gamma or custom curve
+  curve edit              draw a custom curve, applying it as you go
+  tempo        show the tempo detected in the audio
+  pipeline     show each stage audio passes through on its way to devices
+  remember     remember gain, deadzone and ceiling for the current source
+  forget       forget whatever was remembered for the current source
+  boost on     multiply output by the boost factor, until `boost off`
+  boost off    ramp back down from a boost
+  stats        show statistics for the session so far
+  heatmap      show an overview of intensity over the session so far
+  export <path>           write the intensity timeline and event log to a .json or .csv file
+  latency      measure how long audio takes to reach each device
+  arm          start sending output to devices
+  disarm       stop devices immediately, sending nothing until armed again
+  pause        stop vibrating, while still analyzing audio
+  resume       resume vibrating
+  snapshot     save the current session state
+  restore      restore the last saved session state
+  quit         stop and disconnect";
+
+/// Reads commands from stdin on a dedicated thread, as stdin has no async story of its own.
+/// Each command is published to the bus as a user action.
+pub fn spawn_console(bus: EventBus) {
+    thread::spawn(move || {
+        for line in stdin().lock().lines() {
+            let Ok(line) = line else {
+                break;
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            match Command::parse(&line) {
+                Ok(command) => bus.publish(AppEvent::User(command)),
+                Err(e) => println!("{} (type `help` for a list of commands)", e),
+            }
+        }
+    });
+}
@@ -0,0 +1,408 @@
+use buttplug::{
+    ButtplugClient, ButtplugClientDevice, ButtplugClientEvent, ButtplugWebsocketClientTransport,
+    connector::ButtplugRemoteClientConnector, serializer::ButtplugClientJSONSerializer,
+};
+use futures::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use crate::events::{AppEvent, DeviceEvent, EventBus};
+
+/// Settings applied to a single device, saved by device name.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DeviceSettings {
+    /// Whether this device receives commands at all.
+    pub enabled: bool,
+    /// Scales the computed intensity before it is sent to this device.
+    pub multiplier: f64,
+    /// Delays this device's output by this many milliseconds, e.g. so that pulses travel across several devices.
+    pub offset_ms: u64,
+    /// Follows this band from `--band` on its own, rather than the overall intensity.
+    pub band: Option<String>,
+    /// Settings for each of the device's vibration motors in turn, should they be driven separately.
+    /// Motors beyond those listed follow the device's own settings.
+    pub motors: Vec<MotorSettings>,
+    /// Which kinds of actuator are driven, and how.
+    pub actuators: ActuatorSettings,
+}
+
+impl Default for DeviceSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            multiplier: 1.0,
+            offset_ms: 0,
+            band: None,
+            motors: Vec::new(),
+            actuators: ActuatorSettings::default(),
+        }
+    }
+}
+
+impl DeviceSettings {
+    /// The settings motor `index` is driven with, combining its own with the device's.
+    pub fn motor(&self, index: usize) -> MotorSettings {
+        let motor = self.motors.get(index).cloned().unwrap_or_default();
+        MotorSettings {
+            band: motor.band.or_else(|| self.band.clone()),
+            multiplier: motor.multiplier * self.multiplier,
+            offset_ms: motor.offset_ms + self.offset_ms,
+        }
+    }
+}
+
+/// Which of a device's actuators are driven, and how, for devices with more than vibration motors.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ActuatorSettings {
+    /// Whether vibration motors are driven.
+    pub vibrate: bool,
+    /// Whether rotating actuators are driven, at a speed following the intensity.
+    pub rotate: bool,
+    /// Reverses rotation on each strong onset, such as a kick, rather than always rotating the same way.
+    pub reverse_on_onset: bool,
+    /// Whether linear actuators, such as strokers, are driven through strokes following the intensity.
+    pub linear: bool,
+    /// Whether oscillating actuators are driven, at a speed following the intensity much as vibration does.
+    pub oscillate: bool,
+    /// Whether pressure actuators that constrict are driven, slowly following the intensity.
+    // TODO(spotlightishere): Drive inflating actuators too, once Buttplug has an output for them.
+    pub constrict: bool,
+}
+
+impl Default for ActuatorSettings {
+    fn default() -> Self {
+        Self {
+            vibrate: true,
+            rotate: true,
+            reverse_on_onset: false,
+            linear: true,
+            oscillate: true,
+            constrict: true,
+        }
+    }
+}
+
+/// Settings applied to one of a device's motors, on top of the device's own.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MotorSettings {
+    /// Follows this band from `--band` on its own, rather than whatever the device follows.
+    pub band: Option<String>,
+    /// Scales this motor's intensity, alongside the device's multiplier.
+    pub multiplier: f64,
+    /// Delays this motor's output by this many milliseconds more than the device's.
+    pub offset_ms: u64,
+}
+
+impl Default for MotorSettings {
+    fn default() -> Self {
+        Self {
+            band: None,
+            multiplier: 1.0,
+            offset_ms: 0,
+        }
+    }
+}
+
+/// A connected device, alongside the settings it was routed with.
+pub struct RoutedDevice {
+    pub device: ButtplugClientDevice,
+    pub settings: DeviceSettings,
+}
+
+/// Tracks which devices are currently connected, and how commands should be routed to them.
+///
+/// Devices come and go over the course of a session; the vibration loop only ever
+/// sends to whatever is present at the time of each tick.
+#[derive(Default)]
+pub struct DeviceRouter {
+    devices: BTreeMap<u32, RoutedDevice>,
+    saved: BTreeMap<String, DeviceSettings>,
+    path: Option<PathBuf>,
+}
+
+pub type SharedRouter = Arc<Mutex<DeviceRouter>>;
+
+impl DeviceRouter {
+    /// Creates a router, loading previously saved per-device settings from `path` if present.
+    pub fn load(path: Option<PathBuf>) -> Self {
+        let saved = path
+            .as_ref()
+            .and_then(|path| std::fs::read_to_string(path).ok())
+            .and_then(|contents| match parse_saved_settings(&contents) {
+                Ok(saved) => Some(saved),
+                Err(e) => {
+                    println!("Ignoring malformed device settings: {}", e);
+                    None
+                }
+            })
+            .unwrap_or_default();
+
+        Self {
+            devices: BTreeMap::new(),
+            saved,
+            path,
+        }
+    }
+
+    /// Begins routing to a device, applying its saved settings or the defaults.
+    ///
+    /// Returns the settings applied, or `None` if the device was already being routed to.
+    pub fn add(&mut self, device: ButtplugClientDevice) -> Option<DeviceSettings> {
+        if self.devices.contains_key(&device.index()) {
+            return None;
+        }
+
+        let name = device.name().clone();
+        let settings = match self.saved.get(&name) {
+            Some(saved) => saved.clone(),
+            None => {
+                // Record the defaults so they can be tweaked by hand later on.
+                self.saved.insert(name.clone(), DeviceSettings::default());
+                self.save();
+                DeviceSettings::default()
+            }
+        };
+
+        self.devices.insert(
+            device.index(),
+            RoutedDevice {
+                device,
+                settings: settings.clone(),
+            },
+        );
+        Some(settings)
+    }
+
+    /// Stops routing to a device, returning its name. Other devices are unaffected.
+    pub fn remove(&mut self, index: u32) -> Option<String> {
+        self.devices
+            .remove(&index)
+            .map(|routed| routed.device.name().clone())
+    }
+
+    /// Stops routing to every device, such as once the server connection is dropped.
+    pub fn clear(&mut self) {
+        self.devices.clear();
+    }
+
+    /// Returns every enabled device alongside its multiplier.
+    pub fn active(&self) -> Vec<(ButtplugClientDevice, DeviceSettings)> {
+        self.devices
+            .values()
+            .filter(|routed| routed.settings.enabled)
+            .map(|routed| (routed.device.clone(), routed.settings.clone()))
+            .collect()
+    }
+
+    /// Returns every connected device alongside its settings, including those that are disabled.
+    pub fn connected(&self) -> Vec<(ButtplugClientDevice, DeviceSettings)> {
+        self.devices
+            .values()
+            .map(|routed| (routed.device.clone(), routed.settings.clone()))
+            .collect()
+    }
+
+    /// Routes every connected device named `name` to follow `band`, or the overall intensity if `None`.
+    ///
+    /// Returns whether any such device is connected.
+    pub fn set_band(&mut self, name: &str, band: Option<String>) -> bool {
+        !self
+            .update(name, |settings| settings.band = band.clone())
+            .is_empty()
+    }
+
+    /// Routes motor `motor` of every connected device named `name` to follow `band`,
+    /// or whatever the device follows if `None`.
+    ///
+    /// Returns whether any such device is connected.
+    pub fn set_motor_band(&mut self, name: &str, motor: usize, band: Option<String>) -> bool {
+        !self
+            .update(name, |settings| {
+                if settings.motors.len() <= motor {
+                    settings.motors.resize(motor + 1, MotorSettings::default());
+                }
+                settings.motors[motor].band = band.clone();
+            })
+            .is_empty()
+    }
+
+    /// Enables or disables every connected device named `name`, returning those affected.
+    pub fn set_enabled(&mut self, name: &str, enabled: bool) -> Vec<ButtplugClientDevice> {
+        self.update(name, |settings| settings.enabled = enabled)
+    }
+
+    /// Scales the intensity sent to every connected device named `name`, returning whether any is connected.
+    pub fn set_multiplier(&mut self, name: &str, multiplier: f64) -> bool {
+        !self
+            .update(name, |settings| settings.multiplier = multiplier)
+            .is_empty()
+    }
+
+    /// Changes and saves the settings of every connected device named `name`, returning those affected.
+    fn update(
+        &mut self,
+        name: &str,
+        change: impl Fn(&mut DeviceSettings),
+    ) -> Vec<ButtplugClientDevice> {
+        let mut found = Vec::new();
+        for routed in self.devices.values_mut() {
+            if routed.device.name() == name {
+                change(&mut routed.settings);
+                self.saved.insert(name.to_string(), routed.settings.clone());
+                found.push(routed.device.clone());
+            }
+        }
+        if !found.is_empty() {
+            self.save();
+        }
+        found
+    }
+
+    /// Settings for every device seen so far, connected or not.
+    pub fn saved_settings(&self) -> &BTreeMap<String, DeviceSettings> {
+        &self.saved
+    }
+
+    /// Replaces all saved settings, re-routing connected devices accordingly.
+    pub fn restore(&mut self, saved: BTreeMap<String, DeviceSettings>) {
+        self.saved = saved;
+        for routed in self.devices.values_mut() {
+            routed.settings = self
+                .saved
+                .get(routed.device.name())
+                .cloned()
+                .unwrap_or_default();
+        }
+        self.save();
+    }
+
+    fn save(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        if let Some(parent) = path.parent() {
+            let _ = std::fs::create_dir_all(parent);
+        }
+        match serde_json::to_string_pretty(&self.saved) {
+            Ok(contents) => {
+                if let Err(e) = std::fs::write(path, contents) {
+                    println!("Failed to save device settings: {}", e);
+                }
+            }
+            Err(e) => println!("Failed to serialize device settings: {}", e),
+        }
+    }
+}
+
+/// Parses saved per-device settings. As these are meant to be edited by hand, this must never panic.
+pub fn parse_saved_settings(
+    contents: &str,
+) -> Result<BTreeMap<String, DeviceSettings>, serde_json::Error> {
+    serde_json::from_str(contents)
+}
+
+/// Begins routing to a device, announcing it on the bus.
+pub fn add_device(router: &SharedRouter, bus: &EventBus, device: ButtplugClientDevice) {
+    let index = device.index();
+    let name = device.name().clone();
+    let added = router.lock().unwrap().add(device);
+    if let Some(settings) = added {
+        bus.publish(AppEvent::Device(DeviceEvent::Added {
+            index,
+            name,
+            settings,
+        }));
+    }
+}
+
+/// Keeps the router in sync with the devices the server reports, until the server disconnects.
+pub async fn watch_devices(
+    mut events: impl Stream<Item = ButtplugClientEvent> + Unpin,
+    router: SharedRouter,
+    bus: EventBus,
+) {
+    while let Some(event) = events.next().await {
+        match event {
+            ButtplugClientEvent::DeviceAdded(device) => add_device(&router, &bus, device),
+            ButtplugClientEvent::DeviceRemoved(device) => {
+                let index = device.index();
+                let removed = router.lock().unwrap().remove(index);
+                if let Some(name) = removed {
+                    bus.publish(AppEvent::Device(DeviceEvent::Removed { index, name }));
+                }
+            }
+            ButtplugClientEvent::ServerDisconnect => {
+                bus.publish(AppEvent::Device(DeviceEvent::ServerDisconnected));
+                break;
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Where the Buttplug server, e.g. Intiface Central, listens.
+const SERVER_ADDRESS: &str = "ws://127.0.0.1:12345";
+
+/// Our connection to the Buttplug server, which may be dropped mid-session to let devices sleep
+/// and later re-established.
+#[derive(Clone)]
+pub struct Connection {
+    router: SharedRouter,
+    bus: EventBus,
+    client: Arc<Mutex<Option<ButtplugClient>>>,
+}
+
+impl Connection {
+    pub fn new(router: SharedRouter, bus: EventBus) -> Self {
+        Self {
+            router,
+            bus,
+            client: Arc::default(),
+        }
+    }
+
+    /// Connects and begins scanning, routing to devices as the server reports them.
+    ///
+    /// Clients can't reconnect once disconnected, so each connection uses a fresh one.
+    pub async fn connect(&self) -> anyhow::Result<()> {
+        let connector = ButtplugRemoteClientConnector::<
+            ButtplugWebsocketClientTransport,
+            ButtplugClientJSONSerializer,
+        >::new(ButtplugWebsocketClientTransport::new_insecure_connector(
+            SERVER_ADDRESS,
+        ));
+        let client = ButtplugClient::new("subwoofer");
+
+        // Subscribe before connecting, so that no device events are missed during the handshake.
+        let events = client.event_stream();
+
+        // TODO(spotlightishere): Properly handle errors if scanning fails
+        client.connect(connector).await?;
+        client.start_scanning().await?;
+
+        // Devices may be added or removed at any point during the session.
+        // We seed our router with what the server already knows about, and let it follow along from there.
+        for (_, device) in client.devices() {
+            add_device(&self.router, &self.bus, device);
+        }
+        tokio::spawn(watch_devices(events, self.router.clone(), self.bus.clone()));
+        *self.client.lock().unwrap() = Some(client);
+        Ok(())
+    }
+
+    /// Disconnects from the server, forgetting every device until connecting again.
+    pub async fn disconnect(&self) -> anyhow::Result<()> {
+        let Some(client) = self.client.lock().unwrap().take() else {
+            return Ok(());
+        };
+        self.router.lock().unwrap().clear();
+        client.stop_scanning().await?;
+        client.disconnect().await?;
+        Ok(())
+    }
+}
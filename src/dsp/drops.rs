@@ -0,0 +1,104 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// How often the level is sampled to follow a build-up, and how long build-ups are followed over.
+const BUILD_UP_STEP: Duration = Duration::from_millis(250);
+const BUILD_UP_TIME: Duration = Duration::from_secs(8);
+/// How many times louder the end of a build-up must be than its beginning.
+const BUILD_UP_RISE: f64 = 1.5;
+/// Build-ups quieter than this are only noise creeping up.
+const MIN_BUILD_UP_LEVEL: f64 = 1e-4;
+/// How long after a build-up a drop may still come, leaving room for the silence that often precedes one.
+const DROP_WINDOW: Duration = Duration::from_secs(2);
+/// How many times louder than the height of the build-up the level must suddenly become to be a drop.
+const DROP_RATIO: f64 = 1.5;
+/// How quickly the level a drop is judged by follows the envelope.
+const DROP_TIME: Duration = Duration::from_millis(50);
+/// Drops closer together than this are taken to be the same one.
+const MIN_DROP_GAP: Duration = Duration::from_secs(15);
+
+/// Detects drops, as in EDM: a sudden spike in the envelope, following a build-up over which it steadily rose.
+pub struct DropDetector {
+    /// The level over the last moment, which a drop spikes.
+    level: f64,
+    /// The envelope summed over the current step so far, and how long that step has lasted.
+    step_total: f64,
+    step_time: Duration,
+    /// The average level over each recent step, oldest first.
+    steps: VecDeque<f64>,
+    /// How long ago the latest build-up was still rising, and how loud it got.
+    built_up: Option<(Duration, f64)>,
+    since_drop: Duration,
+}
+
+impl Default for DropDetector {
+    fn default() -> Self {
+        Self {
+            level: 0.0,
+            step_total: 0.0,
+            step_time: Duration::ZERO,
+            steps: VecDeque::new(),
+            built_up: None,
+            since_drop: MIN_DROP_GAP,
+        }
+    }
+}
+
+impl DropDetector {
+    /// Feeds the envelope's value `elapsed` after the previous call, returning whether a drop begins here.
+    pub fn process(&mut self, value: f64, elapsed: Duration) -> bool {
+        self.since_drop += elapsed;
+        let alpha = 1.0 - (-elapsed.as_secs_f64() / DROP_TIME.as_secs_f64()).exp();
+        self.level += (value - self.level) * alpha;
+
+        self.step_total += value * elapsed.as_secs_f64();
+        self.step_time += elapsed;
+        if self.step_time >= BUILD_UP_STEP {
+            self.steps
+                .push_back(self.step_total / self.step_time.as_secs_f64());
+            (self.step_total, self.step_time) = (0.0, Duration::ZERO);
+            let len = (BUILD_UP_TIME.as_millis() / BUILD_UP_STEP.as_millis()) as usize;
+            if self.steps.len() > len {
+                self.steps.pop_front();
+            }
+            if self.steps.len() == len
+                && let Some(peak) = self.building()
+            {
+                self.built_up = Some((Duration::ZERO, peak));
+            }
+        }
+
+        let Some((since, peak)) = &mut self.built_up else {
+            return false;
+        };
+        *since += elapsed;
+        if *since > DROP_WINDOW {
+            self.built_up = None;
+            return false;
+        }
+        let dropped = self.level >= *peak * DROP_RATIO && self.since_drop >= MIN_DROP_GAP;
+        if dropped {
+            self.built_up = None;
+            self.since_drop = Duration::ZERO;
+        }
+        dropped
+    }
+
+    /// How loud the build-up has become, should each quarter of recent steps be louder than the last.
+    fn building(&self) -> Option<f64> {
+        let quarter = self.steps.len() / 4;
+        let quarters: Vec<f64> = (0..4)
+            .map(|i| {
+                self.steps
+                    .range(i * quarter..(i + 1) * quarter)
+                    .sum::<f64>()
+                    / quarter as f64
+            })
+            .collect();
+        let (first, last) = (quarters[0], quarters[3]);
+        (quarters.windows(2).all(|pair| pair[1] > pair[0])
+            && last >= first * BUILD_UP_RISE
+            && last >= MIN_BUILD_UP_LEVEL)
+            .then_some(last)
+    }
+}
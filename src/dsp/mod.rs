@@ -0,0 +1,602 @@
+mod drops;
+mod onset;
+mod spectrum;
+
+pub use drops::DropDetector;
+pub use onset::{FluxDetector, KickDetector, OnsetDetector, TEMPO_RANGE, TempoEstimator};
+pub use spectrum::{
+    Band, FFT_HOP, FFT_LEN, FFT_LEN_RANGE, FftSettings, SpectrumAnalyzer, WindowFunction,
+    band_levels,
+};
+
+use std::collections::VecDeque;
+use std::f32::consts::TAU;
+use std::time::Duration;
+
+/// A second-order IIR filter, using the coefficients from Robert Bristow-Johnson's Audio EQ Cookbook.
+#[derive(Clone, Debug)]
+pub struct Biquad {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+    x1: f32,
+    x2: f32,
+    y1: f32,
+    y2: f32,
+}
+
+/// A Q giving a maximally flat passband.
+pub const BUTTERWORTH_Q: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+impl Biquad {
+    pub fn lowpass(sample_rate: f32, frequency: f32, q: f32) -> Self {
+        let (cos, alpha) = Self::prepare(sample_rate, frequency, q);
+        Self::normalized(
+            (1.0 - cos) / 2.0,
+            1.0 - cos,
+            (1.0 - cos) / 2.0,
+            1.0 + alpha,
+            -2.0 * cos,
+            1.0 - alpha,
+        )
+    }
+
+    pub fn highpass(sample_rate: f32, frequency: f32, q: f32) -> Self {
+        let (cos, alpha) = Self::prepare(sample_rate, frequency, q);
+        Self::normalized(
+            (1.0 + cos) / 2.0,
+            -(1.0 + cos),
+            (1.0 + cos) / 2.0,
+            1.0 + alpha,
+            -2.0 * cos,
+            1.0 - alpha,
+        )
+    }
+
+    /// A band-pass filter with a peak gain of 0 dB.
+    pub fn bandpass(sample_rate: f32, frequency: f32, q: f32) -> Self {
+        let (cos, alpha) = Self::prepare(sample_rate, frequency, q);
+        Self::normalized(alpha, 0.0, -alpha, 1.0 + alpha, -2.0 * cos, 1.0 - alpha)
+    }
+
+    /// A high shelf, boosting (or cutting, given a negative gain) everything above `frequency` by `gain_db`.
+    pub fn high_shelf(sample_rate: f32, frequency: f32, gain_db: f32, q: f32) -> Self {
+        let (cos, alpha) = Self::prepare(sample_rate, frequency, q);
+        let a = 10f32.powf(gain_db / 40.0);
+        let root = 2.0 * a.sqrt() * alpha;
+        Self::normalized(
+            a * ((a + 1.0) + (a - 1.0) * cos + root),
+            -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+            a * ((a + 1.0) + (a - 1.0) * cos - root),
+            (a + 1.0) - (a - 1.0) * cos + root,
+            2.0 * ((a - 1.0) - (a + 1.0) * cos),
+            (a + 1.0) - (a - 1.0) * cos - root,
+        )
+    }
+
+    /// A first-order high-pass filter, rolling off at 6 dB per octave.
+    pub fn highpass_first_order(sample_rate: f32, frequency: f32) -> Self {
+        let frequency = frequency.clamp(1.0, sample_rate * 0.49);
+        let k = (std::f32::consts::PI * frequency / sample_rate).tan();
+        Self::normalized(1.0, -1.0, 0.0, 1.0 + k, k - 1.0, 0.0)
+    }
+
+    /// How much this filter multiplies a sine at `frequency` by.
+    pub fn gain_at(&self, sample_rate: f32, frequency: f32) -> f32 {
+        let omega = TAU * frequency / sample_rate;
+        // Evaluating the transfer function on the unit circle, at e^(-jω) and e^(-2jω).
+        let (c1, s1) = (omega.cos(), -omega.sin());
+        let (c2, s2) = ((2.0 * omega).cos(), -(2.0 * omega).sin());
+        let numerator = (
+            self.b0 + self.b1 * c1 + self.b2 * c2,
+            self.b1 * s1 + self.b2 * s2,
+        );
+        let denominator = (
+            1.0 + self.a1 * c1 + self.a2 * c2,
+            self.a1 * s1 + self.a2 * s2,
+        );
+        (numerator.0.hypot(numerator.1)) / denominator.0.hypot(denominator.1)
+    }
+
+    /// A band-stop filter, removing frequencies around `frequency` while passing everything else.
+    pub fn notch(sample_rate: f32, frequency: f32, q: f32) -> Self {
+        let (cos, alpha) = Self::prepare(sample_rate, frequency, q);
+        Self::normalized(1.0, -2.0 * cos, 1.0, 1.0 + alpha, -2.0 * cos, 1.0 - alpha)
+    }
+
+    /// Filters a single sample.
+    pub fn run(&mut self, x: f32) -> f32 {
+        let y = self.b0 * x + self.b1 * self.x1 + self.b2 * self.x2
+            - self.a1 * self.y1
+            - self.a2 * self.y2;
+        self.x2 = self.x1;
+        self.x1 = x;
+        self.y2 = self.y1;
+        self.y1 = y;
+        y
+    }
+
+    /// Frequencies at or beyond Nyquist are meaningless, so they're kept just below it.
+    fn prepare(sample_rate: f32, frequency: f32, q: f32) -> (f32, f32) {
+        let frequency = frequency.clamp(1.0, sample_rate * 0.49);
+        let omega = TAU * frequency / sample_rate;
+        (omega.cos(), omega.sin() / (2.0 * q.max(0.01)))
+    }
+
+    fn normalized(b0: f32, b1: f32, b2: f32, a0: f32, a1: f32, a2: f32) -> Self {
+        Self {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+            x1: 0.0,
+            x2: 0.0,
+            y1: 0.0,
+            y2: 0.0,
+        }
+    }
+}
+
+/// Weights a signal by how loud its frequencies sound, rather than how large they are.
+///
+/// Only the shape of each curve below about 1 kHz is modelled, which is all that matters for bass.
+/// Higher cutoffs and high-pass filters reach above that, where the curves here keep rising rather than
+/// rolling off or shelving, so they're only approximate there.
+/// Gain is unity at the reference frequency, so that weighting changes the balance within what's followed,
+/// rather than how strong it is overall.
+pub struct LoudnessWeighting {
+    stages: Vec<Biquad>,
+    gain: f32,
+}
+
+impl LoudnessWeighting {
+    /// The A-weighting curve, from IEC 61672.
+    pub fn a_weighting(sample_rate: f32, reference: f32) -> Self {
+        let stages = [20.6, 20.6, 107.7, 737.9]
+            .into_iter()
+            .map(|frequency| Biquad::highpass_first_order(sample_rate, frequency))
+            .collect();
+        Self::new(stages, sample_rate, reference)
+    }
+
+    /// The K-weighting curve, from ITU-R BS.1770. Its high shelf lies almost entirely above the bass band,
+    /// so only its high-pass stage is modelled.
+    pub fn k_weighting(sample_rate: f32, reference: f32) -> Self {
+        Self::new(
+            vec![Biquad::highpass(sample_rate, 38.1, 0.5)],
+            sample_rate,
+            reference,
+        )
+    }
+
+    fn new(stages: Vec<Biquad>, sample_rate: f32, reference: f32) -> Self {
+        let at_reference: f32 = stages
+            .iter()
+            .map(|stage| stage.gain_at(sample_rate, reference))
+            .product();
+        Self {
+            stages,
+            gain: 1.0 / at_reference.max(f32::EPSILON),
+        }
+    }
+
+    pub fn run(&mut self, sample: f32) -> f32 {
+        self.stages
+            .iter_mut()
+            .fold(sample * self.gain, |sample, stage| stage.run(sample))
+    }
+}
+
+/// Measures loudness in LUFS, as ITU-R BS.1770 does, over a sliding window: 400 ms for momentary loudness,
+/// or 3 s for short-term loudness.
+///
+/// Unlike [`LoudnessWeighting::k_weighting`], the whole K-weighting curve is modelled, as every frequency counts.
+/// Only a single channel is measured, so audio should be downmixed beforehand.
+pub struct LoudnessMeter {
+    shelf: Biquad,
+    highpass: Biquad,
+    /// The square of each weighted sample within the window, oldest first, alongside their sum.
+    window: VecDeque<f32>,
+    total: f64,
+    len: usize,
+}
+
+impl LoudnessMeter {
+    pub fn new(sample_rate: f32, window: Duration) -> Self {
+        // The standard only gives coefficients at 48 kHz, which these match closely, but they hold at any rate.
+        Self {
+            shelf: Biquad::high_shelf(sample_rate, 1500.0, 4.0, BUTTERWORTH_Q),
+            highpass: Biquad::highpass(sample_rate, 38.0, 0.5),
+            window: VecDeque::new(),
+            total: 0.0,
+            len: ((sample_rate as f64 * window.as_secs_f64()) as usize).max(1),
+        }
+    }
+
+    /// Feeds a block of mono samples, returning the loudness of the window as of its end.
+    pub fn process(&mut self, block: &[f32]) -> f64 {
+        for &sample in block {
+            let weighted = self.highpass.run(self.shelf.run(sample));
+            self.window.push_back(weighted * weighted);
+            self.total += (weighted * weighted) as f64;
+            if self.window.len() > self.len {
+                self.total -= self.window.pop_front().unwrap_or_default() as f64;
+            }
+        }
+        // Silence is taken to be -120 LUFS, rather than infinitely quiet.
+        let mean_square = self.total.max(0.0) / self.len as f64;
+        -0.691 + 10.0 * mean_square.max(1e-12).log10()
+    }
+}
+
+/// How a block's worth of levels is summarized as one.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BlockSummary {
+    /// The level at the end of the block, which aliases with the block size.
+    Last,
+    /// The root mean square of every level within the block.
+    Rms,
+    /// The highest level within the block.
+    Peak,
+    /// The level this fraction (0 to 1) of the block lies at or below.
+    Percentile(f32),
+}
+
+impl BlockSummary {
+    /// Summarizes `levels`, reordering them as it goes.
+    pub fn summarize(self, levels: &mut [f32]) -> f32 {
+        if levels.is_empty() {
+            return 0.0;
+        }
+        match self {
+            BlockSummary::Last => levels[levels.len() - 1],
+            BlockSummary::Rms => {
+                (levels.iter().map(|level| level * level).sum::<f32>() / levels.len() as f32).sqrt()
+            }
+            BlockSummary::Peak => levels.iter().copied().fold(0.0, f32::max),
+            BlockSummary::Percentile(fraction) => {
+                let index = ((levels.len() - 1) as f32 * fraction.clamp(0.0, 1.0)).round() as usize;
+                *levels.select_nth_unstable_by(index, f32::total_cmp).1
+            }
+        }
+    }
+}
+
+/// Follows the level of a signal sample by sample, rising over the attack time and falling over the release time.
+pub struct EnvelopeFollower {
+    attack: f32,
+    release: f32,
+    /// Whether the mean square is followed, rather than the absolute value.
+    rms: bool,
+    level: f32,
+    summary: BlockSummary,
+}
+
+impl EnvelopeFollower {
+    pub fn new(sample_rate: f32, attack: Duration, release: Duration, rms: bool) -> Self {
+        // Each coefficient is how much of the previous level remains after one sample.
+        let coefficient = |time: Duration| {
+            let samples = time.as_secs_f32() * sample_rate;
+            if samples > 0.0 {
+                (-1.0 / samples).exp()
+            } else {
+                0.0
+            }
+        };
+        Self {
+            attack: coefficient(attack),
+            release: coefficient(release),
+            rms,
+            level: 0.0,
+            summary: BlockSummary::Last,
+        }
+    }
+
+    /// Summarizes each block's levels with `summary`, rather than taking the last.
+    pub fn with_summary(mut self, summary: BlockSummary) -> Self {
+        self.summary = summary;
+        self
+    }
+
+    pub fn run(&mut self, sample: f32) -> f32 {
+        let target = if self.rms {
+            sample * sample
+        } else {
+            sample.abs()
+        };
+        let coefficient = if target > self.level {
+            self.attack
+        } else {
+            self.release
+        };
+        self.level = target + coefficient * (self.level - target);
+        if self.rms {
+            self.level.sqrt()
+        } else {
+            self.level
+        }
+    }
+
+    /// Follows a whole block, returning its summarized level.
+    pub fn process(&mut self, block: &[f32]) -> f64 {
+        let mut levels: Vec<f32> = block.iter().map(|sample| self.run(*sample)).collect();
+        self.summary.summarize(&mut levels).into()
+    }
+}
+
+/// Exponentially smooths a value over time, with the given time constant.
+pub struct Smoother {
+    time_constant: Duration,
+    value: f64,
+}
+
+impl Smoother {
+    pub fn new(time_constant: Duration) -> Self {
+        Self {
+            time_constant,
+            value: 0.0,
+        }
+    }
+
+    /// Moves towards `target`, `elapsed` after the previous call, returning the smoothed value.
+    pub fn run(&mut self, target: f64, elapsed: Duration) -> f64 {
+        if self.time_constant.is_zero() {
+            self.value = target;
+        } else {
+            let alpha = 1.0 - (-elapsed.as_secs_f64() / self.time_constant.as_secs_f64()).exp();
+            self.value += (target - self.value) * alpha;
+        }
+        self.value
+    }
+}
+
+/// The share of recent envelope values automatic gain control treats as peaks.
+const AGC_PERCENTILE: f64 = 0.95;
+/// How far automatic gain control may turn the envelope down or up, relative to the configured gain.
+const AGC_RANGE: f64 = 10.0;
+/// Envelopes whose peaks are quieter than this are too close to silence to adjust for.
+const AGC_FLOOR: f64 = 1e-4;
+/// How quickly automatic gain control moves towards the gain it wants, so that it doesn't audibly pump.
+const AGC_TIME_CONSTANT: Duration = Duration::from_secs(2);
+
+/// Automatic gain control: scales the envelope so that its recent peaks land on a target intensity,
+/// so that quiet and loud albums drive devices across a similar range.
+///
+/// Unlike [`LoudnessNormalizer`], this works on the envelope rather than the audio, and over a rolling window
+/// rather than per track.
+pub struct AutoGain {
+    window: Duration,
+    /// The intensity recent peaks are mapped onto.
+    target: f64,
+    /// Recent envelope values, oldest first, alongside how long ago they arrived.
+    history: VecDeque<(Duration, f64)>,
+    clock: Duration,
+    factor: Smoother,
+}
+
+impl AutoGain {
+    pub fn new(window: Duration, target: f64) -> Self {
+        let mut factor = Smoother::new(AGC_TIME_CONSTANT);
+        factor.value = 1.0;
+        Self {
+            window,
+            target,
+            history: VecDeque::new(),
+            clock: Duration::ZERO,
+            factor,
+        }
+    }
+
+    /// Feeds the envelope's value `elapsed` after the previous call, returning the factor it should be scaled by,
+    /// given that it's then multiplied by `gain` on its way to becoming an intensity.
+    pub fn process(&mut self, value: f64, elapsed: Duration, gain: f64) -> f64 {
+        self.clock += elapsed;
+        self.history.push_back((self.clock, value));
+        while self
+            .history
+            .front()
+            .is_some_and(|(at, _)| self.clock - *at > self.window)
+        {
+            self.history.pop_front();
+        }
+
+        let mut recent: Vec<f64> = self.history.iter().map(|(_, value)| *value).collect();
+        recent.sort_by(f64::total_cmp);
+        let peak = recent[((recent.len() - 1) as f64 * AGC_PERCENTILE) as usize];
+        // During silence, the factor is held, ready for the music to return.
+        if peak < AGC_FLOOR || gain <= 0.0 {
+            return self.factor.value;
+        }
+        let wanted = (self.target / (peak * gain)).clamp(1.0 / AGC_RANGE, AGC_RANGE);
+        self.factor.run(wanted, elapsed)
+    }
+}
+
+/// The loudness, as RMS, tracks are normalized towards.
+const TARGET_RMS: f32 = 0.1;
+/// How far normalization may turn a track down or up.
+const MIN_GAIN: f32 = 0.25;
+const MAX_GAIN: f32 = 8.0;
+/// Blocks quieter than this count as silence.
+const SILENCE_RMS: f32 = 0.003;
+/// Silence lasting this long is taken to be the gap between two tracks.
+const TRACK_GAP_SECS: f32 = 2.0;
+/// How much of a track is heard before its loudness is trusted.
+const SETTLE_SECS: f32 = 1.0;
+
+/// Evens out loudness between tracks, so that quietly mastered ones drive devices as strongly as loud ones.
+///
+/// Each track's loudness is the mean square of everything heard since it began.
+/// A stretch of silence is taken to mean a new track, whose loudness is then measured afresh.
+pub struct LoudnessNormalizer {
+    sample_rate: f32,
+    energy: f64,
+    samples: usize,
+    silent: usize,
+    gain: f32,
+}
+
+impl LoudnessNormalizer {
+    pub fn new(sample_rate: f32) -> Self {
+        Self {
+            sample_rate,
+            energy: 0.0,
+            samples: 0,
+            silent: 0,
+            gain: 1.0,
+        }
+    }
+
+    /// Normalizes a block of mono samples in place.
+    pub fn process(&mut self, block: &mut [f32]) {
+        if block.is_empty() {
+            return;
+        }
+        let block_energy: f64 = block.iter().map(|sample| (sample * sample) as f64).sum();
+        let block_rms = (block_energy / block.len() as f64).sqrt() as f32;
+        if block_rms < SILENCE_RMS {
+            // Silence says nothing about how loud a track is, so it isn't counted.
+            self.silent += block.len();
+            if self.silent as f32 >= self.sample_rate * TRACK_GAP_SECS {
+                self.energy = 0.0;
+                self.samples = 0;
+            }
+        } else {
+            self.silent = 0;
+            self.energy += block_energy;
+            self.samples += block.len();
+        }
+
+        // Until enough of a track has been heard, the previous track's gain carries on.
+        if self.samples as f32 >= self.sample_rate * SETTLE_SECS {
+            let rms = (self.energy / self.samples as f64).sqrt() as f32;
+            self.gain = (TARGET_RMS / rms.max(f32::EPSILON)).clamp(MIN_GAIN, MAX_GAIN);
+        }
+        for sample in block.iter_mut() {
+            *sample = (*sample * self.gain).clamp(-1.0, 1.0);
+        }
+    }
+}
+
+/// Where the anti-aliasing filter rolls off when downsampling, as a fraction of the new sample rate.
+/// Anything left above half of it would fold back down, possibly into the bass.
+const ANTI_ALIAS_FRACTION: f32 = 0.4;
+
+/// Converts a stream of mono samples from one sample rate to another by linear interpolation.
+/// When downsampling, whatever the new rate can't represent is filtered out first, so that it can't alias.
+///
+/// State carries across blocks, so that block boundaries don't introduce discontinuities.
+pub struct Resampler {
+    /// How far through the input each output sample moves.
+    step: f64,
+    /// Two cascaded low-pass filters, for a steeper roll-off, present only when downsampling.
+    anti_alias: Option<[Biquad; 2]>,
+    /// Where the next output sample falls, relative to the last sample of the previous block.
+    position: f64,
+    last: f32,
+}
+
+impl Resampler {
+    pub fn new(from: f32, to: f32) -> Self {
+        let lowpass = || Biquad::lowpass(from, to * ANTI_ALIAS_FRACTION, BUTTERWORTH_Q);
+        Self {
+            step: from as f64 / to as f64,
+            anti_alias: (from > to).then(|| [lowpass(), lowpass()]),
+            position: 0.0,
+            last: 0.0,
+        }
+    }
+
+    pub fn process(&mut self, input: &[f32]) -> Vec<f32> {
+        if self.step == 1.0 {
+            return input.to_vec();
+        }
+        let filtered;
+        let input = match &mut self.anti_alias {
+            Some(filters) => {
+                filtered = input
+                    .iter()
+                    .map(|&sample| filters.iter_mut().fold(sample, |sample, f| f.run(sample)))
+                    .collect::<Vec<_>>();
+                &filtered[..]
+            }
+            None => input,
+        };
+        let last = self.last;
+        let at = |index: usize| if index == 0 { last } else { input[index - 1] };
+        let len = input.len() as f64;
+        let mut output = Vec::with_capacity((len / self.step) as usize + 1);
+        while self.position < len {
+            let whole = self.position as usize;
+            let fraction = (self.position - whole as f64) as f32;
+            let (a, b) = (at(whole), at(whole + 1));
+            output.push(a + (b - a) * fraction);
+            self.position += self.step;
+        }
+        self.position -= len;
+        if let Some(&sample) = input.last() {
+            self.last = sample;
+        }
+        output
+    }
+}
+
+/// How long each frame whose energy is compared against its neighbours lasts, in seconds.
+const SEPARATION_FRAME_SECS: f32 = 0.005;
+/// How many recent frames the sustained part is the median of. Hits shorter than half of this stand out.
+const SEPARATION_MEDIAN_FRAMES: usize = 31;
+
+/// Splits a signal into its sustained and percussive parts, in the manner of harmonic/percussive source separation.
+///
+/// Median filtering across time leaves sustained notes intact while removing short hits, so the median energy
+/// of recent frames is taken as the sustained part and anything above it as percussive.
+/// As we only ever look at a single band, there's no median across frequency as there'd be with a spectrogram.
+pub struct HarmonicPercussive {
+    frame_len: usize,
+    /// Energy of the frame so far, and how many samples it holds.
+    energy: f32,
+    count: usize,
+    history: VecDeque<f32>,
+    /// How much of each sample is sustained, from the last complete frame.
+    sustained_share: f32,
+}
+
+impl HarmonicPercussive {
+    pub fn new(sample_rate: f32) -> Self {
+        Self {
+            frame_len: ((sample_rate * SEPARATION_FRAME_SECS) as usize).max(1),
+            energy: 0.0,
+            count: 0,
+            history: VecDeque::with_capacity(SEPARATION_MEDIAN_FRAMES),
+            sustained_share: 1.0,
+        }
+    }
+
+    /// Returns the sustained and percussive parts of `sample`, which add back up to it.
+    pub fn split(&mut self, sample: f32) -> (f32, f32) {
+        self.energy += sample * sample;
+        self.count += 1;
+        if self.count == self.frame_len {
+            let rms = (self.energy / self.count as f32).sqrt();
+            if self.history.len() == SEPARATION_MEDIAN_FRAMES {
+                self.history.pop_front();
+            }
+            self.history.push_back(rms);
+            let mut sorted: Vec<f32> = self.history.iter().copied().collect();
+            sorted.sort_by(f32::total_cmp);
+            let median = sorted[sorted.len() / 2];
+            self.sustained_share = if rms > 0.0 {
+                (median / rms).min(1.0)
+            } else {
+                1.0
+            };
+            self.energy = 0.0;
+            self.count = 0;
+        }
+        let sustained = sample * self.sustained_share;
+        (sustained, sample - sustained)
+    }
+}
@@ -0,0 +1,252 @@
+use std::collections::VecDeque;
+use std::ops::RangeInclusive;
+use std::time::Duration;
+
+use super::{Band, FftSettings, SpectrumAnalyzer};
+
+/// How long the envelope is averaged over, to judge whether it has suddenly risen.
+const AVERAGE_TIME: Duration = Duration::from_millis(400);
+/// Onsets closer together than this are taken to be the same one.
+const MIN_ONSET_GAP: Duration = Duration::from_millis(100);
+
+/// Where a kick's body sits, and its beater's click.
+const KICK_BODY: Band = Band {
+    low_hz: 40.0,
+    high_hz: 120.0,
+};
+const KICK_CLICK: Band = Band {
+    low_hz: 2000.0,
+    high_hz: 6000.0,
+};
+/// How far above its recent average the click must rise alongside the body, as a ratio.
+const CLICK_SENSITIVITY: f64 = 1.2;
+/// Everything up to where kicks' clicks end is followed for spectral flux.
+const FLUX_BAND: Band = Band {
+    low_hz: 20.0,
+    high_hz: 6000.0,
+};
+/// Flux below this is only noise, or the window sliding over a sustained sound.
+const MIN_FLUX: f64 = 0.02;
+/// Clicks quieter than this, about -60 dBFS, are only leakage from the bass.
+const MIN_CLICK_LEVEL: f64 = 1e-3;
+/// How long after the click the body may take to stand out. Over other bass, it only does so once
+/// it fills much of the analysis window, whereas the click stands out against near silence at once.
+const CLICK_LEAD: Duration = Duration::from_millis(100);
+
+/// How far back onsets are considered when estimating the tempo.
+const TEMPO_WINDOW: Duration = Duration::from_secs(8);
+/// Onsets further apart than this are too far apart to tell the tempo from.
+const MAX_BEAT_GAP: Duration = Duration::from_secs(2);
+/// How many onsets must be heard within the window before a tempo is estimated.
+const MIN_TEMPO_ONSETS: usize = 8;
+/// How strongly pairs of onsets must agree on the tempo, relative to how many onsets were heard, for it to be trusted.
+const MIN_TEMPO_AGREEMENT: f64 = 0.6;
+/// How far from a whole number of beats apart two onsets may be, as a share of a beat, to refine the tempo with.
+const BEAT_TOLERANCE: f64 = 0.1;
+/// Tempos are reported within this range, with anything outside it taken to be half or double time.
+pub const TEMPO_RANGE: RangeInclusive<f64> = 70.0..=180.0;
+
+/// Detects onsets, such as kicks, within an envelope: moments where it rises well above its recent average.
+pub struct OnsetDetector {
+    /// How far above its recent average the envelope must rise, as a ratio.
+    sensitivity: f64,
+    average: f64,
+    previous: f64,
+    since_onset: Duration,
+}
+
+impl OnsetDetector {
+    pub fn new(sensitivity: f64) -> Self {
+        Self {
+            sensitivity,
+            average: 0.0,
+            previous: 0.0,
+            since_onset: MIN_ONSET_GAP,
+        }
+    }
+
+    /// Feeds the envelope's value `elapsed` after the previous call, returning whether an onset begins here.
+    pub fn process(&mut self, value: f64, elapsed: Duration) -> bool {
+        self.since_onset += elapsed;
+        let onset = value > self.previous
+            && value > self.average * self.sensitivity
+            && self.since_onset >= MIN_ONSET_GAP;
+        if onset {
+            self.since_onset = Duration::ZERO;
+        }
+
+        let alpha = 1.0 - (-elapsed.as_secs_f64() / AVERAGE_TIME.as_secs_f64()).exp();
+        self.average += (value - self.average) * alpha;
+        self.previous = value;
+        onset
+    }
+}
+
+/// Detects kick drums specifically, ignoring other low-frequency energy such as a bass guitar's sustain.
+///
+/// A kick is a sudden rise in the bass that a click from the beater accompanies. Bass notes rise
+/// just as suddenly, but carry nothing in the click's range.
+pub struct KickDetector {
+    spectrum: SpectrumAnalyzer,
+    body: OnsetDetector,
+    click: OnsetDetector,
+    /// How long ago the click last rose.
+    since_click: Duration,
+    /// How many samples have arrived since the last analysis.
+    pending: usize,
+    sample_rate: f32,
+}
+
+impl KickDetector {
+    pub fn new(sample_rate: f32, sensitivity: f64, fft: FftSettings) -> Self {
+        Self {
+            spectrum: SpectrumAnalyzer::with_fft(sample_rate, vec![KICK_BODY, KICK_CLICK], fft),
+            // As the click must confirm each kick, the body needn't rise as far as a level onset's would.
+            body: OnsetDetector::new(1.0 + (sensitivity - 1.0) / 2.0),
+            click: OnsetDetector::new(CLICK_SENSITIVITY),
+            since_click: CLICK_LEAD,
+            pending: 0,
+            sample_rate,
+        }
+    }
+
+    /// Feeds mono samples, returning whether a kick begins within them.
+    pub fn process(&mut self, samples: &[f32]) -> bool {
+        self.pending += samples.len();
+        let Some(levels) = self.spectrum.process(samples) else {
+            return false;
+        };
+        let elapsed = Duration::from_secs_f32(self.pending as f32 / self.sample_rate);
+        self.pending = 0;
+
+        let click = levels[1] as f64;
+        if self.click.process(click, elapsed) && click >= MIN_CLICK_LEVEL {
+            self.since_click = Duration::ZERO;
+        } else {
+            self.since_click += elapsed;
+        }
+        self.body.process(levels[0] as f64, elapsed) && self.since_click <= CLICK_LEAD
+    }
+}
+
+/// Detects onsets from spectral flux: how much frequencies rise by from one analysis to the next.
+///
+/// Each hit still changes the spectrum within heavily compressed masters, whose level otherwise barely moves.
+pub struct FluxDetector {
+    spectrum: SpectrumAnalyzer,
+    onsets: OnsetDetector,
+    /// How many samples have arrived since the last analysis.
+    pending: usize,
+    sample_rate: f32,
+}
+
+impl FluxDetector {
+    pub fn new(sample_rate: f32, sensitivity: f64, fft: FftSettings) -> Self {
+        Self {
+            spectrum: SpectrumAnalyzer::with_fft(sample_rate, vec![FLUX_BAND], fft),
+            onsets: OnsetDetector::new(sensitivity),
+            pending: 0,
+            sample_rate,
+        }
+    }
+
+    /// Feeds mono samples, returning whether an onset begins within them.
+    pub fn process(&mut self, samples: &[f32]) -> bool {
+        self.pending += samples.len();
+        if self.spectrum.process(samples).is_none() {
+            return false;
+        }
+        let elapsed = Duration::from_secs_f32(self.pending as f32 / self.sample_rate);
+        self.pending = 0;
+
+        let flux = self.spectrum.flux() as f64;
+        self.onsets.process(flux, elapsed) && flux >= MIN_FLUX
+    }
+}
+
+/// Estimates the tempo from when onsets happen, as the beat length most pairs of recent onsets are a multiple of.
+#[derive(Default)]
+pub struct TempoEstimator {
+    /// How long we've been listening for.
+    clock: Duration,
+    /// When each recent onset happened, oldest first.
+    onsets: VecDeque<Duration>,
+}
+
+impl TempoEstimator {
+    /// Advances by `elapsed`, noting whether an onset begins here.
+    pub fn advance(&mut self, elapsed: Duration, onset: bool) {
+        self.clock += elapsed;
+        if onset {
+            self.onsets.push_back(self.clock);
+        }
+        while self
+            .onsets
+            .front()
+            .is_some_and(|at| self.clock - *at > TEMPO_WINDOW)
+        {
+            self.onsets.pop_front();
+        }
+    }
+
+    /// The estimated tempo in beats per minute, or `None` while there isn't a steady beat to go by.
+    pub fn bpm(&self) -> Option<f64> {
+        if self.onsets.len() < MIN_TEMPO_ONSETS {
+            return None;
+        }
+
+        // Each pair of onsets votes for the tempo their gap implies, folded into our range.
+        // Pairs further apart count for less, so that they don't outvote neighbours in favour of half time.
+        let lowest = *TEMPO_RANGE.start();
+        let bins = (*TEMPO_RANGE.end() - lowest) as usize + 1;
+        let mut votes = vec![Vec::new(); bins];
+        for (i, earlier) in self.onsets.iter().enumerate() {
+            for (apart, later) in self.onsets.iter().skip(i + 1).enumerate() {
+                let gap = *later - *earlier;
+                if gap > MAX_BEAT_GAP {
+                    break;
+                }
+                let mut bpm = 60.0 / gap.as_secs_f64();
+                while bpm < lowest {
+                    bpm *= 2.0;
+                }
+                while bpm > *TEMPO_RANGE.end() {
+                    bpm /= 2.0;
+                }
+                votes[(bpm - lowest).round() as usize].push((bpm, 1.0 / (apart + 1) as f64));
+            }
+        }
+
+        // Neighbouring bins count together, so that a tempo between two of them isn't split in half.
+        let (support, bpm) = (0..bins)
+            .map(|bin| {
+                let neighbours = bin.saturating_sub(1)..=(bin + 1).min(bins - 1);
+                let (support, total) = neighbours
+                    .flat_map(|bin| votes[bin].iter())
+                    .fold((0.0, 0.0), |(support, total), (bpm, weight)| {
+                        (support + weight, total + bpm * weight)
+                    });
+                (support, total / support)
+            })
+            .max_by(|(a, _), (b, _)| a.total_cmp(b))?;
+        if support < self.onsets.len() as f64 * MIN_TEMPO_AGREEMENT {
+            return None;
+        }
+
+        // Onsets several beats apart pin the beat down more precisely than neighbours do,
+        // so the estimate is refined using every pair that's close to a whole number of beats apart.
+        let beat = 60.0 / bpm;
+        let (mut spanned, mut beats) = (0.0, 0.0);
+        for (i, earlier) in self.onsets.iter().enumerate() {
+            for later in self.onsets.iter().skip(i + 1) {
+                let gap = (*later - *earlier).as_secs_f64();
+                let apart = (gap / beat).round();
+                if apart >= 1.0 && (gap / apart - beat).abs() < beat * BEAT_TOLERANCE {
+                    spanned += gap;
+                    beats += apart;
+                }
+            }
+        }
+        Some(60.0 * beats / spanned)
+    }
+}
@@ -0,0 +1,348 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use spectrum_analyzer::windows::{hamming_window, hann_window};
+use spectrum_analyzer::{FrequencyLimit, samples_fft_to_spectrum};
+use std::collections::VecDeque;
+use std::f32::consts::PI;
+use std::ops::RangeInclusive;
+
+/// How many samples each FFT covers. At 48 kHz, bins are about 12 Hz apart, fine enough to split up the bass band.
+pub const FFT_LEN: usize = 4096;
+/// How many new samples arrive between analyses, so that levels update more often than whole windows.
+pub const FFT_HOP: usize = 1024;
+/// The FFT lengths that can be chosen, each of which must also be a power of two.
+pub const FFT_LEN_RANGE: RangeInclusive<usize> = 256..=16384;
+/// How strongly magnitudes are compressed before measuring flux, so that quiet frequencies rising count as well as loud ones.
+const FLUX_COMPRESSION: f32 = 1000.0;
+
+/// The window applied to each block before its FFT, trading how precisely frequencies are told apart
+/// against how much a loud one leaks into its neighbours.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum WindowFunction {
+    /// A good balance of the two.
+    #[default]
+    Hann,
+    /// Tells neighbouring frequencies apart a little better, leaking a little more further out.
+    Hamming,
+    /// Leaks least of all, blurring neighbouring frequencies together the most.
+    BlackmanHarris,
+}
+
+impl WindowFunction {
+    fn apply(self, block: &[f32]) -> Vec<f32> {
+        match self {
+            WindowFunction::Hann => hann_window(block),
+            WindowFunction::Hamming => hamming_window(block),
+            WindowFunction::BlackmanHarris => blackman_harris(block),
+        }
+    }
+
+    /// The window's mean square, by which it attenuates a signal's power.
+    fn mean_square(self, len: usize) -> f32 {
+        let window = self.apply(&vec![1.0; len]);
+        window.iter().map(|weight| weight * weight).sum::<f32>() / len.max(1) as f32
+    }
+}
+
+/// Applies a 4-term Blackman-Harris window.
+// spectrum_analyzer's own takes each sample's value in place of its position, so can't be used.
+fn blackman_harris(block: &[f32]) -> Vec<f32> {
+    const TERMS: [f32; 4] = [0.35875, -0.48829, 0.14128, -0.01168];
+    let len = block.len() as f32;
+    block
+        .iter()
+        .enumerate()
+        .map(|(i, sample)| {
+            let weight: f32 = TERMS
+                .iter()
+                .enumerate()
+                .map(|(k, term)| term * (2.0 * PI * k as f32 * i as f32 / len).cos())
+                .sum();
+            sample * weight
+        })
+        .collect()
+}
+
+/// How blocks are taken from streaming audio for FFTs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FftSettings {
+    /// How many samples each FFT covers, which must be a power of two. Longer FFTs tell lower frequencies apart,
+    /// but respond more slowly.
+    pub len: usize,
+    /// How many new samples arrive between analyses, at most `len`.
+    pub hop: usize,
+    pub window: WindowFunction,
+}
+
+impl Default for FftSettings {
+    fn default() -> Self {
+        Self {
+            len: FFT_LEN,
+            hop: FFT_HOP,
+            window: WindowFunction::default(),
+        }
+    }
+}
+
+/// A range of frequencies, in Hz. The lower bound is inclusive and the upper exclusive.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Band {
+    pub low_hz: f32,
+    pub high_hz: f32,
+}
+
+impl Band {
+    pub fn new(low_hz: f32, high_hz: f32) -> Self {
+        Self { low_hz, high_hz }
+    }
+
+    fn contains(&self, frequency: f32) -> bool {
+        (self.low_hz..self.high_hz).contains(&frequency)
+    }
+}
+
+/// The RMS level of each band within `block`, whose length must be a power of two.
+///
+/// Levels are comparable to those of the original signal: a full-scale sine within a band
+/// gives it a level of about 0.707, despite the window applied beforehand.
+pub fn band_levels(block: &[f32], sample_rate: f32, bands: &[Band]) -> Vec<f32> {
+    let window = WindowFunction::Hann;
+    let mean_square = window.mean_square(block.len());
+    levels_within(
+        &power_spectrum(block, sample_rate, window, mean_square),
+        bands,
+    )
+}
+
+/// Each frequency within `block` alongside its share of the block's mean square,
+/// having applied `window`, whose mean square is given.
+fn power_spectrum(
+    block: &[f32],
+    sample_rate: f32,
+    window: WindowFunction,
+    mean_square: f32,
+) -> Vec<(f32, f32)> {
+    let windowed = window.apply(block);
+    let Ok(spectrum) =
+        samples_fft_to_spectrum(&windowed, sample_rate as u32, FrequencyLimit::All, None)
+    else {
+        return Vec::new();
+    };
+
+    // By Parseval's theorem, a bin's share of the mean square is |X|^2 / N^2,
+    // doubled to account for the negative frequencies we don't see.
+    let len = block.len() as f32;
+    spectrum
+        .data()
+        .iter()
+        .map(|(frequency, value)| {
+            (
+                frequency.val(),
+                2.0 * value.val().powi(2) / (len * len * mean_square),
+            )
+        })
+        .collect()
+}
+
+fn levels_within(spectrum: &[(f32, f32)], bands: &[Band]) -> Vec<f32> {
+    bands
+        .iter()
+        .map(|band| {
+            let power: f32 = spectrum
+                .iter()
+                .filter(|(frequency, _)| band.contains(*frequency))
+                .map(|(_, power)| power)
+                .sum();
+            power.sqrt()
+        })
+        .collect()
+}
+
+/// Measures the level of several bands over a sliding window, as samples stream in.
+pub struct SpectrumAnalyzer {
+    sample_rate: f32,
+    bands: Vec<Band>,
+    fft: FftSettings,
+    /// The window function's mean square, for the FFT's length.
+    mean_square: f32,
+    window: VecDeque<f32>,
+    /// How many samples have arrived since the last analysis.
+    pending: usize,
+    /// The compressed magnitude of each frequency within the bands, as of the last analysis.
+    magnitudes: Vec<f32>,
+    flux: f32,
+}
+
+impl SpectrumAnalyzer {
+    pub fn new(sample_rate: f32, bands: Vec<Band>) -> Self {
+        Self::with_fft(sample_rate, bands, FftSettings::default())
+    }
+
+    pub fn with_fft(sample_rate: f32, bands: Vec<Band>, fft: FftSettings) -> Self {
+        let fft = FftSettings {
+            hop: fft.hop.clamp(1, fft.len),
+            ..fft
+        };
+        Self {
+            sample_rate,
+            bands,
+            fft,
+            mean_square: fft.window.mean_square(fft.len),
+            // Starting from silence means levels are available after the first hop, rather than a whole window.
+            window: VecDeque::from(vec![0.0; fft.len]),
+            pending: 0,
+            magnitudes: Vec::new(),
+            flux: 0.0,
+        }
+    }
+
+    pub fn bands(&self) -> &[Band] {
+        &self.bands
+    }
+
+    /// How much frequencies within the bands rose by between the latest two analyses, on average.
+    /// Falling frequencies don't count against those rising.
+    pub fn flux(&self) -> f32 {
+        self.flux
+    }
+
+    /// Feeds mono samples, returning the level of each band should another hop's worth have arrived.
+    /// Only the latest levels are returned if several hops arrive at once.
+    pub fn process(&mut self, samples: &[f32]) -> Option<Vec<f32>> {
+        let mut due = false;
+        for &sample in samples {
+            self.window.pop_front();
+            self.window.push_back(sample);
+            self.pending += 1;
+            if self.pending == self.fft.hop {
+                self.pending = 0;
+                due = true;
+            }
+        }
+        due.then(|| {
+            let block: Vec<f32> = self.window.iter().copied().collect();
+            let spectrum =
+                power_spectrum(&block, self.sample_rate, self.fft.window, self.mean_square);
+            let magnitudes: Vec<f32> = spectrum
+                .iter()
+                .filter(|(frequency, _)| self.bands.iter().any(|band| band.contains(*frequency)))
+                .map(|(_, power)| (FLUX_COMPRESSION * power.sqrt()).ln_1p())
+                .collect();
+            let rises = magnitudes
+                .iter()
+                .zip(&self.magnitudes)
+                .map(|(now, before)| (now - before).max(0.0));
+            self.flux = rises.sum::<f32>() / magnitudes.len().max(1) as f32;
+            self.magnitudes = magnitudes;
+            levels_within(&spectrum, &self.bands)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::TAU;
+
+    const SAMPLE_RATE: f32 = 48000.0;
+
+    fn sine(frequency: f32, amplitude: f32, len: usize) -> Vec<f32> {
+        (0..len)
+            .map(|i| amplitude * (TAU * frequency * i as f32 / SAMPLE_RATE).sin())
+            .collect()
+    }
+
+    fn bass_and_mid() -> Vec<Band> {
+        vec![Band::new(20.0, 120.0), Band::new(250.0, 2000.0)]
+    }
+
+    #[test]
+    fn sine_lands_in_its_own_band() {
+        let levels = band_levels(&sine(55.0, 1.0, FFT_LEN), SAMPLE_RATE, &bass_and_mid());
+        assert!(
+            (levels[0] - std::f32::consts::FRAC_1_SQRT_2).abs() < 0.05,
+            "bass level was {}",
+            levels[0]
+        );
+        assert!(levels[1] < 0.01, "mid level was {}", levels[1]);
+    }
+
+    #[test]
+    fn levels_scale_with_amplitude() {
+        let bands = bass_and_mid();
+        let loud = band_levels(&sine(700.0, 0.8, FFT_LEN), SAMPLE_RATE, &bands)[1];
+        let quiet = band_levels(&sine(700.0, 0.2, FFT_LEN), SAMPLE_RATE, &bands)[1];
+        assert!((loud / quiet - 4.0).abs() < 0.01, "{} vs {}", loud, quiet);
+    }
+
+    #[test]
+    fn silence_has_no_level() {
+        let levels = band_levels(&[0.0; FFT_LEN], SAMPLE_RATE, &bass_and_mid());
+        assert!(levels.iter().all(|level| *level == 0.0), "{:?}", levels);
+    }
+
+    #[test]
+    fn streaming_reports_once_per_hop() {
+        let mut analyzer = SpectrumAnalyzer::new(SAMPLE_RATE, bass_and_mid());
+        let signal = sine(55.0, 1.0, FFT_LEN * 2);
+        let reports: Vec<Vec<f32>> = signal
+            .chunks(FFT_HOP / 4)
+            .filter_map(|block| analyzer.process(block))
+            .collect();
+        assert_eq!(reports.len(), signal.len() / FFT_HOP);
+
+        // Once the window is full of the sine, the analyzer agrees with analyzing it all at once.
+        let whole = band_levels(
+            &signal[signal.len() - FFT_LEN..],
+            SAMPLE_RATE,
+            analyzer.bands(),
+        );
+        let streamed = reports.last().unwrap();
+        assert!((streamed[0] - whole[0]).abs() < 1e-3);
+    }
+
+    #[test]
+    fn every_window_measures_the_same_level() {
+        for window in WindowFunction::value_variants() {
+            let fft = FftSettings {
+                len: 2048,
+                hop: 512,
+                window: *window,
+            };
+            let mut analyzer = SpectrumAnalyzer::with_fft(SAMPLE_RATE, bass_and_mid(), fft);
+            let levels = sine(700.0, 1.0, fft.len)
+                .chunks(fft.hop)
+                .filter_map(|block| analyzer.process(block))
+                .last()
+                .unwrap();
+            assert!(
+                (levels[1] - std::f32::consts::FRAC_1_SQRT_2).abs() < 0.05,
+                "{:?} measured {}",
+                window,
+                levels[1]
+            );
+        }
+    }
+
+    #[test]
+    fn flux_only_rises_as_a_sound_begins() {
+        let mut analyzer = SpectrumAnalyzer::new(SAMPLE_RATE, bass_and_mid());
+        let mut signal = vec![0.0; FFT_LEN];
+        signal.extend(sine(700.0, 0.5, FFT_LEN * 2));
+        let flux: Vec<f32> = signal
+            .chunks(FFT_HOP)
+            .filter_map(|block| analyzer.process(block).map(|_| analyzer.flux()))
+            .collect();
+
+        let silence = FFT_LEN / FFT_HOP;
+        assert!(
+            flux[..silence].iter().all(|flux| *flux == 0.0),
+            "{:?}",
+            flux
+        );
+        assert!(flux[silence] > 0.1, "{:?}", flux);
+        // Once the sine fills the window, nothing more rises.
+        assert!(flux[flux.len() - 1] < 0.01, "{:?}", flux);
+    }
+}
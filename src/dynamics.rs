@@ -0,0 +1,176 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::Duration;
+
+use crate::mapping::clamp_unit;
+
+/// Reins in loud transients within the intensity, so that they don't slam devices to full while
+/// everything else sits far below.
+///
+/// Intensities above the threshold only rise by a fraction of what they otherwise would,
+/// easing in across the knee, and nothing passes the limit at all.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Compressor {
+    /// The intensity (0 to 1) above which compression begins.
+    pub threshold: f64,
+    /// How many times less intensities above the threshold rise by.
+    pub ratio: f64,
+    /// How wide a range around the threshold compression eases in across.
+    pub knee: f64,
+    /// No intensity the compressor lets through is higher than this.
+    pub limit: f64,
+}
+
+impl Compressor {
+    pub fn run(&self, intensity: f64) -> f64 {
+        let intensity = clamp_unit(intensity);
+        let threshold = clamp_unit(self.threshold);
+        let slope = 1.0 / self.ratio.max(1.0);
+        let knee = self.knee.max(0.0);
+        let over = intensity - threshold;
+
+        let compressed = if 2.0 * over <= -knee {
+            intensity
+        } else if 2.0 * over >= knee {
+            threshold + over * slope
+        } else {
+            // Within the knee, the slope eases from 1 to the ratio's along a quadratic.
+            let into = over + knee / 2.0;
+            intensity + (slope - 1.0) * into * into / (2.0 * knee)
+        };
+        compressed.min(clamp_unit(self.limit))
+    }
+}
+
+impl fmt::Display for Compressor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:1 above {}, knee {}, limit {}",
+            self.ratio, self.threshold, self.knee, self.limit
+        )
+    }
+}
+
+/// Silences the intensity while it's low, without chattering as it hovers around the threshold.
+///
+/// The gate opens once the intensity reaches `open`, and only closes again once it has stayed below `close`
+/// for the whole hold time.
+#[derive(Clone, Debug)]
+pub struct NoiseGate {
+    open: f64,
+    close: f64,
+    hold: Duration,
+    is_open: bool,
+    /// How long the intensity has been below the closing threshold while open.
+    below_for: Duration,
+}
+
+impl NoiseGate {
+    pub fn new(open: f64, close: f64, hold: Duration) -> Self {
+        Self {
+            open,
+            // Closing above the opening threshold would leave nothing between them to hover in.
+            close: close.min(open),
+            hold,
+            is_open: false,
+            below_for: Duration::ZERO,
+        }
+    }
+
+    /// Gates `intensity`, `elapsed` after the previous call.
+    pub fn run(&mut self, intensity: f64, elapsed: Duration) -> f64 {
+        if intensity >= self.open {
+            self.is_open = true;
+        }
+        if intensity < self.close {
+            self.below_for += elapsed;
+            if self.below_for >= self.hold {
+                self.is_open = false;
+            }
+        } else {
+            self.below_for = Duration::ZERO;
+        }
+        if self.is_open { intensity } else { 0.0 }
+    }
+}
+
+impl fmt::Display for NoiseGate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "opens at {}, closes below {} after {} ms",
+            self.open,
+            self.close,
+            self.hold.as_millis()
+        )
+    }
+}
+
+/// What happens to the intensity while someone is speaking.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DuckMode {
+    /// Lowers the intensity by the duck's depth.
+    #[default]
+    Duck,
+    /// Holds the intensity wherever it was when speech began.
+    Freeze,
+}
+
+/// Lowers or freezes the intensity while speech is detected, such as a call bleeding into the mix,
+/// easing back once it ends.
+#[derive(Clone, Debug)]
+pub struct Ducker {
+    mode: DuckMode,
+    /// How much of the intensity is taken away while ducked, from 0 to 1.
+    depth: f64,
+    /// How long the intensity takes to return to normal once speech ends.
+    release: Duration,
+    /// How far the intensity has returned to normal, from 0 while speaking to 1 once released.
+    recovery: f64,
+    /// The intensity when speech began, while frozen.
+    held: f64,
+}
+
+impl Ducker {
+    pub fn new(mode: DuckMode, depth: f64, release: Duration) -> Self {
+        Self {
+            mode,
+            depth: clamp_unit(depth),
+            release,
+            recovery: 1.0,
+            held: 0.0,
+        }
+    }
+
+    /// Ducks `intensity` if `speaking`, `elapsed` after the previous call.
+    pub fn run(&mut self, intensity: f64, speaking: bool, elapsed: Duration) -> f64 {
+        if speaking {
+            if self.recovery >= 1.0 {
+                self.held = intensity;
+            }
+            self.recovery = 0.0;
+        } else if self.release.is_zero() {
+            self.recovery = 1.0;
+        } else {
+            self.recovery =
+                (self.recovery + elapsed.as_secs_f64() / self.release.as_secs_f64()).min(1.0);
+        }
+        match self.mode {
+            DuckMode::Duck => intensity * (1.0 - self.depth * (1.0 - self.recovery)),
+            DuckMode::Freeze => self.held + (intensity - self.held) * self.recovery,
+        }
+    }
+}
+
+impl fmt::Display for Ducker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.mode {
+            DuckMode::Duck => write!(f, "{}% while speaking", self.depth * 100.0)?,
+            DuckMode::Freeze => write!(f, "freeze while speaking")?,
+        }
+        write!(f, ", release {} ms", self.release.as_millis())
+    }
+}
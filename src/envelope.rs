@@ -0,0 +1,214 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// How the envelope channel behaves once the vibration loop falls behind
+/// and the queue is full.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BackpressurePolicy {
+    /// Discard the oldest queued value to make room for the new one.
+    #[default]
+    DropOldest,
+    /// Discard the incoming value, keeping what is already queued.
+    DropNewest,
+    /// Keep only the most recent value, replacing anything queued.
+    CoalesceLatest,
+}
+
+/// Counters describing what the channel has done with the values sent through it.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct EnvelopeStats {
+    pub sent: u64,
+    pub dropped_oldest: u64,
+    pub dropped_newest: u64,
+    pub coalesced: u64,
+}
+
+#[derive(Default)]
+struct Counters {
+    sent: AtomicU64,
+    dropped_oldest: AtomicU64,
+    dropped_newest: AtomicU64,
+    coalesced: AtomicU64,
+}
+
+struct Shared {
+    queue: Mutex<VecDeque<f64>>,
+    capacity: usize,
+    policy: BackpressurePolicy,
+    closed: AtomicBool,
+    counters: Counters,
+}
+
+impl Shared {
+    fn stats(&self) -> EnvelopeStats {
+        EnvelopeStats {
+            sent: self.counters.sent.load(Ordering::Relaxed),
+            dropped_oldest: self.counters.dropped_oldest.load(Ordering::Relaxed),
+            dropped_newest: self.counters.dropped_newest.load(Ordering::Relaxed),
+            coalesced: self.counters.coalesced.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Returned when sending to a channel whose receiver has gone away.
+#[derive(Debug)]
+pub struct ChannelClosed;
+
+/// The sending half, held by audio analysis.
+pub struct EnvelopeSender {
+    shared: Arc<Shared>,
+}
+
+/// The receiving half, held by the vibration loop.
+pub struct EnvelopeReceiver {
+    shared: Arc<Shared>,
+}
+
+/// Creates a bounded envelope channel holding at most `capacity` values.
+pub fn channel(capacity: usize, policy: BackpressurePolicy) -> (EnvelopeSender, EnvelopeReceiver) {
+    assert!(capacity > 0, "envelope channel capacity must be non-zero");
+    let shared = Arc::new(Shared {
+        queue: Mutex::new(VecDeque::with_capacity(capacity)),
+        capacity,
+        policy,
+        closed: AtomicBool::new(false),
+        counters: Counters::default(),
+    });
+    (
+        EnvelopeSender {
+            shared: shared.clone(),
+        },
+        EnvelopeReceiver { shared },
+    )
+}
+
+impl EnvelopeSender {
+    /// Queues a value, applying the configured policy if the queue is full.
+    /// This never blocks, as it is called from within audio processing.
+    pub fn send(&self, value: f64) -> Result<(), ChannelClosed> {
+        if self.shared.closed.load(Ordering::Acquire) {
+            return Err(ChannelClosed);
+        }
+
+        let counters = &self.shared.counters;
+        {
+            let mut queue = self.shared.queue.lock().unwrap();
+            match self.shared.policy {
+                BackpressurePolicy::CoalesceLatest => {
+                    if !queue.is_empty() {
+                        counters
+                            .coalesced
+                            .fetch_add(queue.len() as u64, Ordering::Relaxed);
+                        queue.clear();
+                    }
+                }
+                BackpressurePolicy::DropOldest => {
+                    if queue.len() >= self.shared.capacity {
+                        queue.pop_front();
+                        counters.dropped_oldest.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+                BackpressurePolicy::DropNewest => {
+                    if queue.len() >= self.shared.capacity {
+                        counters.dropped_newest.fetch_add(1, Ordering::Relaxed);
+                        return Ok(());
+                    }
+                }
+            }
+            queue.push_back(value);
+        }
+        counters.sent.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+impl Drop for EnvelopeSender {
+    fn drop(&mut self) {
+        self.shared.closed.store(true, Ordering::Release);
+    }
+}
+
+impl EnvelopeReceiver {
+    /// Moves up to `limit` queued values into `buffer` without waiting, returning how many were moved.
+    ///
+    /// Fails once the sender has been dropped and the queue is drained.
+    pub fn try_recv_many(
+        &mut self,
+        buffer: &mut Vec<f64>,
+        limit: usize,
+    ) -> Result<usize, ChannelClosed> {
+        let mut queue = self.shared.queue.lock().unwrap();
+        if queue.is_empty() && self.shared.closed.load(Ordering::Acquire) {
+            return Err(ChannelClosed);
+        }
+        let count = usize::min(limit, queue.len());
+        buffer.extend(queue.drain(..count));
+        Ok(count)
+    }
+
+    pub fn stats(&self) -> EnvelopeStats {
+        self.shared.stats()
+    }
+}
+
+impl Drop for EnvelopeReceiver {
+    fn drop(&mut self) {
+        self.shared.closed.store(true, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sends `values` through a channel holding two, then drains it.
+    fn send_all(policy: BackpressurePolicy, values: &[f64]) -> (Vec<f64>, EnvelopeStats) {
+        let (tx, mut rx) = channel(2, policy);
+        for &value in values {
+            tx.send(value).unwrap();
+        }
+        let mut received = Vec::new();
+        rx.try_recv_many(&mut received, usize::MAX).unwrap();
+        (received, rx.stats())
+    }
+
+    #[test]
+    fn drop_oldest_keeps_the_latest_values() {
+        let (received, stats) = send_all(BackpressurePolicy::DropOldest, &[1.0, 2.0, 3.0, 4.0]);
+        assert_eq!(received, [3.0, 4.0]);
+        assert_eq!(stats.sent, 4);
+        assert_eq!(stats.dropped_oldest, 2);
+        assert_eq!(stats.dropped_newest, 0);
+    }
+
+    #[test]
+    fn drop_newest_keeps_the_earliest_values() {
+        let (received, stats) = send_all(BackpressurePolicy::DropNewest, &[1.0, 2.0, 3.0, 4.0]);
+        assert_eq!(received, [1.0, 2.0]);
+        assert_eq!(stats.sent, 2);
+        assert_eq!(stats.dropped_newest, 2);
+        assert_eq!(stats.dropped_oldest, 0);
+    }
+
+    #[test]
+    fn coalesce_latest_keeps_only_the_last_value() {
+        let (received, stats) = send_all(BackpressurePolicy::CoalesceLatest, &[1.0, 2.0, 3.0]);
+        assert_eq!(received, [3.0]);
+        assert_eq!(stats.sent, 3);
+        assert_eq!(stats.coalesced, 2);
+    }
+
+    #[test]
+    fn closes_once_drained() {
+        let (tx, mut rx) = channel(2, BackpressurePolicy::DropOldest);
+        tx.send(1.0).unwrap();
+        drop(tx);
+        let mut received = Vec::new();
+        assert_eq!(rx.try_recv_many(&mut received, 1).unwrap(), 1);
+        assert!(rx.try_recv_many(&mut received, 1).is_err());
+    }
+}
@@ -0,0 +1,321 @@
+use std::time::Duration;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::audio::Genre;
+use crate::calibration::CalibrationProgress;
+use crate::console::Command;
+use crate::devices::DeviceSettings;
+use crate::media::NowPlaying;
+use crate::schedule::CyclePhase;
+use crate::updates::Release;
+
+/// How many lines of release notes are shown when an update is available.
+const CHANGELOG_LINES: usize = 15;
+
+/// How many events a slow subscriber may fall behind by before it starts missing them.
+const EVENT_CAPACITY: usize = 256;
+
+/// Something that happened within one subsystem, which others may be interested in.
+#[derive(Clone, Debug)]
+pub enum AppEvent {
+    Audio(AudioEvent),
+    Device(DeviceEvent),
+    Session(SessionEvent),
+    /// An action requested by the user, e.g. via the console.
+    User(Command),
+    /// A failure that doesn't belong to any particular subsystem.
+    Error(String),
+}
+
+#[derive(Clone, Debug)]
+pub enum AudioEvent {
+    /// A capture stream was opened on the named device.
+    StreamOpened {
+        device: String,
+        sample_rate: u32,
+        channels: u16,
+    },
+    /// A capture stream could not be opened on the named device.
+    StreamFailed { device: String, reason: String },
+    /// A running stream reported an error.
+    StreamError(String),
+    /// Audio in the given format is being ignored, as it can't be analyzed.
+    FormatRejected {
+        sample_rate: u32,
+        channels: u16,
+        reason: String,
+    },
+    /// Incoming audio exceeded full scale after input gain.
+    Clipping { input_gain: f32 },
+    /// The content being analyzed seems to have changed genre.
+    GenreDetected(Genre),
+    /// Capture has stopped for good.
+    Stopped,
+}
+
+#[derive(Clone, Debug)]
+pub enum DeviceEvent {
+    Added {
+        index: u32,
+        name: String,
+        settings: DeviceSettings,
+    },
+    Removed {
+        index: u32,
+        name: String,
+    },
+    ServerDisconnected,
+}
+
+#[derive(Clone, Debug)]
+pub enum SessionEvent {
+    /// Output was stopped and paused after the given amount of activity.
+    AutoStopped {
+        after: Duration,
+    },
+    /// An interval cycle moved into a new phase.
+    CyclePhaseChanged(CyclePhase),
+    Calibration(CalibrationProgress),
+    /// An injected click took `analysis` to reach the vibration loop, and each device took
+    /// the given time thereafter to acknowledge the resulting command.
+    LatencyMeasured {
+        analysis: Duration,
+        devices: Vec<(String, Duration)>,
+    },
+    /// An injected click, at the given frequency in Hz, never made it through analysis in time.
+    LatencyFailed {
+        click_hz: f32,
+        timeout: Duration,
+    },
+    /// Output is resting after staying intense for the given amount of time.
+    DutyRestStarted {
+        after: Duration,
+    },
+    /// A rest from the duty-cycle limiter is over.
+    DutyRestEnded,
+    /// Quiet hours began, holding output at or below the given ceiling, or ended given `None`.
+    QuietHours(Option<f64>),
+    /// Nothing has been playing for a while, so devices are let go of; or something is again, given `false`.
+    Idle(bool),
+    /// A newer release than the one running has been published.
+    UpdateAvailable(Release),
+    /// Output was paused as the named device disconnected.
+    PausedForDisconnect {
+        device: String,
+    },
+    /// Every device that disconnected mid-session is back, the named one last.
+    DevicesReconnected {
+        device: String,
+    },
+    /// The sleep timer ran out, ending the session.
+    SleepEnded {
+        after: Duration,
+    },
+    /// Media players began playing the given track, or stopped reporting one given `None`.
+    TrackChanged(Option<NowPlaying>),
+    /// An Ableton Link session's tempo changed, or every peer left it given `None`.
+    LinkTempo(Option<f64>),
+    /// A build-up gave way to a drop, which output is boosted for.
+    Drop,
+}
+
+/// A broadcast bus carrying [`AppEvent`]s between subsystems.
+///
+/// High-rate audio analysis values do not travel over the bus;
+/// they have their own envelope channel with explicit backpressure.
+#[derive(Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<AppEvent>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        let (sender, _) = broadcast::channel(EVENT_CAPACITY);
+        Self { sender }
+    }
+}
+
+impl EventBus {
+    /// Publishes an event to all current subscribers. Having no subscribers is not an error.
+    pub fn publish(&self, event: AppEvent) {
+        let _ = self.sender.send(event);
+    }
+
+    pub fn subscribe(&self) -> EventSubscriber {
+        EventSubscriber {
+            receiver: self.sender.subscribe(),
+        }
+    }
+}
+
+/// Receives events published after it subscribed.
+pub struct EventSubscriber {
+    receiver: broadcast::Receiver<AppEvent>,
+}
+
+impl EventSubscriber {
+    /// Waits for the next event, skipping over any this subscriber was too slow to receive.
+    ///
+    /// Returns `None` once every publisher has gone away.
+    pub async fn recv(&mut self) -> Option<AppEvent> {
+        loop {
+            match self.receiver.recv().await {
+                Ok(event) => return Some(event),
+                Err(RecvError::Lagged(missed)) => {
+                    println!("Event subscriber fell behind, missing {} events", missed)
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+}
+
+/// Prints subsystem events to the console as they happen.
+pub async fn log_events(mut events: EventSubscriber) {
+    while let Some(event) = events.recv().await {
+        match event {
+            AppEvent::Audio(AudioEvent::StreamOpened {
+                device,
+                sample_rate,
+                channels,
+            }) => println!(
+                "Now monitoring: {} ({} Hz, {} channels)",
+                device, sample_rate, channels
+            ),
+            AppEvent::Audio(AudioEvent::StreamFailed { device, reason }) => {
+                println!("Unable to monitor {}: {}", device, reason)
+            }
+            AppEvent::Audio(AudioEvent::StreamError(e)) => println!("Audio stream error: {}", e),
+            AppEvent::Audio(AudioEvent::FormatRejected {
+                sample_rate,
+                channels,
+                reason,
+            }) => println!(
+                "Ignoring audio at {} Hz with {} channels, as {}.",
+                sample_rate, channels, reason
+            ),
+            AppEvent::Audio(AudioEvent::Clipping { input_gain }) => println!(
+                "Input is clipping at an input gain of {}; try lowering it with `input-gain`.",
+                input_gain
+            ),
+            AppEvent::Audio(AudioEvent::GenreDetected(genre)) => {
+                println!("Detected genre: {}", genre.name())
+            }
+            AppEvent::Audio(AudioEvent::Stopped) => println!("Audio capture stopped."),
+            AppEvent::Device(DeviceEvent::Added { name, settings, .. }) => println!(
+                "Device added: {} (enabled: {}, multiplier: {})",
+                name, settings.enabled, settings.multiplier
+            ),
+            AppEvent::Device(DeviceEvent::Removed { name, .. }) => {
+                println!("Device removed: {}", name)
+            }
+            AppEvent::Device(DeviceEvent::ServerDisconnected) => println!("Server disconnected!"),
+            AppEvent::Session(SessionEvent::AutoStopped { after }) => println!(
+                "Stopped automatically after {} minutes of output. Type `resume` to continue.",
+                after.as_secs_f64() / 60.0
+            ),
+            AppEvent::Session(SessionEvent::CyclePhaseChanged(phase)) => match phase {
+                CyclePhase::Active => println!("Interval cycle: active"),
+                CyclePhase::Rest => println!("Interval cycle: resting"),
+            },
+            AppEvent::Session(SessionEvent::Calibration(progress)) => match progress {
+                CalibrationProgress::MeasuringNoise => {
+                    println!(
+                        "Calibrating: make sure nothing is playing, measuring the noise floor..."
+                    )
+                }
+                CalibrationProgress::WaitingForMusic => {
+                    println!("Calibrating: now play some typical music.")
+                }
+                CalibrationProgress::MeasuringMusic => {
+                    println!("Calibrating: measuring music, keep it playing...")
+                }
+                CalibrationProgress::Finished(mapping) => println!(
+                    "Calibration finished: gain {:.2}, deadzone {:.2}. These are kept in the session snapshot for --restore.",
+                    mapping.gain, mapping.deadzone
+                ),
+                CalibrationProgress::Failed(reason) => println!("Calibration failed: {}", reason),
+            },
+            AppEvent::Session(SessionEvent::LatencyMeasured { analysis, devices }) => {
+                println!("Audio to command: {} ms", analysis.as_millis());
+                if devices.is_empty() {
+                    println!(
+                        "  No devices acknowledged a command; are any connected and unpaused?"
+                    );
+                }
+                for (name, acknowledged) in devices {
+                    println!(
+                        "  {}: acknowledged after a further {} ms ({} ms in total)",
+                        name,
+                        acknowledged.as_millis(),
+                        (analysis + acknowledged).as_millis()
+                    );
+                }
+            }
+            AppEvent::Session(SessionEvent::LatencyFailed { click_hz, timeout }) => println!(
+                "The test click at {:.0} Hz wasn't detected within {} seconds; make sure nothing else is playing, \
+                 and that analysis isn't filtered away from it, then try again.",
+                click_hz,
+                timeout.as_secs()
+            ),
+            AppEvent::Session(SessionEvent::DutyRestStarted { after }) => println!(
+                "Output has been intense for {} minutes; resting for a moment.",
+                after.as_secs_f64() / 60.0
+            ),
+            AppEvent::Session(SessionEvent::DutyRestEnded) => println!("Rest over."),
+            AppEvent::Session(SessionEvent::QuietHours(ceiling)) => match ceiling {
+                Some(0.0) => println!("Quiet hours have begun; output is stopped until they end."),
+                Some(ceiling) => println!(
+                    "Quiet hours have begun; output is limited to {:.2}.",
+                    ceiling
+                ),
+                None => println!("Quiet hours are over."),
+            },
+            AppEvent::Session(SessionEvent::Idle(true)) => {
+                println!("No audio for a while; disconnecting from devices until it returns.")
+            }
+            AppEvent::Session(SessionEvent::Idle(false)) => {
+                println!("Audio is back; reconnecting to devices.")
+            }
+            AppEvent::Session(SessionEvent::UpdateAvailable(release)) => {
+                println!(
+                    "subwoofer {} is available (you have {}): {}",
+                    release.tag_name,
+                    env!("CARGO_PKG_VERSION"),
+                    release.html_url
+                );
+                // Only the start of the changelog, so as not to drown out everything else.
+                if let Some(notes) = &release.body {
+                    for line in notes.lines().take(CHANGELOG_LINES) {
+                        println!("  {}", line);
+                    }
+                }
+            }
+            AppEvent::Session(SessionEvent::PausedForDisconnect { device }) => {
+                println!("!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!");
+                println!("{} disconnected; output is paused.", device);
+                println!("!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!");
+            }
+            AppEvent::Session(SessionEvent::DevicesReconnected { device }) => {
+                println!("{} reconnected. Type `resume` to continue.", device)
+            }
+            AppEvent::Session(SessionEvent::SleepEnded { after }) => println!(
+                "Sleep timer of {} minutes has run out. Good night!",
+                after.as_secs_f64() / 60.0
+            ),
+            AppEvent::Session(SessionEvent::TrackChanged(Some(playing))) => {
+                println!("Now playing: {}", playing)
+            }
+            AppEvent::Session(SessionEvent::TrackChanged(None)) => {}
+            AppEvent::Session(SessionEvent::LinkTempo(Some(bpm))) => {
+                println!("Following Ableton Link at {:.1} BPM", bpm)
+            }
+            AppEvent::Session(SessionEvent::LinkTempo(None)) => {
+                println!("Ableton Link session ended; estimating the tempo again.")
+            }
+            AppEvent::Session(SessionEvent::Drop) => println!("Drop!"),
+            AppEvent::Error(e) => println!("Error: {}", e),
+            AppEvent::User(_) => {}
+        }
+    }
+}
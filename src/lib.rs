@@ -0,0 +1,27 @@
+pub mod audio;
+pub mod calibration;
+pub mod console;
+pub mod devices;
+pub mod dsp;
+pub mod dynamics;
+pub mod envelope;
+pub mod events;
+pub mod link;
+pub mod mapping;
+pub mod media;
+pub mod output;
+pub mod patterns;
+pub mod pipeline;
+pub mod presets;
+pub mod profiles;
+pub mod recording;
+pub mod recovery;
+pub mod schedule;
+pub mod scheduler;
+pub mod settings;
+pub mod state;
+pub mod stats;
+pub mod stroke;
+pub mod updates;
+#[cfg(feature = "visualizer")]
+pub mod visualizer;
@@ -0,0 +1,134 @@
+use socket2::{Domain, Protocol, Socket, Type};
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+
+use crate::events::{AppEvent, EventBus, SessionEvent};
+
+/// The multicast group Ableton Link peers announce themselves to.
+const LINK_GROUP: Ipv4Addr = Ipv4Addr::new(224, 76, 78, 75);
+const LINK_PORT: u16 = 20808;
+/// Every discovery message begins with this.
+const PROTOCOL_HEADER: &[u8; 8] = b"_asdp_v\x01";
+/// Discovery message types: a peer announcing itself, replying to another, or leaving.
+const ALIVE: u8 = 1;
+const RESPONSE: u8 = 2;
+const BYE_BYE: u8 = 3;
+/// The payload entry holding a peer's timeline, beginning with its tempo in microseconds per beat.
+const TIMELINE_KEY: u32 = u32::from_be_bytes(*b"tmln");
+/// Tempo changes smaller than this aren't worth announcing.
+const TEMPO_EPSILON: f64 = 0.01;
+
+/// What a discovery message says about the peer that sent it.
+enum Announcement {
+    Alive {
+        peer: [u8; 8],
+        /// How long the peer should be considered present for, unless it announces itself again.
+        ttl: Duration,
+        tempo: Option<f64>,
+    },
+    Leaving {
+        peer: [u8; 8],
+    },
+}
+
+/// Follows the tempo of Ableton Link sessions on the local network, announcing it on the bus.
+///
+/// We only ever listen, never joining the session as a peer ourselves.
+// TODO(spotlightishere): Align to the session's beat phase as well. That needs our clock to be
+// synchronized with a peer's, through Link's ping/pong measurements, which we don't take part in yet.
+pub async fn follow_link(bus: EventBus) {
+    let socket = match bind() {
+        Ok(socket) => socket,
+        Err(e) => {
+            println!("Unable to listen for Ableton Link sessions: {:#}", e);
+            return;
+        }
+    };
+
+    let mut peers: HashMap<[u8; 8], (Instant, f64)> = HashMap::new();
+    let mut announced: Option<f64> = None;
+    let mut buffer = [0; 512];
+    loop {
+        // Peers are checked on every message, and at least once a second, so that silent ones expire.
+        if let Ok(Ok(len)) =
+            tokio::time::timeout(Duration::from_secs(1), socket.recv(&mut buffer)).await
+        {
+            match parse(&buffer[..len]) {
+                Some(Announcement::Alive {
+                    peer,
+                    ttl,
+                    tempo: Some(tempo),
+                }) => {
+                    peers.insert(peer, (Instant::now() + ttl, tempo));
+                }
+                Some(Announcement::Leaving { peer }) => {
+                    peers.remove(&peer);
+                }
+                _ => {}
+            }
+        }
+        let now = Instant::now();
+        peers.retain(|_, (expires, _)| *expires > now);
+
+        // Every peer in a session shares its tempo, so whichever expires last is as good as any.
+        let tempo = peers
+            .values()
+            .max_by_key(|(expires, _)| *expires)
+            .map(|(_, tempo)| *tempo);
+        let changed = match (announced, tempo) {
+            (Some(before), Some(after)) => (before - after).abs() >= TEMPO_EPSILON,
+            (before, after) => before.is_some() != after.is_some(),
+        };
+        if changed {
+            announced = tempo;
+            bus.publish(AppEvent::Session(SessionEvent::LinkTempo(tempo)));
+        }
+    }
+}
+
+/// Joins the discovery group, sharing its port with any Link-enabled software running alongside us.
+fn bind() -> anyhow::Result<UdpSocket> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_reuse_address(true)?;
+    #[cfg(unix)]
+    socket.set_reuse_port(true)?;
+    socket.bind(&SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, LINK_PORT).into())?;
+    socket.join_multicast_v4(&LINK_GROUP, &Ipv4Addr::UNSPECIFIED)?;
+    socket.set_nonblocking(true)?;
+    Ok(UdpSocket::from_std(socket.into())?)
+}
+
+/// Parses a discovery message. Anything malformed, or from another version of the protocol, is ignored.
+fn parse(message: &[u8]) -> Option<Announcement> {
+    let message = message.strip_prefix(PROTOCOL_HEADER)?;
+    // The type and TTL are followed by a session group, which is always zero, and the peer's ID.
+    let (&[kind, ttl, _, _], rest) = message.split_first_chunk::<4>()?;
+    let (peer, mut payload) = rest.split_first_chunk::<8>()?;
+    match kind {
+        ALIVE | RESPONSE => {
+            let mut tempo = None;
+            while let Some((key, rest)) = payload.split_first_chunk::<4>()
+                && let Some((size, rest)) = rest.split_first_chunk::<4>()
+            {
+                let size = u32::from_be_bytes(*size) as usize;
+                let value = rest.get(..size)?;
+                if u32::from_be_bytes(*key) == TIMELINE_KEY
+                    && let Some((micros_per_beat, _)) = value.split_first_chunk::<8>()
+                {
+                    let micros_per_beat = i64::from_be_bytes(*micros_per_beat);
+                    tempo = (micros_per_beat > 0).then(|| 60_000_000.0 / micros_per_beat as f64);
+                }
+                payload = &rest[size..];
+            }
+            Some(Announcement::Alive {
+                peer: *peer,
+                ttl: Duration::from_secs(ttl as u64),
+                tempo,
+            })
+        }
+        BYE_BYE => Some(Announcement::Leaving { peer: *peer }),
+        _ => None,
+    }
+}
@@ -1,187 +1,1338 @@
-use audio_visualizer::dynamic::{
-    live_input::AudioDevAndCfg,
-    window_top_btm::{TransformFn, open_window_connect_audio},
-};
+use anyhow::Context;
+use buttplug_core::message::OutputType;
+use clap::ValueEnum;
+use cpal::traits::DeviceTrait;
+use std::collections::BTreeSet;
+use std::io::IsTerminal;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time;
 
-use buttplug::{
-    ButtplugClient, ButtplugWebsocketClientTransport, connector::ButtplugRemoteClientConnector,
-    device::ClientDeviceOutputCommand, serializer::ButtplugClientJSONSerializer,
+use subwoofer::audio::{
+    self, AnalysisOptions, AudioHandle, AudioMonitor, LatencyProbe, LiveSource, OnsetMethod,
+    SourceSpec,
 };
-use core::panic;
-use cpal::{
-    Device,
-    traits::{DeviceTrait, HostTrait},
+use subwoofer::calibration::{Calibration, CalibrationProgress};
+use subwoofer::console::{self, Command, SourceRequest};
+use subwoofer::devices::{Connection, DeviceRouter, SharedRouter};
+use subwoofer::dsp::{DropDetector, Smoother, TempoEstimator};
+use subwoofer::envelope::{self, EnvelopeReceiver};
+use subwoofer::events::{
+    self, AppEvent, AudioEvent, DeviceEvent, EventBus, EventSubscriber, SessionEvent,
 };
-use lowpass_filter::lowpass_filter;
-use std::io::{BufRead, stdin};
-use std::sync::OnceLock;
-use std::time::Duration;
-use tokio::sync::mpsc::Sender;
-use tokio::sync::mpsc::error::TrySendError;
-use tokio::{sync::mpsc, time};
+use subwoofer::link;
+use subwoofer::mapping::{IntensityMapping, ResponseCurve};
+use subwoofer::media;
+use subwoofer::output::{Aggregation, DeviceOutput};
+use subwoofer::patterns::{PATTERN_BPM, Pattern, PatternPlayer, ResponseMode};
+use subwoofer::pipeline::{DEFAULT_STAGES, Pipeline, check_stages};
+use subwoofer::profiles::AudioProfiles;
+use subwoofer::recovery::{self, Heartbeat};
+use subwoofer::scheduler::TickScheduler;
+use subwoofer::settings::{AppSettings, config_dir};
+use subwoofer::state::{self, GenreMode, RuntimeState, SharedState, Snapshot};
+use subwoofer::updates;
+#[cfg(feature = "visualizer")]
+use subwoofer::visualizer;
 
 /// For now, a maximum of 16 persisted samples at any given run is good enough to average.
 const SAMPLE_LIMIT: usize = 16;
 
-/// A global transmit channel used to send
-// TODO(spotlightishere): This is obviously unsafe, and should be removed with a GUI redesign.
-// It is used to work around the lack of local variable usage within the TransformFn closure.
-static GLOBAL_TX: OnceLock<Sender<f64>> = OnceLock::new();
+/// Envelope values this large only come from an injected click, played at full scale.
+const CLICK_THRESHOLD: f64 = 0.3;
+/// How long an injected click may take before we give up on it.
+const CLICK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long audio must be silent before an idle pattern takes over.
+const IDLE_PATTERN_DELAY: Duration = Duration::from_secs(2);
+
+/// How long to wait before first reopening a failed stream. Each further attempt waits twice as long.
+const REOPEN_BACKOFF_MIN: Duration = Duration::from_secs(1);
+/// The longest we'll wait between attempts to reopen a failed stream.
+const REOPEN_BACKOFF_MAX: Duration = Duration::from_secs(30);
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let connector = ButtplugRemoteClientConnector::<
-        ButtplugWebsocketClientTransport,
-        ButtplugClientJSONSerializer,
-    >::new(ButtplugWebsocketClientTransport::new_insecure_connector(
-        "ws://127.0.0.1:12345",
+    let mut settings = AppSettings::from_command_line();
+    recovery::install_panic_hook();
+
+    // A heartbeat left behind means the previous session never exited cleanly.
+    if let Some(crashed) = recovery::previous_crash() {
+        recovery::log_crash(&format!(
+            "session started {} (pid {}) ended uncleanly; last seen {}, analyzing {}, {}",
+            crashed.started,
+            crashed.pid,
+            crashed.last_beat,
+            crashed.source.as_deref().unwrap_or("nothing"),
+            if crashed.paused { "paused" } else { "playing" }
+        ));
+        if let Some(path) = recovery::crash_log_path() {
+            println!(
+                "The previous session didn't exit cleanly; details were logged to {}.",
+                path.display()
+            );
+        }
+        // Without a terminal, such as when launched from a desktop shortcut, there's nobody to ask.
+        if !settings.restore && std::io::stdin().is_terminal() {
+            println!("Restore it? [y/N]");
+            let mut answer = String::new();
+            let _ = std::io::stdin().read_line(&mut answer);
+            settings.restore = answer.trim().eq_ignore_ascii_case("y");
+        }
+    }
+
+    // When restoring, the snapshot takes precedence over anything passed on the command line.
+    let snapshot = match (settings.restore, state::snapshot_path()) {
+        (true, Some(path)) => match Snapshot::load(&path) {
+            Ok(snapshot) => Some(snapshot),
+            Err(e) => {
+                println!("Not restoring: {:#}", e);
+                None
+            }
+        },
+        _ => None,
+    };
+    if let Some(snapshot) = &snapshot {
+        // Arming is never restored, though --armed may still be passed alongside --restore.
+        let armed = settings.armed;
+        settings = snapshot.settings.clone();
+        settings.armed = armed;
+        // Snapshots from before the pipeline was checked may still order it in ways that no longer run.
+        if let Err(e) = check_stages(&settings.pipeline) {
+            println!("Using the default pipeline, as {}.", e);
+            settings.pipeline = DEFAULT_STAGES.to_vec();
+        }
+    }
+    if let Some(host) = &settings.host {
+        audio::select_host(host)?;
+    }
+    if settings.quiet_refuse && settings.quiet_ceiling_now().is_some() {
+        anyhow::bail!("it's currently quiet hours; try again later");
+    }
+    let state: SharedState = Arc::new(RuntimeState::default());
+    state.set_profiles(AudioProfiles::load(
+        config_dir().map(|dir| dir.join("audio_profiles.json")),
     ));
-    let client = ButtplugClient::new("subwoofer");
-
-    // TODO(spotlightishere): Properly handle errors if scanning fails
-    client.connect(connector).await?;
-    client.start_scanning().await?;
-    client.stop_scanning().await?;
-
-    // TODO(spotlightishere): We currently assume that only one device is attached.
-    //
-    // This should be refactored in the future to support multiple,
-    // similar to how we support multiple audio devices.
-    let all_devices = client.devices();
-    let Some((_, client_device)) = all_devices.first_key_value() else {
-        panic!("Unable to obtain the first client device!");
+    // Arming is never restored, so that relaunching can't surprise anyone with audio already playing.
+    state.set_armed(settings.armed);
+    state.set_response(settings.response);
+
+    // Subsystems talk to each other over a shared event bus.
+    let bus = EventBus::default();
+    tokio::spawn(events::log_events(bus.subscribe()));
+    if settings.check_updates {
+        tokio::spawn(updates::check_for_updates(bus.clone()));
+    }
+
+    let router = Arc::new(Mutex::new(DeviceRouter::load(
+        config_dir().map(|dir| dir.join("devices.json")),
+    )));
+    if let Some(snapshot) = &snapshot {
+        router.lock().unwrap().restore(snapshot.devices.clone());
+        state.set_paused(snapshot.paused);
+        state.set_pattern(snapshot.pattern);
+        state.set_metronome(snapshot.metronome);
+        state.set_genre_mode(snapshot.genre);
+    } else {
+        state.set_pattern(settings.pattern);
+        state.set_metronome(settings.metronome.map(|bpm| settings.metronome_at(bpm)));
+        if settings.genre_detect {
+            state.set_genre_mode(GenreMode::Auto);
+        }
+    }
+    tokio::spawn(follow_genres(bus.subscribe(), state.clone()));
+    if settings.now_playing {
+        tokio::spawn(media::follow_media(bus.clone()));
+    }
+    if settings.link {
+        tokio::spawn(link::follow_link(bus.clone()));
+        tokio::spawn(follow_link_tempo(bus.subscribe(), state.clone()));
+    }
+    tokio::spawn(record_events(bus.subscribe(), state.clone()));
+    if !settings.continue_on_disconnect {
+        tokio::spawn(pause_on_disconnect(
+            bus.subscribe(),
+            state.clone(),
+            bus.clone(),
+        ));
+    }
+    let connection = Connection::new(router.clone(), bus.clone());
+    connection.connect().await?;
+    if settings.idle_disconnect().is_some() {
+        tokio::spawn(disconnect_while_idle(bus.subscribe(), connection.clone()));
+    }
+
+    // We'll utilize a bounded envelope channel to communicate between our audio analysis and vibration threads.
+    // Its backpressure policy decides what happens once the vibration loop falls behind.
+    let (tx, rx) = envelope::channel(SAMPLE_LIMIT, settings.backpressure);
+
+    // A restored or requested source takes precedence; otherwise, we ask which output device to monitor.
+    // On Linux, ALSA can't hear what's playing, so the default sink's monitor is preferred where there is one.
+    let requested = snapshot
+        .as_ref()
+        .and_then(|snapshot| snapshot.source.clone())
+        .or_else(|| settings.source())
+        .or_else(|| {
+            cfg!(target_os = "linux")
+                .then(audio::default_monitor_source)
+                .flatten()
+                .map(SourceSpec::Monitor)
+        });
+    let (spec, source) = match requested.map(|spec| (spec.open(settings.capture_options()), spec)) {
+        Some((Ok(source), spec)) => (spec, source),
+        other => {
+            if let Some((Err(e), spec)) = other {
+                println!("Unable to open {}: {:#}", spec, e);
+            }
+            let default_out_dev = if settings.follow_default {
+                audio::default_output_dev().context("no default output device")?
+            } else {
+                audio::select_output_dev()
+            };
+            let default_dev_name = default_out_dev.name()?;
+            println!("Using default output device: {}", default_dev_name);
+            let source = audio::output_source(default_out_dev, settings.capture_options());
+            (SourceSpec::Live(default_dev_name), source)
+        }
     };
+    state.set_source(spec);
 
-    // We'll utilize Tokio channels to communicate between our audio analysis and vibration threads.
-    //
-    // TODO(spotlightishere): A stream might be preferable, perhaps with some sort of debounce/throttle.
-    let (tx, mut rx) = mpsc::channel::<f64>(SAMPLE_LIMIT);
-    let _ = GLOBAL_TX.get_or_init(|| tx);
+    // Audio is captured on its own thread, which can swap sources without disturbing anything downstream.
+    let monitor = Arc::new(AudioMonitor::new(audio::PROCESSING_RATE as f32));
+    let probe = Arc::new(LatencyProbe::default());
+    // Subscribing first means a stream that fails to open straight away is still noticed.
+    let stream_events = bus.subscribe();
+    let audio = audio::spawn_capture(
+        source,
+        tx,
+        monitor.clone(),
+        settings.analysis_options(),
+        probe.clone(),
+        bus.clone(),
+    );
 
-    let default_out_dev = select_output_dev();
-    let default_out_config = default_out_dev.default_output_config().unwrap().config();
-    let default_dev_name = default_out_dev.name()?;
-    println!("Using default output device: {}", default_dev_name);
+    // Closing the visualizer ends the session.
+    #[cfg(feature = "visualizer")]
+    if !settings.no_visualizer {
+        let visualizer_audio = audio.clone();
+        let visualizer_monitor = monitor.clone();
+        let visualizer_state = state.clone();
+        let visualizer_bus = bus.clone();
+        tokio::task::spawn_blocking(move || {
+            visualizer::open_window(visualizer_monitor, visualizer_state, visualizer_bus);
+            visualizer_audio.shutdown();
+        });
+    }
+    let recovery_monitor = monitor.clone();
+    // Only the window, stream recovery and the vibration loop need to see the monitor.
+    let band_monitor = monitor;
 
+    // Without a window to close, Ctrl+C is the other way out.
+    let interrupt_audio = audio.clone();
     tokio::spawn(async move {
-        open_window_connect_audio(
-            "Live Audio Lowpass Filter View",
-            None,
-            None,
-            None,
-            None,
-            "time (seconds)",
-            "Amplitude (with Lowpass filter)",
-            AudioDevAndCfg::new(Some(default_out_dev), Some(default_out_config)),
-            // lowpass filter, data processing
-            // TODO(spotlightishere): Split this up into its own function when designing for a new GUI.
-            TransformFn::Basic(|direct_values: &[f32], sampling_rate: f32| {
-                // Apply our lowpass filter prior to any other processing
-                let mut raw_values = direct_values.to_vec();
-                lowpass_filter(&mut raw_values, sampling_rate, 80.0);
-
-                // We'll sample exactly the first frequency and adjust for vibration intensity.
-                // This is not necessarily correct, but for most intents/purposes,
-                // it provides a general value.
-                let mut first_freq: f64 = *raw_values.last().unwrap() as f64;
-                first_freq = f64::abs(first_freq);
-                first_freq *= 10.0;
-
-                // Lastly, broadcast our adjusted first value!
-                // We should not be too concerned if sending fails. The queue may be full.
-                //
-                // TODO(spotlightishere): Rewrite to avoid OnceLock and the forcible unwrapping of GLOBAL_TX.
-                let Some(global_tx) = GLOBAL_TX.get() else {
-                    println!("Failed to get global TX...");
-                    return raw_values;
-                };
-
-                if let Err(TrySendError::Closed(_)) = global_tx.try_send(first_freq) {
-                    println!("Error while sending to channel... closed!");
-                }
-
-                raw_values
-            }),
-        );
+        if tokio::signal::ctrl_c().await.is_ok() {
+            interrupt_audio.shutdown();
+        }
     });
 
-    // We'll now loop over our sent channel values at a fixed rate of 35 ms.
-    // This specific interval was determined by trial and error.
-    let mut interval = time::interval(Duration::from_millis(35));
-    loop {
-        // Obtain our values.
-        //
-        // If all recievers have been cancelled, we can assume that
-        // the GUI has been closed, and thus we no longer need to handle future values.
-        // TODO(spotlightishere): This doesn't quite work!
-        let mut collected_values: Vec<f64> = Vec::with_capacity(SAMPLE_LIMIT);
-        let result = rx.recv_many(&mut collected_values, SAMPLE_LIMIT).await;
-        // If our result size is zero, the channel has been closed and we should cease looping.
-        if result == 0 {
-            println!("Detected end of tx!");
-            break;
+    let session = Session {
+        settings,
+        state: state.clone(),
+        router: router.clone(),
+        audio,
+        probe: probe.clone(),
+        bus: bus.clone(),
+    };
+    tokio::spawn(autosave(session.clone()));
+    tokio::spawn(reopen_failed_streams(
+        stream_events,
+        session.clone(),
+        recovery_monitor,
+    ));
+    if session.settings.follow_default {
+        tokio::spawn(follow_default_device(session.clone()));
+    }
+    tokio::spawn(handle_commands(bus.subscribe(), session.clone()));
+    println!("{}", console::HELP);
+    if !session.state.armed() {
+        println!("Not sending anything to devices yet; type `arm` to start.");
+    }
+    let bus_for_output = bus.clone();
+    console::spawn_console(bus);
+
+    run_vibration_logic(
+        rx,
+        router,
+        state,
+        probe,
+        band_monitor,
+        bus_for_output,
+        &session.settings,
+    )
+    .await;
+    // The loop may also end on its own, such as once the sleep timer runs out.
+    session.audio.shutdown();
+    session.save_snapshot();
+    recovery::clear();
+
+    connection.disconnect().await?;
+
+    Ok(())
+}
+
+/// Handles to everything commands may act upon.
+#[derive(Clone)]
+struct Session {
+    settings: AppSettings,
+    state: SharedState,
+    router: SharedRouter,
+    audio: AudioHandle,
+    probe: Arc<LatencyProbe>,
+    bus: EventBus,
+}
+
+impl Session {
+    fn save_snapshot(&self) {
+        let Some(path) = state::snapshot_path() else {
+            return;
+        };
+        let snapshot = Snapshot::capture(&self.settings, &self.state, &self.router.lock().unwrap());
+        if let Err(e) = snapshot.save(&path) {
+            self.bus
+                .publish(AppEvent::Error(format!("failed to save snapshot: {:#}", e)));
         }
+    }
 
-        // Average our values.
-        let collected_length = collected_values.len();
-        let mean_value: f64 = collected_values.iter().sum::<f64>() / collected_length as f64;
-        let computed_intensity = f64::min(mean_value, 1.0);
+    fn restore_snapshot(&self) {
+        let Some(path) = state::snapshot_path() else {
+            return;
+        };
+        let snapshot = match Snapshot::load(&path) {
+            Ok(snapshot) => snapshot,
+            Err(e) => {
+                self.bus.publish(AppEvent::Error(format!(
+                    "failed to restore snapshot: {:#}",
+                    e
+                )));
+                return;
+            }
+        };
+
+        self.state.set_paused(snapshot.paused);
+        self.state.set_pattern(snapshot.pattern);
+        self.state.set_metronome(snapshot.metronome);
+        self.state.set_genre_mode(snapshot.genre);
+        self.router.lock().unwrap().restore(snapshot.devices);
+        if let Some(spec) = snapshot.source
+            && self.state.source().as_ref() != Some(&spec)
+        {
+            self.switch_source(spec);
+        }
+        // The snapshot's mapping wins over anything remembered for its source.
+        self.state.set_mapping(snapshot.settings.mapping());
+        self.state
+            .set_max_intensity(snapshot.settings.max_intensity);
+        // Settings such as the backpressure policy are fixed for the lifetime of the process.
+        println!("Restored snapshot; saved settings take effect on next launch with --restore.");
+    }
+
+    /// Analyzes audio differently, restarting the current source to apply it.
+    fn reconfigure_analysis(&self, options: AnalysisOptions) {
+        self.audio.set_analysis_options(options);
+        if let Some(spec) = self.state.source() {
+            self.switch_source(spec);
+        }
+    }
 
-        // Play!
-        // println!("Playing {}", computed_intensity);
-        let _ = client_device
-            .run_output(&ClientDeviceOutputCommand::Vibrate(
-                computed_intensity.into(),
-            ))
-            .await;
+    fn switch_source(&self, spec: SourceSpec) {
+        match spec.open(self.settings.capture_options()) {
+            Ok(source) => {
+                self.state.set_source(spec);
+                self.audio.switch(source);
+            }
+            Err(e) => println!("Unable to open {}: {:#}", spec, e),
+        }
+    }
+}
 
+/// Periodically saves a snapshot, so that a crash mid-session can be resumed with `--restore`.
+/// A heartbeat is kept alongside it, so that a crash is noticed on next launch.
+async fn autosave(session: Session) {
+    let mut heartbeat = Heartbeat::start();
+    let mut interval = time::interval(Duration::from_secs(5));
+    loop {
         interval.tick().await;
+        session.save_snapshot();
+        heartbeat.beat(
+            session.state.source().map(|source| source.to_string()),
+            session.state.paused(),
+        );
     }
+}
 
-    client.disconnect().await?;
+/// Reopens the current source whenever its stream fails, such as when a device is unplugged,
+/// waiting longer after each failed attempt.
+async fn reopen_failed_streams(
+    mut events: EventSubscriber,
+    session: Session,
+    monitor: Arc<AudioMonitor>,
+) {
+    // Set while recovering, to when the next attempt is due and how long the one after waits.
+    let mut retry: Option<(time::Instant, Duration)> = None;
+    loop {
+        let event = match retry {
+            Some((due, backoff)) => match time::timeout_at(due, events.recv()).await {
+                Ok(event) => event,
+                Err(_) => {
+                    if let Some(spec) = session.state.source() {
+                        println!("Reopening {}...", spec);
+                        session.switch_source(spec);
+                    }
+                    let next = (backoff * 2).min(REOPEN_BACKOFF_MAX);
+                    retry = Some((time::Instant::now() + backoff, next));
+                    continue;
+                }
+            },
+            None => events.recv().await,
+        };
+        match event {
+            Some(AppEvent::Audio(AudioEvent::StreamError(_) | AudioEvent::StreamFailed { .. })) => {
+                monitor.set_stream_lost(true);
+                if retry.is_none() {
+                    retry = Some((
+                        time::Instant::now() + REOPEN_BACKOFF_MIN,
+                        REOPEN_BACKOFF_MIN * 2,
+                    ));
+                }
+            }
+            Some(AppEvent::Audio(AudioEvent::StreamOpened { .. })) => {
+                monitor.set_stream_lost(false);
+                retry = None;
+            }
+            // Letting go of the monitor once capture stops allows the window to close.
+            Some(AppEvent::Audio(AudioEvent::Stopped)) | None => break,
+            _ => {}
+        }
+    }
+}
 
-    Ok(())
+/// Switches to the system's default output device whenever it changes, while monitoring a live device,
+/// or to the default sink's monitor, while monitoring one.
+async fn follow_default_device(session: Session) {
+    let mut interval = time::interval(Duration::from_secs(2));
+    loop {
+        interval.tick().await;
+        match session.state.source() {
+            Some(SourceSpec::Live(current)) => {
+                if let Ok(Some(default)) =
+                    tokio::task::spawn_blocking(audio::default_output_name).await
+                    && current != default
+                {
+                    println!("Default output device changed to {}", default);
+                    session.switch_source(SourceSpec::Live(default));
+                }
+            }
+            Some(SourceSpec::Monitor(current)) => {
+                if let Ok(Some(monitor)) =
+                    tokio::task::spawn_blocking(audio::default_monitor_source).await
+                    && current != monitor
+                {
+                    println!("Default sink changed; now monitoring {}", monitor);
+                    session.switch_source(SourceSpec::Monitor(monitor));
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Applies user actions published to the bus.
+async fn handle_commands(mut events: EventSubscriber, session: Session) {
+    while let Some(event) = events.recv().await {
+        let AppEvent::User(command) = event else {
+            continue;
+        };
+        match command {
+            Command::Help => println!("{}", console::HELP),
+            Command::ListAudio => audio::print_output_devs(&audio::list_output_devs()),
+            Command::SwitchAudio(index) => {
+                let mut devs = audio::list_output_devs();
+                if index >= devs.len() {
+                    println!("No output device at index {}", index);
+                    continue;
+                }
+                let (name, dev) = devs.remove(index);
+                session.state.set_source(SourceSpec::Live(name));
+                session.audio.switch(audio::output_source(
+                    dev,
+                    session.settings.capture_options(),
+                ));
+            }
+            Command::ListInputs => audio::print_input_devs(&audio::list_input_devs()),
+            Command::SwitchInput(index) => {
+                let mut devs = audio::list_input_devs();
+                if index >= devs.len() {
+                    println!("No input device at index {}", index);
+                    continue;
+                }
+                let (name, dev) = devs.remove(index);
+                session.state.set_source(SourceSpec::Input(name));
+                session.audio.switch(Box::new(LiveSource::input(
+                    dev,
+                    session.settings.capture_options(),
+                )));
+            }
+            Command::ListMonitors => audio::print_monitor_sources(&audio::list_monitor_sources()),
+            Command::SwitchMonitor(index) => match audio::list_monitor_sources().get(index) {
+                Some(name) => session.switch_source(SourceSpec::Monitor(name.clone())),
+                None => println!("No monitor source at index {}", index),
+            },
+            Command::ListApplications => audio::list_application_streams()
+                .iter()
+                .for_each(|(_, name)| println!("  {}", name)),
+            Command::SwitchApplication(name) => {
+                session.switch_source(SourceSpec::Application(name))
+            }
+            Command::ListCables => {
+                let cables = audio::detect_virtual_cables();
+                if cables.is_empty() {
+                    println!("No virtual cables found.");
+                }
+                for cable in cables {
+                    println!("  {} ({})", cable.device, cable.kind);
+                }
+                println!("{}", audio::routing_instructions());
+            }
+            Command::CreateCable => match audio::create_null_sink() {
+                Ok(monitor) => {
+                    println!(
+                        "Created the {} sink; route applications to it to follow them.",
+                        audio::NULL_SINK_NAME
+                    );
+                    session.switch_source(SourceSpec::Monitor(monitor));
+                }
+                Err(e) => println!("Unable to create a null sink: {:#}", e),
+            },
+            Command::SwitchSource(request) => session.switch_source(match request {
+                SourceRequest::Device(name) => SourceSpec::Live(name),
+                SourceRequest::File(path) => SourceSpec::File(path.into()),
+                SourceRequest::Playback(path) => SourceSpec::Playback(path.into()),
+                SourceRequest::Network(address) => session.settings.network_source(address),
+                SourceRequest::Synthetic(signal) => SourceSpec::Synthetic(signal),
+            }),
+            Command::ListPatterns => Pattern::value_variants()
+                .iter()
+                .for_each(|pattern| println!("  {}", pattern.name())),
+            Command::SetPattern(pattern) => {
+                session.state.set_pattern(pattern);
+                match pattern {
+                    Some(pattern) => println!("Playing pattern: {}", pattern.name()),
+                    None => println!("Following audio."),
+                }
+            }
+            Command::SetMetronome(bpm) => {
+                let metronome = bpm.map(|bpm| session.settings.metronome_at(bpm));
+                session.state.set_metronome(metronome);
+                match bpm {
+                    Some(bpm) => println!("Metronome at {} BPM.", bpm),
+                    None => println!("Metronome stopped."),
+                }
+            }
+            Command::ShowGenre => {
+                match (session.state.genre_mode(), session.state.detected_genre()) {
+                    (GenreMode::Off, _) => println!("No genre profile is applied."),
+                    (GenreMode::Auto, Some(genre)) => println!("Detected genre: {}", genre.name()),
+                    (GenreMode::Auto, None) => println!("No genre detected yet."),
+                    (GenreMode::Manual(genre), _) => {
+                        println!("Applying genre profile: {}", genre.name())
+                    }
+                }
+            }
+            Command::SetResponse(response) => {
+                session.state.set_response(response);
+                println!(
+                    "Response: {}",
+                    response.to_possible_value().unwrap().get_name()
+                );
+            }
+            Command::ShowTempo => match (session.state.tempo(), session.state.link_tempo()) {
+                (_, Some(bpm)) => println!("Tempo: {:.1} BPM, from Ableton Link", bpm),
+                (Some(bpm), None) => println!("Tempo: {:.1} BPM", bpm),
+                (None, None) => println!("No steady beat detected yet."),
+            },
+            Command::ShowPipeline => {
+                let mapping = session
+                    .state
+                    .mapping()
+                    .unwrap_or(session.settings.mapping());
+                let stages = session
+                    .settings
+                    .pipeline()
+                    .describe(&session.audio.analysis_options(), &mapping);
+                for (i, stage) in stages.iter().enumerate() {
+                    println!("{}. {}", i + 1, stage);
+                }
+            }
+            Command::SetGenre(mode) => {
+                session.state.set_genre_mode(mode);
+                println!("Genre profile: {:?}", mode);
+            }
+            Command::Calibrate => session.state.request_calibration(),
+            Command::SetInputGain(gain) => {
+                session.audio.set_input_gain(gain);
+                println!("Input gain: {}", gain);
+            }
+            Command::SetMaxIntensity(max) => {
+                session.state.set_max_intensity(max);
+                println!("Devices will never be sent more than {}", max);
+            }
+            Command::SetCutoff(cutoff_hz) => {
+                session.reconfigure_analysis(AnalysisOptions {
+                    cutoff_hz,
+                    ..session.audio.analysis_options()
+                });
+                println!("Cutoff: {} Hz", cutoff_hz);
+            }
+            Command::SetFilter(filter) => {
+                session.reconfigure_analysis(AnalysisOptions {
+                    filter,
+                    ..session.audio.analysis_options()
+                });
+                println!("Filter: {}", filter.to_possible_value().unwrap().get_name());
+            }
+            Command::SetQ(q) => {
+                session.reconfigure_analysis(AnalysisOptions {
+                    q,
+                    ..session.audio.analysis_options()
+                });
+                println!("Q: {}", q);
+            }
+            Command::SetAttack(attack) => {
+                session.reconfigure_analysis(AnalysisOptions {
+                    attack,
+                    ..session.audio.analysis_options()
+                });
+                println!("Attack: {} ms", attack.as_millis());
+            }
+            Command::SetRelease(release) => {
+                session.reconfigure_analysis(AnalysisOptions {
+                    release,
+                    ..session.audio.analysis_options()
+                });
+                println!("Release: {} ms", release.as_millis());
+            }
+            Command::SetSlope(slope) => {
+                session.reconfigure_analysis(AnalysisOptions {
+                    slope,
+                    ..session.audio.analysis_options()
+                });
+                println!(
+                    "Slope: {} dB per octave",
+                    slope.to_possible_value().unwrap().get_name()
+                );
+            }
+            Command::ListBands => {
+                if session.settings.bands.is_empty() {
+                    println!("No bands to list; split audio into some with --band.");
+                }
+                let intensities = session.state.band_intensities();
+                let active = session.router.lock().unwrap().active();
+                for band in &session.settings.bands {
+                    let intensity = intensities
+                        .iter()
+                        .find(|(name, _)| *name == band.name)
+                        .map_or(0.0, |(_, intensity)| *intensity);
+                    let devices: Vec<String> = active
+                        .iter()
+                        .filter(|(_, settings)| settings.band.as_ref() == Some(&band.name))
+                        .map(|(device, _)| device.name().clone())
+                        .collect();
+                    println!(
+                        "  {}: {}-{} Hz, weight {}, intensity {:.2}, driving {}",
+                        band.name,
+                        band.low_hz,
+                        band.high_hz,
+                        band.weight,
+                        intensity,
+                        if devices.is_empty() {
+                            "no devices".to_string()
+                        } else {
+                            devices.join(", ")
+                        }
+                    );
+                }
+            }
+            Command::RouteBand(band, device) => {
+                if let Some(band) = &band
+                    && !session.settings.bands.iter().any(|spec| spec.name == *band)
+                {
+                    println!("No band named {}; see `bands`.", band);
+                    continue;
+                }
+                let routed = session
+                    .router
+                    .lock()
+                    .unwrap()
+                    .set_band(&device, band.clone());
+                match (routed, band) {
+                    (false, _) => println!("No connected device named {}", device),
+                    (true, Some(band)) => println!("{} now follows the {} band", device, band),
+                    (true, None) => println!("{} now follows the overall intensity", device),
+                }
+            }
+            Command::RouteMotor(motor, band, device) => {
+                if let Some(band) = &band
+                    && !session.settings.bands.iter().any(|spec| spec.name == *band)
+                {
+                    println!("No band named {}; see `bands`.", band);
+                    continue;
+                }
+                let routed =
+                    session
+                        .router
+                        .lock()
+                        .unwrap()
+                        .set_motor_band(&device, motor, band.clone());
+                match (routed, band) {
+                    (false, _) => println!("No connected device named {}", device),
+                    (true, Some(band)) => {
+                        println!(
+                            "Motor {} of {} now follows the {} band",
+                            motor + 1,
+                            device,
+                            band
+                        )
+                    }
+                    (true, None) => println!(
+                        "Motor {} of {} now follows the rest of the device",
+                        motor + 1,
+                        device
+                    ),
+                }
+            }
+            Command::ListDevices => {
+                let connected = session.router.lock().unwrap().connected();
+                if connected.is_empty() {
+                    println!("No devices connected.");
+                }
+                for (device, settings) in connected {
+                    let actuators = &settings.actuators;
+                    let kinds: Vec<String> = [
+                        (OutputType::Vibrate, "vibration", actuators.vibrate),
+                        (OutputType::Oscillate, "oscillation", actuators.oscillate),
+                        (OutputType::Rotate, "rotation", actuators.rotate),
+                        (OutputType::Constrict, "pressure", actuators.constrict),
+                        (
+                            OutputType::HwPositionWithDuration,
+                            "strokes",
+                            actuators.linear,
+                        ),
+                    ]
+                    .into_iter()
+                    .filter_map(|(kind, name, driven)| {
+                        let count = device.outputs(kind).len();
+                        match (count, driven) {
+                            (0, _) => None,
+                            (1, true) => Some(name.to_string()),
+                            (count, true) => Some(format!("{} x{}", name, count)),
+                            (_, false) => Some(format!("{} (off)", name)),
+                        }
+                    })
+                    .collect();
+                    println!(
+                        "  {}: {}, {}, multiplier {}{}",
+                        device.name(),
+                        kinds.join(", "),
+                        if settings.enabled {
+                            "enabled"
+                        } else {
+                            "disabled"
+                        },
+                        settings.multiplier,
+                        settings
+                            .band
+                            .map_or_else(String::new, |band| format!(", following {}", band))
+                    );
+                }
+            }
+            Command::EnableDevice(enabled, name) => {
+                let devices = session.router.lock().unwrap().set_enabled(&name, enabled);
+                match (devices.is_empty(), enabled) {
+                    (true, _) => println!("No connected device named {}", name),
+                    (false, true) => println!("{} enabled", name),
+                    (false, false) => {
+                        // Disabled devices are no longer sent anything, so they'd otherwise keep vibrating.
+                        for device in devices {
+                            tokio::spawn(async move {
+                                let _ = device.stop().await;
+                            });
+                        }
+                        println!("{} disabled", name);
+                    }
+                }
+            }
+            Command::ScaleDevice(multiplier, name) => {
+                if session
+                    .router
+                    .lock()
+                    .unwrap()
+                    .set_multiplier(&name, multiplier)
+                {
+                    println!("{} now receives {}x the intensity", name, multiplier);
+                } else {
+                    println!("No connected device named {}", name);
+                }
+            }
+            #[cfg(feature = "visualizer")]
+            Command::EditCurve => {
+                let state = session.state.clone();
+                let base = session.settings.mapping();
+                tokio::task::spawn_blocking(move || visualizer::open_curve_editor(state, base));
+            }
+            #[cfg(not(feature = "visualizer"))]
+            Command::EditCurve => println!("The curve editor needs the visualizer feature"),
+            Command::SetCurve(curve, gamma) => {
+                let mapping = session
+                    .state
+                    .mapping()
+                    .unwrap_or(session.settings.mapping());
+                let gamma = gamma.unwrap_or(mapping.gamma);
+                session.state.set_mapping(IntensityMapping {
+                    curve,
+                    gamma,
+                    ..mapping
+                });
+                match curve {
+                    ResponseCurve::Gamma => println!("Curve: gamma {}", gamma),
+                    ResponseCurve::Custom => println!("Curve: custom {}", mapping.points),
+                    curve => println!("Curve: {}", curve.to_possible_value().unwrap().get_name()),
+                }
+            }
+            Command::Remember => {
+                let mapping = session
+                    .state
+                    .mapping()
+                    .unwrap_or(session.settings.mapping());
+                if let Some(source) = session.state.remember_mapping(mapping) {
+                    println!("Remembered settings for {}.", source);
+                }
+            }
+            Command::Forget => {
+                if session.state.forget_mapping() {
+                    println!("Forgot remembered settings.");
+                } else {
+                    println!("Nothing was remembered for this source.");
+                }
+            }
+            Command::Boost(boosting) => {
+                session.state.set_boosting(boosting);
+                if boosting {
+                    println!("Boosting.");
+                } else {
+                    println!("Boost released.");
+                }
+            }
+            Command::Stats => print!("{}", session.state.stats()),
+            Command::Heatmap => println!("{}", session.state.heatmap()),
+            Command::Export(path) => match session.state.export_recording(path.as_ref()) {
+                Ok(()) => println!("Exported session to {}", path),
+                Err(e) => println!("Unable to export session: {:#}", e),
+            },
+            Command::MeasureLatency => {
+                println!("Measuring latency; make sure nothing else is playing...");
+                session.probe.request();
+            }
+            Command::Arm => {
+                session.state.set_armed(true);
+                println!("Armed.");
+            }
+            Command::Disarm => {
+                session.state.set_armed(false);
+                println!("Disarmed.");
+            }
+            Command::Pause => {
+                session.state.set_paused(true);
+                println!("Paused.");
+            }
+            Command::Resume => {
+                session.state.set_paused(false);
+                println!("Resumed.");
+            }
+            Command::Snapshot => {
+                session.save_snapshot();
+                println!("Saved snapshot.");
+            }
+            Command::Restore => session.restore_snapshot(),
+            Command::Quit => session.audio.shutdown(),
+        }
+    }
 }
 
-/// Helps to select available output devices.
-pub fn list_output_devs() -> Vec<(String, cpal::Device)> {
-    let host = cpal::default_host();
-    let mut devs: Vec<(String, Device)> = host
-        .output_devices()
-        .unwrap()
-        .map(|dev| {
-            (
-                match dev.name() {
-                    Ok(name) => name,
-                    Err(_) => "<unknown>".to_string(),
-                },
-                dev,
-            )
-        })
-        .collect();
-    devs.sort_by(|(n1, _), (n2, _)| n1.cmp(n2));
-    devs
+/// Remembers the most recently detected genre, for automatic profile switching.
+async fn follow_genres(mut events: EventSubscriber, state: SharedState) {
+    while let Some(event) = events.recv().await {
+        match event {
+            AppEvent::Audio(AudioEvent::GenreDetected(genre)) => state.set_detected_genre(genre),
+            AppEvent::Session(SessionEvent::TrackChanged(playing)) => {
+                state.set_now_playing(playing)
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Keeps the runtime state up to date with the tempo of the Ableton Link session being followed.
+async fn follow_link_tempo(mut events: EventSubscriber, state: SharedState) {
+    while let Some(event) = events.recv().await {
+        if let AppEvent::Session(SessionEvent::LinkTempo(tempo)) = event {
+            state.set_link_tempo(tempo);
+        }
+    }
 }
 
-/// Helps to select the default output device.
-// TODO(spotlightishere): Please graft this to something GUI in the future!
-fn select_output_dev() -> cpal::Device {
-    let mut devs = list_output_devs();
-    assert!(!devs.is_empty(), "no output devices found!");
-    if devs.len() == 1 {
-        return devs.remove(0).1;
-    }
-    println!("Type the number of the output device audio is playing to, and press enter.");
-    devs.iter().enumerate().for_each(|(i, (name, dev))| {
-        println!(
-            "  [{}] {} {:?}",
-            i,
-            name,
-            dev.default_output_config().unwrap()
+/// Pauses output whenever a device disconnects, so that the remaining devices don't carry on alone.
+/// Once every disconnected device is back, the user is offered to resume.
+async fn pause_on_disconnect(mut events: EventSubscriber, state: SharedState, bus: EventBus) {
+    let mut missing = BTreeSet::new();
+    while let Some(event) = events.recv().await {
+        match event {
+            // Devices dropping while paused by the user needn't interrupt anyone.
+            AppEvent::Device(DeviceEvent::Removed { name, .. })
+                if !state.paused() || !missing.is_empty() =>
+            {
+                state.set_paused(true);
+                missing.insert(name.clone());
+                bus.publish(AppEvent::Session(SessionEvent::PausedForDisconnect {
+                    device: name,
+                }));
+            }
+            AppEvent::Device(DeviceEvent::Added { name, .. }) if missing.contains(&name) => {
+                missing.remove(&name);
+                if missing.is_empty() && state.paused() {
+                    bus.publish(AppEvent::Session(SessionEvent::DevicesReconnected {
+                        device: name,
+                    }));
+                }
+            }
+            // Resuming by hand means carrying on without whichever devices are still missing.
+            AppEvent::User(Command::Resume) => missing.clear(),
+            _ => {}
+        }
+    }
+}
+
+/// Disconnects from devices while the session is idle, saving their batteries, and reconnects once audio returns.
+async fn disconnect_while_idle(mut events: EventSubscriber, connection: Connection) {
+    while let Some(event) = events.recv().await {
+        let AppEvent::Session(SessionEvent::Idle(idle)) = event else {
+            continue;
+        };
+        let result = if idle {
+            connection.disconnect().await
+        } else {
+            connection.connect().await
+        };
+        if let Err(e) = result {
+            println!(
+                "Unable to {}: {:#}",
+                if idle { "disconnect" } else { "reconnect" },
+                e
+            );
+        }
+    }
+}
+
+/// Keeps a log of events for session exports.
+async fn record_events(mut events: EventSubscriber, state: SharedState) {
+    while let Some(event) = events.recv().await {
+        state.record_event(&event);
+    }
+}
+
+/// Drives all routed devices from the envelope channel until it closes.
+async fn run_vibration_logic(
+    mut rx: EnvelopeReceiver,
+    router: SharedRouter,
+    state: SharedState,
+    probe: Arc<LatencyProbe>,
+    monitor: Arc<AudioMonitor>,
+    bus: EventBus,
+    settings: &AppSettings,
+) {
+    let mut calibration: Option<Calibration> = None;
+    let mut auto_stop = settings.auto_stop();
+    let mut warm_up = settings.warm_up();
+    let mut randomizer = settings.randomizer();
+    let mut sleep_timer = settings.sleep_timer();
+    let mut cycle = settings.interval_cycle();
+    let mut cycle_phase = None;
+    let mut boost = settings.boost();
+    let mut drop_boost = settings
+        .drop_boost()
+        .map(|boost| (DropDetector::default(), boost));
+    let mut duty_limiter = settings.duty_limiter();
+    let mut quiet_ceiling = None;
+    let idle_disconnect = settings.idle_disconnect();
+    let silence_pause = settings.silence_pause();
+    let mut idle = false;
+    let mut fade_in = None;
+    let mut was_paused = state.paused();
+    let mut smoother = Smoother::new(Duration::from_millis(settings.smoothing));
+    let mut auto_gain = settings.auto_gain();
+    let mut pipeline = Pipeline::new(&settings.pipeline());
+    // Bands are only compressed, as a gate would need to keep track of each one separately.
+    let compressor = settings.compressor();
+    let mut onsets = settings.onset_detector();
+    let mut pulse = settings.pulse_envelope();
+    let mut tempo = TempoEstimator::default();
+    let mut player = PatternPlayer::default();
+    let mut metronome_player = PatternPlayer::default();
+    let mut silent_for = Duration::ZERO;
+
+    let command_interval = settings.interval();
+    let mut output = DeviceOutput::new(
+        router,
+        command_interval,
+        settings.mapping(),
+        settings.cooldown(),
+    )
+    .with_wave(Duration::from_millis(settings.wave_offset))
+    .with_max_slew(settings.max_slew)
+    .with_strokes(settings.stroke_limits())
+    .with_pressure(settings.pressure_profile())
+    .with_min_change(
+        settings.min_change,
+        Duration::from_millis(settings.keepalive),
+    );
+
+    // We'll now loop over our sent channel values at a fixed rate.
+    let mut scheduler = TickScheduler::new(command_interval, SAMPLE_LIMIT);
+    loop {
+        // Obtain our values.
+        //
+        // Once audio capture shuts down (e.g. the visualizer has been closed),
+        // the channel closes, and we no longer need to handle future values.
+        //
+        // Should audio stop arriving altogether, ticks carry on regardless, treating it as silence,
+        // so that patterns and fades continue.
+        let Some(tick) = scheduler.next(&mut rx).await else {
+            println!("Detected end of tx!");
+            break;
+        };
+        state.record_envelope(rx.stats());
+        let collected_values = tick.values;
+        let (now, elapsed) = (tick.at, tick.elapsed);
+
+        // Average our values, or take their peak when responsiveness matters most.
+        let mean_value = settings.aggregation.combine(&collected_values);
+
+        // An injected click is unmistakable in the raw envelope, regardless of mapping.
+        let mut click_arrived = None;
+        if let Some(injected) = probe.injected_at() {
+            let peak = Aggregation::Peak.combine(&collected_values);
+            if peak >= CLICK_THRESHOLD {
+                probe.take();
+                click_arrived = Some(now - injected);
+            } else if now - injected >= CLICK_TIMEOUT {
+                probe.take();
+                bus.publish(AppEvent::Session(SessionEvent::LatencyFailed {
+                    click_hz: probe.click_hz(),
+                    timeout: CLICK_TIMEOUT,
+                }));
+            }
+        }
+
+        // Calibration observes the raw envelope, before any mapping.
+        if state.take_calibration_request() {
+            let base = state.mapping().unwrap_or(settings.mapping());
+            calibration = Some(Calibration::new(base));
+            bus.publish(AppEvent::Session(SessionEvent::Calibration(
+                CalibrationProgress::MeasuringNoise,
+            )));
+        }
+        if let Some(progress) = calibration
+            .as_mut()
+            .and_then(|calibration| calibration.record(mean_value, elapsed))
+        {
+            match &progress {
+                CalibrationProgress::Finished(mapping) => {
+                    state.set_mapping(*mapping);
+                    // Calibrating is specific to the source, so its result is remembered for that source.
+                    state.remember_mapping(*mapping);
+                    calibration = None;
+                }
+                CalibrationProgress::Failed(_) => calibration = None,
+                _ => {}
+            }
+            bus.publish(AppEvent::Session(SessionEvent::Calibration(progress)));
+        }
+
+        let base_mapping = state.mapping().unwrap_or(settings.mapping());
+        let mapping = state
+            .genre_profile()
+            .map_or(base_mapping, |genre| genre.profile(base_mapping));
+        // Devices are capped by the mapping in use after their multipliers, not just the one launched with.
+        output.set_mapping(mapping);
+        // Automatic gain control comes after calibration, which must see the envelope as it is.
+        let agc_factor = auto_gain
+            .as_mut()
+            .map_or(1.0, |agc| agc.process(mean_value, elapsed, mapping.gain));
+        let mut computed_intensity = pipeline.run(
+            mean_value * agc_factor,
+            &mapping,
+            monitor.speaking(),
+            elapsed,
         );
-    });
-    let mut input = String::new();
-    stdin().lock().read_line(&mut input).unwrap();
-    let index = input[0..1].parse::<usize>().unwrap();
-    devs.remove(index).1
+        // Devices following a band skip smoothing and effects, but are still held down by rests, limits and timers.
+        let mut band_factor = 1.0;
+
+        // Pulses are shaped already, so smoothing them would only blunt them.
+        let onset = match settings.onsets {
+            OnsetMethod::Level => onsets.process(mean_value, elapsed),
+            OnsetMethod::Kick | OnsetMethod::Flux => monitor.take_onset(),
+        };
+        tempo.advance(elapsed, onset);
+        if onset && computed_intensity >= settings.reverse_level {
+            output.reverse_rotation();
+        }
+        // A Link session knows its tempo exactly, so there's no need to estimate it.
+        let bpm = state.link_tempo().or_else(|| tempo.bpm());
+        state.set_tempo(bpm);
+        pulse.set_decay(settings.pulse_decay_at(bpm));
+        computed_intensity = match state.response() {
+            ResponseMode::Follow => smoother.run(computed_intensity, elapsed),
+            ResponseMode::Pulse => {
+                if onset && computed_intensity > 0.0 {
+                    pulse.trigger(computed_intensity);
+                }
+                pulse.advance(elapsed)
+            }
+        };
+
+        // A pattern may stand in for audio, either because one was chosen outright,
+        // or because audio has gone quiet for a while.
+        if computed_intensity > 0.0 {
+            silent_for = Duration::ZERO;
+        } else {
+            silent_for += elapsed;
+        }
+        let pattern = state.pattern().or(settings
+            .idle_pattern
+            .filter(|_| silent_for >= IDLE_PATTERN_DELAY));
+        // Randomizing patterns swaps whichever plays for one of those picked from.
+        let pattern = pattern.map(|pattern| {
+            randomizer
+                .as_ref()
+                .and_then(|randomizer| randomizer.pattern())
+                .unwrap_or(pattern)
+        });
+        // Patterns can follow the music's tempo, once there's one to follow.
+        let pattern_elapsed = match bpm {
+            Some(bpm) if settings.beat_sync => elapsed.mul_f64(bpm / PATTERN_BPM),
+            _ => elapsed,
+        };
+        match pattern {
+            Some(pattern) => computed_intensity = player.advance(pattern, pattern_elapsed),
+            None => player.stop(),
+        }
+        // Devices are let go of while nothing at all is driving them, and reconnected once audio returns.
+        let idle_now = idle_disconnect.is_some_and(|after| silent_for >= after)
+            && pattern.is_none()
+            && state.metronome().is_none();
+        if idle_now != idle {
+            idle = idle_now;
+            bus.publish(AppEvent::Session(SessionEvent::Idle(idle)));
+        }
+        // A metronome takes precedence over everything else, as it's chosen outright.
+        match state.metronome() {
+            Some(metronome) => computed_intensity = metronome_player.advance(metronome, elapsed),
+            None => metronome_player.stop(),
+        }
+
+        // Warming up only counts time spent actually playing.
+        let paused = state.paused() || !state.armed();
+        let mut max_intensity = state.max_intensity().unwrap_or(settings.max_intensity);
+        if let Some(ramp) = &mut warm_up
+            && !paused
+        {
+            let factor = ramp.advance(elapsed, computed_intensity > 0.0);
+            if settings.warm_up_limit {
+                let start = settings.warm_up_start;
+                max_intensity = max_intensity.min(start + (max_intensity - start) * factor);
+            } else {
+                computed_intensity *= factor;
+                band_factor *= factor;
+            }
+        }
+
+        // Resuming fades back in from silence, rather than jumping straight to the current level.
+        if was_paused && !paused {
+            fade_in = Some(settings.fade_in());
+        }
+        was_paused = paused;
+        if let Some(ramp) = &mut fade_in
+            && !paused
+        {
+            let factor = ramp.advance(elapsed, true);
+            computed_intensity *= factor;
+            band_factor *= factor;
+        }
+
+        if let Some(randomizer) = &mut randomizer
+            && !paused
+        {
+            computed_intensity *= randomizer.advance(elapsed);
+        }
+
+        if let Some(cycle) = &mut cycle
+            && !paused
+        {
+            let (phase, factor) = cycle.advance(elapsed);
+            computed_intensity *= factor;
+            band_factor *= factor;
+            if cycle_phase != Some(phase) {
+                cycle_phase = Some(phase);
+                bus.publish(AppEvent::Session(SessionEvent::CyclePhaseChanged(phase)));
+            }
+        }
+
+        // Boosting may push output up to, but never past, the ceiling, which devices are also held to after their multipliers.
+        let mut boost_factor = boost.advance(state.boosting(), elapsed);
+        if let Some((drops, boost)) = &mut drop_boost {
+            let dropped = drops.process(mean_value, elapsed);
+            if dropped {
+                bus.publish(AppEvent::Session(SessionEvent::Drop));
+            }
+            boost_factor *= boost.advance(dropped, elapsed);
+        }
+        computed_intensity = (computed_intensity * boost_factor).min(mapping.ceiling);
+
+        if let Some(limiter) = &mut duty_limiter
+            && !paused
+        {
+            let was_resting = limiter.resting();
+            let factor = limiter.advance(computed_intensity, elapsed);
+            computed_intensity *= factor;
+            band_factor *= factor;
+            match (was_resting, limiter.resting()) {
+                (false, true) => bus.publish(AppEvent::Session(SessionEvent::DutyRestStarted {
+                    after: limiter.limit(),
+                })),
+                (true, false) => bus.publish(AppEvent::Session(SessionEvent::DutyRestEnded)),
+                _ => {}
+            }
+        }
+
+        let quiet_now = settings.quiet_ceiling_now();
+        if quiet_now != quiet_ceiling {
+            quiet_ceiling = quiet_now;
+            bus.publish(AppEvent::Session(SessionEvent::QuietHours(quiet_now)));
+        }
+        // Quiet hours cap what devices are sent after their multipliers, as the maximum intensity does.
+        if let Some(ceiling) = quiet_ceiling {
+            max_intensity = max_intensity.min(ceiling);
+        }
+
+        // The sleep timer runs on the clock, paused or not, as its user may well be asleep.
+        if let Some(timer) = &mut sleep_timer {
+            // Its falling ceiling caps devices after their multipliers, as quiet hours do.
+            let ceiling = mapping.ceiling * timer.advance(elapsed);
+            max_intensity = max_intensity.min(ceiling);
+            if timer.expired() {
+                bus.publish(AppEvent::Session(SessionEvent::SleepEnded {
+                    after: timer.duration(),
+                }));
+                break;
+            }
+        }
+
+        output.set_max_intensity(max_intensity);
+
+        // Resuming after an automatic stop begins a fresh session.
+        if let Some(timer) = &mut auto_stop {
+            if !paused && timer.expired() {
+                timer.reset();
+            }
+            if !paused {
+                let factor = timer.advance(elapsed);
+                computed_intensity *= factor;
+                band_factor *= factor;
+                if timer.expired() {
+                    state.set_paused(true);
+                    bus.publish(AppEvent::Session(SessionEvent::AutoStopped {
+                        after: timer.limit(),
+                    }));
+                }
+            }
+        }
+
+        // Patterns and metronomes stand in for audio on every device, including those following a band.
+        let band_intensities = if pattern.is_some() || state.metronome().is_some() {
+            Vec::new()
+        } else {
+            settings
+                .bands
+                .iter()
+                .zip(monitor.band_levels())
+                .enumerate()
+                .map(|(index, (band, level))| {
+                    let mut intensity = mapping.map(level * agc_factor);
+                    if let Some(compressor) = &compressor {
+                        intensity = compressor.run(intensity);
+                    }
+                    let randomized = randomizer
+                        .as_ref()
+                        .map_or(1.0, |randomizer| randomizer.band_factor(index));
+                    let intensity = intensity * band_factor * randomized;
+                    (band.name.clone(), intensity)
+                })
+                .collect()
+        };
+        state.set_band_intensities(band_intensities.clone());
+        output.set_bands(band_intensities);
+
+        // While disarmed, devices are stopped outright rather than faded, and sent nothing further.
+        // While paused, we keep draining the channel so that values don't go stale,
+        // while output fades to silence.
+        let sent = if !state.armed() {
+            if !output.stopped() {
+                output.stop().await;
+            }
+            Vec::new()
+        } else if computed_intensity == 0.0
+            && silence_pause.is_some_and(|after| silent_for >= after)
+            && pattern.is_none()
+            && state.metronome().is_none()
+            && output.silent()
+        {
+            // Nothing is playing; once devices have been stopped, there's no need to keep telling them so.
+            if !output.stopped() {
+                output.stop().await;
+            }
+            Vec::new()
+        } else if state.paused() {
+            output.wind_down(elapsed).await;
+            Vec::new()
+        } else {
+            // Play! Every connected device receives its own scaled intensity.
+            // println!("Playing {}", computed_intensity);
+            let sent = output.send(computed_intensity).await;
+            state.record_output(elapsed, computed_intensity, &sent);
+            sent
+        };
+        if let Some(analysis) = click_arrived {
+            bus.publish(AppEvent::Session(SessionEvent::LatencyMeasured {
+                analysis,
+                devices: sent
+                    .into_iter()
+                    .map(|command| (command.device, command.acknowledged))
+                    .collect(),
+            }));
+        }
+    }
+    output.fade_out().await;
+
+    state.record_envelope(rx.stats());
+    print!("{}", state.stats());
+    println!("Intensity over the session:\n{}", state.heatmap());
 }
@@ -0,0 +1,335 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// How strongly each level after the deadzone is felt. Perceived strength isn't linear in the intensity sent,
+/// so curves that lift the middle make mid-level audio feel like it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ResponseCurve {
+    /// Intensity is proportional to the envelope.
+    #[default]
+    Linear,
+    /// Lifts quiet and mid-level audio, flattening out towards the top.
+    Log,
+    /// Holds quiet audio down, rising steeply towards the top.
+    Exp,
+    /// Raises the level to the power of the mapping's gamma: below 1 lifts mid-level audio, above 1 holds it down.
+    Gamma,
+    /// Passes through the mapping's own points, such as those drawn in the curve editor.
+    Custom,
+}
+
+/// How far the log and exp curves bend: the log curve starts out this many times steeper than linear.
+const CURVE_BEND: f64 = 9.0;
+
+/// The most points a custom curve may have.
+pub const MAX_CURVE_POINTS: usize = 8;
+
+/// The points a custom curve passes through, as pairs of level and intensity within `[0, 1]`, in order of level.
+///
+/// The curve begins at silence and ends at full intensity unless points say otherwise, interpolating linearly
+/// between its points. It never falls: a point lower than one before it is treated as level with it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(from = "Vec<(f64, f64)>", into = "Vec<(f64, f64)>")]
+pub struct CurvePoints {
+    points: [(f64, f64); MAX_CURVE_POINTS],
+    len: usize,
+}
+
+impl CurvePoints {
+    pub fn points(&self) -> &[(f64, f64)] {
+        &self.points[..self.len]
+    }
+
+    /// Adds a point, returning its index, or `None` if there's no room for more.
+    pub fn insert(&mut self, (level, intensity): (f64, f64)) -> Option<usize> {
+        if self.len == MAX_CURVE_POINTS {
+            return None;
+        }
+        let point = (clamp_unit(level), clamp_unit(intensity));
+        let index = self
+            .points()
+            .partition_point(|(other, _)| *other <= point.0);
+        self.points.copy_within(index..self.len, index + 1);
+        self.points[index] = point;
+        self.len += 1;
+        Some(index)
+    }
+
+    pub fn remove(&mut self, index: usize) {
+        if index < self.len {
+            self.points.copy_within(index + 1..self.len, index);
+            self.len -= 1;
+        }
+    }
+
+    /// Moves a point, returning its index once it's back in order.
+    pub fn relocate(&mut self, index: usize, point: (f64, f64)) -> Option<usize> {
+        self.remove(index);
+        self.insert(point)
+    }
+
+    /// The intensity the curve gives `level`.
+    pub fn interpolate(&self, level: f64) -> f64 {
+        let mut previous = (0.0, 0.0);
+        for &(x, y) in self.points().iter().chain([(1.0, 1.0)].iter()) {
+            let y = y.max(previous.1);
+            if level <= x {
+                if x <= previous.0 {
+                    return y;
+                }
+                let t = (level - previous.0) / (x - previous.0);
+                return previous.1 + (y - previous.1) * t;
+            }
+            previous = (x, y);
+        }
+        previous.1
+    }
+}
+
+impl From<Vec<(f64, f64)>> for CurvePoints {
+    fn from(points: Vec<(f64, f64)>) -> Self {
+        let mut curve = CurvePoints::default();
+        for point in points {
+            curve.insert(point);
+        }
+        curve
+    }
+}
+
+impl From<CurvePoints> for Vec<(f64, f64)> {
+    fn from(curve: CurvePoints) -> Self {
+        curve.points().to_vec()
+    }
+}
+
+impl FromStr for CurvePoints {
+    type Err = String;
+
+    /// Parses points given as `level:intensity`, separated by commas, such as `0.2:0,0.5:0.7`.
+    /// Nothing at all, which is how no points are shown, parses as no points.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if value.trim().is_empty() {
+            return Ok(CurvePoints::default());
+        }
+        let points = value
+            .split(',')
+            .map(|point| {
+                point
+                    .split_once(':')
+                    .and_then(|(level, intensity)| {
+                        Some((level.trim().parse().ok()?, intensity.trim().parse().ok()?))
+                    })
+                    .filter(|(level, intensity): &(f64, f64)| {
+                        (0.0..=1.0).contains(level) && (0.0..=1.0).contains(intensity)
+                    })
+                    .ok_or_else(|| {
+                        format!("expected `level:intensity` between 0 and 1, not {}", point)
+                    })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if points.len() > MAX_CURVE_POINTS {
+            return Err(format!("at most {} points are supported", MAX_CURVE_POINTS));
+        }
+        Ok(points.into())
+    }
+}
+
+impl fmt::Display for CurvePoints {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let points: Vec<String> = self
+            .points()
+            .iter()
+            .map(|(level, intensity)| format!("{:.2}:{:.2}", level, intensity))
+            .collect();
+        write!(f, "{}", points.join(","))
+    }
+}
+
+/// Maps averaged envelope values onto the intensities we send to devices.
+///
+/// Everything leaving this module is guaranteed to lie within `[0, ceiling]`,
+/// as it directly controls hardware.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct IntensityMapping {
+    /// Scales the raw envelope prior to anything else.
+    pub gain: f64,
+    /// Scaled values at or below this are treated as silence, and everything above
+    /// is rescaled so that output still begins at zero.
+    pub deadzone: f64,
+    /// No device ever receives an intensity above this.
+    pub ceiling: f64,
+    #[serde(default)]
+    pub curve: ResponseCurve,
+    /// The exponent used by the gamma curve.
+    #[serde(default = "default_gamma")]
+    pub gamma: f64,
+    /// The points the custom curve passes through.
+    #[serde(default)]
+    pub points: CurvePoints,
+}
+
+fn default_gamma() -> f64 {
+    IntensityMapping::default().gamma
+}
+
+impl Default for IntensityMapping {
+    fn default() -> Self {
+        Self {
+            gain: 10.0,
+            deadzone: 0.0,
+            ceiling: 1.0,
+            curve: ResponseCurve::default(),
+            gamma: 0.6,
+            points: CurvePoints::default(),
+        }
+    }
+}
+
+impl IntensityMapping {
+    /// Maps an envelope value into an intensity.
+    pub fn map(&self, envelope: f64) -> f64 {
+        let scaled = clamp_unit(envelope.abs() * self.gain.max(0.0));
+        let deadzone = clamp_unit(self.deadzone);
+        if scaled <= deadzone {
+            return 0.0;
+        }
+        let rescaled = (scaled - deadzone) / (1.0 - deadzone);
+        self.limit(self.shape(rescaled))
+    }
+
+    /// Applies a per-device multiplier to an intensity, without ever exceeding the ceiling.
+    pub fn scale(&self, intensity: f64, multiplier: f64) -> f64 {
+        self.limit(intensity * multiplier.max(0.0))
+    }
+
+    /// Applies the response curve to a level within `[0, 1]`.
+    fn shape(&self, level: f64) -> f64 {
+        match self.curve {
+            ResponseCurve::Linear => level,
+            ResponseCurve::Log => (1.0 + CURVE_BEND * level).ln() / (1.0 + CURVE_BEND).ln(),
+            ResponseCurve::Exp => ((1.0 + CURVE_BEND).powf(level) - 1.0) / CURVE_BEND,
+            ResponseCurve::Gamma => {
+                // Anything that isn't a positive exponent would turn silence into full intensity, or worse.
+                let gamma = if self.gamma.is_finite() && self.gamma > 0.0 {
+                    self.gamma
+                } else {
+                    1.0
+                };
+                level.powf(gamma)
+            }
+            ResponseCurve::Custom => self.points.interpolate(level),
+        }
+    }
+
+    fn limit(&self, intensity: f64) -> f64 {
+        f64::min(clamp_unit(intensity), clamp_unit(self.ceiling))
+    }
+}
+
+/// Clamps a value into `[0, 1]`, treating anything that isn't a number as silence.
+pub fn clamp_unit(value: f64) -> f64 {
+    if value.is_nan() {
+        0.0
+    } else {
+        value.clamp(0.0, 1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    const CASES: u32 = 10_000;
+
+    /// Envelope values, biased towards the interesting range but including hostile inputs.
+    fn envelope() -> impl Strategy<Value = f64> {
+        prop_oneof![
+            1 => prop::sample::select(vec![f64::NAN, f64::INFINITY, f64::NEG_INFINITY, -0.0]),
+            1 => -1e6..1e6,
+            8 => 0.0..1.0,
+        ]
+    }
+
+    fn mapping() -> impl Strategy<Value = IntensityMapping> {
+        (
+            0.0..50.0,
+            0.0..0.99,
+            0.0..=1.0,
+            prop::sample::select(ResponseCurve::value_variants()),
+            -1.0..5.0,
+            prop::collection::vec((0.0..=1.0, 0.0..=1.0), 0..=MAX_CURVE_POINTS),
+        )
+            .prop_map(
+                |(gain, deadzone, ceiling, curve, gamma, points)| IntensityMapping {
+                    gain,
+                    deadzone,
+                    ceiling,
+                    curve,
+                    gamma,
+                    points: points.into(),
+                },
+            )
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(CASES))]
+
+        #[test]
+        fn output_is_always_within_unit_interval(
+            mapping in mapping(),
+            value in envelope(),
+            multiplier in -10.0..10.0,
+        ) {
+            let mapped = mapping.map(value);
+            prop_assert!(
+                (0.0..=1.0).contains(&mapped),
+                "{mapping:?} mapped {value} to {mapped}"
+            );
+            let scaled = mapping.scale(mapped, multiplier);
+            prop_assert!(
+                (0.0..=1.0).contains(&scaled),
+                "{mapping:?} scaled {mapped} to {scaled}"
+            );
+        }
+
+        #[test]
+        fn output_never_exceeds_ceiling(
+            mapping in mapping(),
+            value in envelope(),
+            multiplier in 0.0..10.0,
+        ) {
+            let mapped = mapping.map(value);
+            prop_assert!(mapped <= mapping.ceiling, "{mapping:?} produced {mapped}");
+            let scaled = mapping.scale(mapped, multiplier);
+            prop_assert!(scaled <= mapping.ceiling, "{mapping:?} produced {scaled}");
+        }
+
+        #[test]
+        fn output_is_monotonic(mapping in mapping(), a in 0.0..1.0, b in 0.0..1.0) {
+            let (low, high) = if a <= b { (a, b) } else { (b, a) };
+            prop_assert!(
+                mapping.map(low) <= mapping.map(high),
+                "{mapping:?} is not monotonic between {low} and {high}"
+            );
+        }
+
+        #[test]
+        fn deadzone_is_silent(mapping in mapping(), depth in 0.0..=1.0) {
+            prop_assume!(mapping.gain != 0.0);
+            let value = depth * mapping.deadzone / mapping.gain;
+            prop_assert_eq!(mapping.map(value), 0.0, "{:?} let {} through", mapping, value);
+        }
+    }
+
+    #[test]
+    fn curve_points_parse_as_shown() {
+        // Clap shows the default, no points at all, and parses it back again.
+        for points in [CurvePoints::default(), vec![(0.2, 0.0), (0.5, 0.7)].into()] {
+            assert_eq!(points.to_string().parse::<CurvePoints>(), Ok(points));
+        }
+    }
+}
@@ -0,0 +1,113 @@
+use std::fmt;
+use std::time::Duration;
+
+use crate::audio::Genre;
+use crate::events::{AppEvent, EventBus, SessionEvent};
+
+/// How often media players are asked what they're playing.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Words found in genre tags, alongside the genre whose profile suits them. Earlier entries win.
+const GENRE_KEYWORDS: &[(&str, Genre)] = &[
+    ("podcast", Genre::Spoken),
+    ("audiobook", Genre::Spoken),
+    ("speech", Genre::Spoken),
+    ("spoken", Genre::Spoken),
+    ("ambient", Genre::Ambient),
+    ("classical", Genre::Ambient),
+    ("chill", Genre::Ambient),
+    ("drum", Genre::Electronic),
+    ("dubstep", Genre::Electronic),
+    ("electro", Genre::Electronic),
+    ("techno", Genre::Electronic),
+    ("house", Genre::Electronic),
+    ("trance", Genre::Electronic),
+    ("edm", Genre::Electronic),
+    ("rock", Genre::Rock),
+    ("metal", Genre::Rock),
+    ("punk", Genre::Rock),
+];
+
+/// The track a media player reports as playing.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NowPlaying {
+    pub artist: String,
+    pub title: String,
+    /// The track's genre tag, as the player reports it.
+    pub genre: Option<String>,
+}
+
+impl NowPlaying {
+    /// The genre profile suiting this track's genre tag, if it has one we recognise.
+    pub fn genre_profile(&self) -> Option<Genre> {
+        let tag = self.genre.as_deref()?.to_lowercase();
+        GENRE_KEYWORDS
+            .iter()
+            .find(|(keyword, _)| tag.contains(keyword))
+            .map(|(_, genre)| *genre)
+    }
+}
+
+impl fmt::Display for NowPlaying {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.artist.is_empty() {
+            write!(f, "{}", self.title)
+        } else {
+            write!(f, "{} - {}", self.artist, self.title)
+        }
+    }
+}
+
+/// Announces on the bus whenever the track playing in media players changes.
+// TODO(spotlightishere): Switch presets per playlist too, once players tell us which one is playing.
+pub async fn follow_media(bus: EventBus) {
+    let mut interval = tokio::time::interval(POLL_INTERVAL);
+    let mut current = None;
+    loop {
+        interval.tick().await;
+        let playing = now_playing().await;
+        if playing != current {
+            bus.publish(AppEvent::Session(SessionEvent::TrackChanged(
+                playing.clone(),
+            )));
+            current = playing;
+        }
+    }
+}
+
+/// Asks MPRIS media players what they're playing, by way of playerctl.
+#[cfg(target_os = "linux")]
+async fn now_playing() -> Option<NowPlaying> {
+    let output = tokio::process::Command::new("playerctl")
+        .args([
+            "metadata",
+            "--format",
+            "{{artist}}\t{{title}}\t{{xesam:genre}}",
+        ])
+        .output()
+        .await
+        .ok()?;
+    // Without any players, playerctl fails.
+    if !output.status.success() {
+        return None;
+    }
+    let text = String::from_utf8_lossy(&output.stdout);
+    let mut fields = text.trim_end_matches('\n').split('\t');
+    let artist = fields.next()?.to_string();
+    let title = fields.next()?.to_string();
+    let genre = fields.next().filter(|genre| !genre.is_empty());
+    if artist.is_empty() && title.is_empty() {
+        return None;
+    }
+    Some(NowPlaying {
+        artist,
+        title,
+        genre: genre.map(str::to_string),
+    })
+}
+
+// TODO(spotlightishere): Read Windows' System Media Transport Controls, and the Now Playing info on macOS.
+#[cfg(not(target_os = "linux"))]
+async fn now_playing() -> Option<NowPlaying> {
+    None
+}
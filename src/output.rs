@@ -0,0 +1,741 @@
+use buttplug::ButtplugClientDevice;
+use buttplug::device::{ClientDeviceFeature, ClientDeviceOutputCommand};
+use buttplug_core::message::OutputType;
+use clap::ValueEnum;
+use futures::FutureExt;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+use tokio::time;
+
+use crate::devices::{DeviceSettings, SharedRouter};
+use crate::dsp::Smoother;
+use crate::mapping::{IntensityMapping, clamp_unit};
+use crate::stroke::{StrokeLimits, StrokeSynthesizer};
+
+/// The longest any device's output may be delayed by.
+const MAX_DELAY: Duration = Duration::from_secs(5);
+
+/// How several envelope values received at once are combined into one.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Aggregation {
+    /// Averages values, smoothing over brief spikes.
+    #[default]
+    Mean,
+    /// Takes the loudest value, reacting to every spike.
+    Peak,
+}
+
+impl Aggregation {
+    /// Combines `values`, treating no values at all as silence.
+    pub fn combine(self, values: &[f64]) -> f64 {
+        if values.is_empty() {
+            return 0.0;
+        }
+        match self {
+            Aggregation::Mean => values.iter().sum::<f64>() / values.len() as f64,
+            Aggregation::Peak => values.iter().copied().fold(0.0, f64::max),
+        }
+    }
+}
+
+/// A command sent to a single device.
+#[derive(Clone, Debug)]
+pub struct SentCommand {
+    pub device: String,
+    /// The intensity sent, after per-device scaling, regardless of which way rotation turns.
+    pub intensity: f64,
+    /// How long the device took to acknowledge the command.
+    pub acknowledged: Duration,
+}
+
+/// Something commands are sent to: a whole device, or one of its motors on its own.
+struct Target {
+    device: ButtplugClientDevice,
+    /// The kind of actuator driven: vibration, oscillation, rotation, pressure, or strokes to positions.
+    kind: OutputType,
+    /// The motor, for devices whose motors are driven separately.
+    motor: Option<ClientDeviceFeature>,
+    /// How the target is shown, and told apart from others.
+    name: String,
+    band: Option<String>,
+    multiplier: f64,
+    offset: Duration,
+    /// Whether rotation follows the direction reversed on strong onsets.
+    reversible: bool,
+}
+
+impl Target {
+    /// Every target the given devices are driven through, in order.
+    fn all(devices: Vec<(ButtplugClientDevice, DeviceSettings)>) -> Vec<Target> {
+        let mut targets = Vec::new();
+        for (device, settings) in devices {
+            let actuators = &settings.actuators;
+            if actuators.rotate && device.output_available(OutputType::Rotate) {
+                targets.push(Target {
+                    device: device.clone(),
+                    kind: OutputType::Rotate,
+                    motor: None,
+                    name: format!("{} (rotation)", device.name()),
+                    band: settings.band.clone(),
+                    multiplier: settings.multiplier,
+                    offset: Duration::from_millis(settings.offset_ms),
+                    reversible: actuators.reverse_on_onset,
+                });
+            }
+            if actuators.oscillate && device.output_available(OutputType::Oscillate) {
+                targets.push(Target {
+                    device: device.clone(),
+                    kind: OutputType::Oscillate,
+                    motor: None,
+                    name: format!("{} (oscillation)", device.name()),
+                    band: settings.band.clone(),
+                    multiplier: settings.multiplier,
+                    offset: Duration::from_millis(settings.offset_ms),
+                    reversible: false,
+                });
+            }
+            if actuators.constrict && device.output_available(OutputType::Constrict) {
+                targets.push(Target {
+                    device: device.clone(),
+                    kind: OutputType::Constrict,
+                    motor: None,
+                    name: format!("{} (pressure)", device.name()),
+                    band: settings.band.clone(),
+                    multiplier: settings.multiplier,
+                    offset: Duration::from_millis(settings.offset_ms),
+                    reversible: false,
+                });
+            }
+            if actuators.linear && device.output_available(OutputType::HwPositionWithDuration) {
+                targets.push(Target {
+                    device: device.clone(),
+                    kind: OutputType::HwPositionWithDuration,
+                    motor: None,
+                    name: format!("{} (strokes)", device.name()),
+                    band: settings.band.clone(),
+                    multiplier: settings.multiplier,
+                    offset: Duration::from_millis(settings.offset_ms),
+                    reversible: false,
+                });
+            }
+            if !actuators.vibrate || !device.output_available(OutputType::Vibrate) {
+                continue;
+            }
+            if settings.motors.is_empty() {
+                targets.push(Target {
+                    name: device.name().clone(),
+                    kind: OutputType::Vibrate,
+                    motor: None,
+                    band: settings.band,
+                    multiplier: settings.multiplier,
+                    offset: Duration::from_millis(settings.offset_ms),
+                    reversible: false,
+                    device,
+                });
+                continue;
+            }
+            for (index, motor) in device.outputs(OutputType::Vibrate).into_iter().enumerate() {
+                let motor_settings = settings.motor(index);
+                targets.push(Target {
+                    device: device.clone(),
+                    kind: OutputType::Vibrate,
+                    motor: Some(motor),
+                    name: format!("{} (motor {})", device.name(), index + 1),
+                    band: motor_settings.band,
+                    multiplier: motor_settings.multiplier,
+                    offset: Duration::from_millis(motor_settings.offset_ms),
+                    reversible: false,
+                });
+            }
+        }
+        targets
+    }
+}
+
+/// How pressure actuators, which take seconds rather than milliseconds to respond, are driven.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PressureProfile {
+    /// How long their intensity takes to follow the audio, smoothing away anything quicker.
+    pub smoothing: Duration,
+    /// The least time between commands, as each one takes a while to carry out.
+    pub interval: Duration,
+}
+
+impl Default for PressureProfile {
+    fn default() -> Self {
+        Self {
+            smoothing: Duration::from_secs(3),
+            interval: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Where a pressure actuator is headed, and when it was last told.
+struct Pressure {
+    smoother: Smoother,
+    sent_at: Option<Instant>,
+}
+
+/// Sends intensities to every routed device.
+///
+/// All device commands pass through here, so that behaviour such as fading out
+/// applies regardless of what produced the intensity.
+pub struct DeviceOutput {
+    router: SharedRouter,
+    interval: Duration,
+    /// Each successive device is delayed by this much more than the last.
+    wave: Duration,
+    /// Recently sent intensities, oldest first, for devices whose output is delayed.
+    history: VecDeque<(Instant, f64)>,
+    /// The mapping in use, whose ceiling no device is ever sent more than, whatever its multiplier.
+    mapping: IntensityMapping,
+    cooldown: Duration,
+    /// The intensity most recently sent, before per-device scaling.
+    level: f64,
+    /// The intensity of each band, for devices routed to follow one on its own.
+    bands: Vec<(String, f64)>,
+    fade: Option<Fade>,
+    stopped: bool,
+    /// The most any device's intensity may change by per second, if limited.
+    max_slew: Option<f64>,
+    /// What each device or motor was last sent, and when, for limiting how quickly it changes.
+    last_sent: HashMap<String, (f64, Instant)>,
+    /// When commands were last sent to any device.
+    last_sent_at: Option<Instant>,
+    /// How much an intensity must change by before it's sent, unless `keepalive` has passed since it last was.
+    min_change: f64,
+    keepalive: Duration,
+    /// Whether devices that reverse on strong onsets currently rotate the other way.
+    reversed: bool,
+    strokes: StrokeLimits,
+    /// Strokes under way for each linear actuator.
+    strokers: HashMap<String, StrokeSynthesizer>,
+    /// No device is ever sent more than this.
+    max_intensity: f64,
+    pressure_profile: PressureProfile,
+    /// The smoothed intensity of each pressure actuator.
+    pressures: HashMap<String, Pressure>,
+}
+
+/// A fade to silence in progress.
+struct Fade {
+    from: f64,
+    elapsed: Duration,
+}
+
+impl DeviceOutput {
+    /// Creates an output sending commands every `interval`, which fades to silence over `cooldown` when winding down.
+    pub fn new(
+        router: SharedRouter,
+        interval: Duration,
+        mapping: IntensityMapping,
+        cooldown: Duration,
+    ) -> Self {
+        Self {
+            router,
+            interval,
+            wave: Duration::ZERO,
+            history: VecDeque::new(),
+            mapping,
+            cooldown,
+            level: 0.0,
+            bands: Vec::new(),
+            fade: None,
+            stopped: true,
+            max_slew: None,
+            last_sent: HashMap::new(),
+            last_sent_at: None,
+            min_change: 0.0,
+            keepalive: Duration::ZERO,
+            reversed: false,
+            strokes: StrokeLimits::default(),
+            strokers: HashMap::new(),
+            max_intensity: 1.0,
+            pressure_profile: PressureProfile::default(),
+            pressures: HashMap::new(),
+        }
+    }
+
+    /// Delays each successive device by `wave` more than the last, so that pulses travel across them in turn.
+    pub fn with_wave(mut self, wave: Duration) -> Self {
+        self.wave = wave;
+        self
+    }
+
+    /// Limits how quickly each device's intensity may change, to `max_slew` per second,
+    /// so that devices ramp rather than jumping, which many render as a harsh click.
+    pub fn with_max_slew(mut self, max_slew: Option<f64>) -> Self {
+        self.max_slew = max_slew.filter(|rate| *rate > 0.0);
+        self
+    }
+
+    /// Only sends an intensity once it has changed by `min_change` since it was last sent,
+    /// or once `keepalive` has passed, so that steady output doesn't flood the connection.
+    pub fn with_min_change(mut self, min_change: f64, keepalive: Duration) -> Self {
+        self.min_change = min_change.max(0.0);
+        self.keepalive = keepalive;
+        self
+    }
+
+    /// Limits how far and how quickly linear actuators stroke.
+    pub fn with_strokes(mut self, strokes: StrokeLimits) -> Self {
+        self.strokes = strokes;
+        self
+    }
+
+    /// Drives pressure actuators according to `profile`.
+    pub fn with_pressure(mut self, profile: PressureProfile) -> Self {
+        self.pressure_profile = profile;
+        self
+    }
+
+    /// Caps every command sent from now on at the ceiling of `mapping`, should it have changed since.
+    pub fn set_mapping(&mut self, mapping: IntensityMapping) {
+        self.mapping = mapping;
+    }
+
+    /// Caps every command sent from now on at `max_intensity`, whatever produced it.
+    pub fn set_max_intensity(&mut self, max_intensity: f64) {
+        self.max_intensity = clamp_unit(max_intensity);
+    }
+
+    /// Sets the intensity of each named band, sent from then on to the devices routed to follow it.
+    pub fn set_bands(&mut self, bands: Vec<(String, f64)>) {
+        self.bands = bands;
+    }
+
+    /// Reverses the rotation of devices set to reverse on strong onsets, from the next command onwards.
+    pub fn reverse_rotation(&mut self) {
+        self.reversed = !self.reversed;
+    }
+
+    /// Sends `intensity` to every active device, scaled by its own multiplier.
+    ///
+    /// Returns the commands sent, once every device has acknowledged its own.
+    pub async fn send(&mut self, intensity: f64) -> Vec<SentCommand> {
+        self.fade = None;
+        self.stopped = false;
+        self.level = intensity;
+        self.send_all(intensity, 1.0).await
+    }
+
+    /// Continues fading towards silence, `elapsed` after the previous call.
+    /// Once the fade completes, devices are told to stop, and further calls do nothing.
+    pub async fn wind_down(&mut self, elapsed: Duration) {
+        if self.stopped {
+            return;
+        }
+        let level = self.level;
+        let fade = self.fade.get_or_insert(Fade {
+            from: level,
+            elapsed: Duration::ZERO,
+        });
+        fade.elapsed += elapsed;
+        if fade.elapsed >= self.cooldown {
+            self.stop().await;
+            return;
+        }
+        let remaining = 1.0 - fade.elapsed.as_secs_f64() / self.cooldown.as_secs_f64();
+        let intensity = fade.from * remaining;
+        let _ = self.send_all(intensity, remaining).await;
+    }
+
+    /// Fades out entirely before returning, e.g. prior to exiting.
+    pub async fn fade_out(&mut self) {
+        let mut interval = time::interval(self.interval);
+        while !self.stopped {
+            interval.tick().await;
+            self.wind_down(self.interval).await;
+        }
+    }
+
+    /// Whether every device is currently being sent silence, including those whose output is delayed.
+    pub fn silent(&self) -> bool {
+        self.level == 0.0
+            && self.bands.iter().all(|(_, intensity)| *intensity == 0.0)
+            && self.history.iter().all(|(_, intensity)| *intensity == 0.0)
+    }
+
+    /// Whether devices have been told to stop, and nothing has been sent since.
+    pub fn stopped(&self) -> bool {
+        self.stopped
+    }
+
+    /// Stops every active device immediately.
+    pub async fn stop(&mut self) {
+        let active_devices = self.router.lock().unwrap().active();
+        let stops = active_devices.iter().map(|(device, _)| device.stop());
+        let _ = futures::future::join_all(stops).await;
+        self.history.clear();
+        self.fade = None;
+        self.stopped = true;
+        self.level = 0.0;
+        self.bands.clear();
+        // Stopping is immediate, so the next command ramps up from silence.
+        self.last_sent.clear();
+        self.strokers.clear();
+        self.pressures.clear();
+    }
+
+    /// Sends `intensity` to every active device, other than those following a band,
+    /// which are sent their band's intensity scaled by `band_scale`.
+    async fn send_all(&mut self, intensity: f64, band_scale: f64) -> Vec<SentCommand> {
+        let now = Instant::now();
+        let targets = Target::all(self.router.lock().unwrap().active());
+        let delays: Vec<Duration> = targets
+            .iter()
+            .enumerate()
+            .map(|(position, target)| {
+                let wave = self.wave.saturating_mul(position as u32);
+                (wave + target.offset).min(MAX_DELAY)
+            })
+            .collect();
+
+        // Only as much history as the longest delay needs is kept around.
+        self.history.push_back((now, intensity));
+        let longest = delays.iter().copied().max().unwrap_or_default();
+        while self
+            .history
+            .get(1)
+            .is_some_and(|(sent, _)| now.duration_since(*sent) >= longest)
+        {
+            self.history.pop_front();
+        }
+
+        // Stopping always happens at once; only commands in between are slew-limited.
+        let since = self
+            .last_sent_at
+            .map_or(self.interval, |at| now.duration_since(at));
+        self.last_sent_at = Some(now);
+
+        let commands = targets
+            .into_iter()
+            .zip(delays)
+            .filter_map(|(target, delay)| {
+                // Devices following a band do so immediately, as their delays are meant for waves of the overall intensity.
+                // Should their band no longer exist, they follow the overall intensity like any other.
+                let band = target.band.as_ref().and_then(|name| {
+                    self.bands
+                        .iter()
+                        .find(|(band, _)| band == name)
+                        .map(|(_, intensity)| intensity * band_scale)
+                });
+                let intensity = band.unwrap_or_else(|| self.delayed(now, delay));
+                let mut intensity = self.scaled(intensity, target.multiplier);
+                // Linear actuators move in whole strokes, each limited in speed already, so only begin a new one once due.
+                if target.kind == OutputType::HwPositionWithDuration {
+                    let strokes = self.strokes;
+                    let (position, duration) = self
+                        .strokers
+                        .entry(target.name.clone())
+                        .or_insert_with(|| StrokeSynthesizer::new(strokes))
+                        .next(now, intensity)?;
+                    let command = target.device.run_output(
+                        &ClientDeviceOutputCommand::HwPositionWithDuration(
+                            position.into(),
+                            duration.as_millis() as u32,
+                        ),
+                    );
+                    return Some(
+                        async move {
+                            let _ = command.await;
+                            SentCommand {
+                                device: target.name,
+                                intensity,
+                                acknowledged: now.elapsed(),
+                            }
+                        }
+                        .boxed(),
+                    );
+                }
+                // Pressure actuators follow slowly, and are only told where to head every so often.
+                if target.kind == OutputType::Constrict {
+                    let profile = self.pressure_profile;
+                    let pressure = self
+                        .pressures
+                        .entry(target.name.clone())
+                        .or_insert_with(|| Pressure {
+                            smoother: Smoother::new(profile.smoothing),
+                            sent_at: None,
+                        });
+                    intensity = pressure.smoother.run(intensity, since);
+                    if pressure
+                        .sent_at
+                        .is_some_and(|at| now.duration_since(at) < profile.interval)
+                    {
+                        return None;
+                    }
+                    pressure.sent_at = Some(now);
+                }
+                // Rotation turns the other way given a negative speed. Slew limiting then eases it through a stop.
+                if target.reversible && self.reversed {
+                    intensity = -intensity;
+                }
+                let intensity = self.limit(&target.name, intensity, now)?;
+                let output = match target.kind {
+                    OutputType::Rotate => ClientDeviceOutputCommand::Rotate(intensity.into()),
+                    OutputType::Oscillate => ClientDeviceOutputCommand::Oscillate(intensity.into()),
+                    OutputType::Constrict => ClientDeviceOutputCommand::Constrict(intensity.into()),
+                    _ => ClientDeviceOutputCommand::Vibrate(intensity.into()),
+                };
+                let command = match &target.motor {
+                    Some(motor) => motor.run_output(&output),
+                    None => target.device.run_output(&output),
+                };
+                Some(
+                    async move {
+                        let _ = command.await;
+                        SentCommand {
+                            device: target.name,
+                            intensity: intensity.abs(),
+                            acknowledged: now.elapsed(),
+                        }
+                    }
+                    .boxed(),
+                )
+            });
+        futures::future::join_all(commands).await
+    }
+
+    /// Scales `intensity` by a device's `multiplier`, never exceeding the cap.
+    fn scaled(&self, intensity: f64, multiplier: f64) -> f64 {
+        self.mapping
+            .scale(intensity, multiplier)
+            .min(self.max_intensity)
+    }
+
+    /// Limits how quickly the target `name` changes towards `intensity`, recording what it's sent.
+    ///
+    /// Returns `None` should the change be too small to be worth sending.
+    fn limit(&mut self, name: &str, mut intensity: f64, now: Instant) -> Option<f64> {
+        let previous = self.last_sent.get(name).copied();
+        // Each step is measured from when this device was last sent anything, as it may have been skipped since.
+        if let Some(rate) = self.max_slew {
+            let (value, elapsed) = previous.map_or((0.0, self.interval), |(value, at)| {
+                (value, now.duration_since(at))
+            });
+            let max_step = rate * elapsed.as_secs_f64();
+            intensity = intensity.clamp(value - max_step, value + max_step);
+        }
+        // Easing from above a cap that has since been lowered, by slew limiting or smoothing, mustn't exceed it either.
+        intensity = intensity.clamp(-self.max_intensity, self.max_intensity);
+        // Commands that barely change anything are skipped, other than every so often in case one was missed.
+        // Reaching silence always counts as a change, so that devices never idle just above it.
+        if let Some((value, at)) = previous
+            && (intensity - value).abs() < self.min_change
+            && (intensity != 0.0 || value == 0.0)
+            && now.duration_since(at) < self.keepalive
+        {
+            return None;
+        }
+        self.last_sent.insert(name.to_string(), (intensity, now));
+        Some(intensity)
+    }
+
+    /// The intensity that was sent `delay` before `now`, or silence if that predates our history.
+    fn delayed(&self, now: Instant, delay: Duration) -> f64 {
+        self.history
+            .iter()
+            .rev()
+            .find(|(sent, _)| now.duration_since(*sent) >= delay)
+            .map_or(0.0, |(_, intensity)| *intensity)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TICK: Duration = Duration::from_millis(35);
+
+    /// An output routing to no devices at all, mapping intensities as they are.
+    fn output() -> DeviceOutput {
+        DeviceOutput::new(
+            SharedRouter::default(),
+            TICK,
+            IntensityMapping::default(),
+            Duration::from_secs(1),
+        )
+    }
+
+    #[test]
+    fn cap_is_never_exceeded_when_boosted() {
+        let mut output = output();
+        output.set_max_intensity(0.6);
+        for intensity in [0.5, 0.6, 0.9, 1.0, 1.8, 5.0, f64::INFINITY] {
+            for multiplier in [0.5, 1.0, 2.0, 10.0] {
+                let scaled = output.scaled(intensity, multiplier);
+                assert!(scaled <= 0.6, "{intensity} × {multiplier} became {scaled}");
+            }
+        }
+    }
+
+    #[test]
+    fn lowered_ceiling_is_never_exceeded_when_scaled() {
+        let mut output = output();
+        // A remembered profile, or the `curve` command, lowers the ceiling after launch.
+        output.set_mapping(IntensityMapping {
+            ceiling: 0.4,
+            ..IntensityMapping::default()
+        });
+        for multiplier in [1.0, 1.5, 2.0, 10.0] {
+            let scaled = output.scaled(0.4, multiplier);
+            assert!(scaled <= 0.4, "0.4 × {multiplier} became {scaled}");
+        }
+    }
+
+    #[test]
+    fn ceiling_is_never_exceeded_when_boosted() {
+        let mut output = output();
+        output.set_mapping(IntensityMapping {
+            ceiling: 0.7,
+            ..IntensityMapping::default()
+        });
+        // Boosting holds output to the ceiling only before each device's multiplier applies.
+        let boosted = (0.6 * 1.5_f64).min(0.7);
+        for multiplier in [1.0, 1.2, 2.0, 10.0] {
+            let scaled = output.scaled(boosted, multiplier);
+            assert!(scaled <= 0.7, "{boosted} × {multiplier} became {scaled}");
+        }
+    }
+
+    #[test]
+    fn quiet_hours_are_never_exceeded_when_scaled() {
+        let mut output = output();
+        // Quiet hours lower the maximum intensity, here to 0.3 of a full 1.
+        output.set_max_intensity(0.3);
+        for intensity in [0.1, 0.3, 0.5, 1.0] {
+            for multiplier in [1.0, 2.0, 10.0] {
+                let scaled = output.scaled(intensity, multiplier);
+                assert!(
+                    scaled <= 0.3,
+                    "{intensity} × {multiplier} became {scaled} during quiet hours"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn cap_is_never_exceeded_while_warming_up() {
+        let mut output = output();
+        let start = Instant::now();
+        // Warming up raises the cap from 0.1 to 0.6, while a loud song plays boosted from the outset.
+        for tick in 0..=100 {
+            let cap = 0.1 + 0.5 * tick as f64 / 100.0;
+            output.set_max_intensity(cap);
+            let now = start + TICK * tick;
+            let scaled = output.scaled(1.5, 2.0);
+            let sent = output.limit("device", scaled, now).unwrap();
+            assert!(sent <= cap, "{sent} was sent with the cap at {cap}");
+        }
+    }
+
+    #[test]
+    fn lowering_the_cap_takes_effect_despite_slew_limiting() {
+        let mut output = output().with_max_slew(Some(0.5));
+        let start = Instant::now();
+        for tick in 0..100 {
+            output.limit("device", 1.0, start + TICK * tick);
+        }
+        output.set_max_intensity(0.2);
+        let sent = output.limit("device", 1.0, start + TICK * 100).unwrap();
+        assert!(sent <= 0.2, "{sent} was sent with the cap at 0.2");
+        // Rotation reversed at speed is held to the cap just the same.
+        let sent = output.limit("rotation", -1.0, start).unwrap();
+        assert!(sent >= -0.2, "{sent} was sent with the cap at 0.2");
+    }
+
+    #[test]
+    fn small_changes_are_skipped() {
+        let mut output = output().with_min_change(0.05, Duration::from_secs(1));
+        let start = Instant::now();
+        assert_eq!(output.limit("device", 0.5, start), Some(0.5));
+        assert_eq!(output.limit("device", 0.52, start + TICK), None);
+        assert_eq!(output.limit("device", 0.48, start + TICK * 2), None);
+        // Changes are measured from what was last sent, not from what was skipped.
+        assert_eq!(output.limit("device", 0.56, start + TICK * 3), Some(0.56));
+    }
+
+    #[test]
+    fn keepalive_is_resent() {
+        let keepalive = Duration::from_secs(1);
+        let mut output = output().with_min_change(0.05, keepalive);
+        let start = Instant::now();
+        assert_eq!(output.limit("device", 0.5, start), Some(0.5));
+        assert_eq!(output.limit("device", 0.5, start + keepalive / 2), None);
+        assert_eq!(output.limit("device", 0.5, start + keepalive), Some(0.5));
+        assert_eq!(output.limit("device", 0.5, start + keepalive + TICK), None);
+    }
+
+    #[test]
+    fn dropping_to_silence_is_never_skipped() {
+        let mut output = output().with_min_change(0.1, Duration::from_secs(1));
+        let start = Instant::now();
+        assert_eq!(output.limit("device", 0.05, start), Some(0.05));
+        assert_eq!(output.limit("device", 0.0, start + TICK), Some(0.0));
+        // Silence already sent needn't be sent again.
+        assert_eq!(output.limit("device", 0.0, start + TICK * 2), None);
+    }
+
+    #[test]
+    fn slew_limits_each_step() {
+        let rate = 2.0;
+        let mut output = output().with_max_slew(Some(rate));
+        let mut now = Instant::now();
+        let mut previous = 0.0;
+        // Ticks arrive irregularly, and the target jumps about.
+        for (tick, target) in [1.0, 1.0, 0.0, 0.8, 0.1, 1.0, 0.0, 0.5]
+            .iter()
+            .cycle()
+            .take(200)
+            .enumerate()
+        {
+            let elapsed = TICK * (1 + tick as u32 % 3);
+            now += elapsed;
+            let sent = output.limit("device", *target, now).unwrap();
+            let max_step = rate * elapsed.as_secs_f64() + 1e-9;
+            assert!(
+                (sent - previous).abs() <= max_step,
+                "stepped from {previous} to {sent} in {elapsed:?}"
+            );
+            previous = sent;
+        }
+    }
+
+    #[test]
+    fn slew_limiting_reaches_silence() {
+        let mut output = output().with_max_slew(Some(2.0));
+        let mut now = Instant::now();
+        for _ in 0..100 {
+            now += TICK;
+            output.limit("device", 1.0, now);
+        }
+        let ticks = (0..100)
+            .position(|_| {
+                now += TICK;
+                output.limit("device", 0.0, now) == Some(0.0)
+            })
+            .expect("never reached silence");
+        // At 2 per second, falling from full intensity takes half a second.
+        assert!(ticks <= 15, "took {ticks} ticks to reach silence");
+    }
+
+    #[tokio::test]
+    async fn stopping_is_not_slew_limited() {
+        let mut output = output().with_max_slew(Some(2.0));
+        let mut now = Instant::now();
+        for _ in 0..100 {
+            now += TICK;
+            output.limit("device", 1.0, now);
+        }
+        output.stop().await;
+        assert!(output.stopped());
+        // The next command ramps up from the silence stopping left devices in, rather than from full intensity.
+        assert_eq!(
+            output.limit("device", 1.0, now + TICK),
+            Some(2.0 * TICK.as_secs_f64())
+        );
+    }
+}
@@ -0,0 +1,218 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::f64::consts::TAU;
+use std::time::Duration;
+
+/// A built-in haptic pattern, which drives devices without any audio at all.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Pattern {
+    /// Smoothly rises and falls every four seconds.
+    Wave,
+    /// Half a second on, half a second off.
+    Pulse,
+    /// Two quick beats every second.
+    Heartbeat,
+    /// Builds from gentle to full over thirty seconds, then starts over.
+    Escalation,
+    /// Climbs through four steps, three seconds each.
+    Staircase,
+}
+
+/// The tempo built-in patterns are timed for, where following the detected tempo speeds them up or slows them down.
+pub const PATTERN_BPM: f64 = 120.0;
+
+/// Something that produces intensities over time on its own, without any audio.
+pub trait Generator: Copy + PartialEq {
+    /// The intensity, within `[0, 1]`, called for `elapsed` after it began.
+    fn level(self, elapsed: Duration) -> f64;
+}
+
+impl Pattern {
+    pub fn name(self) -> String {
+        self.to_possible_value()
+            .map(|value| value.get_name().to_string())
+            .unwrap_or_default()
+    }
+}
+
+impl Generator for Pattern {
+    fn level(self, elapsed: Duration) -> f64 {
+        let t = elapsed.as_secs_f64();
+        match self {
+            Pattern::Wave => 0.5 - 0.5 * (TAU * t / 4.0).cos(),
+            Pattern::Pulse => {
+                if t % 1.0 < 0.5 {
+                    0.8
+                } else {
+                    0.0
+                }
+            }
+            Pattern::Heartbeat => match t % 1.0 {
+                beat if beat < 0.1 => 1.0,
+                beat if (0.25..0.35).contains(&beat) => 0.7,
+                _ => 0.0,
+            },
+            Pattern::Escalation => 0.2 + 0.8 * (t % 30.0) / 30.0,
+            Pattern::Staircase => ((t / 3.0).floor() % 4.0 + 1.0) * 0.25,
+        }
+    }
+}
+
+/// Pulses on every beat at a fixed tempo.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Metronome {
+    pub bpm: f64,
+    /// How long each pulse lasts.
+    pub pulse: Duration,
+    /// The intensity of each pulse.
+    pub strength: f64,
+    /// If set, the first of every this many beats is played at full intensity.
+    pub accent_every: Option<u32>,
+}
+
+impl Metronome {
+    /// The range of tempos we accept, beyond which pulses either blur together or barely happen.
+    pub const BPM_RANGE: std::ops::RangeInclusive<f64> = 20.0..=300.0;
+}
+
+impl Generator for Metronome {
+    fn level(self, elapsed: Duration) -> f64 {
+        let bpm = if self.bpm.is_finite() {
+            self.bpm
+                .clamp(*Self::BPM_RANGE.start(), *Self::BPM_RANGE.end())
+        } else {
+            120.0
+        };
+        let beat_length = 60.0 / bpm;
+        let t = elapsed.as_secs_f64();
+        let beat = (t / beat_length).floor();
+        if t - beat * beat_length >= self.pulse.as_secs_f64() {
+            return 0.0;
+        }
+        match self.accent_every {
+            Some(every) if every > 0 && (beat as u64).is_multiple_of(every as u64) => 1.0,
+            _ => self.strength.clamp(0.0, 1.0),
+        }
+    }
+}
+
+/// How devices respond to audio.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ResponseMode {
+    /// Continuously follows the audio's level.
+    #[default]
+    Follow,
+    /// Plays a short, sharp pulse on each onset, such as a kick, and nothing in between.
+    Pulse,
+}
+
+/// The shape of each pulse: an attack, a decay, a sustain and a release.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Adsr {
+    /// How long a pulse takes to reach its peak.
+    pub attack: Duration,
+    /// How long a pulse takes to decay about two thirds of the way from its peak to the sustain level.
+    pub decay: Duration,
+    /// The share of its peak a pulse decays to and holds at.
+    pub sustain: f64,
+    /// How long after its onset a pulse is held for before releasing, or `None` to hold it until the next one.
+    pub hold: Option<Duration>,
+    /// How long a pulse takes to fall to nothing once released.
+    pub release: Duration,
+}
+
+impl Adsr {
+    /// How strong a pulse peaking at `peak` is `t` into it, were it never released.
+    fn held(&self, peak: f64, t: Duration) -> f64 {
+        if t < self.attack {
+            return peak * t.as_secs_f64() / self.attack.as_secs_f64();
+        }
+        let decayed = (t - self.attack).as_secs_f64() / self.decay.as_secs_f64().max(f64::EPSILON);
+        let sustain = self.sustain.clamp(0.0, 1.0);
+        peak * (sustain + (1.0 - sustain) * (-decayed).exp())
+    }
+}
+
+/// Shapes pulses triggered by onsets, following an [`Adsr`] envelope.
+pub struct PulseEnvelope {
+    shape: Adsr,
+    peak: f64,
+    /// How long ago the current pulse was triggered, if one has been.
+    position: Option<Duration>,
+}
+
+impl PulseEnvelope {
+    pub fn new(shape: Adsr) -> Self {
+        Self {
+            shape,
+            peak: 0.0,
+            position: None,
+        }
+    }
+
+    /// Changes how long pulses take to decay, including the one in progress.
+    pub fn set_decay(&mut self, decay: Duration) {
+        self.shape.decay = decay;
+    }
+
+    /// Starts a fresh pulse peaking at `strength`, cutting short any pulse in progress.
+    pub fn trigger(&mut self, strength: f64) {
+        self.peak = strength.clamp(0.0, 1.0);
+        self.position = Some(Duration::ZERO);
+    }
+
+    /// Advances by `elapsed`, returning the level of the current pulse.
+    pub fn advance(&mut self, elapsed: Duration) -> f64 {
+        let Some(position) = &mut self.position else {
+            return 0.0;
+        };
+        let t = *position;
+        *position += elapsed;
+        let Some(hold) = self.shape.hold.filter(|hold| t >= *hold) else {
+            return self.shape.held(self.peak, t);
+        };
+        // Once released, the pulse falls steadily from wherever it was held at.
+        let released =
+            (t - hold).as_secs_f64() / self.shape.release.as_secs_f64().max(f64::EPSILON);
+        if released >= 1.0 {
+            self.position = None;
+            return 0.0;
+        }
+        self.shape.held(self.peak, hold) * (1.0 - released)
+    }
+}
+
+/// Plays a generator, keeping track of how far into it we are.
+pub struct PatternPlayer<G> {
+    current: Option<(G, Duration)>,
+}
+
+impl<G> Default for PatternPlayer<G> {
+    fn default() -> Self {
+        Self { current: None }
+    }
+}
+
+impl<G: Generator> PatternPlayer<G> {
+    /// Advances `pattern` by `elapsed`, returning its level. Switching patterns starts from the beginning.
+    pub fn advance(&mut self, pattern: G, elapsed: Duration) -> f64 {
+        let position = match &mut self.current {
+            Some((current, position)) if *current == pattern => {
+                *position += elapsed;
+                *position
+            }
+            _ => {
+                self.current = Some((pattern, Duration::ZERO));
+                Duration::ZERO
+            }
+        };
+        pattern.level(position)
+    }
+
+    /// Forgets the current position, so that the next pattern played starts from the beginning.
+    pub fn stop(&mut self) {
+        self.current = None;
+    }
+}
@@ -0,0 +1,173 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use crate::audio::{AnalysisMode, AnalysisOptions};
+use crate::dynamics::{Compressor, Ducker, NoiseGate};
+use crate::mapping::{IntensityMapping, ResponseCurve};
+
+/// A stage the envelope passes through once analyzed, on its way to becoming an intensity.
+///
+/// Filtering and following the envelope always come first, configured by the analysis options rather than reordered here.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum StageKind {
+    /// Gain, deadzone, the response curve and the ceiling, from the intensity mapping.
+    Curve,
+    /// The noise gate, should one be configured.
+    Gate,
+    /// The compressor and limiter, should either be configured.
+    Compressor,
+    /// Ducking while someone speaks, should it be configured.
+    Duck,
+}
+
+/// The order stages run in, unless configured otherwise.
+pub const DEFAULT_STAGES: [StageKind; 4] = [
+    StageKind::Curve,
+    StageKind::Gate,
+    StageKind::Compressor,
+    StageKind::Duck,
+];
+
+/// Checks that `stages` begin with the curve, which the others need to see intensities rather than the envelope,
+/// and name none twice.
+pub fn check_stages(stages: &[StageKind]) -> Result<(), String> {
+    if stages.first() != Some(&StageKind::Curve) {
+        return Err(format!(
+            "the pipeline must begin with {}, as the stages after it work on intensities",
+            value_name(StageKind::Curve)
+        ));
+    }
+    for (position, stage) in stages.iter().enumerate() {
+        if stages[..position].contains(stage) {
+            return Err(format!(
+                "{} appears in the pipeline more than once",
+                value_name(*stage)
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Describes the stages following analysis, which filters the audio and follows its envelope.
+#[derive(Clone, Debug, Default)]
+pub struct PipelineConfig {
+    /// Stages in the order they run, beginning with the curve. Any that aren't configured are skipped.
+    pub stages: Vec<StageKind>,
+    pub gate: Option<NoiseGate>,
+    pub compressor: Option<Compressor>,
+    pub ducker: Option<Ducker>,
+}
+
+impl PipelineConfig {
+    /// Describes each stage audio passes through, including analysis, in order.
+    pub fn describe(&self, analysis: &AnalysisOptions, mapping: &IntensityMapping) -> Vec<String> {
+        let mut stages = vec![match analysis.mode {
+            AnalysisMode::Voice => "filter: voice detection".to_string(),
+            _ => format!(
+                "filter: {} at {} Hz, {}",
+                value_name(analysis.filter),
+                analysis.cutoff_hz,
+                value_name(analysis.slope),
+            ),
+        }];
+        stages.push(match analysis.mode {
+            AnalysisMode::Spectrum => "envelope: spectrum".to_string(),
+            AnalysisMode::Loudness => format!(
+                "envelope: {} loudness",
+                value_name(analysis.loudness_window)
+            ),
+            AnalysisMode::Voice => "envelope: voice activity".to_string(),
+            AnalysisMode::Bass => format!(
+                "envelope: {}, attack {} ms, release {} ms, {} of each block",
+                value_name(analysis.detector),
+                analysis.attack.as_millis(),
+                analysis.release.as_millis(),
+                value_name(analysis.block_statistic)
+            ),
+        });
+        for kind in &self.stages {
+            stages.push(match kind {
+                StageKind::Curve => {
+                    let curve = match mapping.curve {
+                        ResponseCurve::Gamma => format!("gamma {}", mapping.gamma),
+                        ResponseCurve::Custom => format!("custom {}", mapping.points),
+                        curve => value_name(curve),
+                    };
+                    format!(
+                        "curve: gain {}, deadzone {}, {}, ceiling {}",
+                        mapping.gain, mapping.deadzone, curve, mapping.ceiling
+                    )
+                }
+                StageKind::Gate => match &self.gate {
+                    Some(gate) => format!("gate: {}", gate),
+                    None => "gate: off".to_string(),
+                },
+                StageKind::Compressor => match &self.compressor {
+                    Some(compressor) => format!("compressor: {}", compressor),
+                    None => "compressor: off".to_string(),
+                },
+                StageKind::Duck => match &self.ducker {
+                    Some(ducker) => format!("duck: {}", ducker),
+                    None => "duck: off".to_string(),
+                },
+            });
+        }
+        stages
+    }
+}
+
+enum Stage {
+    Curve,
+    Gate(NoiseGate),
+    Compressor(Compressor),
+    Duck(Ducker),
+}
+
+/// Runs the envelope through each configured stage in turn.
+// TODO(spotlightishere): Render controls for each stage in the visualizer, once it can draw widgets.
+pub struct Pipeline {
+    stages: Vec<Stage>,
+}
+
+impl Pipeline {
+    pub fn new(config: &PipelineConfig) -> Self {
+        let stages = config
+            .stages
+            .iter()
+            .filter_map(|kind| match kind {
+                StageKind::Curve => Some(Stage::Curve),
+                StageKind::Gate => config.gate.clone().map(Stage::Gate),
+                StageKind::Compressor => config.compressor.map(Stage::Compressor),
+                StageKind::Duck => config.ducker.clone().map(Stage::Duck),
+            })
+            .collect();
+        Self { stages }
+    }
+
+    /// Runs `value` through each stage, `elapsed` after the previous call, noting whether someone is `speaking`.
+    pub fn run(
+        &mut self,
+        value: f64,
+        mapping: &IntensityMapping,
+        speaking: bool,
+        elapsed: Duration,
+    ) -> f64 {
+        self.stages
+            .iter_mut()
+            .fold(value, |value, stage| match stage {
+                Stage::Curve => mapping.map(value),
+                Stage::Gate(gate) => gate.run(value, elapsed),
+                Stage::Compressor(compressor) => compressor.run(value),
+                Stage::Duck(ducker) => ducker.run(value, speaking, elapsed),
+            })
+    }
+}
+
+/// What a value is called on the command line.
+fn value_name(value: impl ValueEnum) -> String {
+    value
+        .to_possible_value()
+        .map_or_else(String::new, |value| value.get_name().to_string())
+}
@@ -0,0 +1,280 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::audio::{AnalysisMode, BandSpec, ChannelSelection, OnsetMethod};
+use crate::mapping::ResponseCurve;
+use crate::output::Aggregation;
+use crate::settings::{AppSettings, MIN_INTERVAL_MS};
+
+/// A bundle of settings tuned for a particular kind of content.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Preset {
+    /// Follows a voice, such as a partner's on a call, rather than music.
+    Voice,
+    /// For quiet content such as ASMR or podcasts, where the defaults barely produce any output.
+    Quiet,
+    /// For games, where responsiveness matters more than smoothness.
+    Game,
+    /// Fast breakbeats over deep sub-bass: sub and kick bands, kick onsets, and a punchy curve.
+    DrumAndBass,
+    /// Heavy, wobbling bass with big drops: sub and wobble bands, spectral onsets, and a lifting curve.
+    Dubstep,
+    /// Kick drums and bass guitar, which sit higher than electronic bass: kick and bass bands, and kick onsets.
+    Rock,
+    /// Wide dynamics and little sustained bass: timpani and cello bands, and a curve lifting quiet passages.
+    Classical,
+    /// Speech, with the occasional music bed, followed with voice analysis and a lifting curve.
+    Podcast,
+    /// Films mixed in surround, whose explosions and rumble live on the LFE channel:
+    /// rumble and impact bands, spectral onsets, and a curve holding dialogue down.
+    Movie,
+}
+
+impl Preset {
+    /// Applies this preset to `settings`.
+    ///
+    /// Only settings for which `is_default` returns true are changed,
+    /// so that anything passed explicitly on the command line still wins.
+    pub fn apply(self, settings: &mut AppSettings, is_default: impl Fn(&str) -> bool) {
+        let is_default = &is_default;
+        match self {
+            Preset::Voice => {
+                set(
+                    is_default,
+                    "analysis",
+                    &mut settings.analysis,
+                    AnalysisMode::Voice,
+                );
+                // Speech is far quieter in its band than bass tends to be.
+                set(is_default, "gain", &mut settings.gain, 8.0);
+                set(is_default, "deadzone", &mut settings.deadzone, 0.1);
+            }
+            Preset::Quiet => {
+                // Heavy gain brings quiet content up, while a low ceiling
+                // and long smoothing keep the result gentle.
+                set(is_default, "gain", &mut settings.gain, 40.0);
+                set(is_default, "deadzone", &mut settings.deadzone, 0.0);
+                set(is_default, "ceiling", &mut settings.ceiling, 0.5);
+                set(is_default, "smoothing", &mut settings.smoothing, 400);
+            }
+            Preset::Game => {
+                // Responsiveness matters more than smoothness here: react to peaks as soon as possible,
+                // and skip drawing anything.
+                set(
+                    is_default,
+                    "aggregation",
+                    &mut settings.aggregation,
+                    Aggregation::Peak,
+                );
+                set(
+                    is_default,
+                    "interval",
+                    &mut settings.interval,
+                    MIN_INTERVAL_MS,
+                );
+                set(is_default, "low_latency", &mut settings.low_latency, true);
+                set(
+                    is_default,
+                    "no_visualizer",
+                    &mut settings.no_visualizer,
+                    true,
+                );
+                set(is_default, "smoothing", &mut settings.smoothing, 0);
+            }
+            Preset::DrumAndBass => {
+                // Drums are fast enough that every kick should come through on its own,
+                // so kicks are followed alongside the sub-bass and the curve keeps hits punchy.
+                set(is_default, "cutoff", &mut settings.cutoff, 100);
+                set(
+                    is_default,
+                    "bands",
+                    &mut settings.bands,
+                    vec![band("sub", 30.0, 60.0, 1.0), band("kick", 60.0, 100.0, 0.6)],
+                );
+                set(
+                    is_default,
+                    "onsets",
+                    &mut settings.onsets,
+                    OnsetMethod::Kick,
+                );
+                set(is_default, "curve", &mut settings.curve, ResponseCurve::Exp);
+                set(
+                    is_default,
+                    "aggregation",
+                    &mut settings.aggregation,
+                    Aggregation::Peak,
+                );
+                set(is_default, "deadzone", &mut settings.deadzone, 0.1);
+                set(is_default, "smoothing", &mut settings.smoothing, 0);
+            }
+            Preset::Dubstep => {
+                // Wobbles live low down; a little smoothing stops them turning into a buzz.
+                set(is_default, "cutoff", &mut settings.cutoff, 60);
+                set(
+                    is_default,
+                    "bands",
+                    &mut settings.bands,
+                    vec![
+                        band("sub", 20.0, 45.0, 1.0),
+                        band("wobble", 45.0, 90.0, 0.7),
+                    ],
+                );
+                // Drops are mastered loud enough that their hits only stand out in the spectrum.
+                set(
+                    is_default,
+                    "onsets",
+                    &mut settings.onsets,
+                    OnsetMethod::Flux,
+                );
+                set(is_default, "curve", &mut settings.curve, ResponseCurve::Log);
+                set(is_default, "gain", &mut settings.gain, 12.0);
+                set(is_default, "deadzone", &mut settings.deadzone, 0.05);
+                set(is_default, "smoothing", &mut settings.smoothing, 40);
+            }
+            Preset::Rock => {
+                // The kick counts for more than the bass guitar, and only it sets off pulses.
+                set(is_default, "cutoff", &mut settings.cutoff, 120);
+                set(
+                    is_default,
+                    "bands",
+                    &mut settings.bands,
+                    vec![
+                        band("kick", 40.0, 100.0, 1.0),
+                        band("bass", 100.0, 160.0, 0.4),
+                    ],
+                );
+                set(
+                    is_default,
+                    "onsets",
+                    &mut settings.onsets,
+                    OnsetMethod::Kick,
+                );
+                set(
+                    is_default,
+                    "curve",
+                    &mut settings.curve,
+                    ResponseCurve::Gamma,
+                );
+                set(is_default, "gamma", &mut settings.gamma, 0.8);
+                set(is_default, "gain", &mut settings.gain, 15.0);
+                set(is_default, "deadzone", &mut settings.deadzone, 0.05);
+                set(is_default, "smoothing", &mut settings.smoothing, 60);
+            }
+            Preset::Classical => {
+                // Cellos and timpani need a boost, but swells should stay gentle.
+                set(is_default, "cutoff", &mut settings.cutoff, 150);
+                set(
+                    is_default,
+                    "bands",
+                    &mut settings.bands,
+                    vec![
+                        band("timpani", 30.0, 100.0, 1.0),
+                        band("cello", 100.0, 250.0, 0.5),
+                    ],
+                );
+                set(is_default, "curve", &mut settings.curve, ResponseCurve::Log);
+                set(is_default, "gain", &mut settings.gain, 25.0);
+                set(is_default, "deadzone", &mut settings.deadzone, 0.0);
+                set(is_default, "ceiling", &mut settings.ceiling, 0.7);
+                set(is_default, "smoothing", &mut settings.smoothing, 300);
+            }
+            Preset::Podcast => {
+                set(
+                    is_default,
+                    "analysis",
+                    &mut settings.analysis,
+                    AnalysisMode::Voice,
+                );
+                // Bands would replace what voice analysis follows, so only the curve changes here.
+                set(is_default, "curve", &mut settings.curve, ResponseCurve::Log);
+                set(is_default, "gain", &mut settings.gain, 8.0);
+                set(is_default, "deadzone", &mut settings.deadzone, 0.15);
+                set(is_default, "smoothing", &mut settings.smoothing, 150);
+            }
+            Preset::Movie => {
+                set(
+                    is_default,
+                    "channel",
+                    &mut settings.channel,
+                    ChannelSelection::Lfe,
+                );
+                // LFE carries content up to 120 Hz, all of which is meant to be felt.
+                set(is_default, "cutoff", &mut settings.cutoff, 120);
+                set(
+                    is_default,
+                    "bands",
+                    &mut settings.bands,
+                    vec![
+                        band("rumble", 20.0, 60.0, 1.0),
+                        band("impact", 60.0, 120.0, 0.6),
+                    ],
+                );
+                // Explosions cover the whole spectrum at once, rather than rising in any one band.
+                set(
+                    is_default,
+                    "onsets",
+                    &mut settings.onsets,
+                    OnsetMethod::Flux,
+                );
+                set(
+                    is_default,
+                    "aggregation",
+                    &mut settings.aggregation,
+                    Aggregation::Peak,
+                );
+                set(is_default, "curve", &mut settings.curve, ResponseCurve::Exp);
+                // Dialogue and score shouldn't rumble; only properly loud effects should.
+                set(is_default, "deadzone", &mut settings.deadzone, 0.1);
+                set(is_default, "smoothing", &mut settings.smoothing, 0);
+            }
+        }
+    }
+}
+
+/// A band for a preset to follow.
+fn band(name: &str, low_hz: f32, high_hz: f32, weight: f32) -> BandSpec {
+    BandSpec {
+        name: name.to_string(),
+        low_hz,
+        high_hz,
+        weight,
+    }
+}
+
+/// Sets `field` to `value`, unless it was given explicitly.
+fn set<T>(is_default: &impl Fn(&str) -> bool, id: &str, field: &mut T, value: T) {
+    if is_default(id) {
+        *field = value;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+    use std::cell::RefCell;
+
+    #[test]
+    fn every_preset_sets_real_arguments() {
+        let command = AppSettings::command();
+        for preset in Preset::value_variants() {
+            let ids = RefCell::new(Vec::new());
+            let mut settings = AppSettings::default();
+            preset.apply(&mut settings, |id| {
+                ids.borrow_mut().push(id.to_string());
+                true
+            });
+            for id in ids.into_inner() {
+                assert!(
+                    command
+                        .get_arguments()
+                        .any(|arg| arg.get_id() == id.as_str()),
+                    "{:?} sets {}, which isn't an argument",
+                    preset,
+                    id
+                );
+            }
+        }
+    }
+}
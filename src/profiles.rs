@@ -0,0 +1,69 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use crate::mapping::IntensityMapping;
+
+/// Mappings remembered for each audio source, so that switching between e.g. headphones and speakers
+/// brings back whatever suited each.
+///
+/// Sources are keyed by their description, which for output devices is simply their name.
+#[derive(Default)]
+pub struct AudioProfiles {
+    path: Option<PathBuf>,
+    profiles: BTreeMap<String, IntensityMapping>,
+}
+
+impl AudioProfiles {
+    /// Loads remembered mappings from `path`, if there are any.
+    pub fn load(path: Option<PathBuf>) -> Self {
+        let profiles = path
+            .as_ref()
+            .and_then(|path| std::fs::read_to_string(path).ok())
+            .and_then(|contents| match serde_json::from_str(&contents) {
+                Ok(profiles) => Some(profiles),
+                Err(e) => {
+                    println!("Ignoring malformed audio profiles: {}", e);
+                    None
+                }
+            })
+            .unwrap_or_default();
+        Self { path, profiles }
+    }
+
+    /// The mapping remembered for `source`, if any.
+    pub fn get(&self, source: &str) -> Option<IntensityMapping> {
+        self.profiles.get(source).copied()
+    }
+
+    /// Remembers `mapping` for `source`, saving it for future sessions.
+    pub fn remember(&mut self, source: String, mapping: IntensityMapping) {
+        self.profiles.insert(source, mapping);
+        self.save();
+    }
+
+    /// Forgets whatever was remembered for `source`, returning whether there was anything.
+    pub fn forget(&mut self, source: &str) -> bool {
+        let forgotten = self.profiles.remove(source).is_some();
+        if forgotten {
+            self.save();
+        }
+        forgotten
+    }
+
+    fn save(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        if let Some(parent) = path.parent() {
+            let _ = std::fs::create_dir_all(parent);
+        }
+        match serde_json::to_string_pretty(&self.profiles) {
+            Ok(contents) => {
+                if let Err(e) = std::fs::write(path, contents) {
+                    println!("Failed to save audio profiles: {}", e);
+                }
+            }
+            Err(e) => println!("Failed to serialize audio profiles: {}", e),
+        }
+    }
+}
@@ -0,0 +1,184 @@
+use anyhow::{Context, bail};
+use serde::Serialize;
+use std::fmt::Write;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::console::Command;
+use crate::events::AppEvent;
+
+/// How many columns the heatmap spans.
+const HEATMAP_WIDTH: usize = 60;
+/// Shades from silence to full intensity.
+const HEATMAP_SHADES: [char; 5] = [' ', '░', '▒', '▓', '█'];
+
+/// Intensities are kept at most this often, so that long sessions stay small.
+const TIMELINE_RESOLUTION: Duration = Duration::from_millis(100);
+
+/// A record of the session, for analysis in external tools.
+#[derive(Clone, Debug, Serialize)]
+pub struct SessionRecording {
+    #[serde(skip)]
+    started: Instant,
+    timeline: Vec<TimelinePoint>,
+    events: Vec<LoggedEvent>,
+}
+
+#[derive(Clone, Copy, Debug, Serialize)]
+struct TimelinePoint {
+    /// Seconds since the session started.
+    seconds: f64,
+    intensity: f64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+struct LoggedEvent {
+    seconds: f64,
+    /// The subsystem the event came from.
+    kind: &'static str,
+    description: String,
+}
+
+impl Default for SessionRecording {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            timeline: Vec::new(),
+            events: Vec::new(),
+        }
+    }
+}
+
+impl SessionRecording {
+    fn seconds(&self) -> f64 {
+        self.started.elapsed().as_secs_f64()
+    }
+
+    /// Records the intensity currently being sent.
+    pub fn record_intensity(&mut self, intensity: f64) {
+        let seconds = self.seconds();
+        if let Some(last) = self.timeline.last()
+            && seconds - last.seconds < TIMELINE_RESOLUTION.as_secs_f64()
+        {
+            return;
+        }
+        self.timeline.push(TimelinePoint { seconds, intensity });
+    }
+
+    /// Records an event, should it be of any interest afterwards.
+    pub fn record_event(&mut self, event: &AppEvent) {
+        let (kind, description) = match event {
+            AppEvent::Audio(event) => ("audio", format!("{:?}", event)),
+            AppEvent::Device(event) => ("device", format!("{:?}", event)),
+            AppEvent::Session(event) => ("session", format!("{:?}", event)),
+            // Commands that only show information don't change anything worth keeping.
+            AppEvent::User(
+                Command::Help
+                | Command::ListAudio
+                | Command::ListInputs
+                | Command::ListMonitors
+                | Command::ListApplications
+                | Command::ListCables
+                | Command::ListBands
+                | Command::ListDevices
+                | Command::ListPatterns
+                | Command::ShowGenre
+                | Command::ShowTempo
+                | Command::ShowPipeline
+                | Command::Stats
+                | Command::Heatmap
+                | Command::Export(_),
+            ) => return,
+            AppEvent::User(command) => ("user", format!("{:?}", command)),
+            AppEvent::Error(e) => ("error", e.clone()),
+        };
+        let seconds = self.seconds();
+        self.events.push(LoggedEvent {
+            seconds,
+            kind,
+            description,
+        });
+    }
+
+    /// Writes the recording to `path`, as either JSON or CSV depending on its extension.
+    ///
+    /// CSV holds the timeline and events in a single table ordered by time,
+    /// with events leaving the intensity column empty.
+    pub fn export(&self, path: &Path) -> anyhow::Result<()> {
+        let contents = match path.extension().and_then(|extension| extension.to_str()) {
+            Some("json") => serde_json::to_string_pretty(self)?,
+            Some("csv") => self.to_csv(),
+            _ => bail!("expected a path ending in .json or .csv"),
+        };
+        std::fs::write(path, contents)
+            .with_context(|| format!("unable to write {}", path.display()))
+    }
+
+    /// A strip across the whole session, each column shaded by the strongest intensity within it,
+    /// so that it's clear at a glance which parts drove devices hardest.
+    pub fn heatmap(&self) -> String {
+        let Some(last) = self.timeline.last() else {
+            return "Nothing has been played yet.".to_string();
+        };
+        let duration = last.seconds.max(f64::EPSILON);
+        let mut peaks = [0.0f64; HEATMAP_WIDTH];
+        for point in &self.timeline {
+            let column =
+                ((point.seconds / duration * HEATMAP_WIDTH as f64) as usize).min(HEATMAP_WIDTH - 1);
+            peaks[column] = peaks[column].max(point.intensity);
+        }
+        let strip: String = peaks
+            .iter()
+            .map(|peak| {
+                let shade = (peak * (HEATMAP_SHADES.len() - 1) as f64).round() as usize;
+                HEATMAP_SHADES[shade.min(HEATMAP_SHADES.len() - 1)]
+            })
+            .collect();
+
+        let minutes = |seconds: f64| format!("{}:{:02}", seconds as u64 / 60, seconds as u64 % 60);
+        let end = minutes(last.seconds);
+        format!(
+            "|{}|\n 0:00{:>width$}",
+            strip,
+            end,
+            width = HEATMAP_WIDTH + 2 - " 0:00".len()
+        )
+    }
+
+    fn to_csv(&self) -> String {
+        let mut csv = String::from("seconds,intensity,kind,event\n");
+        let mut timeline = self.timeline.iter().peekable();
+        let mut events = self.events.iter().peekable();
+        loop {
+            let point_first = match (timeline.peek(), events.peek()) {
+                (Some(point), Some(event)) => point.seconds <= event.seconds,
+                (Some(_), None) => true,
+                (None, Some(_)) => false,
+                (None, None) => break,
+            };
+            if point_first {
+                let point = timeline.next().unwrap();
+                let _ = writeln!(csv, "{:.3},{:.4},,", point.seconds, point.intensity);
+            } else {
+                let event = events.next().unwrap();
+                let _ = writeln!(
+                    csv,
+                    "{:.3},,{},{}",
+                    event.seconds,
+                    event.kind,
+                    csv_field(&event.description)
+                );
+            }
+        }
+        csv
+    }
+}
+
+/// Quotes a field should it contain anything CSV treats specially.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
@@ -0,0 +1,99 @@
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::PathBuf;
+
+use crate::settings::config_dir;
+
+/// Written periodically while a session runs and removed once it exits cleanly,
+/// so that the next launch can tell whether the previous session crashed.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Heartbeat {
+    pub pid: u32,
+    pub started: String,
+    pub last_beat: String,
+    /// The source being analyzed and whether output was paused, as of the last beat.
+    pub source: Option<String>,
+    pub paused: bool,
+}
+
+impl Heartbeat {
+    /// A heartbeat for a session starting now.
+    pub fn start() -> Self {
+        let now = timestamp();
+        Self {
+            pid: std::process::id(),
+            started: now.clone(),
+            last_beat: now,
+            source: None,
+            paused: false,
+        }
+    }
+
+    /// Records that the session is still running, alongside its current state.
+    pub fn beat(&mut self, source: Option<String>, paused: bool) {
+        self.last_beat = timestamp();
+        self.source = source;
+        self.paused = paused;
+        let Some(path) = heartbeat_path() else {
+            return;
+        };
+        if let Some(parent) = path.parent() {
+            let _ = std::fs::create_dir_all(parent);
+        }
+        if let Ok(contents) = serde_json::to_string(self) {
+            let _ = std::fs::write(path, contents);
+        }
+    }
+}
+
+fn timestamp() -> String {
+    chrono::Local::now().to_rfc3339()
+}
+
+fn heartbeat_path() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("heartbeat.json"))
+}
+
+/// Where crashes are logged, for inclusion in bug reports.
+pub fn crash_log_path() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("crash.log"))
+}
+
+/// The heartbeat left behind by a previous session that never exited cleanly, if any.
+pub fn previous_crash() -> Option<Heartbeat> {
+    let contents = std::fs::read_to_string(heartbeat_path()?).ok()?;
+    serde_json::from_str(&contents).ok()
+}
+
+/// Marks the session as having exited cleanly.
+pub fn clear() {
+    if let Some(path) = heartbeat_path() {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+/// Appends an entry to the crash log.
+pub fn log_crash(entry: &str) {
+    let Some(path) = crash_log_path() else {
+        return;
+    };
+    if let Some(parent) = path.parent() {
+        let _ = std::fs::create_dir_all(parent);
+    }
+    if let Ok(mut file) = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+    {
+        let _ = writeln!(file, "[{}] {}", timestamp(), entry);
+    }
+}
+
+/// Logs panics to the crash log, in addition to printing them as usual.
+pub fn install_panic_hook() {
+    let default = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        log_crash(&format!("panic: {}", info));
+        default(info);
+    }));
+}
@@ -0,0 +1,470 @@
+use chrono::{Datelike, NaiveDateTime, NaiveTime, Weekday};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::patterns::Pattern;
+
+/// Ends a session after a set amount of activity, so that devices are never left running overnight.
+///
+/// Output is ramped down over the final stretch, rather than cut off abruptly.
+pub struct AutoStop {
+    limit: Duration,
+    ramp: Duration,
+    active: Duration,
+}
+
+impl AutoStop {
+    pub fn new(limit: Duration, ramp: Duration) -> Self {
+        Self {
+            limit,
+            // The ramp can't start before the session does.
+            ramp: ramp.min(limit),
+            active: Duration::ZERO,
+        }
+    }
+
+    /// Accounts for `elapsed` time of activity, returning the factor output should be scaled by.
+    pub fn advance(&mut self, elapsed: Duration) -> f64 {
+        self.active = (self.active + elapsed).min(self.limit);
+        let remaining = self.limit - self.active;
+        if remaining >= self.ramp {
+            1.0
+        } else {
+            remaining.as_secs_f64() / self.ramp.as_secs_f64()
+        }
+    }
+
+    pub fn expired(&self) -> bool {
+        self.active >= self.limit
+    }
+
+    /// Starts counting afresh, e.g. once the user resumes after an automatic stop.
+    pub fn reset(&mut self) {
+        self.active = Duration::ZERO;
+    }
+
+    pub fn limit(&self) -> Duration {
+        self.limit
+    }
+}
+
+/// Eases into a session, starting output at a fraction of its configured level
+/// and ramping up to the full level once audio has been playing for a while.
+pub struct WarmUp {
+    duration: Duration,
+    start: f64,
+    /// How long audio has been detected for, if it has been at all.
+    elapsed: Option<Duration>,
+}
+
+impl WarmUp {
+    pub fn new(duration: Duration, start: f64) -> Self {
+        Self {
+            duration,
+            start: start.clamp(0.0, 1.0),
+            elapsed: None,
+        }
+    }
+
+    /// Accounts for `elapsed` time, returning the factor output should be scaled by.
+    ///
+    /// The ramp only begins once `audible` is first true; until then, output is kept at the start level.
+    pub fn advance(&mut self, elapsed: Duration, audible: bool) -> f64 {
+        let progress = match &mut self.elapsed {
+            Some(total) => {
+                *total = (*total + elapsed).min(self.duration);
+                if self.duration.is_zero() {
+                    1.0
+                } else {
+                    total.as_secs_f64() / self.duration.as_secs_f64()
+                }
+            }
+            None if audible => {
+                self.elapsed = Some(Duration::ZERO);
+                0.0
+            }
+            None => 0.0,
+        };
+        self.start + (1.0 - self.start) * progress
+    }
+}
+
+/// Periodically picks a new output multiplier at random, for those who want some unpredictability,
+/// and optionally a new pattern and a multiplier for each band as well.
+pub struct Randomizer {
+    interval: Duration,
+    min: f64,
+    max: f64,
+    current: f64,
+    /// Patterns to pick from while one plays, if any.
+    patterns: Vec<Pattern>,
+    pattern: Option<Pattern>,
+    /// A multiplier for each band, should bands be randomized too.
+    bands: Vec<f64>,
+    since_change: Duration,
+    rng: StdRng,
+}
+
+impl Randomizer {
+    /// Picks a new multiplier within `[min, max]` every `interval`.
+    pub fn new(interval: Duration, min: f64, max: f64) -> Self {
+        // Bounds come from the user, so anything unusable falls back to leaving output unchanged.
+        let finite_or_one = |value: f64| if value.is_finite() { value } else { 1.0 };
+        let (min, max) = (finite_or_one(min), finite_or_one(max));
+        let (min, max) = if min <= max { (min, max) } else { (max, min) };
+        let mut randomizer = Self {
+            interval,
+            min: min.max(0.0),
+            max: max.max(0.0),
+            current: 1.0,
+            patterns: Vec::new(),
+            pattern: None,
+            bands: Vec::new(),
+            since_change: Duration::ZERO,
+            rng: StdRng::from_os_rng(),
+        };
+        randomizer.shuffle();
+        randomizer
+    }
+
+    /// Also picks which of `patterns` plays whenever a pattern does.
+    pub fn with_patterns(mut self, patterns: Vec<Pattern>) -> Self {
+        self.patterns = patterns;
+        self.shuffle();
+        self
+    }
+
+    /// Also picks a multiplier within the same bounds for each of `bands` bands, for the devices following them.
+    pub fn with_bands(mut self, bands: usize) -> Self {
+        self.bands = vec![1.0; bands];
+        self.shuffle();
+        self
+    }
+
+    /// The pattern to play in place of whichever was chosen, if patterns are randomized.
+    pub fn pattern(&self) -> Option<Pattern> {
+        self.pattern
+    }
+
+    /// The multiplier for the band at `index`, which is 1 unless bands are randomized.
+    pub fn band_factor(&self, index: usize) -> f64 {
+        self.bands.get(index).copied().unwrap_or(1.0)
+    }
+
+    /// Accounts for `elapsed` time, returning the factor output should be scaled by.
+    pub fn advance(&mut self, elapsed: Duration) -> f64 {
+        self.since_change += elapsed;
+        if self.since_change >= self.interval {
+            self.since_change = Duration::ZERO;
+            self.shuffle();
+        }
+        self.current
+    }
+
+    /// Picks everything afresh.
+    fn shuffle(&mut self) {
+        self.current = self.pick();
+        self.pattern = match self.patterns.len() {
+            0 => None,
+            len => Some(self.patterns[self.rng.random_range(0..len)]),
+        };
+        for index in 0..self.bands.len() {
+            self.bands[index] = self.pick();
+        }
+    }
+
+    fn pick(&mut self) -> f64 {
+        self.rng.random_range(self.min..=self.max)
+    }
+}
+
+/// Gradually lowers the maximum output until it reaches zero, for falling asleep to music.
+pub struct SleepTimer {
+    duration: Duration,
+    elapsed: Duration,
+}
+
+impl SleepTimer {
+    pub fn new(duration: Duration) -> Self {
+        Self {
+            duration,
+            elapsed: Duration::ZERO,
+        }
+    }
+
+    /// Accounts for `elapsed` time, returning the fraction of the ceiling output may still reach.
+    pub fn advance(&mut self, elapsed: Duration) -> f64 {
+        self.elapsed = (self.elapsed + elapsed).min(self.duration);
+        if self.duration.is_zero() {
+            return 0.0;
+        }
+        1.0 - self.elapsed.as_secs_f64() / self.duration.as_secs_f64()
+    }
+
+    pub fn expired(&self) -> bool {
+        self.elapsed >= self.duration
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+}
+
+/// Which half of an interval cycle we're in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CyclePhase {
+    Active,
+    Rest,
+}
+
+/// Alternates between periods of output and rest, fading in and out at each boundary.
+pub struct IntervalCycle {
+    active: Duration,
+    rest: Duration,
+    fade: Duration,
+    position: Duration,
+}
+
+impl IntervalCycle {
+    pub fn new(active: Duration, rest: Duration, fade: Duration) -> Self {
+        Self {
+            active,
+            rest,
+            // Fading in and out must both fit within the active period.
+            fade: fade.min(active / 2),
+            position: Duration::ZERO,
+        }
+    }
+
+    /// Accounts for `elapsed` time, returning the current phase alongside the factor output should be scaled by.
+    pub fn advance(&mut self, elapsed: Duration) -> (CyclePhase, f64) {
+        let period = self.active + self.rest;
+        if period.is_zero() {
+            return (CyclePhase::Active, 1.0);
+        }
+        self.position =
+            Duration::from_secs_f64((self.position + elapsed).as_secs_f64() % period.as_secs_f64());
+        if self.position >= self.active {
+            return (CyclePhase::Rest, 0.0);
+        }
+
+        let edge = self.position.min(self.active - self.position);
+        let factor = if edge >= self.fade {
+            1.0
+        } else {
+            edge.as_secs_f64() / self.fade.as_secs_f64()
+        };
+        (CyclePhase::Active, factor)
+    }
+}
+
+/// Temporarily multiplies output while held, ramping back down once released.
+pub struct Boost {
+    factor: f64,
+    release: Duration,
+    level: f64,
+}
+
+impl Boost {
+    pub fn new(factor: f64, release: Duration) -> Self {
+        Self {
+            // A boost never turns output down.
+            factor: if factor.is_finite() {
+                factor.max(1.0)
+            } else {
+                1.0
+            },
+            release,
+            level: 1.0,
+        }
+    }
+
+    /// Accounts for `elapsed` time, returning the factor output should be scaled by.
+    pub fn advance(&mut self, held: bool, elapsed: Duration) -> f64 {
+        if held {
+            self.level = self.factor;
+        } else if self.release.is_zero() {
+            self.level = 1.0;
+        } else {
+            let step = (self.factor - 1.0) * elapsed.as_secs_f64() / self.release.as_secs_f64();
+            self.level = (self.level - step).max(1.0);
+        }
+        self.level
+    }
+}
+
+/// Inserts a short rest after a prolonged stretch of high-intensity output,
+/// sparing both motors and their users during long, loud sets.
+pub struct DutyLimiter {
+    /// How long output may stay at or above `level` before resting.
+    limit: Duration,
+    level: f64,
+    rest: Duration,
+    fade: Duration,
+    /// Time spent at or above `level`, which drains again while output is lower.
+    high_for: Duration,
+    /// How far into the current rest we are, including fading out and back in.
+    resting: Option<Duration>,
+}
+
+impl DutyLimiter {
+    pub fn new(limit: Duration, level: f64, rest: Duration, fade: Duration) -> Self {
+        Self {
+            limit,
+            level,
+            rest,
+            fade,
+            high_for: Duration::ZERO,
+            resting: None,
+        }
+    }
+
+    /// Accounts for `elapsed` time at `intensity`, returning the factor output should be scaled by.
+    pub fn advance(&mut self, intensity: f64, elapsed: Duration) -> f64 {
+        if let Some(into) = &mut self.resting {
+            *into += elapsed;
+            let into = *into;
+            let fading_in = self.fade + self.rest;
+            if into >= fading_in + self.fade {
+                self.resting = None;
+                self.high_for = Duration::ZERO;
+                return 1.0;
+            }
+            return if into < self.fade {
+                1.0 - into.as_secs_f64() / self.fade.as_secs_f64()
+            } else if into < fading_in {
+                0.0
+            } else {
+                (into - fading_in).as_secs_f64() / self.fade.as_secs_f64()
+            };
+        }
+
+        if intensity >= self.level {
+            self.high_for += elapsed;
+        } else {
+            self.high_for = self.high_for.saturating_sub(elapsed);
+        }
+        if self.high_for >= self.limit {
+            self.resting = Some(Duration::ZERO);
+        }
+        1.0
+    }
+
+    pub fn resting(&self) -> bool {
+        self.resting.is_some()
+    }
+
+    pub fn limit(&self) -> Duration {
+        self.limit
+    }
+}
+
+/// A recurring stretch of the week during which output must stay quiet, such as overnight.
+///
+/// Written as `22:00-07:00`, optionally preceded by the days it starts on: `sun-thu 23:00-07:00`
+/// or `sat,sun 08:00-10:00`. A stretch ending earlier than it starts runs past midnight.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct QuietHours {
+    /// The days a stretch may start on, or every day if empty.
+    days: Vec<Weekday>,
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+impl QuietHours {
+    pub fn contains(&self, now: NaiveDateTime) -> bool {
+        let starts_on = |day: Weekday| self.days.is_empty() || self.days.contains(&day);
+        let (time, day) = (now.time(), now.weekday());
+        if self.start <= self.end {
+            starts_on(day) && time >= self.start && time < self.end
+        } else {
+            (starts_on(day) && time >= self.start) || (starts_on(day.pred()) && time < self.end)
+        }
+    }
+}
+
+impl FromStr for QuietHours {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (days, times) = match value.trim().rsplit_once(' ') {
+            Some((days, times)) => (parse_days(days.trim())?, times),
+            None => (Vec::new(), value.trim()),
+        };
+        let Some((start, end)) = times.split_once('-') else {
+            return Err(format!(
+                "expected a time range such as 22:00-07:00, not {}",
+                times
+            ));
+        };
+        let time = |time: &str| {
+            NaiveTime::parse_from_str(time, "%H:%M")
+                .map_err(|_| format!("expected a time such as 22:00, not {}", time))
+        };
+        Ok(Self {
+            days,
+            start: time(start)?,
+            end: time(end)?,
+        })
+    }
+}
+
+/// Parses days such as `mon,wed` or `mon-fri`.
+fn parse_days(value: &str) -> Result<Vec<Weekday>, String> {
+    let day = |day: &str| {
+        Weekday::from_str(day).map_err(|_| format!("expected a day such as mon, not {}", day))
+    };
+    let mut days = Vec::new();
+    for part in value.split(',') {
+        match part.split_once('-') {
+            Some((first, last)) => {
+                let (mut current, last) = (day(first)?, day(last)?);
+                days.push(current);
+                while current != last {
+                    current = current.succ();
+                    days.push(current);
+                }
+            }
+            None => days.push(day(part)?),
+        }
+    }
+    Ok(days)
+}
+
+impl fmt::Display for QuietHours {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.days.is_empty() {
+            let days: Vec<String> = self
+                .days
+                .iter()
+                .map(|day| day.to_string().to_lowercase())
+                .collect();
+            write!(f, "{} ", days.join(","))?;
+        }
+        write!(
+            f,
+            "{}-{}",
+            self.start.format("%H:%M"),
+            self.end.format("%H:%M")
+        )
+    }
+}
+
+impl TryFrom<String> for QuietHours {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<QuietHours> for String {
+    fn from(hours: QuietHours) -> Self {
+        hours.to_string()
+    }
+}
@@ -0,0 +1,54 @@
+use std::time::{Duration, Instant};
+use tokio::time::{self, Interval, MissedTickBehavior};
+
+use crate::envelope::EnvelopeReceiver;
+
+/// One tick of the vibration loop.
+pub struct Tick {
+    /// Every envelope value that arrived since the previous tick, up to the sampling limit.
+    pub values: Vec<f64>,
+    pub at: Instant,
+    /// Time since the previous tick.
+    pub elapsed: Duration,
+}
+
+/// Drives the vibration loop at a fixed rate, on a monotonic clock.
+///
+/// Envelope values are sampled at each tick rather than awaited, so that when commands are sent
+/// never depends on how many values happen to be queued, and time spent handling one tick
+/// doesn't push back every tick after it.
+pub struct TickScheduler {
+    interval: Interval,
+    last: Instant,
+    limit: usize,
+}
+
+impl TickScheduler {
+    /// Ticks every `period`, sampling at most `limit` values each time.
+    pub fn new(period: Duration, limit: usize) -> Self {
+        let mut interval = time::interval(period);
+        // Should a tick overrun, e.g. waiting on a slow device, the schedule carries on from the next one
+        // rather than bursting to catch up.
+        interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        Self {
+            interval,
+            last: Instant::now(),
+            limit,
+        }
+    }
+
+    /// Waits for the next tick, returning `None` once the channel has closed for good.
+    pub async fn next(&mut self, rx: &mut EnvelopeReceiver) -> Option<Tick> {
+        self.interval.tick().await;
+        let mut values = Vec::with_capacity(self.limit);
+        rx.try_recv_many(&mut values, self.limit).ok()?;
+        let at = Instant::now();
+        let elapsed = at - self.last;
+        self.last = at;
+        Some(Tick {
+            values,
+            at,
+            elapsed,
+        })
+    }
+}
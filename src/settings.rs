@@ -0,0 +1,994 @@
+use clap::parser::ValueSource;
+use clap::{CommandFactory, FromArgMatches, Parser};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::audio::{
+    AnalysisMode, AnalysisOptions, BandSpec, BassComponent, BlockStatistic, CUTOFF_RANGE_HZ,
+    CaptureFormat, CaptureOptions, ChannelSelection, DEFAULT_ATTACK, DEFAULT_CUTOFF_HZ,
+    DEFAULT_RELEASE, EnvelopeDetector, FilterSlope, FilterType, LoudnessWindow, MixCombine,
+    MixInput, OnsetMethod, SourceSpec, TestSignal, Weighting,
+};
+use crate::dsp::{
+    AutoGain, BUTTERWORTH_Q, FFT_HOP, FFT_LEN, FFT_LEN_RANGE, FftSettings, OnsetDetector,
+    WindowFunction,
+};
+use crate::dynamics::{Compressor, DuckMode, Ducker, NoiseGate};
+use crate::envelope::BackpressurePolicy;
+use crate::mapping::{CurvePoints, IntensityMapping, ResponseCurve};
+use crate::output::{Aggregation, PressureProfile};
+use crate::patterns::{Adsr, Metronome, Pattern, PulseEnvelope, ResponseMode};
+use crate::pipeline::{DEFAULT_STAGES, PipelineConfig, StageKind, check_stages};
+use crate::presets::Preset;
+use crate::schedule::{
+    AutoStop, Boost, DutyLimiter, IntervalCycle, QuietHours, Randomizer, SleepTimer, WarmUp,
+};
+use crate::stroke::StrokeLimits;
+
+/// The shortest interval between commands, in milliseconds, that devices reliably keep up with.
+pub const MIN_INTERVAL_MS: u64 = 20;
+
+/// How long output takes to ramp down before an automatic stop.
+const AUTO_STOP_RAMP: Duration = Duration::from_secs(10);
+
+/// How long output takes to fade in and out at each boundary of an interval cycle.
+const CYCLE_FADE: Duration = Duration::from_secs(3);
+
+/// How long output takes to fade in and out around each rest from --rest-after.
+const DUTY_REST_FADE: Duration = Duration::from_secs(2);
+
+/// Runtime settings, populated from the command line.
+#[derive(Parser, Clone, Debug, Serialize, Deserialize)]
+#[command(version, about)]
+#[serde(default)]
+pub struct AppSettings {
+    /// What to do with new envelope values once the vibration loop falls behind.
+    #[arg(long, value_enum, default_value_t = BackpressurePolicy::default())]
+    pub backpressure: BackpressurePolicy,
+
+    /// Start from settings tuned for a particular kind of content. Options passed explicitly take precedence.
+    #[arg(long, value_enum)]
+    pub preset: Option<Preset>,
+
+    /// What audio is analyzed for.
+    #[arg(long, value_enum, default_value_t = AnalysisMode::default())]
+    pub analysis: AnalysisMode,
+
+    /// How far back loudness is measured over, with `--analysis loudness`.
+    #[arg(long, value_enum, default_value_t = LoudnessWindow::default())]
+    pub loudness_window: LoudnessWindow,
+
+    /// Which part of the bass to follow: `all` of it, only `percussive` hits such as kicks, or only `sustained` bass.
+    #[arg(long, value_enum, default_value_t = BassComponent::default())]
+    pub react_to: BassComponent,
+
+    /// Weight frequencies by how loud they sound before analysis: `none`, `a` for A-weighting, or `k` for K-weighting.
+    #[arg(long, value_enum, default_value_t = Weighting::default())]
+    pub weighting: Weighting,
+
+    /// Detect the genre of what's playing, and automatically apply a matching profile.
+    #[arg(long)]
+    pub genre_detect: bool,
+
+    /// Follow the track playing in media players, showing it and preferring its genre tag with --genre-detect.
+    /// Needs playerctl on Linux.
+    #[arg(long)]
+    pub now_playing: bool,
+
+    /// Follow the tempo of an Ableton Link session on the local network, e.g. from DJ software or a DAW,
+    /// rather than estimating it from the audio.
+    #[arg(long)]
+    pub link: bool,
+
+    /// Which channels to analyze: `downmix` (or `mono`) for all of them, `left`, `right`, `lfe` for the LFE channel of 5.1 or 7.1 audio,
+    /// a channel index, a range of them averaged together such as `2-3` for an interface's third and fourth inputs,
+    /// or comma-separated weights for each channel in turn, such as `1,0.5`.
+    #[arg(long, default_value_t = ChannelSelection::default())]
+    pub channel: ChannelSelection,
+
+    /// The filter's frequency, in Hz. With the default low-pass filter, frequencies below this count as bass.
+    #[arg(long, default_value_t = DEFAULT_CUTOFF_HZ, value_parser = clap::value_parser!(u32).range(*CUTOFF_RANGE_HZ.start() as i64..=*CUTOFF_RANGE_HZ.end() as i64))]
+    pub cutoff: u32,
+
+    /// How steeply frequencies above the cutoff are rolled off, in dB per octave: 6, 12 or 24.
+    #[arg(long, value_enum, default_value_t = FilterSlope::default())]
+    pub slope: FilterSlope,
+
+    /// Which frequencies to follow, relative to --cutoff: `lowpass` for bass, `highpass` for e.g. hi-hats,
+    /// `bandpass` for e.g. a vocal band around it, or `notch` for everything but that band.
+    #[arg(long, value_enum, default_value_t = FilterType::default())]
+    pub filter: FilterType,
+
+    /// The filter's Q: higher is narrower for band-pass and notch filters, and more resonant at the cutoff otherwise.
+    /// Gentle low-pass and high-pass filters, and steep ones, ignore it.
+    #[arg(long, default_value_t = BUTTERWORTH_Q)]
+    pub q: f32,
+
+    /// How the envelope is measured from the filtered audio: `rms` for a steadier level, or `peak` for a sharper one.
+    #[arg(long, value_enum, default_value_t = EnvelopeDetector::default())]
+    pub detector: EnvelopeDetector,
+
+    /// Milliseconds the envelope takes to rise to meet the audio.
+    #[arg(long, default_value_t = DEFAULT_ATTACK.as_millis() as u64)]
+    pub attack: u64,
+
+    /// Milliseconds the envelope takes to fall once the audio quietens.
+    #[arg(long, default_value_t = DEFAULT_RELEASE.as_millis() as u64)]
+    pub release: u64,
+
+    /// How many samples each FFT covers, as a power of two, for `--analysis spectrum` and spectral onsets.
+    /// Longer FFTs tell low frequencies apart more finely, but respond more slowly.
+    #[arg(long, default_value_t = FFT_LEN, value_parser = fft_size)]
+    pub fft_size: usize,
+
+    /// How many new samples arrive between FFTs, at most --fft-size. Smaller hops update more often, costing more CPU time.
+    #[arg(long, default_value_t = FFT_HOP)]
+    pub fft_hop: usize,
+
+    /// The window applied before each FFT.
+    #[arg(long, value_enum, default_value_t = WindowFunction::default())]
+    pub fft_window: WindowFunction,
+
+    /// How the envelope across each block of audio is summarized as one value.
+    #[arg(long, value_enum, default_value_t = BlockStatistic::default())]
+    pub block_stat: BlockStatistic,
+
+    /// The percentile measured with `--block-stat percentile`, from 0 to 100.
+    #[arg(long, default_value_t = 90.0, value_parser = percentile)]
+    pub block_percentile: f32,
+
+    /// Split the audio into a named band, such as `sub=20-60` or `kick=60-120*0.5`, followed separately
+    /// and weighted into the overall intensity. May be given several times; devices can be routed to
+    /// follow a single band with the console's `route` command.
+    #[arg(long = "band")]
+    pub bands: Vec<BandSpec>,
+
+    /// Even out loudness between tracks, so that quietly mastered ones drive devices as strongly as loud ones.
+    #[arg(long)]
+    pub normalize: bool,
+
+    /// Automatically adjust gain so that recent peaks in the envelope reach --agc-target,
+    /// so that quiet and loud albums drive devices across a similar range.
+    #[arg(long)]
+    pub agc: bool,
+
+    /// Seconds of recent audio automatic gain control considers.
+    #[arg(long, default_value_t = 20.0)]
+    pub agc_window: f64,
+
+    /// The intensity (0 to 1) automatic gain control brings recent peaks to.
+    #[arg(long, default_value_t = 0.8, value_parser = unit_interval)]
+    pub agc_target: f64,
+
+    /// How strongly the audio envelope drives devices.
+    #[arg(long, default_value_t = IntensityMapping::default().gain)]
+    pub gain: f64,
+
+    /// Intensities at or below this level (0 to 1) are treated as silence.
+    #[arg(long, default_value_t = IntensityMapping::default().deadzone, value_parser = unit_interval)]
+    pub deadzone: f64,
+
+    /// The maximum intensity (0 to 1) any device will ever receive.
+    #[arg(long, default_value_t = IntensityMapping::default().ceiling, value_parser = unit_interval)]
+    pub ceiling: f64,
+
+    /// How strongly each level is felt: `linear`, `log` to lift quiet and mid-level audio,
+    /// `exp` to hold it down, or `gamma` to raise it to the power of --gamma.
+    #[arg(long, value_enum, default_value_t = ResponseCurve::default())]
+    pub curve: ResponseCurve,
+
+    /// The exponent for `--curve gamma`: below 1 lifts mid-level audio, above 1 holds it down.
+    #[arg(long, default_value_t = IntensityMapping::default().gamma)]
+    pub gamma: f64,
+
+    /// The points `--curve custom` passes through, as `level:intensity` pairs such as `0.2:0,0.5:0.7`.
+    /// They can also be drawn with the console's `curve edit`.
+    #[arg(long, default_value_t = CurvePoints::default())]
+    pub curve_points: CurvePoints,
+
+    /// The order the gate, compressor and ducking run in once the curve has turned the envelope into an intensity,
+    /// e.g. `curve,compressor,gate,duck` to gate what the compressor leaves. The curve must come first;
+    /// any others left out are skipped. Filtering and envelope following always come before, and aren't reordered.
+    #[arg(long, value_enum, value_delimiter = ',', default_values_t = DEFAULT_STAGES)]
+    pub pipeline: Vec<StageKind>,
+
+    /// Silence intensities until they reach this level (0 to 1), and keep doing so once they fall below
+    /// --gate-close for --gate-hold. Unlike --deadzone, intensity hovering around it doesn't chatter.
+    #[arg(long, value_parser = unit_interval)]
+    pub gate_open: Option<f64>,
+
+    /// The intensity (0 to 1) below which the gate closes again. Defaults to half of --gate-open.
+    #[arg(long, value_parser = unit_interval)]
+    pub gate_close: Option<f64>,
+
+    /// Milliseconds intensity must stay below --gate-close for before the gate closes.
+    #[arg(long, default_value_t = 250)]
+    pub gate_hold: u64,
+
+    /// Compress intensities above this level (0 to 1), so that loud transients don't slam devices to full.
+    #[arg(long, value_parser = unit_interval)]
+    pub compress_above: Option<f64>,
+
+    /// How many times less intensities above --compress-above rise by.
+    #[arg(long, default_value_t = 4.0)]
+    pub compress_ratio: f64,
+
+    /// How wide a range of intensities around --compress-above compression eases in across.
+    #[arg(long, default_value_t = 0.1, value_parser = unit_interval)]
+    pub compress_knee: f64,
+
+    /// Never let audio drive devices above this intensity (0 to 1). Unlike --ceiling,
+    /// patterns, metronomes and boosts may still go beyond it.
+    #[arg(long, default_value_t = 1.0, value_parser = unit_interval)]
+    pub limit: f64,
+
+    /// Never send any device more than this intensity (0 to 1), whatever produced it. Unlike --ceiling and
+    /// --limit, nothing gets past it: not gain, calibration, patterns, boosts, nor per-device multipliers.
+    #[arg(long, default_value_t = 1.0, value_parser = unit_interval)]
+    pub max_intensity: f64,
+
+    /// Lower intensities by this much (0 to 1) while someone is speaking, such as a call bleeding into the mix.
+    #[arg(long, value_parser = unit_interval)]
+    pub duck_voice: Option<f64>,
+
+    /// Whether speech lowers intensities by --duck-voice, or holds them where they were.
+    #[arg(long, value_enum, default_value_t = DuckMode::default())]
+    pub duck_mode: DuckMode,
+
+    /// Milliseconds intensities take to recover once speech ends.
+    #[arg(long, default_value_t = 500)]
+    pub duck_release: u64,
+
+    /// Automatically stop and pause after this many minutes of output.
+    #[arg(long)]
+    pub auto_stop: Option<f64>,
+
+    /// Lower the maximum output to zero over this many minutes, then stop and disconnect.
+    #[arg(long)]
+    pub sleep: Option<f64>,
+
+    /// Alternate between this many minutes of output and a rest period.
+    #[arg(long)]
+    pub cycle_on: Option<f64>,
+
+    /// Minutes of rest between each period of output with --cycle-on.
+    #[arg(long, default_value_t = 1.0)]
+    pub cycle_off: f64,
+
+    /// Rest for a little while after output has stayed at or above --rest-level for this many minutes.
+    #[arg(long)]
+    pub rest_after: Option<f64>,
+
+    /// The intensity (0 to 1) at or above which output counts towards --rest-after.
+    #[arg(long, default_value_t = 0.7, value_parser = unit_interval)]
+    pub rest_level: f64,
+
+    /// Seconds of silence each rest lasts for. Fading out and back in takes a further few seconds.
+    #[arg(long, default_value_t = 10.0)]
+    pub rest_for: f64,
+
+    /// Hold output down during these hours, e.g. `22:00-07:00` or `sun-thu 23:00-07:00`. May be given several times.
+    #[arg(long)]
+    pub quiet_hours: Vec<QuietHours>,
+
+    /// The maximum intensity (0 to 1) during quiet hours.
+    #[arg(long, default_value_t = 0.3, value_parser = unit_interval)]
+    pub quiet_ceiling: f64,
+
+    /// Refuse to run at all during quiet hours, rather than only lowering the ceiling.
+    #[arg(long)]
+    pub quiet_refuse: bool,
+
+    /// Stop sending commands after this many seconds of silence, resuming once audio returns. 0 keeps sending them regardless.
+    #[arg(long, default_value_t = 2.0)]
+    pub silence_pause: f64,
+
+    /// Disconnect from devices after this many minutes without audio, reconnecting once it returns.
+    #[arg(long)]
+    pub idle_disconnect: Option<f64>,
+
+    /// Ramp output up to its full level over this many minutes, starting once audio is first detected.
+    #[arg(long)]
+    pub warm_up: Option<f64>,
+
+    /// The fraction (0 to 1) of the full level output starts at while warming up.
+    #[arg(long, default_value_t = 0.2, value_parser = unit_interval)]
+    pub warm_up_start: f64,
+
+    /// Rather than scaling output down while warming up, ramp the most devices may be sent from
+    /// --warm-up-start up to --max-intensity, so that a loud song right away can't start at full power.
+    #[arg(long)]
+    pub warm_up_limit: bool,
+
+    /// Pick a new random output multiplier every this many seconds.
+    #[arg(long)]
+    pub randomize: Option<f64>,
+
+    /// The smallest multiplier --randomize may pick.
+    #[arg(long, default_value_t = 0.5)]
+    pub randomize_min: f64,
+
+    /// The largest multiplier --randomize may pick.
+    #[arg(long, default_value_t = 1.0)]
+    pub randomize_max: f64,
+
+    /// Whenever a pattern plays, swap it for one of these each time --randomize picks, e.g. `wave,pulse,heartbeat`.
+    #[arg(long, value_enum, value_delimiter = ',')]
+    pub randomize_patterns: Vec<Pattern>,
+
+    /// Also give each --band its own random multiplier each time --randomize picks, for the devices following it.
+    #[arg(long)]
+    pub randomize_bands: bool,
+
+    /// Drive devices with a built-in pattern instead of audio.
+    #[arg(long, value_enum)]
+    #[serde(skip)]
+    pub pattern: Option<Pattern>,
+
+    /// Play a built-in pattern whenever audio has been silent for a moment.
+    #[arg(long, value_enum)]
+    pub idle_pattern: Option<Pattern>,
+
+    /// Pulse devices on the beat at this tempo, instead of following audio.
+    #[arg(long)]
+    #[serde(skip)]
+    pub metronome: Option<f64>,
+
+    /// How long each metronome pulse lasts, in milliseconds.
+    #[arg(long, default_value_t = 100)]
+    pub metronome_pulse: u64,
+
+    /// The intensity (0 to 1) of each metronome pulse.
+    #[arg(long, default_value_t = 0.6, value_parser = unit_interval)]
+    pub metronome_strength: f64,
+
+    /// Accent the first of every this many beats with a full-intensity pulse.
+    #[arg(long)]
+    pub metronome_accent: Option<u32>,
+
+    /// How often, in milliseconds, commands are sent to devices.
+    #[arg(long, default_value_t = 35, value_parser = clap::value_parser!(u64).range(MIN_INTERVAL_MS..))]
+    pub interval: u64,
+
+    /// How envelope values received between commands are combined.
+    #[arg(long, value_enum, default_value_t = Aggregation::default())]
+    pub aggregation: Aggregation,
+
+    /// Capture live audio with the smallest buffer the device supports.
+    #[arg(long)]
+    pub low_latency: bool,
+
+    /// Capture live audio in buffers of this many frames. Smaller buffers lower latency, but cost more CPU time.
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub buffer_frames: Option<u32>,
+
+    /// With --play, analyze audio this many milliseconds before it's heard, so that vibrations land on the beat
+    /// despite devices' latency, which the `latency` console command measures.
+    #[arg(long, default_value_t = 0)]
+    pub lookahead: u64,
+
+    /// Capture live audio in this sample format, rather than the device's default.
+    #[arg(long, value_enum)]
+    pub sample_format: Option<CaptureFormat>,
+
+    /// Check GitHub for a newer release on startup. Nothing but the request itself is sent.
+    #[arg(long)]
+    pub check_updates: bool,
+
+    /// Don't open the waveform window.
+    #[arg(long)]
+    pub no_visualizer: bool,
+
+    /// Delay each successive device by this many more milliseconds than the last, so that pulses travel across them like a wave.
+    #[arg(long, default_value_t = 0)]
+    pub wave_offset: u64,
+
+    /// Start armed, sending output as soon as audio plays, rather than waiting for `arm`.
+    #[arg(long)]
+    #[serde(skip)]
+    pub armed: bool,
+
+    /// Keep vibrating the remaining devices when one disconnects, rather than pausing.
+    #[arg(long)]
+    pub continue_on_disconnect: bool,
+
+    /// Multiply incoming audio by this much before filtering, for quiet sources. Unlike --gain, this shows in the visualizer.
+    #[arg(long, default_value_t = 1.0)]
+    pub input_gain: f32,
+
+    /// How much the `boost` console command multiplies output by while held.
+    #[arg(long, default_value_t = 1.5)]
+    pub boost_factor: f64,
+
+    /// Seconds taken to ramp back down once a boost is released.
+    #[arg(long, default_value_t = 1.0)]
+    pub boost_release: f64,
+
+    /// Multiply output by this much at each drop, as in EDM, after a build-up: a sustained rise in energy
+    /// followed by a sudden spike.
+    #[arg(long)]
+    pub drop_boost: Option<f64>,
+
+    /// Seconds taken for a drop's boost to decay back to normal.
+    #[arg(long, default_value_t = 4.0)]
+    pub drop_decay: f64,
+
+    /// Seconds taken to fade output back in from silence when resuming.
+    #[arg(long, default_value_t = 1.0)]
+    pub fade_in: f64,
+
+    /// Seconds taken to fade output to silence when pausing or exiting.
+    #[arg(long, default_value_t = 2.0)]
+    pub cooldown: f64,
+
+    /// Smooth intensity changes over this many milliseconds.
+    #[arg(long, default_value_t = 0)]
+    pub smoothing: u64,
+
+    /// The most each device's intensity may change by per second, e.g. 4 to take a quarter of a second
+    /// from silence to full, so that devices ramp rather than clicking. Stopping is always immediate.
+    #[arg(long)]
+    pub max_slew: Option<f64>,
+
+    /// How much a device's intensity must change by (0 to 1) before it's sent again. 0 sends every command.
+    #[arg(long, default_value_t = 0.01, value_parser = unit_interval)]
+    pub min_change: f64,
+
+    /// Milliseconds after which an unchanged intensity is sent again regardless, in case a command was lost.
+    #[arg(long, default_value_t = 1000)]
+    pub keepalive: u64,
+
+    /// How intense an onset must be (0 to 1) to reverse rotation, for devices set to `reverse_on_onset`
+    /// in devices.json.
+    #[arg(long, default_value_t = 0.7, value_parser = unit_interval)]
+    pub reverse_level: f64,
+
+    /// The lowest position (0 to 1) linear actuators, such as strokers, move to.
+    #[arg(long, default_value_t = 0.0, value_parser = unit_interval)]
+    pub stroke_min: f64,
+
+    /// The highest position (0 to 1) linear actuators move to, at full intensity.
+    #[arg(long, default_value_t = 1.0, value_parser = unit_interval)]
+    pub stroke_max: f64,
+
+    /// The fastest linear actuators may ever move, in full lengths of travel per second.
+    /// Strokes that would be any faster are slowed down.
+    #[arg(long, default_value_t = 2.0)]
+    pub stroke_speed: f64,
+
+    /// Milliseconds pressure actuators, which constrict, take to follow the intensity.
+    /// They respond over seconds, so anything quicker only keeps their pumps busy.
+    #[arg(long, default_value_t = 3000)]
+    pub pressure_smoothing: u64,
+
+    /// The fewest milliseconds between commands to pressure actuators.
+    #[arg(long, default_value_t = 1000)]
+    pub pressure_interval: u64,
+
+    /// Whether devices `follow` the audio's level, or `pulse` on each onset, such as a kick, which is punchier on EDM.
+    #[arg(long, value_enum, default_value_t = ResponseMode::default())]
+    pub response: ResponseMode,
+
+    /// Milliseconds each pulse takes to rise to its peak, with `--response pulse`.
+    #[arg(long, default_value_t = 10)]
+    pub pulse_attack: u64,
+
+    /// Milliseconds each pulse takes to decay, with `--response pulse`.
+    #[arg(long, default_value_t = 150)]
+    pub pulse_decay: u64,
+
+    /// The share of its peak each pulse decays to and holds at, from 0 to 1, e.g. 0.6 for a throbbing feel.
+    #[arg(long, default_value_t = 0.0, value_parser = unit_interval)]
+    pub pulse_sustain: f64,
+
+    /// Milliseconds after its onset that each pulse is held for before releasing. By default, pulses are held until the next onset.
+    #[arg(long)]
+    pub pulse_hold: Option<u64>,
+
+    /// Milliseconds each pulse takes to fade out once released.
+    #[arg(long, default_value_t = 100)]
+    pub pulse_release: u64,
+
+    /// Make each pulse decay over this many beats of the detected tempo instead, e.g. 0.25 for a sixteenth note.
+    #[arg(long)]
+    pub pulse_beats: Option<f64>,
+
+    /// Play patterns, including --idle-pattern, in time with the detected tempo rather than at their usual 120 BPM.
+    #[arg(long)]
+    pub beat_sync: bool,
+
+    /// How many times louder than its recent average audio must suddenly become to count as an onset.
+    #[arg(long, default_value_t = 1.5)]
+    pub onset_sensitivity: f64,
+
+    /// What counts as an onset for pulses and tempo detection: any sudden rise in `level`,
+    /// only a `kick` drum, ignoring bass lines, or any sudden change in the spectrum (`flux`),
+    /// which suits heavily compressed masters.
+    #[arg(long, value_enum, default_value_t = OnsetMethod::default())]
+    pub onsets: OnsetMethod,
+
+    /// Monitor the system's default output device, following it whenever it changes, e.g. on plugging in headphones.
+    #[arg(long)]
+    pub follow_default: bool,
+
+    /// Monitor several devices at once, given as `name` or `name=gain`, with `input:` before the name of input devices,
+    /// or `app:` before the name of an application to capture via PulseAudio or PipeWire. May be given several times.
+    #[arg(long, group = "source")]
+    #[serde(skip)]
+    pub mix: Vec<MixInput>,
+
+    /// Isolate the bass and drums with this ONNX source separation model before analysis, so that vocals
+    /// don't drive devices. Needs the `stems` feature, and ONNX Runtime installed.
+    #[arg(long)]
+    pub stem_model: Option<PathBuf>,
+
+    /// How mixed devices are combined: following whichever is loudest (`max`), or adding them together (`sum`).
+    #[arg(long, value_enum, default_value_t = MixCombine::default())]
+    pub mix_combine: MixCombine,
+
+    /// Use the named audio host rather than the platform's default, such as `asio` on Windows builds with the `asio` feature.
+    #[arg(long)]
+    pub host: Option<String>,
+
+    /// Capture the named input device, such as a microphone or line-in, instead of an output device.
+    #[arg(long, group = "source")]
+    #[serde(skip)]
+    pub input: Option<String>,
+
+    /// Capture the named PulseAudio or PipeWire monitor source, such as `alsa_output.pci.analog-stereo.monitor`.
+    #[arg(long, group = "source")]
+    #[serde(skip)]
+    pub monitor: Option<String>,
+
+    /// Capture only what the named application plays (e.g. `Spotify`), via PulseAudio or PipeWire.
+    #[arg(long, group = "source")]
+    #[serde(skip)]
+    pub app: Option<String>,
+
+    /// Analyze a WAV, FLAC or MP3 file instead of a live output device.
+    #[arg(long, group = "source")]
+    #[serde(skip)]
+    pub file: Option<PathBuf>,
+
+    /// Play a WAV, FLAC or MP3 file through the default output device, analyzing it as it plays.
+    #[arg(long, group = "source")]
+    #[serde(skip)]
+    pub play: Option<PathBuf>,
+
+    /// Listen for raw 16-bit PCM over UDP on this address (e.g. 0.0.0.0:5000), instead of a live output device.
+    #[arg(long, group = "source")]
+    #[serde(skip)]
+    pub listen: Option<String>,
+
+    /// Sample rate of audio received with --listen.
+    #[arg(long, default_value_t = 48000)]
+    pub listen_rate: u32,
+
+    /// Channel count of audio received with --listen.
+    #[arg(long, default_value_t = 2)]
+    pub listen_channels: u16,
+
+    /// Expect RTP carrying 16-bit audio (L16) with --listen, rather than raw PCM.
+    #[arg(long)]
+    pub listen_rtp: bool,
+
+    /// Generate a test signal instead of analyzing real audio: a pulsing tone by default,
+    /// or `tone`, `sweep` or `pink-noise`.
+    #[arg(long, group = "source", num_args = 0..=1, default_missing_value = "pulse")]
+    #[serde(skip)]
+    pub synthetic: Option<TestSignal>,
+
+    /// Resume from the last saved snapshot, rather than starting afresh.
+    #[arg(long)]
+    #[serde(skip)]
+    pub restore: bool,
+}
+
+impl Default for AppSettings {
+    /// Our defaults are whatever the command line would produce given no arguments.
+    fn default() -> Self {
+        Self::parse_from(["subwoofer"])
+    }
+}
+
+impl AppSettings {
+    /// Parses settings from the command line, applying any requested preset.
+    pub fn from_command_line() -> Self {
+        let matches = Self::command().get_matches();
+        let mut settings = Self::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+        if let Err(e) = check_stages(&settings.pipeline) {
+            Self::command()
+                .error(clap::error::ErrorKind::ValueValidation, e)
+                .exit();
+        }
+        if let Some(preset) = settings.preset {
+            // Arguments without a default, such as --band, have no source at all unless given.
+            preset.apply(&mut settings, |id| {
+                matches
+                    .value_source(id)
+                    .is_none_or(|source| source == ValueSource::DefaultValue)
+            });
+        }
+        settings
+    }
+
+    /// The source requested on the command line, if any other than prompting for an output device.
+    pub fn source(&self) -> Option<SourceSpec> {
+        if !self.mix.is_empty() {
+            Some(SourceSpec::Mix(self.mix.clone()))
+        } else if let Some(name) = &self.input {
+            Some(SourceSpec::Input(name.clone()))
+        } else if let Some(name) = &self.monitor {
+            Some(SourceSpec::Monitor(name.clone()))
+        } else if let Some(name) = &self.app {
+            Some(SourceSpec::Application(name.clone()))
+        } else if let Some(path) = &self.file {
+            Some(SourceSpec::File(path.clone()))
+        } else if let Some(path) = &self.play {
+            Some(SourceSpec::Playback(path.clone()))
+        } else if let Some(address) = &self.listen {
+            Some(self.network_source(address.clone()))
+        } else {
+            self.synthetic.map(SourceSpec::Synthetic)
+        }
+    }
+
+    /// A network source on `address`, using the configured format.
+    pub fn network_source(&self, address: String) -> SourceSpec {
+        SourceSpec::Network {
+            address,
+            sample_rate: self.listen_rate,
+            channels: self.listen_channels,
+            rtp: self.listen_rtp,
+        }
+    }
+
+    /// The session timer requested with --auto-stop, if any.
+    pub fn auto_stop(&self) -> Option<AutoStop> {
+        self.auto_stop
+            .filter(|minutes| minutes.is_finite() && *minutes > 0.0)
+            .map(|minutes| AutoStop::new(Duration::from_secs_f64(minutes * 60.0), AUTO_STOP_RAMP))
+    }
+
+    /// The sleep timer requested with --sleep, if any.
+    pub fn sleep_timer(&self) -> Option<SleepTimer> {
+        self.sleep
+            .filter(|minutes| minutes.is_finite() && *minutes > 0.0)
+            .map(|minutes| SleepTimer::new(Duration::from_secs_f64(minutes * 60.0)))
+    }
+
+    /// The interval cycle requested with --cycle-on, if any.
+    pub fn interval_cycle(&self) -> Option<IntervalCycle> {
+        let minutes = |minutes: f64| Duration::try_from_secs_f64(minutes * 60.0).ok();
+        let active = self.cycle_on.and_then(minutes).filter(|on| !on.is_zero())?;
+        let rest = minutes(self.cycle_off).unwrap_or_default();
+        Some(IntervalCycle::new(active, rest, CYCLE_FADE))
+    }
+
+    /// The ceiling in effect at this moment, should quiet hours currently apply.
+    pub fn quiet_ceiling_now(&self) -> Option<f64> {
+        let now = chrono::Local::now().naive_local();
+        self.quiet_hours
+            .iter()
+            .any(|hours| hours.contains(now))
+            .then_some(if self.quiet_refuse {
+                0.0
+            } else {
+                self.quiet_ceiling
+            })
+    }
+
+    /// How long audio must be absent before commands stop being sent, if at all.
+    pub fn silence_pause(&self) -> Option<Duration> {
+        Duration::try_from_secs_f64(self.silence_pause)
+            .ok()
+            .filter(|pause| !pause.is_zero())
+    }
+
+    /// How long audio must be absent before disconnecting from devices, if at all.
+    pub fn idle_disconnect(&self) -> Option<Duration> {
+        self.idle_disconnect
+            .filter(|minutes| minutes.is_finite() && *minutes > 0.0)
+            .map(|minutes| Duration::from_secs_f64(minutes * 60.0))
+    }
+
+    /// The noise gate requested with --gate-open, if any.
+    pub fn noise_gate(&self) -> Option<NoiseGate> {
+        let open = self.gate_open?;
+        Some(NoiseGate::new(
+            open,
+            self.gate_close.unwrap_or(open / 2.0),
+            Duration::from_millis(self.gate_hold),
+        ))
+    }
+
+    pub fn pipeline(&self) -> PipelineConfig {
+        PipelineConfig {
+            stages: self.pipeline.clone(),
+            gate: self.noise_gate(),
+            compressor: self.compressor(),
+            ducker: self.ducker(),
+        }
+    }
+
+    /// Ducking while someone speaks, requested with --duck-voice or --duck-mode freeze.
+    pub fn ducker(&self) -> Option<Ducker> {
+        if self.duck_voice.is_none() && self.duck_mode == DuckMode::Duck {
+            return None;
+        }
+        Some(Ducker::new(
+            self.duck_mode,
+            self.duck_voice.unwrap_or(1.0),
+            Duration::from_millis(self.duck_release),
+        ))
+    }
+
+    pub fn stroke_limits(&self) -> StrokeLimits {
+        StrokeLimits {
+            min: self.stroke_min,
+            max: self.stroke_max.max(self.stroke_min),
+            max_speed: self.stroke_speed,
+        }
+    }
+
+    pub fn pressure_profile(&self) -> PressureProfile {
+        PressureProfile {
+            smoothing: Duration::from_millis(self.pressure_smoothing),
+            interval: Duration::from_millis(self.pressure_interval),
+        }
+    }
+
+    /// The compressor and limiter applied to intensities following audio, if either is in use.
+    pub fn compressor(&self) -> Option<Compressor> {
+        if self.compress_above.is_none() && self.limit >= 1.0 {
+            return None;
+        }
+        Some(Compressor {
+            threshold: self.compress_above.unwrap_or(1.0),
+            ratio: self.compress_ratio,
+            knee: self.compress_knee,
+            limit: self.limit,
+        })
+    }
+
+    pub fn auto_gain(&self) -> Option<AutoGain> {
+        let window = Duration::try_from_secs_f64(self.agc_window)
+            .ok()
+            .filter(|window| !window.is_zero())?;
+        self.agc.then(|| AutoGain::new(window, self.agc_target))
+    }
+
+    /// The duty-cycle limiter requested with --rest-after, if any.
+    pub fn duty_limiter(&self) -> Option<DutyLimiter> {
+        let limit = self
+            .rest_after
+            .and_then(|minutes| Duration::try_from_secs_f64(minutes * 60.0).ok())
+            .filter(|limit| !limit.is_zero())?;
+        let rest = Duration::try_from_secs_f64(self.rest_for).unwrap_or_default();
+        Some(DutyLimiter::new(
+            limit,
+            self.rest_level,
+            rest,
+            DUTY_REST_FADE,
+        ))
+    }
+
+    /// The warm-up ramp requested with --warm-up, if any.
+    ///
+    /// With --warm-up-limit, the ramp starts from zero instead, measuring how far the cap has risen.
+    pub fn warm_up(&self) -> Option<WarmUp> {
+        let start = if self.warm_up_limit {
+            0.0
+        } else {
+            self.warm_up_start
+        };
+        self.warm_up
+            .filter(|minutes| minutes.is_finite() && *minutes > 0.0)
+            .map(|minutes| WarmUp::new(Duration::from_secs_f64(minutes * 60.0), start))
+    }
+
+    pub fn boost(&self) -> Boost {
+        Boost::new(
+            self.boost_factor,
+            Duration::try_from_secs_f64(self.boost_release).unwrap_or_default(),
+        )
+    }
+
+    /// The boost applied at each drop, if requested with --drop-boost.
+    pub fn drop_boost(&self) -> Option<Boost> {
+        self.drop_boost.map(|factor| {
+            Boost::new(
+                factor,
+                Duration::try_from_secs_f64(self.drop_decay).unwrap_or_default(),
+            )
+        })
+    }
+
+    /// The randomizer requested with --randomize, if any.
+    pub fn randomizer(&self) -> Option<Randomizer> {
+        self.randomize
+            .filter(|seconds| seconds.is_finite() && *seconds > 0.0)
+            .map(|seconds| {
+                let randomizer = Randomizer::new(
+                    Duration::from_secs_f64(seconds),
+                    self.randomize_min,
+                    self.randomize_max,
+                )
+                .with_patterns(self.randomize_patterns.clone());
+                if self.randomize_bands {
+                    randomizer.with_bands(self.bands.len())
+                } else {
+                    randomizer
+                }
+            })
+    }
+
+    /// A metronome at `bpm`, using the configured pulse.
+    pub fn metronome_at(&self, bpm: f64) -> Metronome {
+        Metronome {
+            bpm,
+            pulse: Duration::from_millis(self.metronome_pulse),
+            strength: self.metronome_strength,
+            accent_every: self.metronome_accent,
+        }
+    }
+
+    pub fn onset_detector(&self) -> OnsetDetector {
+        OnsetDetector::new(self.onset_sensitivity.max(1.0))
+    }
+
+    pub fn pulse_envelope(&self) -> PulseEnvelope {
+        PulseEnvelope::new(Adsr {
+            attack: Duration::from_millis(self.pulse_attack),
+            decay: Duration::from_millis(self.pulse_decay),
+            sustain: self.pulse_sustain,
+            hold: self.pulse_hold.map(Duration::from_millis),
+            release: Duration::from_millis(self.pulse_release),
+        })
+    }
+
+    /// How long each pulse takes to decay at `tempo`, should pulses be measured in beats.
+    pub fn pulse_decay_at(&self, tempo: Option<f64>) -> Duration {
+        match (self.pulse_beats, tempo) {
+            (Some(beats), Some(bpm)) if beats > 0.0 => {
+                Duration::try_from_secs_f64(60.0 / bpm * beats).unwrap_or_default()
+            }
+            _ => Duration::from_millis(self.pulse_decay),
+        }
+    }
+
+    /// A ramp from silence, for resuming after a pause.
+    pub fn fade_in(&self) -> WarmUp {
+        WarmUp::new(
+            Duration::try_from_secs_f64(self.fade_in).unwrap_or_default(),
+            0.0,
+        )
+    }
+
+    /// How long output takes to fade to silence when pausing or exiting.
+    pub fn cooldown(&self) -> Duration {
+        Duration::try_from_secs_f64(self.cooldown).unwrap_or_default()
+    }
+
+    /// How often commands are sent to devices.
+    pub fn interval(&self) -> Duration {
+        Duration::from_millis(self.interval.max(MIN_INTERVAL_MS))
+    }
+
+    pub fn capture_options(&self) -> CaptureOptions {
+        CaptureOptions {
+            low_latency: self.low_latency,
+            min_channels: self.channel.min_channels(),
+            buffer_frames: self.buffer_frames,
+            sample_format: self.sample_format,
+            lookahead: Duration::from_millis(self.lookahead),
+        }
+    }
+
+    /// The Q requested with --q, ignoring anything that isn't a positive number.
+    pub fn q_factor(&self) -> f32 {
+        Some(self.q)
+            .filter(|q| q.is_finite() && *q > 0.0)
+            .unwrap_or(BUTTERWORTH_Q)
+    }
+
+    /// The input gain requested with --input-gain, ignoring anything that isn't a positive number.
+    pub fn input_gain_factor(&self) -> f32 {
+        Some(self.input_gain)
+            .filter(|gain| gain.is_finite() && *gain > 0.0)
+            .unwrap_or(1.0)
+    }
+
+    pub fn analysis_options(&self) -> AnalysisOptions {
+        AnalysisOptions {
+            mode: self.analysis,
+            component: self.react_to,
+            weighting: self.weighting,
+            channels: self.channel,
+            cutoff_hz: self.cutoff,
+            slope: self.slope,
+            filter: self.filter,
+            q: self.q_factor(),
+            bands: self.bands.clone(),
+            detector: self.detector,
+            attack: Duration::from_millis(self.attack),
+            release: Duration::from_millis(self.release),
+            block_statistic: self.block_stat,
+            block_percentile: self.block_percentile,
+            normalize: self.normalize,
+            input_gain: self.input_gain_factor(),
+            loudness_window: self.loudness_window,
+            fft: FftSettings {
+                len: self.fft_size,
+                hop: self.fft_hop,
+                window: self.fft_window,
+            },
+            onsets: self.onsets,
+            onset_sensitivity: self.onset_sensitivity.max(1.0),
+            detect_voice: self.ducker().is_some(),
+            mix_combine: self.mix_combine,
+            stem_model: self.stem_model.clone(),
+        }
+    }
+
+    pub fn mapping(&self) -> IntensityMapping {
+        IntensityMapping {
+            gain: self.gain,
+            deadzone: self.deadzone,
+            ceiling: self.ceiling,
+            curve: self.curve,
+            gamma: self.gamma,
+            points: self.curve_points,
+        }
+    }
+
+    pub fn set_mapping(&mut self, mapping: IntensityMapping) {
+        self.gain = mapping.gain;
+        self.deadzone = mapping.deadzone;
+        self.ceiling = mapping.ceiling;
+        self.curve = mapping.curve;
+        self.gamma = mapping.gamma;
+        self.curve_points = mapping.points;
+    }
+}
+
+/// Parses an FFT length, which must be a power of two within `FFT_LEN_RANGE`.
+fn fft_size(value: &str) -> Result<usize, String> {
+    let value: usize = value.parse().map_err(|e| format!("{}", e))?;
+    if FFT_LEN_RANGE.contains(&value) && value.is_power_of_two() {
+        Ok(value)
+    } else {
+        Err(format!(
+            "{} is not a power of two between {} and {}",
+            value,
+            FFT_LEN_RANGE.start(),
+            FFT_LEN_RANGE.end()
+        ))
+    }
+}
+
+/// Parses a percentile, which must lie within `[0, 100]`.
+fn percentile(value: &str) -> Result<f32, String> {
+    let value: f32 = value.parse().map_err(|e| format!("{}", e))?;
+    if (0.0..=100.0).contains(&value) {
+        Ok(value)
+    } else {
+        Err(format!("{} is not between 0 and 100", value))
+    }
+}
+
+/// Parses a value that must lie within `[0, 1]`.
+fn unit_interval(value: &str) -> Result<f64, String> {
+    let value: f64 = value.parse().map_err(|e| format!("{}", e))?;
+    if (0.0..=1.0).contains(&value) {
+        Ok(value)
+    } else {
+        Err(format!("{} is not between 0 and 1", value))
+    }
+}
+
+/// The directory subwoofer persists its state in, if one is available on this platform.
+pub fn config_dir() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("subwoofer"))
+}
@@ -0,0 +1,338 @@
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::audio::{Genre, SourceSpec};
+use crate::devices::{DeviceRouter, DeviceSettings};
+use crate::envelope::EnvelopeStats;
+use crate::events::AppEvent;
+use crate::mapping::IntensityMapping;
+use crate::media::NowPlaying;
+use crate::output::SentCommand;
+use crate::patterns::{Metronome, Pattern, ResponseMode};
+use crate::profiles::AudioProfiles;
+use crate::recording::SessionRecording;
+use crate::settings::{AppSettings, config_dir};
+use crate::stats::SessionStats;
+
+/// State that changes over the course of a session, shared between the console and the vibration loop.
+#[derive(Default)]
+pub struct RuntimeState {
+    paused: AtomicBool,
+    armed: AtomicBool,
+    boosting: AtomicBool,
+    source: Mutex<Option<SourceSpec>>,
+    pattern: Mutex<Option<Pattern>>,
+    metronome: Mutex<Option<Metronome>>,
+    genre_mode: Mutex<GenreMode>,
+    detected_genre: Mutex<Option<Genre>>,
+    now_playing: Mutex<Option<NowPlaying>>,
+    band_intensities: Mutex<Vec<(String, f64)>>,
+    response: Mutex<ResponseMode>,
+    tempo: Mutex<Option<f64>>,
+    link_tempo: Mutex<Option<f64>>,
+    mapping: Mutex<Option<IntensityMapping>>,
+    max_intensity: Mutex<Option<f64>>,
+    profiles: Mutex<AudioProfiles>,
+    calibration_requested: AtomicBool,
+    stats: Mutex<SessionStats>,
+    recording: Mutex<SessionRecording>,
+}
+
+/// How the genre profile applied to the mapping is chosen.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum GenreMode {
+    /// No genre profile is applied.
+    #[default]
+    Off,
+    /// Follows whichever genre was most recently detected.
+    Auto,
+    /// Always applies the given genre's profile.
+    Manual(Genre),
+}
+
+pub type SharedState = Arc<RuntimeState>;
+
+impl RuntimeState {
+    pub fn paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    pub fn set_paused(&self, paused: bool) {
+        self.paused.store(paused, Ordering::Relaxed);
+    }
+
+    /// Whether output may be sent to devices at all. Nothing is sent until the user arms the session.
+    pub fn armed(&self) -> bool {
+        self.armed.load(Ordering::Relaxed)
+    }
+
+    pub fn set_armed(&self, armed: bool) {
+        self.armed.store(armed, Ordering::Relaxed);
+    }
+
+    /// Whether output is being boosted for the time being.
+    pub fn boosting(&self) -> bool {
+        self.boosting.load(Ordering::Relaxed)
+    }
+
+    pub fn set_boosting(&self, boosting: bool) {
+        self.boosting.store(boosting, Ordering::Relaxed);
+    }
+
+    /// The mapping in effect, if it has changed since launch, e.g. through calibration.
+    pub fn mapping(&self) -> Option<IntensityMapping> {
+        *self.mapping.lock().unwrap()
+    }
+
+    pub fn set_mapping(&self, mapping: IntensityMapping) {
+        *self.mapping.lock().unwrap() = Some(mapping);
+    }
+
+    /// The most any device may be sent, if it has changed since launch.
+    pub fn max_intensity(&self) -> Option<f64> {
+        *self.max_intensity.lock().unwrap()
+    }
+
+    pub fn set_max_intensity(&self, max_intensity: f64) {
+        *self.max_intensity.lock().unwrap() = Some(max_intensity);
+    }
+
+    /// Asks the vibration loop to begin calibrating.
+    pub fn request_calibration(&self) {
+        self.calibration_requested.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns whether calibration was requested since this was last called.
+    pub fn take_calibration_request(&self) -> bool {
+        self.calibration_requested.swap(false, Ordering::Relaxed)
+    }
+
+    /// Statistics gathered over the session so far.
+    pub fn stats(&self) -> SessionStats {
+        self.stats.lock().unwrap().clone()
+    }
+
+    /// Records `elapsed` time of output at `intensity`, along with the commands that resulted from it.
+    pub fn record_output(&self, elapsed: Duration, intensity: f64, sent: &[SentCommand]) {
+        self.stats.lock().unwrap().record(elapsed, intensity, sent);
+        self.recording.lock().unwrap().record_intensity(intensity);
+    }
+
+    /// Records what the envelope channel has done so far, such as values it dropped.
+    pub fn record_envelope(&self, envelope: EnvelopeStats) {
+        self.stats.lock().unwrap().envelope = envelope;
+    }
+
+    pub fn record_event(&self, event: &AppEvent) {
+        self.recording.lock().unwrap().record_event(event);
+    }
+
+    /// An overview of intensity over the session so far.
+    pub fn heatmap(&self) -> String {
+        self.recording.lock().unwrap().heatmap()
+    }
+
+    /// Writes the intensity timeline and event log so far to `path`.
+    pub fn export_recording(&self, path: &Path) -> anyhow::Result<()> {
+        self.recording.lock().unwrap().export(path)
+    }
+
+    /// The audio source currently being analyzed.
+    pub fn source(&self) -> Option<SourceSpec> {
+        self.source.lock().unwrap().clone()
+    }
+
+    /// Changes the audio source, applying whichever mapping was remembered for it.
+    /// Sources without one go back to the configured mapping.
+    pub fn set_source(&self, source: SourceSpec) {
+        let remembered = self.profiles.lock().unwrap().get(&source.to_string());
+        if remembered.is_some() {
+            println!("Applying settings remembered for {}.", source);
+        }
+        *self.mapping.lock().unwrap() = remembered;
+        *self.source.lock().unwrap() = Some(source);
+    }
+
+    /// Uses `profiles` to remember mappings for each source.
+    pub fn set_profiles(&self, profiles: AudioProfiles) {
+        *self.profiles.lock().unwrap() = profiles;
+    }
+
+    /// Remembers `mapping` for the current source, returning its description.
+    pub fn remember_mapping(&self, mapping: IntensityMapping) -> Option<String> {
+        let source = self.source()?.to_string();
+        self.profiles
+            .lock()
+            .unwrap()
+            .remember(source.clone(), mapping);
+        Some(source)
+    }
+
+    /// Forgets the mapping remembered for the current source, returning whether there was one.
+    pub fn forget_mapping(&self) -> bool {
+        let Some(source) = self.source() else {
+            return false;
+        };
+        self.profiles.lock().unwrap().forget(&source.to_string())
+    }
+
+    /// The pattern driving devices in place of audio, if any.
+    pub fn pattern(&self) -> Option<Pattern> {
+        *self.pattern.lock().unwrap()
+    }
+
+    pub fn set_pattern(&self, pattern: Option<Pattern>) {
+        *self.pattern.lock().unwrap() = pattern;
+    }
+
+    /// Whether devices follow the audio's level, or pulse on its onsets.
+    pub fn response(&self) -> ResponseMode {
+        *self.response.lock().unwrap()
+    }
+
+    pub fn set_response(&self, response: ResponseMode) {
+        *self.response.lock().unwrap() = response;
+    }
+
+    /// The tempo of what's playing in beats per minute, if one has been detected.
+    pub fn tempo(&self) -> Option<f64> {
+        *self.tempo.lock().unwrap()
+    }
+
+    pub fn set_tempo(&self, tempo: Option<f64>) {
+        *self.tempo.lock().unwrap() = tempo;
+    }
+
+    /// The tempo of the Ableton Link session being followed, if any, which takes precedence over the estimate.
+    pub fn link_tempo(&self) -> Option<f64> {
+        *self.link_tempo.lock().unwrap()
+    }
+
+    pub fn set_link_tempo(&self, tempo: Option<f64>) {
+        *self.link_tempo.lock().unwrap() = tempo;
+    }
+
+    pub fn genre_mode(&self) -> GenreMode {
+        *self.genre_mode.lock().unwrap()
+    }
+
+    pub fn set_genre_mode(&self, mode: GenreMode) {
+        *self.genre_mode.lock().unwrap() = mode;
+    }
+
+    pub fn detected_genre(&self) -> Option<Genre> {
+        *self.detected_genre.lock().unwrap()
+    }
+
+    pub fn set_detected_genre(&self, genre: Genre) {
+        *self.detected_genre.lock().unwrap() = Some(genre);
+    }
+
+    /// The track media players report as playing, if following them.
+    pub fn now_playing(&self) -> Option<NowPlaying> {
+        self.now_playing.lock().unwrap().clone()
+    }
+
+    pub fn set_now_playing(&self, playing: Option<NowPlaying>) {
+        *self.now_playing.lock().unwrap() = playing;
+    }
+
+    /// The intensity each band from `--band` is currently driving its devices at.
+    pub fn band_intensities(&self) -> Vec<(String, f64)> {
+        self.band_intensities.lock().unwrap().clone()
+    }
+
+    pub fn set_band_intensities(&self, intensities: Vec<(String, f64)>) {
+        *self.band_intensities.lock().unwrap() = intensities;
+    }
+
+    /// The genre whose profile should currently be applied, if any.
+    pub fn genre_profile(&self) -> Option<Genre> {
+        match self.genre_mode() {
+            GenreMode::Off => None,
+            // A track's own genre tag is more trustworthy than what we can hear.
+            GenreMode::Auto => self
+                .now_playing()
+                .and_then(|playing| playing.genre_profile())
+                .or(self.detected_genre()),
+            GenreMode::Manual(genre) => Some(genre),
+        }
+    }
+
+    /// The metronome driving devices in place of audio, if any.
+    pub fn metronome(&self) -> Option<Metronome> {
+        *self.metronome.lock().unwrap()
+    }
+
+    pub fn set_metronome(&self, metronome: Option<Metronome>) {
+        *self.metronome.lock().unwrap() = metronome;
+    }
+}
+
+/// Everything needed to pick up a session exactly where it left off.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Snapshot {
+    pub settings: AppSettings,
+    pub paused: bool,
+    pub source: Option<SourceSpec>,
+    pub pattern: Option<Pattern>,
+    pub metronome: Option<Metronome>,
+    pub genre: GenreMode,
+    pub devices: BTreeMap<String, DeviceSettings>,
+}
+
+impl Snapshot {
+    /// Captures the current runtime state.
+    pub fn capture(settings: &AppSettings, state: &RuntimeState, router: &DeviceRouter) -> Self {
+        let mut settings = settings.clone();
+        if let Some(mapping) = state.mapping() {
+            settings.set_mapping(mapping);
+        }
+        if let Some(max_intensity) = state.max_intensity() {
+            settings.max_intensity = max_intensity;
+        }
+        Self {
+            settings,
+            paused: state.paused(),
+            source: state.source(),
+            pattern: state.pattern(),
+            metronome: state.metronome(),
+            genre: state.genre_mode(),
+            devices: router.saved_settings().clone(),
+        }
+    }
+
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("unable to read snapshot at {}", path.display()))?;
+        Self::from_json(&contents)
+    }
+
+    /// Parses a snapshot. Snapshots may have been edited by hand, so this must never panic.
+    pub fn from_json(contents: &str) -> anyhow::Result<Self> {
+        Ok(serde_json::from_str(contents)?)
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        // Write to a temporary file first, so that a crash mid-write never leaves a torn snapshot behind.
+        let temporary = path.with_extension("json.tmp");
+        std::fs::write(&temporary, serde_json::to_string_pretty(self)?)?;
+        std::fs::rename(&temporary, path)?;
+        Ok(())
+    }
+}
+
+/// Where snapshots are automatically saved to and restored from.
+pub fn snapshot_path() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("snapshot.json"))
+}
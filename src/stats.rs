@@ -0,0 +1,125 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::Duration;
+
+use crate::envelope::EnvelopeStats;
+use crate::output::SentCommand;
+
+/// How many buckets the intensity histogram is divided into.
+const HISTOGRAM_BUCKETS: usize = 10;
+/// The width of the longest histogram bar, in characters.
+const HISTOGRAM_WIDTH: usize = 40;
+
+/// Statistics over the course of a session, for curiosity and tuning.
+#[derive(Clone, Debug, Default)]
+pub struct SessionStats {
+    /// Time spent sending output, i.e. not paused.
+    pub active_time: Duration,
+    pub commands_sent: u64,
+    intensity_sum: f64,
+    intensity_samples: u64,
+    pub peak_intensity: f64,
+    /// Time each device spent active, and how much of that it spent vibrating.
+    devices: BTreeMap<String, DeviceStats>,
+    /// How much time output spent within each tenth of the intensity range.
+    histogram: [Duration; HISTOGRAM_BUCKETS],
+    /// What the envelope channel has done with values the vibration loop fell behind on.
+    pub envelope: EnvelopeStats,
+}
+
+#[derive(Clone, Debug, Default)]
+struct DeviceStats {
+    active: Duration,
+    vibrating: Duration,
+}
+
+impl SessionStats {
+    /// Records `elapsed` time of output at `intensity`, along with the commands that resulted from it.
+    pub fn record(&mut self, elapsed: Duration, intensity: f64, sent: &[SentCommand]) {
+        self.active_time += elapsed;
+        self.commands_sent += sent.len() as u64;
+        self.intensity_sum += intensity;
+        self.intensity_samples += 1;
+        self.peak_intensity = self.peak_intensity.max(intensity);
+
+        let bucket = ((intensity * HISTOGRAM_BUCKETS as f64) as usize).min(HISTOGRAM_BUCKETS - 1);
+        self.histogram[bucket] += elapsed;
+
+        for command in sent {
+            let device = self.devices.entry(command.device.clone()).or_default();
+            device.active += elapsed;
+            if command.intensity > 0.0 {
+                device.vibrating += elapsed;
+            }
+        }
+    }
+
+    pub fn average_intensity(&self) -> f64 {
+        if self.intensity_samples == 0 {
+            0.0
+        } else {
+            self.intensity_sum / self.intensity_samples as f64
+        }
+    }
+}
+
+impl fmt::Display for SessionStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let seconds = self.active_time.as_secs();
+        writeln!(
+            f,
+            "Active for {}:{:02}:{:02}",
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60
+        )?;
+        writeln!(
+            f,
+            "Intensity: {:.2} average, {:.2} peak",
+            self.average_intensity(),
+            self.peak_intensity
+        )?;
+        writeln!(f, "Commands sent: {}", self.commands_sent)?;
+        writeln!(
+            f,
+            "Envelope channel: {} sent, {} dropped (oldest), {} dropped (newest), {} coalesced",
+            self.envelope.sent,
+            self.envelope.dropped_oldest,
+            self.envelope.dropped_newest,
+            self.envelope.coalesced
+        )?;
+
+        for (name, device) in &self.devices {
+            let duty_cycle = if device.active.is_zero() {
+                0.0
+            } else {
+                device.vibrating.as_secs_f64() / device.active.as_secs_f64()
+            };
+            writeln!(
+                f,
+                "  {}: vibrating {:.0}% of the time",
+                name,
+                duty_cycle * 100.0
+            )?;
+        }
+
+        writeln!(f, "Intensity distribution:")?;
+        let longest = self.histogram.iter().max().copied().unwrap_or_default();
+        for (bucket, time) in self.histogram.iter().enumerate() {
+            let width = if longest.is_zero() {
+                0
+            } else {
+                (time.as_secs_f64() / longest.as_secs_f64() * HISTOGRAM_WIDTH as f64).round()
+                    as usize
+            };
+            writeln!(
+                f,
+                "  {:.1}-{:.1} {}",
+                bucket as f64 / HISTOGRAM_BUCKETS as f64,
+                (bucket + 1) as f64 / HISTOGRAM_BUCKETS as f64,
+                "#".repeat(width)
+            )?;
+        }
+        Ok(())
+    }
+}
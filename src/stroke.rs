@@ -0,0 +1,85 @@
+use std::time::{Duration, Instant};
+
+use crate::mapping::clamp_unit;
+
+/// How long each stroke takes at the quietest intensity, and at full intensity.
+const SLOWEST_STROKE: Duration = Duration::from_millis(1000);
+const FASTEST_STROKE: Duration = Duration::from_millis(250);
+/// Strokes shorter than this share of the travel aren't worth moving for.
+const MIN_STROKE: f64 = 0.02;
+
+/// How far, and how quickly, linear actuators may move.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StrokeLimits {
+    /// The lowest position strokes reach, from 0 to 1.
+    pub min: f64,
+    /// The highest position strokes reach, from 0 to 1.
+    pub max: f64,
+    /// The fastest any stroke may move, in full lengths of travel per second.
+    pub max_speed: f64,
+}
+
+impl Default for StrokeLimits {
+    fn default() -> Self {
+        Self {
+            min: 0.0,
+            max: 1.0,
+            max_speed: 2.0,
+        }
+    }
+}
+
+/// Turns intensities into strokes for linear actuators, such as strokers.
+///
+/// Strokes alternate between extending from the lowest position and returning to it.
+/// The more intense the audio, the further and faster each one extends.
+#[derive(Clone, Debug)]
+pub struct StrokeSynthesizer {
+    limits: StrokeLimits,
+    /// Where the current stroke ends.
+    position: f64,
+    /// When the current stroke ends, if one is under way.
+    until: Option<Instant>,
+}
+
+impl StrokeSynthesizer {
+    pub fn new(limits: StrokeLimits) -> Self {
+        Self {
+            limits,
+            position: clamp_unit(limits.min),
+            until: None,
+        }
+    }
+
+    /// The stroke to begin at `now` given `intensity`, as the position to move to and how long to take doing so.
+    ///
+    /// Returns `None` while the previous stroke is still under way, or while there's nowhere worth moving to.
+    pub fn next(&mut self, now: Instant, intensity: f64) -> Option<(f64, Duration)> {
+        if self.until.is_some_and(|until| now < until) {
+            return None;
+        }
+        let intensity = clamp_unit(intensity);
+        let min = clamp_unit(self.limits.min);
+        let max = clamp_unit(self.limits.max).max(min);
+        let target = if self.position > min {
+            min
+        } else {
+            min + (max - min) * intensity
+        };
+        let distance = (target - self.position).abs();
+        if distance < MIN_STROKE {
+            self.until = None;
+            return None;
+        }
+
+        let mut duration =
+            FASTEST_STROKE + (SLOWEST_STROKE - FASTEST_STROKE).mul_f64(1.0 - intensity);
+        // However intense the audio, no stroke may move faster than the limit.
+        if self.limits.max_speed > 0.0 {
+            duration = duration.max(Duration::from_secs_f64(distance / self.limits.max_speed));
+        }
+        self.position = target;
+        self.until = Some(now + duration);
+        Some((target, duration))
+    }
+}
@@ -0,0 +1,89 @@
+use anyhow::{Context, bail};
+use serde::Deserialize;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::rustls::{self, ClientConfig, RootCertStore};
+
+use crate::events::{AppEvent, EventBus, SessionEvent};
+
+const RELEASES_HOST: &str = "api.github.com";
+const LATEST_RELEASE_PATH: &str = "/repos/abstract-creations/subwoofer/releases/latest";
+/// Checking for updates is a courtesy, and must never hold anything up for long.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A published release, as described by the GitHub API.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Release {
+    pub tag_name: String,
+    pub html_url: String,
+    /// Release notes, in Markdown.
+    #[serde(default)]
+    pub body: Option<String>,
+}
+
+impl Release {
+    /// Whether this release is newer than the running version.
+    pub fn is_newer(&self) -> bool {
+        let parse = |version: &str| semver::Version::parse(version.trim_start_matches('v')).ok();
+        match (parse(&self.tag_name), parse(env!("CARGO_PKG_VERSION"))) {
+            (Some(release), Some(current)) => release > current,
+            _ => false,
+        }
+    }
+}
+
+/// Checks GitHub for a newer release, announcing it on the bus should there be one.
+///
+/// Only a single anonymous request is made; nothing about this machine or session is sent.
+pub async fn check_for_updates(bus: EventBus) {
+    match tokio::time::timeout(CHECK_TIMEOUT, latest_release()).await {
+        Ok(Ok(release)) if release.is_newer() => {
+            bus.publish(AppEvent::Session(SessionEvent::UpdateAvailable(release)))
+        }
+        Ok(Ok(_)) => {}
+        Ok(Err(e)) => println!("Unable to check for updates: {:#}", e),
+        Err(_) => println!("Unable to check for updates: timed out"),
+    }
+}
+
+async fn latest_release() -> anyhow::Result<Release> {
+    let roots = RootCertStore {
+        roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+    };
+    let config =
+        ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()?
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+
+    let stream = TcpStream::connect((RELEASES_HOST, 443)).await?;
+    let mut stream = TlsConnector::from(Arc::new(config))
+        .connect(ServerName::try_from(RELEASES_HOST)?, stream)
+        .await?;
+
+    // HTTP/1.0 keeps the response simple: no chunking, and the connection closes once it's sent.
+    let request = format!(
+        "GET {} HTTP/1.0\r\nHost: {}\r\nUser-Agent: subwoofer/{}\r\nAccept: application/vnd.github+json\r\n\r\n",
+        LATEST_RELEASE_PATH,
+        RELEASES_HOST,
+        env!("CARGO_PKG_VERSION")
+    );
+    stream.write_all(request.as_bytes()).await?;
+    let mut response = Vec::new();
+    // Servers often close TLS connections without notifying us first, which is harmless here.
+    let _ = stream.read_to_end(&mut response).await;
+
+    let response = String::from_utf8_lossy(&response);
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .context("malformed response")?;
+    let status = head.lines().next().unwrap_or_default();
+    if !status.contains(" 200 ") {
+        bail!("unexpected response: {}", status);
+    }
+    Ok(serde_json::from_str(body)?)
+}
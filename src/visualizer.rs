@@ -0,0 +1,338 @@
+use audio_visualizer::dynamic::window_top_btm::visualize_minifb::{
+    DEFAULT_H, DEFAULT_W, get_drawing_areas, setup_window,
+};
+use minifb::{Key, KeyRepeat, MouseButton, MouseMode, Window, WindowOptions};
+use plotters::chart::ChartContext;
+use plotters::coord::cartesian::Cartesian2d;
+use plotters::coord::types::RangedCoordf64;
+use plotters::drawing::IntoDrawingArea;
+use plotters::element::{Circle, PathElement};
+use plotters::prelude::BitMapBackend;
+use plotters::series::LineSeries;
+use plotters::style::{BLACK, CYAN, Color, RGBColor, WHITE};
+use plotters_bitmap::bitmap_pixel::BGRXPixel;
+use std::borrow::{Borrow, BorrowMut};
+use std::sync::Arc;
+
+use crate::audio::{self, AudioMonitor, HISTORY_LEN};
+use crate::console::{Command, SourceRequest};
+use crate::events::{AppEvent, EventBus};
+use crate::mapping::{CurvePoints, IntensityMapping, ResponseCurve};
+use crate::state::SharedState;
+
+/// The raw waveform's background while input is clipping.
+const CLIPPING: RGBColor = RGBColor(96, 0, 0);
+/// Both backgrounds while the stream is being reopened.
+const STREAM_LOST: RGBColor = RGBColor(48, 48, 48);
+
+const TITLE: &str = "Live Audio Lowpass Filter View";
+
+const EDITOR_TITLE: &str = "Intensity Curve (left click to add or drag, right click to remove)";
+/// The curve editor's size, and how far its plot is inset from the edges.
+const EDITOR_SIZE: usize = 400;
+const EDITOR_MARGIN: usize = 20;
+/// How close, in pixels, a click must be to a point to grab it.
+const GRAB_RADIUS: f32 = 10.0;
+const GRID: RGBColor = RGBColor(48, 48, 48);
+
+/// Chooses which output device to monitor from within the window, for when there's no console to type in.
+///
+/// The left and right arrow keys step through output devices, shown in the title,
+/// and enter switches to whichever is shown.
+#[derive(Default)]
+struct DevicePicker {
+    /// The devices to choose between and which is shown, while choosing.
+    choosing: Option<(Vec<String>, usize)>,
+}
+
+impl DevicePicker {
+    /// Handles this frame's key presses, switching devices on the bus once one is chosen.
+    /// Returns whether the window should stay open.
+    fn update(&mut self, window: &Window, bus: &EventBus) -> bool {
+        let step = if window.is_key_pressed(Key::Left, KeyRepeat::Yes) {
+            Some(-1)
+        } else if window.is_key_pressed(Key::Right, KeyRepeat::Yes) {
+            Some(1)
+        } else {
+            None
+        };
+        match (&mut self.choosing, step) {
+            (Some((names, shown)), Some(step)) if !names.is_empty() => {
+                *shown = (*shown as isize + step).rem_euclid(names.len() as isize) as usize;
+            }
+            // Devices are listed afresh each time choosing begins, so that newly plugged in ones are offered.
+            (None, Some(_)) => {
+                let names = audio::list_output_devs()
+                    .into_iter()
+                    .map(|(name, _)| name)
+                    .collect();
+                self.choosing = Some((names, 0));
+            }
+            _ => {}
+        }
+        if window.is_key_pressed(Key::Escape, KeyRepeat::No) {
+            // Escape only backs out of choosing, rather than closing the window too.
+            return self.choosing.take().is_some();
+        }
+        if window.is_key_pressed(Key::Enter, KeyRepeat::No)
+            && let Some((mut names, shown)) = self.choosing.take()
+            && shown < names.len()
+        {
+            let name = names.swap_remove(shown);
+            bus.publish(AppEvent::User(Command::SwitchSource(
+                SourceRequest::Device(name),
+            )));
+        }
+        true
+    }
+
+    /// What the title shows while choosing.
+    fn title(&self) -> Option<String> {
+        let (names, shown) = self.choosing.as_ref()?;
+        Some(match names.get(*shown) {
+            Some(name) => format!(
+                "Switch to {} ({} of {})? Left and right to choose, enter to switch, escape to cancel",
+                name,
+                shown + 1,
+                names.len()
+            ),
+            None => "No output devices found (escape to cancel)".to_string(),
+        })
+    }
+}
+
+/// Opens the lowpass filter view, drawing whatever the monitor currently holds,
+/// and naming the track playing and its tempo in its title.
+///
+/// Unlike `open_window_connect_audio`, this does not own an audio stream,
+/// so it keeps working when the monitored device changes. The monitored output device
+/// can be switched from within the window, with [`DevicePicker`].
+/// Blocks until the window is closed.
+pub fn open_window(monitor: Arc<AudioMonitor>, state: SharedState, bus: EventBus) {
+    let time_per_sample = 1.0 / monitor.sample_rate() as f64;
+    let (mut window, top_cs, btm_cs, mut pixel_buf) = setup_window(
+        TITLE,
+        None,
+        None,
+        None,
+        None,
+        "time (seconds)",
+        "Amplitude (with Lowpass filter)",
+        HISTORY_LEN,
+        time_per_sample,
+    );
+    window.set_target_fps(144);
+    let mut shown_title = TITLE.to_string();
+    let mut picker = DevicePicker::default();
+
+    while window.is_open() {
+        // Once capture has stopped for good, nothing else holds on to the monitor.
+        if !picker.update(&window, &bus) || Arc::strong_count(&monitor) == 1 {
+            break;
+        }
+
+        let (top_drawing_area, btm_drawing_area) =
+            get_drawing_areas(pixel_buf.borrow_mut(), DEFAULT_W, DEFAULT_H);
+        let top_chart = top_cs.clone().restore(&top_drawing_area);
+        let btm_chart = btm_cs.clone().restore(&btm_drawing_area);
+        // The window greys out while the stream is being reopened, and the raw waveform turns red while input is clipping.
+        let lost = monitor.stream_lost();
+        let mut title = if lost {
+            format!("{} (audio stream lost, reconnecting...)", TITLE)
+        } else if let Some(playing) = state.now_playing() {
+            format!("{} - {}", TITLE, playing)
+        } else {
+            TITLE.to_string()
+        };
+        // Tempos are rounded, so that the title doesn't change with every small revision.
+        if !lost && let Some(bpm) = state.tempo() {
+            title = format!("{} ({:.0} BPM)", title, bpm);
+        }
+        if let Some(choosing) = picker.title() {
+            title = choosing;
+        }
+        if title != shown_title {
+            window.set_title(&title);
+            shown_title = title;
+        }
+        let (top, bottom) = if lost {
+            (STREAM_LOST, STREAM_LOST)
+        } else if monitor.clipping() {
+            (CLIPPING, BLACK)
+        } else {
+            (BLACK, BLACK)
+        };
+        top_chart.plotting_area().fill(&top).unwrap();
+        btm_chart.plotting_area().fill(&bottom).unwrap();
+
+        let (raw, filtered) = monitor.snapshot();
+        fill_chart_waveform_over_time(top_chart, &raw, time_per_sample);
+        fill_chart_waveform_over_time(btm_chart, &filtered, time_per_sample);
+
+        drop(top_drawing_area);
+        drop(btm_drawing_area);
+        window
+            .update_with_buffer(pixel_buf.borrow(), DEFAULT_W, DEFAULT_H)
+            .unwrap();
+    }
+}
+
+fn fill_chart_waveform_over_time(
+    mut chart: ChartContext<BitMapBackend<BGRXPixel>, Cartesian2d<RangedCoordf64, RangedCoordf64>>,
+    audio_data: &[f32],
+    time_per_sample: f64,
+) {
+    let timeshift = audio_data.len() as f64 * time_per_sample;
+    let data_iter = audio_data
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| *i % 4 == 0)
+        .map(|(i, amplitude)| {
+            let timestamp = time_per_sample * (i as f64) - timeshift;
+            (timestamp, (*amplitude) as f64)
+        });
+
+    chart
+        .draw_series(LineSeries::new(data_iter, &CYAN))
+        .unwrap();
+}
+
+/// Opens an editor for the custom intensity curve, which plots input level against output intensity.
+///
+/// Every change is applied as it's made, switching the mapping to the custom curve.
+/// Blocks until the window is closed.
+pub fn open_curve_editor(state: SharedState, base: IntensityMapping) {
+    let Ok(mut window) = Window::new(
+        EDITOR_TITLE,
+        EDITOR_SIZE,
+        EDITOR_SIZE,
+        WindowOptions::default(),
+    ) else {
+        println!("Unable to open the curve editor");
+        return;
+    };
+    window.set_target_fps(60);
+    let mut points = state.mapping().unwrap_or(base).points;
+    let mut pixel_buf = vec![0u8; EDITOR_SIZE * EDITOR_SIZE * 4];
+    let mut frame = vec![0u32; EDITOR_SIZE * EDITOR_SIZE];
+    // Which point is being dragged, and which buttons were held on the previous frame.
+    let mut dragging: Option<usize> = None;
+    let (mut was_left, mut was_right) = (false, false);
+
+    while window.is_open() && !window.is_key_down(Key::Escape) {
+        let (left, right) = (
+            window.get_mouse_down(MouseButton::Left),
+            window.get_mouse_down(MouseButton::Right),
+        );
+        let mut changed = false;
+        if let Some((x, y)) = window.get_mouse_pos(MouseMode::Clamp) {
+            let nearest = nearest_point(&points, (x, y));
+            if left && !was_left {
+                dragging = nearest.or_else(|| points.insert(from_screen((x, y))));
+                changed = dragging.is_some();
+            } else if left && let Some(index) = dragging {
+                dragging = points.relocate(index, from_screen((x, y)));
+                changed = true;
+            }
+            if right
+                && !was_right
+                && let Some(index) = nearest
+            {
+                points.remove(index);
+                dragging = None;
+                changed = true;
+            }
+        }
+        if !left {
+            dragging = None;
+        }
+        (was_left, was_right) = (left, right);
+
+        if changed {
+            // Anything else changed meanwhile, such as the gain, is kept.
+            state.set_mapping(IntensityMapping {
+                curve: ResponseCurve::Custom,
+                points,
+                ..state.mapping().unwrap_or(base)
+            });
+        }
+
+        draw_curve(&mut pixel_buf, &points);
+        for (pixel, bytes) in frame.iter_mut().zip(pixel_buf.chunks_exact(4)) {
+            *pixel = u32::from_le_bytes(bytes.try_into().unwrap());
+        }
+        window
+            .update_with_buffer(&frame, EDITOR_SIZE, EDITOR_SIZE)
+            .unwrap();
+    }
+    println!("Curve: custom {}", points);
+}
+
+/// Draws the curve, its points and a grid of tenths.
+fn draw_curve(pixel_buf: &mut [u8], points: &CurvePoints) {
+    let root = BitMapBackend::<BGRXPixel>::with_buffer_and_format(
+        pixel_buf,
+        (EDITOR_SIZE as u32, EDITOR_SIZE as u32),
+    )
+    .unwrap()
+    .into_drawing_area();
+    root.fill(&BLACK).unwrap();
+    for tenth in 0..=10 {
+        let at = tenth as f64 / 10.0;
+        root.draw(&PathElement::new(
+            vec![to_screen((at, 0.0)), to_screen((at, 1.0))],
+            GRID,
+        ))
+        .unwrap();
+        root.draw(&PathElement::new(
+            vec![to_screen((0.0, at)), to_screen((1.0, at))],
+            GRID,
+        ))
+        .unwrap();
+    }
+    let plot_width = EDITOR_SIZE - 2 * EDITOR_MARGIN;
+    let curve = (0..=plot_width).map(|x| {
+        let level = x as f64 / plot_width as f64;
+        to_screen((level, points.interpolate(level)))
+    });
+    root.draw(&PathElement::new(curve.collect::<Vec<_>>(), CYAN))
+        .unwrap();
+    for point in points.points() {
+        root.draw(&Circle::new(to_screen(*point), 4, WHITE.filled()))
+            .unwrap();
+    }
+    root.present().unwrap();
+}
+
+/// Where a level and intensity lie within the editor.
+fn to_screen((level, intensity): (f64, f64)) -> (i32, i32) {
+    let plot = (EDITOR_SIZE - 2 * EDITOR_MARGIN) as f64;
+    (
+        EDITOR_MARGIN as i32 + (level * plot).round() as i32,
+        (EDITOR_SIZE - EDITOR_MARGIN) as i32 - (intensity * plot).round() as i32,
+    )
+}
+
+/// The level and intensity at a position within the editor.
+fn from_screen((x, y): (f32, f32)) -> (f64, f64) {
+    let plot = (EDITOR_SIZE - 2 * EDITOR_MARGIN) as f64;
+    (
+        (x as f64 - EDITOR_MARGIN as f64) / plot,
+        ((EDITOR_SIZE - EDITOR_MARGIN) as f64 - y as f64) / plot,
+    )
+}
+
+/// The index of the point closest to a position, if any is close enough to grab.
+fn nearest_point(points: &CurvePoints, (x, y): (f32, f32)) -> Option<usize> {
+    points
+        .points()
+        .iter()
+        .map(|point| {
+            let (px, py) = to_screen(*point);
+            (px as f32 - x).hypot(py as f32 - y)
+        })
+        .enumerate()
+        .filter(|(_, distance)| *distance <= GRAB_RADIUS)
+        .min_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map(|(index, _)| index)
+}